@@ -0,0 +1,72 @@
+// Compares walking the tree with make/undo against copy-make, where each
+// child position is a clone of its parent, to show why make/undo is what
+// perft and search use.
+//
+//     cargo bench --bench copy_make
+
+use std::time::{Duration, Instant};
+
+use chess_rs::engine::board::Board;
+use chess_rs::engine::movegen::{MoveGenerator, MoveList};
+use chess_rs::engine::perft;
+use chess_rs::engine::r#move::UndoInfo;
+
+const POSITIONS: &[(&str, &str)] = &[
+    ("start", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+];
+const DEPTH: usize = 4;
+
+// a child board for each ply, reused with clone_from so only the copy is
+// timed and not the allocation
+fn perft_copy_make(depth: usize, board: &Board, move_generator: &MoveGenerator, boards: &mut [Board], move_lists: &mut [MoveList]) -> u64 {
+    let (child, boards) = boards.split_first_mut().unwrap();
+    let (moves, move_lists) = move_lists.split_first_mut().unwrap();
+
+    child.clone_from(board);
+    move_generator.gen_moves(child, moves);
+    if depth <= 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    let mut info = UndoInfo::default();
+    for i in 0..moves.len() {
+        child.clone_from(board);
+        child.make_move(moves.at(i), &mut info);
+        nodes += perft_copy_make(depth - 1, child, move_generator, boards, move_lists);
+    }
+    nodes
+}
+
+fn nodes_per_second(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+fn main() {
+    let move_generator = MoveGenerator::new();
+
+    println!("Board is {} bytes", std::mem::size_of::<Board>());
+    println!("{:<10} {:>12} {:>16} {:>16}", "position", "nodes", "make/undo nps", "copy-make nps");
+    for (name, fen) in POSITIONS {
+        let mut board = Board::new(fen).unwrap();
+        let start = Instant::now();
+        let nodes = perft::perft(DEPTH, &mut board, &move_generator);
+        let make_undo = start.elapsed();
+
+        let mut boards = vec![board.clone(); DEPTH];
+        let mut move_lists: Vec<MoveList> = (0..DEPTH).map(|_| MoveList::new()).collect();
+        let start = Instant::now();
+        let copy_nodes = perft_copy_make(DEPTH, &board, &move_generator, &mut boards, &mut move_lists);
+        let copy_make = start.elapsed();
+        assert_eq!(nodes, copy_nodes, "{}", fen);
+
+        println!(
+            "{:<10} {:>12} {:>16} {:>16}",
+            name,
+            nodes,
+            nodes_per_second(nodes, make_undo),
+            nodes_per_second(nodes, copy_make)
+        );
+    }
+}
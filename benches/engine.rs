@@ -0,0 +1,19 @@
+// Runs the engine benchmarks from chess_rs::cli::bench, reporting each as
+// operations per second.
+//
+//     cargo bench --bench engine
+
+use chess_rs::cli::bench::{self, BenchSettings};
+use chess_rs::engine::control::ControlHandle;
+
+fn main() {
+    let settings = BenchSettings::FULL;
+    println!(
+        "{} iterations, perft {}, search depth {}",
+        settings.iterations, settings.perft_depth, settings.search_depth
+    );
+
+    for result in bench::run(settings, &ControlHandle::new()) {
+        println!("{}", result);
+    }
+}
@@ -0,0 +1,40 @@
+// Times making moves and checking for repetitions through a game far longer
+// than the board's hash history, to show the checks don't slow down as the
+// game goes on: each looks back no further than the history keeps.
+//
+//     cargo bench --bench long_game
+
+use std::time::{Duration, Instant};
+
+use chess_rs::engine::board::Board;
+use chess_rs::engine::movegen::MoveGenerator;
+use chess_rs::engine::r#move::UndoInfo;
+
+// knight moves there and back, so nothing resets the fifty move count
+const SHUFFLE: [&str; 4] = ["g1f3", "g8f6", "f3g1", "f6g8"];
+const CHUNK_PLIES: usize = 20_000;
+const CHUNKS: usize = 10;
+
+fn plies_per_second(plies: usize, elapsed: Duration) -> u64 {
+    (plies as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+fn main() {
+    let move_generator = MoveGenerator::new();
+    let mut board = Board::default();
+    let mut info = UndoInfo::default();
+    let mut repetitions = 0;
+
+    println!("{:>8} {:>16}", "ply", "plies/second");
+    for _ in 0..CHUNKS {
+        let start = Instant::now();
+        for _ in 0..CHUNK_PLIES / SHUFFLE.len() {
+            for uci_move in &SHUFFLE {
+                board.make_uci_move(uci_move, &move_generator, &mut info).unwrap();
+                repetitions += board.is_threefold_repetition() as usize;
+            }
+        }
+        println!("{:>8} {:>16}", board.ply(), plies_per_second(CHUNK_PLIES, start.elapsed()));
+    }
+    println!("{} repetitions seen", repetitions);
+}
@@ -0,0 +1,54 @@
+// Compares perft with the legal move generator against the pseudo-legal one
+// with each move checked by making it, to see where each is faster.
+//
+//     cargo bench --bench pseudo_legal
+
+use std::time::{Duration, Instant};
+
+use chess_rs::engine::board::Board;
+use chess_rs::engine::movegen::MoveGenerator;
+use chess_rs::engine::perft;
+
+const POSITIONS: &[(&str, &str)] = &[
+    ("start", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+    ("kiwipete", "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1"),
+    ("endgame", "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1"),
+];
+const MAX_DEPTH: usize = 4;
+
+type Perft = fn(usize, &mut Board, &MoveGenerator) -> u64;
+
+fn time(count: Perft, fen: &str, depth: usize, move_generator: &MoveGenerator) -> (u64, Duration) {
+    let mut board = Board::new(fen).unwrap();
+
+    let start = Instant::now();
+    let nodes = count(depth, &mut board, move_generator);
+    (nodes, start.elapsed())
+}
+
+fn nodes_per_second(nodes: u64, elapsed: Duration) -> u64 {
+    (nodes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+fn main() {
+    let move_generator = MoveGenerator::new();
+
+    println!("{:<10} {:>5} {:>12} {:>14} {:>14}  faster", "position", "depth", "nodes", "legal nps", "pseudo nps");
+    for (name, fen) in POSITIONS {
+        for depth in 1..=MAX_DEPTH {
+            let (nodes, legal) = time(perft::perft, fen, depth, &move_generator);
+            let (pseudo_nodes, pseudo_legal) = time(perft::perft_pseudo_legal, fen, depth, &move_generator);
+            assert_eq!(nodes, pseudo_nodes, "{} depth {}", fen, depth);
+
+            println!(
+                "{:<10} {:>5} {:>12} {:>14} {:>14}  {}",
+                name,
+                depth,
+                nodes,
+                nodes_per_second(nodes, legal),
+                nodes_per_second(nodes, pseudo_legal),
+                if legal <= pseudo_legal { "legal" } else { "pseudo-legal" }
+            );
+        }
+    }
+}
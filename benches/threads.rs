@@ -0,0 +1,55 @@
+// Compares the nodes per second of the lazy SMP search with different
+// numbers of threads, searching Kiwipete for a fixed time. The helpers
+// only add to the total given cores to run on, so the numbers depend on
+// the machine more than the others here.
+//
+//     cargo bench --bench threads
+
+use std::time::Instant;
+
+use chess_rs::engine::board::Board;
+use chess_rs::engine::control::ControlHandle;
+use chess_rs::engine::eval::Evaluator;
+use chess_rs::engine::movegen::{MoveGenerator, MoveList};
+use chess_rs::engine::search::{self, SearchContext};
+
+const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+const MILLIS: usize = 2000;
+const MAX_DEPTH: usize = 64;
+const THREADS: &[usize] = &[1, 2, 4, 8];
+
+fn main() {
+    let move_generator = MoveGenerator::new();
+    let cores = std::thread::available_parallelism().map_or(1, |cores| cores.get());
+
+    println!("{} cores, {}ms per search", cores, MILLIS);
+    println!("{:<8} {:>12} {:>12} {:>8}", "threads", "nodes", "nps", "depth");
+    for &threads in THREADS {
+        let mut board = Board::new(KIWIPETE).unwrap();
+        let mut move_lists = (0..MAX_DEPTH).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        let start = Instant::now();
+        search::parallel_search(
+            threads,
+            MAX_DEPTH,
+            &mut board,
+            &mut Evaluator::default(),
+            &move_generator,
+            &mut move_lists,
+            MILLIS,
+            MILLIS,
+            &mut ControlHandle::new(),
+            &mut context,
+        );
+        let elapsed = start.elapsed();
+
+        println!(
+            "{:<8} {:>12} {:>12} {:>8}",
+            threads,
+            context.nodes(),
+            (context.nodes() as f64 / elapsed.as_secs_f64()) as u64,
+            context.stats().last().map_or(0, |last| last.depth)
+        );
+    }
+}
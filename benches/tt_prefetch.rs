@@ -0,0 +1,53 @@
+// Compares search speed with and without prefetching each child's hash
+// table entry before making the move, with a table small enough to stay in
+// the cache and one which doesn't.
+//
+//     cargo bench --bench tt_prefetch
+//
+// When added the two were within the noise of each other (about 10%
+// between runs) for both sizes: the leaves, most of the nodes, don't look
+// in the table, so there are few misses to hide.
+
+use std::time::{Duration, Instant};
+
+use chess_rs::cli::bench::POSITIONS;
+use chess_rs::engine::board::Board;
+use chess_rs::engine::eval::Evaluator;
+use chess_rs::engine::movegen::{MoveGenerator, MoveList};
+use chess_rs::engine::search::{self, SearchContext};
+use chess_rs::engine::tt::TranspositionTable;
+
+const DEPTH: usize = 6;
+const TABLE_MEGABYTES: &[usize] = &[1, 256];
+
+// nodes searched and nodes per second over the bench positions
+fn search_all(move_generator: &MoveGenerator, megabytes: usize, prefetch: bool) -> (u64, u64) {
+    let mut context = SearchContext::new();
+    context.resize_tt(TranspositionTable::entries_in_megabytes(megabytes));
+    context.set_tt_prefetch(prefetch);
+    let mut move_lists = (0..DEPTH).map(|_| MoveList::new()).collect();
+
+    // clearing the table isn't timed
+    let (mut nodes, mut elapsed) = (0, Duration::ZERO);
+    for fen in POSITIONS {
+        let mut board = Board::new(fen).unwrap();
+        context.clear_tt();
+        let start = Instant::now();
+        search::find_best_move(DEPTH, &mut board, &mut Evaluator::default(), move_generator, &mut move_lists, &mut context);
+        elapsed += start.elapsed();
+        nodes += context.nodes();
+    }
+    (nodes, (nodes as f64 / elapsed.as_secs_f64()) as u64)
+}
+
+fn main() {
+    let move_generator = MoveGenerator::new();
+
+    println!("{:<8} {:>12} {:>16} {:>16}", "table", "nodes", "prefetch nps", "no prefetch nps");
+    for &megabytes in TABLE_MEGABYTES {
+        let (other_nodes, without) = search_all(&move_generator, megabytes, false);
+        let (nodes, with) = search_all(&move_generator, megabytes, true);
+        assert_eq!(nodes, other_nodes);
+        println!("{:<8} {:>12} {:>16} {:>16}", format!("{}MB", megabytes), nodes, with, without);
+    }
+}
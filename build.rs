@@ -0,0 +1,68 @@
+// Writes the sliding piece tables to $OUT_DIR/slider_tables.rs so that they
+// are part of the binary, rather than being generated at startup. The output
+// only depends on src/engine/magic.rs, and is the same on every build.
+
+use std::fmt::Write;
+
+#[path = "src/engine/magic.rs"]
+mod magic;
+
+fn write_array<T: std::fmt::LowerHex>(out: &mut String, name: &str, ty: &str, values: &[T]) {
+    writeln!(out, "pub static {}: [{}; {}] = [", name, ty, values.len()).unwrap();
+    for chunk in values.chunks(8) {
+        let line: Vec<String> = chunk.iter().map(|value| format!("{:#x}", value)).collect();
+        writeln!(out, "    {},", line.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=src/engine/magic.rs");
+
+    // the tables are generated when the engine starts instead
+    if std::env::var_os("CARGO_FEATURE_RUNTIME_TABLES").is_some() {
+        return;
+    }
+
+    let tables = magic::SliderTables::generate();
+    let mut out = String::from("// generated by build.rs from src/engine/magic.rs\n");
+
+    write_array(&mut out, "ROOK_MASKS", "u64", &tables.rook_masks);
+    write_array(&mut out, "BISHOP_MASKS", "u64", &tables.bishop_masks);
+    write_array(
+        &mut out,
+        "ROOK_MAGIC_SHIFTS",
+        "usize",
+        &tables.rook_magic_shifts,
+    );
+    write_array(
+        &mut out,
+        "BISHOP_MAGIC_SHIFTS",
+        "usize",
+        &tables.bishop_magic_shifts,
+    );
+    write_array(&mut out, "ROOK_OFFSETS", "usize", &tables.rook_offsets);
+    write_array(&mut out, "BISHOP_OFFSETS", "usize", &tables.bishop_offsets);
+    write_array(&mut out, "ROOK_MOVES", "u64", &tables.rook_moves);
+    write_array(&mut out, "BISHOP_MOVES", "u64", &tables.bishop_moves);
+
+    writeln!(out, "pub static SLIDER_RANGE: [[u64; 64]; 64] = [").unwrap();
+    for row in &tables.slider_range {
+        let row: Vec<String> = row.iter().map(|value| format!("{:#x}", value)).collect();
+        writeln!(out, "    [{}],", row.join(", ")).unwrap();
+    }
+    writeln!(out, "];").unwrap();
+
+    write_array(&mut out, "RANKS", "u64", &tables.ranks);
+    write_array(&mut out, "FILES", "u64", &tables.files);
+    write_array(&mut out, "NOT_RANKS", "u64", &tables.not_ranks);
+    write_array(&mut out, "NOT_FILES", "u64", &tables.not_files);
+
+    let path = std::path::Path::new(&std::env::var("OUT_DIR").unwrap()).join("slider_tables.rs");
+
+    // leave the file alone if nothing changed, so it is not recompiled
+    if std::fs::read_to_string(&path).ok().as_deref() != Some(out.as_str()) {
+        std::fs::write(&path, out).unwrap();
+    }
+}
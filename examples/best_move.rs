@@ -0,0 +1,43 @@
+// Searches a position given on the command line (or the starting position)
+// and prints the best move in SAN, using nothing but the prelude:
+//
+//     cargo run --example best_move -- "<fen>" [depth]
+
+use chess_rs::prelude::*;
+
+const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+fn main() -> Result<(), Error> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let fen = args.first().map_or(STARTING_FEN, String::as_str);
+    let depth = args.get(1).and_then(|d| d.parse().ok()).unwrap_or(4);
+
+    let mut board = Board::new(fen)?;
+    let move_generator = MoveGenerator::new();
+
+    let state = board.game_state(&move_generator);
+    if state.is_over() {
+        println!("{}", state);
+        return Ok(());
+    }
+
+    let mut evaluator = Evaluator::default();
+    // the search keeps a move list for each remaining ply
+    let mut move_lists: Vec<MoveList> = (0..=depth).map(|_| MoveList::new()).collect();
+    let mut context = SearchContext::new();
+    let best = find_best_move(
+        depth,
+        &mut board,
+        &mut evaluator,
+        &move_generator,
+        &mut move_lists,
+        &mut context,
+    );
+
+    if let Some((best_move, score)) = best {
+        let san = move_to_san(&mut board, &move_generator, best_move);
+        println!("{} ({}) score {}", san, best_move.move_to_string(), score);
+    }
+
+    Ok(())
+}
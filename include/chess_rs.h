@@ -0,0 +1,89 @@
+/*
+ * The C interface to chess_rs, for running the engine in-process. See
+ * src/engine/ffi.rs for the details. Build the library with
+ *
+ *     cargo rustc --release --lib --features cdylib --crate-type cdylib
+ *
+ * and link against target/release/libchess_rs.so (or the platform's
+ * equivalent).
+ *
+ * The caller owns the handle from engine_new until it is passed to
+ * engine_free. Strings are UTF-8 and NUL terminated, are only read during
+ * the call, and output is written into the caller's buffers: the engine
+ * never returns memory of its own. A handle must only be used from one
+ * thread at a time.
+ *
+ *     ChessEngine *engine = engine_new();
+ *     char best[6];
+ *     engine_set_position(engine, "startpos", "e2e4,e7e5");
+ *     if (engine_go_depth(engine, 5, best, sizeof best) == ENGINE_OK)
+ *         printf("%s\n", best);
+ *     engine_free(engine);
+ */
+
+#ifndef CHESS_RS_H
+#define CHESS_RS_H
+
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* the int functions return these on failure, and 0 (or a count) otherwise */
+#define ENGINE_OK 0
+#define ENGINE_NULL_ARGUMENT -1
+#define ENGINE_INVALID_UTF8 -2
+#define ENGINE_INVALID_FEN -3
+#define ENGINE_ILLEGAL_MOVE -4
+#define ENGINE_BUFFER_TOO_SMALL -5
+#define ENGINE_INVALID_DEPTH -6
+#define ENGINE_NO_LEGAL_MOVES -7
+/* a bug in the engine: the handle should only be freed */
+#define ENGINE_PANIC -8
+/* a square or piece that doesn't parse, or putting a piece on an occupied
+ * square or removing one from an empty square */
+#define ENGINE_INVALID_EDIT -9
+/* searching, listing moves or evaluating a position which isn't valid, such
+ * as one edited part way */
+#define ENGINE_INVALID_POSITION -10
+
+#define ENGINE_MAX_DEPTH 64
+
+typedef struct ChessEngine ChessEngine;
+
+/* a new engine at the starting position, or NULL */
+ChessEngine *engine_new(void);
+
+/* frees the engine; NULL is ignored */
+void engine_free(ChessEngine *engine);
+
+/* sets the position from a fen (NULL or "startpos" for the starting
+ * position) and comma separated moves in UCI notation (NULL or "" for none).
+ * On error the position is unchanged. */
+int engine_set_position(ChessEngine *engine, const char *fen, const char *moves_csv);
+
+/* edits the position: squares are in algebraic notation ("e4") and pieces
+ * are fen letters ('N' for a white knight, 'n' for a black one). Castling
+ * rights and the en passant square are kept, and engine_clear removes them
+ * along with every piece. */
+int engine_put_piece(ChessEngine *engine, const char *square, char piece);
+int engine_remove_piece(ChessEngine *engine, const char *square);
+int engine_clear(ChessEngine *engine);
+
+/* searches to depth (1 to ENGINE_MAX_DEPTH) and writes the best move in UCI
+ * notation into out_move, which needs at most 6 bytes */
+int engine_go_depth(ChessEngine *engine, int depth, char *out_move, size_t len);
+
+/* writes the legal moves, comma separated, into out_buf and returns how
+ * many there are */
+int engine_legal_moves(ChessEngine *engine, char *out_buf, size_t len);
+
+/* writes the static evaluation in centipawns, for the side to move */
+int engine_eval(ChessEngine *engine, int *out_score);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
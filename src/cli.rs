@@ -0,0 +1,14 @@
+// The commands of the chess_rs binary, and the engine benchmarks for cargo
+// bench. The modules behind them are internal to the library, so these
+// re-exports are all of them it offers.
+
+pub use crate::engine::dumptables::dump_tables_command;
+pub use crate::engine::fencheck::fen_check;
+pub use crate::engine::perft::perft_command;
+pub use crate::engine::pgn::annotate_command;
+pub use crate::engine::script::script_command;
+pub use crate::engine::uci::uci;
+
+pub mod bench {
+    pub use crate::engine::bench::{run, BenchResult, BenchSettings, POSITIONS};
+}
@@ -0,0 +1,497 @@
+// Attack tables for every piece, which can be used anywhere without a
+// MoveGenerator. The non sliding pieces' tables only depend on the square, so
+// they are computed at compile time. The sliding pieces are looked up with
+// magic bitboards through an Attacks.
+
+#[cfg(feature = "runtime_tables")]
+use crate::engine::magic::SliderTables;
+use crate::engine::magic::{BISHOP_MAGICS, ROOK_MAGICS};
+use crate::engine::piece::Color;
+
+// The slider tables are static data generated by build.rs, unless the
+// `runtime_tables` feature is enabled, in which case each Attacks builds its
+// own copy.
+#[cfg(not(feature = "runtime_tables"))]
+pub(crate) type Table<T> = &'static T;
+#[cfg(feature = "runtime_tables")]
+pub(crate) type Table<T> = Box<T>;
+
+#[cfg(not(feature = "runtime_tables"))]
+pub(crate) mod tables {
+    include!(concat!(env!("OUT_DIR"), "/slider_tables.rs"));
+}
+
+pub const KNIGHT_ATTACKS: [u64; 64] = gen_table(&[
+    [-1, 2],
+    [-2, 1],
+    [1, 2],
+    [2, 1],
+    [-1, -2],
+    [-2, -1],
+    [1, -2],
+    [2, -1],
+]);
+
+pub const KING_ATTACKS: [u64; 64] = gen_table(&[
+    [-1, -1],
+    [-1, 0],
+    [-1, 1],
+    [0, -1],
+    [0, 1],
+    [1, -1],
+    [1, 0],
+    [1, 1],
+]);
+
+// indexed by Color::idx, the squares a pawn of that color on the square attacks
+pub const PAWN_ATTACKS: [[u64; 64]; 2] = [
+    // black pawns attack towards rank 1
+    gen_table(&[[-1, 1], [1, 1]]),
+    // white pawns attack towards rank 8
+    gen_table(&[[-1, -1], [1, -1]]),
+];
+
+// Looks up the squares attacked by a piece on a square. For the sliding
+// pieces, the occupancy is every piece which blocks them: each direction
+// stops at the first occupied square, which is included whatever its color.
+// The piece's own square in the occupancy is ignored.
+pub struct Attacks {
+    rook_masks: Table<[u64; 64]>,
+    bishop_masks: Table<[u64; 64]>,
+
+    rook_magic_shifts: Table<[usize; 64]>,
+    bishop_magic_shifts: Table<[usize; 64]>,
+
+    // the rook & bishop attack tables are packed, each square's entries
+    // starting at its offset
+    rook_offsets: Table<[usize; 64]>,
+    bishop_offsets: Table<[usize; 64]>,
+
+    rook_moves: Table<[u64]>,
+    bishop_moves: Table<[u64]>,
+
+    slider_range: Table<[[u64; 64]; 64]>,
+}
+
+impl Attacks {
+    #[cfg(not(feature = "runtime_tables"))]
+    pub fn new() -> Attacks {
+        Attacks {
+            rook_masks: &tables::ROOK_MASKS,
+            bishop_masks: &tables::BISHOP_MASKS,
+
+            rook_magic_shifts: &tables::ROOK_MAGIC_SHIFTS,
+            bishop_magic_shifts: &tables::BISHOP_MAGIC_SHIFTS,
+
+            rook_offsets: &tables::ROOK_OFFSETS,
+            bishop_offsets: &tables::BISHOP_OFFSETS,
+
+            rook_moves: &tables::ROOK_MOVES,
+            bishop_moves: &tables::BISHOP_MOVES,
+
+            slider_range: &tables::SLIDER_RANGE,
+        }
+    }
+
+    #[cfg(feature = "runtime_tables")]
+    pub fn new() -> Attacks {
+        Attacks::from_tables(&SliderTables::generate())
+    }
+
+    #[cfg(feature = "runtime_tables")]
+    pub(crate) fn from_tables(tables: &SliderTables) -> Attacks {
+        Attacks {
+            rook_masks: Box::new(tables.rook_masks),
+            bishop_masks: Box::new(tables.bishop_masks),
+
+            rook_magic_shifts: Box::new(tables.rook_magic_shifts),
+            bishop_magic_shifts: Box::new(tables.bishop_magic_shifts),
+
+            rook_offsets: Box::new(tables.rook_offsets),
+            bishop_offsets: Box::new(tables.bishop_offsets),
+
+            rook_moves: tables.rook_moves.clone().into_boxed_slice(),
+            bishop_moves: tables.bishop_moves.clone().into_boxed_slice(),
+
+            slider_range: Box::new(tables.slider_range),
+        }
+    }
+
+    #[inline(always)]
+    pub fn rook_attacks(&self, sq: usize, mut occupancy: u64) -> u64 {
+        occupancy &= self.rook_masks[sq];
+        let idx = u64::wrapping_mul(ROOK_MAGICS[sq], occupancy) >> self.rook_magic_shifts[sq];
+        self.rook_moves[self.rook_offsets[sq] + idx as usize]
+    }
+    #[inline(always)]
+    pub fn bishop_attacks(&self, sq: usize, mut occupancy: u64) -> u64 {
+        occupancy &= self.bishop_masks[sq];
+        let idx = u64::wrapping_mul(BISHOP_MAGICS[sq], occupancy) >> self.bishop_magic_shifts[sq];
+        self.bishop_moves[self.bishop_offsets[sq] + idx as usize]
+    }
+    #[inline(always)]
+    pub fn queen_attacks(&self, sq: usize, occupancy: u64) -> u64 {
+        self.rook_attacks(sq, occupancy) | self.bishop_attacks(sq, occupancy)
+    }
+    #[inline(always)]
+    pub fn knight_attacks(&self, sq: usize) -> u64 {
+        KNIGHT_ATTACKS[sq]
+    }
+    #[inline(always)]
+    pub fn king_attacks(&self, sq: usize) -> u64 {
+        KING_ATTACKS[sq]
+    }
+    // the squares a pawn of the color on the square attacks
+    #[inline(always)]
+    pub fn pawn_attacks(&self, color: Color, sq: usize) -> u64 {
+        PAWN_ATTACKS[color.idx()][sq]
+    }
+
+    // the squares strictly between two squares on a rank, file or diagonal,
+    // or none if they aren't on one
+    #[inline(always)]
+    pub fn between(&self, start: usize, end: usize) -> u64 {
+        self.slider_range[start][end]
+    }
+
+    // Read only views of the tables, so they can be dumped or compared
+    // between builds
+
+    pub fn rook_masks(&self) -> &[u64] {
+        &self.rook_masks[..]
+    }
+    pub fn bishop_masks(&self) -> &[u64] {
+        &self.bishop_masks[..]
+    }
+    pub fn rook_magics(&self) -> &[u64] {
+        &ROOK_MAGICS[..]
+    }
+    pub fn bishop_magics(&self) -> &[u64] {
+        &BISHOP_MAGICS[..]
+    }
+    pub fn rook_magic_shifts(&self) -> &[usize] {
+        &self.rook_magic_shifts[..]
+    }
+    pub fn bishop_magic_shifts(&self) -> &[usize] {
+        &self.bishop_magic_shifts[..]
+    }
+    pub fn rook_offsets(&self) -> &[usize] {
+        &self.rook_offsets[..]
+    }
+    pub fn bishop_offsets(&self) -> &[usize] {
+        &self.bishop_offsets[..]
+    }
+    // the packed attack tables, indexed by offset + magic index. The slicing
+    // is only redundant without runtime_tables, where they aren't boxed.
+    #[allow(clippy::redundant_slicing)]
+    pub fn rook_table(&self) -> &[u64] {
+        &self.rook_moves[..]
+    }
+    #[allow(clippy::redundant_slicing)]
+    pub fn bishop_table(&self) -> &[u64] {
+        &self.bishop_moves[..]
+    }
+    pub fn slider_ranges(&self) -> &[[u64; 64]] {
+        &self.slider_range[..]
+    }
+}
+
+impl Default for Attacks {
+    fn default() -> Attacks {
+        Attacks::new()
+    }
+}
+
+// vectors are [file, rank], rank 0 being the 8th rank
+const fn gen_table(vectors: &[[i16; 2]]) -> [u64; 64] {
+    let mut table = [0; 64];
+
+    let mut sq = 0;
+    while sq < 64 {
+        let rank = (sq / 8) as i16;
+        let file = (sq % 8) as i16;
+
+        let mut i = 0;
+        while i < vectors.len() {
+            let f = file + vectors[i][0];
+            let r = rank + vectors[i][1];
+            if 0 <= r && r < 8 && 0 <= f && f < 8 {
+                table[sq] |= 1 << (r * 8 + f);
+            }
+            i += 1;
+        }
+
+        sq += 1;
+    }
+
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::bitboard::BitBoardUtils;
+    use crate::engine::piece::Color;
+    use crate::engine::square::Square;
+
+    // the tables as they were generated at runtime by MoveGenerator::init
+    fn runtime_table(vectors: &[[i16; 2]]) -> [u64; 64] {
+        let mut table = [0; 64];
+
+        for (i, entry) in table.iter_mut().enumerate() {
+            let sq = Square::from_usize(i);
+            let rank = sq.rank() as i16;
+            let file = sq.file() as i16;
+
+            for &vector in vectors {
+                let f = file + vector[0];
+                let r = rank + vector[1];
+                if Square::valid_rf(r, f) {
+                    entry.set_bit(Square::from_rf(r as usize, f as usize).sq());
+                }
+            }
+        }
+
+        table
+    }
+
+    #[allow(clippy::needless_range_loop)]
+    fn runtime_pawn_attacks() -> [[u64; 64]; 2] {
+        let mut pawn_attacks = [[0; 64]; 2];
+
+        for i in 0..64 {
+            let sq = Square::from_usize(i);
+
+            if sq.rank() != 0 {
+                if sq.file() != 7 {
+                    pawn_attacks[Color::White.idx()][i].set_bit(i - 7);
+                }
+                if sq.file() != 0 {
+                    pawn_attacks[Color::White.idx()][i].set_bit(i - 9);
+                }
+            }
+            if sq.rank() != 7 {
+                if sq.file() != 7 {
+                    pawn_attacks[Color::Black.idx()][i].set_bit(i + 9);
+                }
+                if sq.file() != 0 {
+                    pawn_attacks[Color::Black.idx()][i].set_bit(i + 7);
+                }
+            }
+        }
+
+        pawn_attacks
+    }
+
+    #[test]
+    fn knight_attacks() {
+        let runtime = runtime_table(&[
+            [-1, 2],
+            [-2, 1],
+            [1, 2],
+            [2, 1],
+            [-1, -2],
+            [-2, -1],
+            [1, -2],
+            [2, -1],
+        ]);
+
+        assert_eq!(KNIGHT_ATTACKS, runtime);
+        assert_eq!(KNIGHT_ATTACKS[Square::A8.sq()].count_1s(), 2);
+        assert_eq!(KNIGHT_ATTACKS[Square::E4.sq()].count_1s(), 8);
+    }
+
+    #[test]
+    fn king_attacks() {
+        let runtime = runtime_table(&[
+            [-1, -1],
+            [-1, 0],
+            [-1, 1],
+            [0, -1],
+            [0, 1],
+            [1, -1],
+            [1, 0],
+            [1, 1],
+        ]);
+
+        assert_eq!(KING_ATTACKS, runtime);
+        assert_eq!(KING_ATTACKS[Square::H1.sq()].count_1s(), 3);
+        assert_eq!(KING_ATTACKS[Square::E4.sq()].count_1s(), 8);
+    }
+
+    #[test]
+    fn pawn_attacks() {
+        assert_eq!(PAWN_ATTACKS, runtime_pawn_attacks());
+
+        // e4 attacks d5 and f5 for white, d3 and f3 for black
+        let mut white = 0u64;
+        white.set_bit(Square::D5.sq()).set_bit(Square::F5.sq());
+        let mut black = 0u64;
+        black.set_bit(Square::D3.sq()).set_bit(Square::F3.sq());
+        assert_eq!(PAWN_ATTACKS[Color::White.idx()][Square::E4.sq()], white);
+        assert_eq!(PAWN_ATTACKS[Color::Black.idx()][Square::E4.sq()], black);
+    }
+
+    fn squares(squares: &[Square]) -> u64 {
+        let mut result = 0u64;
+        for sq in squares {
+            result.set_bit(sq.sq());
+        }
+        result
+    }
+
+    #[test]
+    fn slider_attacks() {
+        use crate::engine::magic;
+        use rand::{Rng, SeedableRng};
+
+        let attacks = Attacks::new();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+
+        for sq in 0..64 {
+            // every blocker configuration that the tables distinguish
+            for idx in 0..(1 << attacks.rook_masks[sq].count_1s()) {
+                let occupancy = magic::idx_to_u64(idx, attacks.rook_masks[sq]);
+                assert_eq!(
+                    attacks.rook_attacks(sq, occupancy),
+                    magic::gen_rook_moves(sq, occupancy)
+                );
+            }
+            for idx in 0..(1 << attacks.bishop_masks[sq].count_1s()) {
+                let occupancy = magic::idx_to_u64(idx, attacks.bishop_masks[sq]);
+                assert_eq!(
+                    attacks.bishop_attacks(sq, occupancy),
+                    magic::gen_bishop_moves(sq, occupancy)
+                );
+            }
+
+            // and whole boards, including the squares outside the masks
+            for _ in 0..1000 {
+                let occupancy: u64 = rng.gen::<u64>() & rng.gen::<u64>();
+                assert_eq!(
+                    attacks.rook_attacks(sq, occupancy),
+                    magic::gen_rook_moves(sq, occupancy)
+                );
+                assert_eq!(
+                    attacks.bishop_attacks(sq, occupancy),
+                    magic::gen_bishop_moves(sq, occupancy)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn empty_and_full_boards() {
+        let attacks = Attacks::new();
+
+        for sq in 0..64 {
+            // a whole rank and file, or both diagonals, less the square
+            assert_eq!(attacks.rook_attacks(sq, 0).count_1s(), 14);
+            let queen = attacks.queen_attacks(sq, 0);
+            assert_eq!(
+                queen,
+                attacks.rook_attacks(sq, 0) | attacks.bishop_attacks(sq, 0)
+            );
+            assert!(!queen.is_bit_set(sq));
+
+            // only the neighbouring squares, the same as a king
+            assert_eq!(
+                attacks.queen_attacks(sq, u64::MAX),
+                attacks.king_attacks(sq)
+            );
+            // and the piece's own square makes no difference
+            assert_eq!(
+                attacks.rook_attacks(sq, 1 << sq),
+                attacks.rook_attacks(sq, 0)
+            );
+        }
+
+        // corners and edges
+        assert_eq!(attacks.bishop_attacks(Square::A8.sq(), 0).count_1s(), 7);
+        assert_eq!(attacks.bishop_attacks(Square::D4.sq(), 0).count_1s(), 13);
+        assert_eq!(
+            attacks.rook_attacks(Square::H1.sq(), u64::MAX),
+            squares(&[Square::G1, Square::H2])
+        );
+        assert_eq!(
+            attacks.bishop_attacks(Square::H1.sq(), u64::MAX),
+            squares(&[Square::G2])
+        );
+        assert_eq!(
+            attacks.knight_attacks(Square::H8.sq()),
+            squares(&[Square::F7, Square::G6])
+        );
+        assert_eq!(
+            attacks.king_attacks(Square::A1.sq()),
+            squares(&[Square::A2, Square::B1, Square::B2])
+        );
+
+        // pawns on the edge files, and the last rank they can be on
+        assert_eq!(
+            attacks.pawn_attacks(Color::White, Square::A2.sq()),
+            squares(&[Square::B3])
+        );
+        assert_eq!(
+            attacks.pawn_attacks(Color::Black, Square::H7.sq()),
+            squares(&[Square::G6])
+        );
+        assert_eq!(attacks.pawn_attacks(Color::White, Square::E8.sq()), 0);
+        assert_eq!(attacks.pawn_attacks(Color::Black, Square::E1.sq()), 0);
+    }
+
+    #[test]
+    fn blockers() {
+        let attacks = Attacks::new();
+
+        // the first blocker in each direction is attacked, whatever it is
+        let occupancy = squares(&[Square::A1, Square::A4, Square::C1, Square::B2, Square::H8]);
+        assert_eq!(
+            attacks.rook_attacks(Square::A1.sq(), occupancy),
+            squares(&[Square::A2, Square::A3, Square::A4, Square::B1, Square::C1])
+        );
+        assert_eq!(
+            attacks.bishop_attacks(Square::A1.sq(), occupancy),
+            squares(&[Square::B2])
+        );
+        assert_eq!(
+            attacks
+                .bishop_attacks(Square::H8.sq(), occupancy)
+                .count_1s(),
+            6
+        );
+    }
+
+    #[test]
+    fn between() {
+        let attacks = Attacks::new();
+
+        assert_eq!(
+            attacks.between(Square::A1.sq(), Square::A4.sq()),
+            squares(&[Square::A2, Square::A3])
+        );
+        assert_eq!(
+            attacks.between(Square::H8.sq(), Square::E5.sq()),
+            squares(&[Square::G7, Square::F6])
+        );
+        assert_eq!(attacks.between(Square::E4.sq(), Square::E5.sq()), 0);
+        assert_eq!(attacks.between(Square::E4.sq(), Square::E4.sq()), 0);
+        // a knight's move away isn't on a line
+        assert_eq!(attacks.between(Square::A1.sq(), Square::B3.sq()), 0);
+    }
+
+    // the tables built into the binary are the ones the generator produces
+    #[cfg(not(feature = "runtime_tables"))]
+    #[test]
+    fn static_tables() {
+        let attacks = Attacks::new();
+        let generated = crate::engine::magic::SliderTables::generate();
+
+        assert_eq!(attacks.rook_moves, &generated.rook_moves[..]);
+        assert_eq!(attacks.bishop_moves, &generated.bishop_moves[..]);
+        assert_eq!(attacks.rook_offsets, &generated.rook_offsets);
+        assert_eq!(attacks.bishop_offsets, &generated.bishop_offsets);
+        assert_eq!(attacks.slider_range, &generated.slider_range);
+        assert_eq!(tables::RANKS, generated.ranks);
+        assert_eq!(tables::NOT_FILES, generated.not_files);
+    }
+}
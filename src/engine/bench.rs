@@ -0,0 +1,343 @@
+// Repeatable measurements of the engine's hot paths: move generation,
+// make/undo, static evaluation, writing fens, perft and search. The positions and moves
+// are fixed here, so numbers can be compared across machines and commits.
+//
+//     cargo bench --bench engine
+//
+// The uci `bench` command runs the perft part, so builds can be compared
+// without a rust toolchain. Each benchmark checks its control handle and
+// stops early once it is stopped, reporting what it had done by then.
+
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+use crate::engine::board::{Board, STARTING_FEN};
+use crate::engine::control::ControlHandle;
+use crate::engine::eval::{Evaluate, Evaluator};
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::perft;
+use crate::engine::r#move::{Move, UndoInfo};
+use crate::engine::search::{self, SearchContext};
+
+// openings, middlegames and endgames, with checks, pins, castling and en
+// passant between them
+pub const POSITIONS: &[&str] = &[
+    STARTING_FEN,
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+    "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+    "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+    "8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1",
+    "4k3/8/8/8/8/8/8/4K2R w K - 0 1",
+];
+
+// the moves of a closed Ruy Lopez from the start position
+pub const GAME: &str =
+    "e2e4 e7e5 g1f3 b8c6 f1b5 a7a6 b5a4 g8f6 e1g1 f8e7 f1e1 b7b5 a4b3 d7d6 c2c3 e8g8 \
+                        h2h3 c6b8 d2d4 b8d7 b1d2 c8b7 b3c2 f8e8 d2f1 e7f8 f1g3 g7g6 a2a4 c7c5 \
+                        d4d5 c5c4 c1g5 h7h6 g5e3 d7c5 d1d2 h6h5";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BenchSettings {
+    // how many times the move generation, make/undo and evaluation
+    // benchmarks go over their positions
+    pub iterations: usize,
+    pub perft_depth: usize,
+    pub search_depth: usize,
+}
+
+impl BenchSettings {
+    pub const FULL: BenchSettings = BenchSettings {
+        iterations: 100_000,
+        perft_depth: 5,
+        search_depth: 6,
+    };
+    // enough to check everything runs
+    pub const QUICK: BenchSettings = BenchSettings {
+        iterations: 2,
+        perft_depth: 3,
+        search_depth: 2,
+    };
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchResult {
+    pub name: &'static str,
+    // moves generated, moves made and undone, positions evaluated or nodes
+    pub ops: u64,
+    pub elapsed: Duration,
+    // false if it was stopped before the end
+    pub complete: bool,
+}
+
+impl BenchResult {
+    fn time(name: &'static str, control: &ControlHandle, run: impl FnOnce() -> u64) -> BenchResult {
+        let start = Instant::now();
+        let ops = run();
+        BenchResult {
+            name,
+            ops,
+            elapsed: start.elapsed(),
+            complete: !control.is_stopped(),
+        }
+    }
+
+    pub fn ops_per_second(&self) -> u64 {
+        (self.ops as f64 / self.elapsed.as_secs_f64().max(1e-9)) as u64
+    }
+}
+
+impl Display for BenchResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<12} {:>12} ops {:>8} ms {:>12} ops/s",
+            self.name,
+            self.ops,
+            self.elapsed.as_millis(),
+            self.ops_per_second()
+        )?;
+        if !self.complete {
+            write!(f, " (stopped)")?;
+        }
+        Ok(())
+    }
+}
+
+fn boards() -> Vec<Board> {
+    POSITIONS
+        .iter()
+        .map(|fen| Board::new(fen).unwrap())
+        .collect()
+}
+
+// the moves of GAME, checked against the position
+fn game_moves(move_generator: &MoveGenerator) -> Vec<Move> {
+    let mut board = Board::default();
+    let mut info = UndoInfo::default();
+
+    GAME.split_whitespace()
+        .map(|uci_move| {
+            board
+                .make_uci_move(uci_move, move_generator, &mut info)
+                .unwrap()
+        })
+        .collect()
+}
+
+// counts the moves generated
+pub fn gen_moves(move_generator: &MoveGenerator, iterations: usize, control: &ControlHandle) -> BenchResult {
+    let mut boards = boards();
+    let mut move_list = MoveList::new();
+
+    BenchResult::time("movegen", control, || {
+        let mut moves = 0;
+        for _ in 0..iterations {
+            if control.is_stopped() {
+                break;
+            }
+            for board in boards.iter_mut() {
+                move_generator.gen_moves(board, &mut move_list);
+                moves += move_list.len() as u64;
+            }
+        }
+        moves
+    })
+}
+
+// counts each move made and undone once
+pub fn make_undo(move_generator: &MoveGenerator, iterations: usize, control: &ControlHandle) -> BenchResult {
+    let moves = game_moves(move_generator);
+    let mut board = Board::default();
+    let mut infos: Vec<UndoInfo> = moves.iter().map(|_| UndoInfo::default()).collect();
+
+    BenchResult::time("make/undo", control, || {
+        let mut done = 0;
+        for _ in 0..iterations {
+            if control.is_stopped() {
+                break;
+            }
+            for (&my_move, info) in moves.iter().zip(infos.iter_mut()) {
+                board.make_move(my_move, info);
+            }
+            for (&my_move, info) in moves.iter().zip(infos.iter()).rev() {
+                board.undo_move(my_move, info);
+            }
+            done += moves.len();
+        }
+        done as u64
+    })
+}
+
+// counts full evaluations, from scratch rather than incrementally
+pub fn evaluate(iterations: usize, control: &ControlHandle) -> BenchResult {
+    let boards = boards();
+    let mut evaluator = Evaluator::default();
+
+    BenchResult::time("eval", control, || {
+        let mut total = 0i64;
+        let mut done = 0;
+        for _ in 0..iterations {
+            if control.is_stopped() {
+                break;
+            }
+            for board in &boards {
+                total = total.wrapping_add(evaluator.full(board) as i64);
+            }
+            done += boards.len();
+        }
+        // keep the scores from being optimised away
+        std::hint::black_box(total);
+        done as u64
+    })
+}
+
+// counts the fens written, into one buffer as a tool writing many would
+pub fn write_fens(iterations: usize, control: &ControlHandle) -> BenchResult {
+    let boards = boards();
+    let mut fen = String::new();
+
+    BenchResult::time("fen", control, || {
+        let mut done = 0;
+        for _ in 0..iterations {
+            if control.is_stopped() {
+                break;
+            }
+            for board in &boards {
+                fen.clear();
+                board.write_fen(&mut fen);
+                std::hint::black_box(&fen);
+            }
+            done += boards.len();
+        }
+        done as u64
+    })
+}
+
+// counts the leaf nodes of a perft from the start position. If stopped, only
+// the root moves counted in full are.
+pub fn perft(move_generator: &MoveGenerator, depth: usize, control: &ControlHandle) -> BenchResult {
+    let mut board = Board::default();
+    let mut stack = perft::PerftStack::new(depth);
+    let mut root_moves = MoveList::new();
+
+    BenchResult::time("perft", control, || {
+        if depth <= 1 {
+            return perft::perft_with_stack(depth, &mut board, move_generator, &mut stack);
+        }
+
+        move_generator.gen_moves(&mut board, &mut root_moves);
+        let mut nodes = 0;
+        let mut info = UndoInfo::default();
+
+        for i in 0..root_moves.len() {
+            let root_move = root_moves.at(i);
+
+            board.make_move(root_move, &mut info);
+            let inner_nodes = perft::perft_stoppable(depth - 1, &mut board, move_generator, &mut stack, control);
+            board.undo_move(root_move, &info);
+
+            match inner_nodes {
+                Some(inner_nodes) => nodes += inner_nodes,
+                None => break,
+            }
+        }
+        nodes
+    })
+}
+
+// counts the nodes of a fixed depth search from the start position
+pub fn search(move_generator: &MoveGenerator, depth: usize, control: &ControlHandle) -> BenchResult {
+    let mut board = Board::default();
+    let mut evaluator = Evaluator::default();
+    let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+    let mut context = SearchContext::new();
+    context.set_stop_flag(control.stop_flag());
+
+    BenchResult::time("search", control, || {
+        search::find_best_move(
+            depth,
+            &mut board,
+            &mut evaluator,
+            move_generator,
+            &mut move_lists,
+            &mut context,
+        );
+        context.nodes()
+    })
+}
+
+// once stopped, the benchmarks after the one running aren't started
+pub fn run(settings: BenchSettings, control: &ControlHandle) -> Vec<BenchResult> {
+    let move_generator = MoveGenerator::new();
+    let benches: [&dyn Fn() -> BenchResult; 6] = [
+        &|| gen_moves(&move_generator, settings.iterations, control),
+        &|| make_undo(&move_generator, settings.iterations, control),
+        &|| evaluate(settings.iterations, control),
+        &|| write_fens(settings.iterations, control),
+        &|| perft(&move_generator, settings.perft_depth, control),
+        &|| search(&move_generator, settings.search_depth, control),
+    ];
+
+    let mut results = Vec::new();
+    for bench in benches {
+        if control.is_stopped() {
+            break;
+        }
+        results.push(bench());
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs() {
+        let control = ControlHandle::new();
+        let results = run(BenchSettings::QUICK, &control);
+
+        let names: Vec<&str> = results.iter().map(|result| result.name).collect();
+        assert_eq!(names, ["movegen", "make/undo", "eval", "fen", "perft", "search"]);
+        assert!(results.iter().all(|result| result.ops > 0 && result.complete), "{:?}", results);
+
+        // the same work every time
+        let move_generator = MoveGenerator::new();
+        assert_eq!(results[1].ops, 2 * GAME.split_whitespace().count() as u64);
+        assert_eq!(results[2].ops, 2 * POSITIONS.len() as u64);
+        assert_eq!(results[3].ops, 2 * POSITIONS.len() as u64);
+        assert_eq!(results[4].ops, 8902);
+        assert_eq!(results[5].ops, search(&move_generator, 2, &control).ops);
+        assert_eq!(gen_moves(&move_generator, 1, &control).ops * 2, results[0].ops);
+    }
+
+    #[test]
+    fn stopped() {
+        let control = ControlHandle::new();
+        control.stop();
+
+        // nothing is started once stopped
+        assert!(run(BenchSettings::FULL, &control).is_empty());
+
+        let move_generator = MoveGenerator::new();
+        let result = perft(&move_generator, 7, &control);
+        assert_eq!(result.ops, 0);
+        assert!(!result.complete);
+        assert!(result.to_string().ends_with(" (stopped)"), "{}", result);
+        assert_eq!(gen_moves(&move_generator, 1000, &control).ops, 0);
+
+        // stopped part way through, the root moves counted so far are kept
+        let control = ControlHandle::new();
+        let stop = control.stop_flag();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+        let result = perft(&move_generator, 7, &control);
+        stopper.join().unwrap();
+        assert!(!result.complete);
+        assert!(result.ops < 3_195_901_860);
+    }
+}
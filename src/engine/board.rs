@@ -1,23 +1,221 @@
-use std::fmt::{Display, Formatter, Result};
+use std::fmt::{Display, Formatter, Result, Write};
 
+use crate::engine::attacks::{KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
+use crate::engine::castling::{CastleSide, CastlingRights};
+use crate::engine::eco::{self, EcoCode};
 use crate::engine::piece::{Color, Pieces};
-use crate::engine::r#move::{Move, MoveUtils, UndoInfo};
+use crate::engine::r#move::{Move, MoveDelta, MoveUtils, UndoInfo};
 use crate::engine::square::Square;
+use crate::engine::error::{FenError, MoveError, PositionError};
 use crate::engine::eval::Evaluator;
+use crate::engine::material::{MaterialClass, MaterialKey, DARK_SQUARES, LIGHT_SQUARES};
+use crate::engine::movegen::{MoveGenerator, MoveList};
 
 use super::bitboard::BitBoardUtils;
 
-pub const BLACK_CASTLE_QS: u8 = 0b0001;
-pub const BLACK_CASTLE_KS: u8 = 0b0010;
-pub const WHITE_CASTLE_QS: u8 = 0b0100;
-pub const WHITE_CASTLE_KS: u8 = 0b1000;
-
-pub const WHITE_CASTLE: u8 = WHITE_CASTLE_KS | WHITE_CASTLE_QS;
-pub const BLACK_CASTLE: u8 = BLACK_CASTLE_KS | BLACK_CASTLE_QS;
 
 pub const STARTING_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
+// How many fields a fen needs. The board, side to move, castling rights and
+// en passant square are always required.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FenParsing {
+    // all six fields, as written by to_fen
+    Strict,
+    // the halfmove clock and fullmove number may be left off, as many tools
+    // do, and default to 0 and 1
+    Standard,
+    // as standard, and anything after the counters (or in their place) which
+    // isn't a counter is ignored, so EPD records with opcodes can be read
+    Lenient,
+}
+
+// Whether the game is over, and why. Checkmate and stalemate take priority
+// over the draw rules, which only matter once a move is available.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GameState {
+    Ongoing,
+    Checkmate,
+    Stalemate,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    InsufficientMaterial,
+    // the side to move lost to a variant's rule: a third check against it,
+    // or the other king reaching the centre
+    ThirdCheck,
+    KingOfTheHill,
+}
+
+impl GameState {
+    pub fn is_over(&self) -> bool {
+        *self != GameState::Ongoing
+    }
+}
+
+impl Display for GameState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            GameState::Ongoing => write!(f, "ongoing"),
+            GameState::Checkmate => write!(f, "checkmate"),
+            GameState::Stalemate => write!(f, "stalemate"),
+            GameState::FiftyMoveRule => write!(f, "draw by fifty move rule"),
+            GameState::ThreefoldRepetition => write!(f, "draw by threefold repetition"),
+            GameState::InsufficientMaterial => write!(f, "draw by insufficient material"),
+            GameState::ThirdCheck => write!(f, "third check"),
+            GameState::KingOfTheHill => write!(f, "king of the hill"),
+        }
+    }
+}
+
+// The rules being played. The variants only add ways to win, so moves are
+// generated the same way for all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    Standard,
+    // giving a third check wins
+    ThreeCheck,
+    // getting the king to d4, e4, d5 or e5 wins
+    KingOfTheHill,
+}
+
+impl Variant {
+    // the names the UCI_Variant option uses, as other engines do
+    pub fn from_name(name: &str) -> Option<Variant> {
+        match name {
+            "standard" | "chess" => Some(Variant::Standard),
+            "3check" | "threecheck" => Some(Variant::ThreeCheck),
+            "kingofthehill" => Some(Variant::KingOfTheHill),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Variant::Standard => "standard",
+            Variant::ThreeCheck => "3check",
+            Variant::KingOfTheHill => "kingofthehill",
+        }
+    }
+}
+
+// the squares a king of the hill wins on
+pub const CENTER_SQUARES: u64 = (1 << Square::D5 as usize)
+    | (1 << Square::E5 as usize)
+    | (1 << Square::D4 as usize)
+    | (1 << Square::E4 as usize);
+
+// Keys for the checks each side has given in three-check, indexed by color
+// and then count. No checks hash to nothing, so the other variants' hashes
+// are the same as standard chess.
+const CHECK_KEYS: [[u64; 4]; 2] = [
+    [0, splitmix64(0xc4ec_0001), splitmix64(0xc4ec_0002), splitmix64(0xc4ec_0003)],
+    [0, splitmix64(0xc4ec_0101), splitmix64(0xc4ec_0102), splitmix64(0xc4ec_0103)],
+];
+
+// The hashes of earlier positions are kept in a ring buffer indexed by ply,
+// so games of any length use the same memory. Only positions since the last
+// pawn move or capture can repeat, and the fifty move rule ends the game
+// long before that many moves have been made.
+const HISTORY_LEN: usize = 512;
+
+// The random keys hashed together for a position. Boards share the fixed
+// ZOBRIST_KEYS unless given their own with rand_zobrist_table.
 #[derive(PartialEq, Clone, Copy)]
+struct ZobristKeys {
+    pieces: [[u64; 12]; 64],
+    // only hashed in when black is to move
+    black_to_move: u64,
+    // indexed by the castling rights
+    castling: [u64; CastlingRights::COUNT],
+    // indexed by the file of the en passant square
+    en_passant: [u64; 8],
+}
+
+impl Default for ZobristKeys {
+    fn default() -> ZobristKeys {
+        ZobristKeys {
+            pieces: [[0; 12]; 64],
+            black_to_move: 0,
+            castling: [0; CastlingRights::COUNT],
+            en_passant: [0; 8],
+        }
+    }
+}
+
+// The keys every board uses unless told otherwise, so that hashes are the
+// same across boards and runs and can be shared or stored. They are made at
+// compile time from a fixed seed. They are not Polyglot's Random64 keys, so
+// the hashes can't be looked up in a Polyglot book.
+const ZOBRIST_KEYS: ZobristKeys = ZobristKeys::fixed(0x5eed_c4e5_5b0a_2d15);
+
+impl ZobristKeys {
+    const fn fixed(seed: u64) -> ZobristKeys {
+        let mut keys = ZobristKeys {
+            pieces: [[0; 12]; 64],
+            black_to_move: 0,
+            castling: [0; CastlingRights::COUNT],
+            en_passant: [0; 8],
+        };
+        let mut state = seed;
+
+        let mut sq = 0;
+        while sq < 64 {
+            let mut piece = 0;
+            while piece < 12 {
+                state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                keys.pieces[sq][piece] = splitmix64(state);
+                piece += 1;
+            }
+            sq += 1;
+        }
+
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        keys.black_to_move = splitmix64(state);
+
+        // a key for each right, combined for the 16 sets of rights, so no
+        // rights hash to nothing as with no en passant square
+        let mut rights = [0; 4];
+        let mut right = 0;
+        while right < 4 {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            rights[right] = splitmix64(state);
+            right += 1;
+        }
+        let mut castling = 0;
+        while castling < CastlingRights::COUNT {
+            let mut right = 0;
+            while right < 4 {
+                if castling & (1 << right) != 0 {
+                    keys.castling[castling] ^= rights[right];
+                }
+                right += 1;
+            }
+            castling += 1;
+        }
+
+        let mut file = 0;
+        while file < 8 {
+            state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            keys.en_passant[file] = splitmix64(state);
+            file += 1;
+        }
+
+        keys
+    }
+}
+
+// the output function of the splitmix64 generator, which is enough to turn
+// a counter into well mixed keys
+const fn splitmix64(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Not Copy, as a board is around 11 KB with its hash history: copies are
+// explicit clones, and search uses make/undo (benches/copy_make.rs compares
+// the two).
+#[derive(PartialEq, Clone)]
 pub struct Board {
     current_color: Color,
 
@@ -25,7 +223,7 @@ pub struct Board {
     full_move_count: usize,
     half_move_count: usize,
 
-    castling: u8,
+    castling: CastlingRights,
     pub en_passant: Option<Square>,
 
     pub pieces: [Option<Pieces>; 64],
@@ -33,11 +231,18 @@ pub struct Board {
     piece_bitboards: [u64; 12],
     combined_bitboards: [u64; 2],
 
-    zobrist_table: [[u64; 12]; 64],
+    material: MaterialKey,
+
+    zobrist: ZobristKeys,
 
     zobrist_hash: u64,
 
-    hash_history: [u64; 512],
+    hash_history: [u64; HISTORY_LEN],
+
+    // kept when a position is loaded, like the zobrist table
+    variant: Variant,
+    // indexed by the color giving them, only counted in three-check
+    checks_given: [u8; 2],
 }
 
 impl Board {
@@ -45,134 +250,425 @@ impl Board {
         self.piece_bitboards.fill(0);
         self.combined_bitboards.fill(0);
         self.pieces.fill(None);
+        self.material = MaterialKey::default();
     }
-    pub fn load_fen(&mut self, fen: &str) -> std::result::Result<(), String> {
-        self.half_move_count = 0;
-        self.zero_boards();
+    // Loads the position, leaving the board as it was if the fen is invalid.
+    // The zobrist table is kept, so hashes can be compared with earlier
+    // positions. The counters may be left off.
+    pub fn load_fen(&mut self, fen: &str) -> std::result::Result<(), FenError> {
+        self.load_fen_with(fen, FenParsing::Standard)
+    }
+
+    pub fn load_fen_with(&mut self, fen: &str, parsing: FenParsing) -> std::result::Result<(), FenError> {
+        let mut args: Vec<&str> = fen.split_whitespace().collect();
+        if args.len() < 4 {
+            return Err(FenError::WrongFieldCount(args.len()));
+        }
+
+        // EPD opcodes start with a letter, unlike the counters
+        if parsing == FenParsing::Lenient {
+            let counters = args[4..]
+                .iter()
+                .take(2)
+                .take_while(|arg| arg.parse::<usize>().is_ok())
+                .count();
+            args.truncate(4 + counters);
+        }
 
-        let args: Vec<&str> = fen.split_whitespace().collect();
-        if args.len() != 6 {
-            return Err(String::from("Expected 6 whitespace delimited arguments"));
+        match (parsing, args.len()) {
+            (FenParsing::Strict, 4 | 5) => return Err(FenError::MissingCounters),
+            (_, 4..=6) => {}
+            (_, count) => return Err(FenError::WrongFieldCount(count)),
         }
 
+        let mut position = BoardBuilder::new();
+
         // parse board
-        let mut pos = 0;
         let mut square: usize = 0;
-        let fen_board_arg = args[0];
-        while square < 64 && pos < fen_board_arg.len() {
-            let mut piece = None;
-
-            match fen_board_arg.chars().nth(pos).unwrap() {
-                'p' => piece = Some(Pieces::BlackPawn),
-                'n' => piece = Some(Pieces::BlackKnight),
-                'b' => piece = Some(Pieces::BlackBishop),
-                'r' => piece = Some(Pieces::BlackRook),
-                'q' => piece = Some(Pieces::BlackQueen),
-                'k' => piece = Some(Pieces::BlackKing),
-
-                'P' => piece = Some(Pieces::WhitePawn),
-                'N' => piece = Some(Pieces::WhiteKnight),
-                'B' => piece = Some(Pieces::WhiteBishop),
-                'R' => piece = Some(Pieces::WhiteRook),
-                'Q' => piece = Some(Pieces::WhiteQueen),
-                'K' => piece = Some(Pieces::WhiteKing),
-
-                '1' | '2' | '3' | '4' | '5' | '6' | '7' | '8' => {
-                    square += fen_board_arg
-                        .chars()
-                        .nth(pos)
-                        .unwrap()
-                        .to_string()
-                        .parse::<usize>()
-                        .unwrap()
+        for c in args[0].chars() {
+            let piece = match c {
+                '1'..='8' => {
+                    square += c.to_digit(10).unwrap() as usize;
+                    None
                 }
 
-                '/' | ' ' => {}
-                _ => return Err(String::from("Unrecognised character in FEN")),
-            }
+                '/' => None,
+                _ => Some(Pieces::from_notation(c).ok_or(FenError::InvalidPieceChar(c))?),
+            };
 
-            if let Some(my_piece) = piece {
-                self.get_bb_mut(my_piece).set_bit(square);
-                self.pieces[square] = piece;
+            if piece.is_some() {
+                if square >= 64 {
+                    return Err(FenError::TooManySquares);
+                }
+                position.pieces[square] = piece;
                 square += 1;
             }
 
-            pos += 1;
+            if square > 64 {
+                return Err(FenError::TooManySquares);
+            }
         }
 
         if square < 64 {
-            return Err(String::from("Expected 64 squares in FEN"));
+            return Err(FenError::TooFewSquares);
         }
 
         // parse current player
-        let player_arg = args[1];
-        self.current_color = match player_arg.chars().next() {
-            Some(c) => match c {
-                'w' => Color::White,
-                'b' => Color::Black,
-                _ => return Err(String::from("Expected w/b for current player")),
-            },
-            None => return Err(String::from("Expected w/b for current player")),
+        position.side_to_move = match args[1] {
+            "w" => Color::White,
+            "b" => Color::Black,
+            _ => return Err(FenError::BadSideToMove),
         };
 
         // parse castling rights
-        self.castling = 0;
-        for c in args[2].chars() {
-            match c {
-                'q' => self.castling |= BLACK_CASTLE_QS,
-                'k' => self.castling |= BLACK_CASTLE_KS,
-                'Q' => self.castling |= WHITE_CASTLE_QS,
-                'K' => self.castling |= WHITE_CASTLE_KS,
-                '-' => break,
-                _ => return Err(String::from("Invalid character in castling rights")),
-            }
+        position.castling = CastlingRights::from_fen(args[2])?;
+        if !position.is_valid_castling() {
+            return Err(FenError::BadCastlingRights);
         }
 
         // parse en passant
         let en_passant_arg = args[3];
-        if en_passant_arg == "-" {
-            self.en_passant = None;
-        } else {
-            self.en_passant = Square::from_notation(en_passant_arg);
+        if en_passant_arg != "-" {
+            position.en_passant = match Square::from_notation(en_passant_arg) {
+                Some(sq) if en_passant_arg.len() == 2 => Some(sq),
+                _ => return Err(FenError::BadEnPassantSquare),
+            };
+            if !position.is_valid_en_passant() {
+                return Err(FenError::BadEnPassantSquare);
+            }
         }
 
         // parse fifty_move
-        self.fifty_move = args[4].parse().unwrap();
+        position.halfmove = match args.get(4) {
+            Some(arg) => arg.parse().map_err(|_| FenError::BadCounter)?,
+            None => 0,
+        };
 
         // parse fullmove count
-        self.full_move_count = args[5].parse().unwrap();
-
-        *self.get_combined_bb_mut(Color::White) = self.get_bb(Pieces::WhitePawn)
-            | self.get_bb(Pieces::WhiteKnight)
-            | self.get_bb(Pieces::WhiteBishop)
-            | self.get_bb(Pieces::WhiteRook)
-            | self.get_bb(Pieces::WhiteQueen)
-            | self.get_bb(Pieces::WhiteKing);
-
-        *self.get_combined_bb_mut(Color::Black) = self.get_bb(Pieces::BlackPawn)
-            | self.get_bb(Pieces::BlackKnight)
-            | self.get_bb(Pieces::BlackBishop)
-            | self.get_bb(Pieces::BlackRook)
-            | self.get_bb(Pieces::BlackQueen)
-            | self.get_bb(Pieces::BlackKing);
+        position.fullmove = match args.get(5) {
+            Some(arg) => arg.parse().map_err(|_| FenError::BadCounter)?,
+            None => 1,
+        };
+
+        self.set_position(&position);
+        Ok(())
+    }
+
+    // sets up the position the builder describes, which must be valid
+    fn set_position(&mut self, position: &BoardBuilder) {
+        self.forget_history();
+        self.zero_boards();
+
+        for (sq, &piece) in position.pieces.iter().enumerate() {
+            if let Some(piece) = piece {
+                self.add_piece(sq, piece);
+            }
+        }
+
+        self.current_color = position.side_to_move;
+        self.castling = position.castling;
+        self.en_passant = position.en_passant;
+        self.fifty_move = position.halfmove;
+        self.full_move_count = position.fullmove;
+        self.checks_given = [0; 2];
+
+        self.zobrist_hash = self.compute_hash();
+    }
+
+    // a board from a position set up by hand, checked in the same way as a
+    // fen
+    pub fn from_position(position: BoardBuilder) -> std::result::Result<Board, FenError> {
+        if !position.is_valid_castling() {
+            return Err(FenError::BadCastlingRights);
+        }
+        if !position.is_valid_en_passant() {
+            return Err(FenError::BadEnPassantSquare);
+        }
+
+        let mut board = Board::blank();
+        match position.zobrist {
+            Some(keys) => board.zobrist = keys,
+            None => board.zobrist = ZOBRIST_KEYS,
+        }
+        board.set_position(&position);
+        Ok(board)
+    }
+
+    // A builder holding the position, to make changes to it. A board built
+    // from it shares this board's zobrist table, so their hashes can be
+    // compared.
+    pub fn into_builder(&self) -> BoardBuilder {
+        BoardBuilder {
+            pieces: self.pieces,
+            side_to_move: self.current_color,
+            castling: self.castling,
+            en_passant: self.en_passant,
+            halfmove: self.fifty_move,
+            fullmove: self.full_move_count,
+            zobrist: Some(self.zobrist),
+        }
+    }
 
+    // Editing the position a piece at a time, as when setting one up in a
+    // GUI. Every change keeps the bitboards, material and hash in step with
+    // the piece array, and the game's history is forgotten, since the
+    // positions before weren't reached by moves. Castling rights and the en
+    // passant square are left alone, so the position may be invalid part
+    // way through: validate it before searching it.
+    pub fn put_piece(&mut self, sq: Square, piece: Pieces) -> std::result::Result<(), PositionError> {
+        if self.pieces[sq.sq()].is_some() {
+            return Err(PositionError::SquareOccupied(sq.notation()));
+        }
+        self.add_piece(sq.sq(), piece);
+        self.forget_history();
         Ok(())
     }
+    // takes the piece off the square, returning it
+    pub fn remove_piece(&mut self, sq: Square) -> std::result::Result<Pieces, PositionError> {
+        let piece = self.pieces[sq.sq()].ok_or_else(|| PositionError::SquareEmpty(sq.notation()))?;
+        self.get_bb_mut(piece).clear_bit(sq.sq());
+        self.get_combined_bb_mut(piece.color()).clear_bit(sq.sq());
+        self.pieces[sq.sq()] = None;
+        self.material.remove(piece);
+        self.zobrist_hash ^= self.zobrist.pieces[sq.sq()][piece.idx()];
+        self.forget_history();
+        Ok(piece)
+    }
+    // an empty board with no castling rights or en passant square, and the
+    // counters of a new game. The side to move is kept.
+    pub fn clear(&mut self) {
+        self.zero_boards();
+        self.forget_history();
+        self.castling = CastlingRights::NONE;
+        self.en_passant = None;
+        self.fifty_move = 0;
+        self.full_move_count = 1;
+        self.checks_given = [0; 2];
+        self.zobrist_hash = self.compute_hash();
+    }
+
+    // The first problem which would stop the position being searched, if
+    // there is one. Positions from a fen or builder can only fail on the
+    // pieces; edited ones can fail on anything.
+    pub fn validate(&self) -> std::result::Result<(), PositionError> {
+        match self.position_problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+    // every problem validate could give
+    pub fn position_problems(&self) -> Vec<PositionError> {
+        let mut problems = Vec::new();
+
+        for color in [Color::White, Color::Black] {
+            let kings = self.get_bb(Pieces::king(color)).count_1s();
+            if kings != 1 {
+                problems.push(PositionError::KingCount(color, kings));
+            }
+        }
+
+        let back_ranks = self.pieces[..8].iter().chain(&self.pieces[56..]);
+        if back_ranks.flatten().any(|piece| piece.is_pawn()) {
+            problems.push(PositionError::PawnOnBackRank);
+        }
+        if !castling_fits(&self.pieces, self.castling) {
+            problems.push(PositionError::BadCastlingRights);
+        }
+        if !en_passant_fits(&self.pieces, self.current_color, self.en_passant) {
+            problems.push(PositionError::BadEnPassantSquare);
+        }
+
+        // the side which just moved cannot have left its king in check,
+        // which only means something with a king each
+        if problems.is_empty() && self.is_king_attacked(self.enemy_color()) {
+            problems.push(PositionError::NotToMoveInCheck(self.enemy_color()));
+        }
+
+        problems
+    }
+
+    fn add_piece(&mut self, sq: usize, piece: Pieces) {
+        self.get_bb_mut(piece).set_bit(sq);
+        self.get_combined_bb_mut(piece.color()).set_bit(sq);
+        self.pieces[sq] = Some(piece);
+        self.material.add(piece);
+        self.zobrist_hash ^= self.zobrist.pieces[sq][piece.idx()];
+    }
+    fn forget_history(&mut self) {
+        self.half_move_count = 0;
+        self.hash_history.fill(0);
+    }
+
+    // the hash of the position from scratch, rather than incrementally
+    fn compute_hash(&self) -> u64 {
+        let mut hash = 0;
+        for sq in 0..64 {
+            if let Some(piece) = self.pieces[sq] {
+                hash ^= self.zobrist.pieces[sq][piece.idx()];
+            }
+        }
+        hash ^ self.state_hash(self.current_color, self.castling, self.en_passant) ^ self.checks_hash()
+    }
+
+    fn checks_hash(&self) -> u64 {
+        if self.variant == Variant::ThreeCheck {
+            CHECK_KEYS[0][self.checks_given[0] as usize] ^ CHECK_KEYS[1][self.checks_given[1] as usize]
+        } else {
+            0
+        }
+    }
+
+    // the part of the hash from the side to move, castling rights and en
+    // passant square
+    fn state_hash(&self, color: Color, castling: CastlingRights, en_passant: Option<Square>) -> u64 {
+        let mut hash = self.zobrist.castling[castling.bits() as usize];
+        if !color.is_white() {
+            hash ^= self.zobrist.black_to_move;
+        }
+        if let Some(sq) = en_passant {
+            hash ^= self.zobrist.en_passant[sq.sq() % 8];
+        }
+        hash
+    }
+
+    // How the hash changes when the side to move flips and the castling
+    // rights and en passant square go from the old values to the new ones.
+    // Xoring it in again takes the change back out, so make_move and
+    // undo_move share it.
+    fn state_hash_change(
+        &self,
+        old: (CastlingRights, Option<Square>),
+        new: (CastlingRights, Option<Square>),
+    ) -> u64 {
+        let (old_castling, old_en_passant) = old;
+        let (new_castling, new_en_passant) = new;
+
+        let mut change = self.zobrist.black_to_move;
+        if old_castling != new_castling {
+            change ^= self.zobrist.castling[old_castling.bits() as usize]
+                ^ self.zobrist.castling[new_castling.bits() as usize];
+        }
+        if old_en_passant != new_en_passant {
+            change ^= self.state_hash(Color::White, CastlingRights::NONE, old_en_passant)
+                ^ self.state_hash(Color::White, CastlingRights::NONE, new_en_passant);
+        }
+        change
+    }
 
     pub fn hash(&self) -> u64 {
         self.zobrist_hash
     }
 
+    // A key for the position, for deduplicating positions outside the
+    // engine (eg. in a dataset or a book): the zobrist hash, which leaves
+    // out the move counters. Boards with the default keys agree on it
+    // across runs, so it can be stored.
+    pub fn position_key(&self) -> u64 {
+        self.zobrist_hash
+    }
+
+    // The hash without the en passant square, which is set after every
+    // double pawn push, so that transpositions ending with different moves
+    // match. For recognising positions rather than searching them.
+    pub fn opening_key(&self) -> u64 {
+        self.zobrist_hash ^ self.en_passant.map_or(0, |sq| self.zobrist.en_passant[sq.sq() % 8])
+    }
+
+    // the opening of a game, from the opening keys of its positions in
+    // order, see eco.rs
+    pub fn identify_opening(history: &[u64]) -> Option<(EcoCode, &'static str)> {
+        eco::identify(history)
+    }
+
+    // Everything that is updated incrementally, recomputed and compared with
+    // what is stored: the bitboards against the piece array, the material
+    // and the hash. Returns what disagrees, so empty if nothing does.
+    pub fn check_consistency(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut piece_bitboards = [0u64; 12];
+        let mut material = MaterialKey::default();
+
+        for sq in 0..64 {
+            if let Some(piece) = self.pieces[sq] {
+                piece_bitboards[piece.idx()] |= 1 << sq;
+                material.add(piece);
+            }
+        }
+
+        for (idx, (&expected, &actual)) in piece_bitboards.iter().zip(&self.piece_bitboards).enumerate() {
+            if expected != actual {
+                problems.push(format!(
+                    "{:?} bitboard is {:#018x}, the piece array gives {:#018x}",
+                    Pieces::from_idx(idx),
+                    actual,
+                    expected
+                ));
+            }
+        }
+        for color in [Color::White, Color::Black] {
+            let expected = (0..12)
+                .filter(|&idx| Pieces::from_idx(idx).color() == color)
+                .fold(0, |all, idx| all | piece_bitboards[idx]);
+            let actual = self.get_combined_bb(color);
+            if expected != actual {
+                problems.push(format!(
+                    "{} bitboard is {:#018x}, the piece array gives {:#018x}",
+                    color, actual, expected
+                ));
+            }
+        }
+        if material != self.material {
+            problems.push("material key doesn't match the pieces".to_string());
+        }
+
+        let hash = self.compute_hash();
+        if hash != self.zobrist_hash {
+            problems.push(format!("hash is {:#018x}, recomputed {:#018x}", self.zobrist_hash, hash));
+        }
+
+        problems
+    }
+
+    // A checksum of every zobrist key the board hashes with, which differs
+    // between boards whose hashes of a position could differ. Tables saved
+    // with one scheme are useless with another.
+    pub fn zobrist_checksum(&self) -> u64 {
+        let keys = &self.zobrist;
+        keys.pieces
+            .iter()
+            .flatten()
+            .chain(std::iter::once(&keys.black_to_move))
+            .chain(keys.castling.iter())
+            .chain(keys.en_passant.iter())
+            .chain(CHECK_KEYS.iter().flatten())
+            .fold(0, |checksum, &key| splitmix64(checksum ^ key))
+    }
+
+    // Replaces the fixed keys with random ones, for experiments which
+    // shouldn't depend on the keys chosen. Hashes from before are no longer
+    // comparable, and earlier positions won't be seen as repetitions.
     pub fn rand_zobrist_table(&mut self, rng: &mut impl rand::Rng) {
         for sq in 0..64 {
             for piece in 0..12 {
-                self.zobrist_table[sq][piece] = rng.gen();
+                self.zobrist.pieces[sq][piece] = rng.gen();
             }
         }
+        self.zobrist.black_to_move = rng.gen();
+        // no castling rights hash to nothing, as with no en passant square
+        self.zobrist.castling[0] = 0;
+        for key in self.zobrist.castling.iter_mut().skip(1) {
+            *key = rng.gen();
+        }
+        for key in self.zobrist.en_passant.iter_mut() {
+            *key = rng.gen();
+        }
+        self.zobrist_hash = self.compute_hash();
     }
 
     pub fn make_move(&mut self, my_move: Move, info: &mut UndoInfo) {
-        self.hash_history[self.half_move_count] = self.hash();
+        debug_assert!(my_move.validate_shape(), "making a move no position has: {:#06x}", my_move);
+
+        self.hash_history[self.half_move_count % HISTORY_LEN] = self.hash();
         self.half_move_count += 1;
 
         // load data from move
@@ -190,6 +686,9 @@ impl Board {
         info.en_passant = self.en_passant;
         info.captured = self.pieces[end];
         info.zobrist = self.zobrist_hash;
+        info.material = self.material;
+        info.delta = self.move_delta(my_move);
+        info.checks_given = self.checks_given;
 
         // store start and end pieces
         let start_piece = self.pieces[start];
@@ -205,9 +704,6 @@ impl Board {
         debug_assert!(start_piece.color() == self.friendly_color());
         debug_assert!(end_piece.is_none() || start_piece.color() != end_piece.unwrap().color());
 
-        // remove start piece from start square
-        self.zobrist_hash ^= self.zobrist_table[start][start_piece.idx()];
-
         // update fifty_move
         if self.pieces[start].unwrap().is_pawn() || self.pieces[end].is_some() {
             self.fifty_move = 0;
@@ -223,11 +719,6 @@ impl Board {
                 let friendly_pawn = Pieces::pawn(friendly_color);
                 let enemy_pawn = Pieces::pawn(enemy_color);
                 let en_passant_sq = self.en_passant.unwrap().sq();
-                
-                // add start piece to en_passant square
-                self.zobrist_hash ^= self.zobrist_table[en_passant_sq][friendly_pawn.idx()];
-                // remove end piece from end square
-                self.zobrist_hash ^= self.zobrist_table[end][enemy_pawn.idx()];
 
                 // friendly piece bb
                 self.get_bb_mut(friendly_pawn)
@@ -236,6 +727,7 @@ impl Board {
 
                 // enemy piece bb
                 self.get_bb_mut(enemy_pawn).clear_bit(end);
+                self.material.remove(enemy_pawn);
 
                 // friendly combined bb
                 self.get_combined_bb_mut(friendly_color)
@@ -259,9 +751,6 @@ impl Board {
                 let friendly_king = Pieces::king(friendly_color);
                 let friendly_rook = Pieces::rook(friendly_color);
 
-                // add king to end square
-                self.zobrist_hash ^= self.zobrist_table[end][friendly_king.idx()];
-                
                 debug_assert_eq!(start_piece, friendly_king);
 
                 // friendly king bb
@@ -281,11 +770,6 @@ impl Board {
                         debug_assert!(self.pieces[offset].is_some());
                         debug_assert_eq!(self.pieces[offset].unwrap(), friendly_rook);
 
-                        // remove rook from start square
-                        self.zobrist_hash ^= self.zobrist_table[offset][friendly_rook.idx()];
-                        // add rook to end square
-                        self.zobrist_hash ^= self.zobrist_table[offset + 3][friendly_rook.idx()];
-
                         // friendly rook bb
                         self.get_bb_mut(friendly_rook)
                             .clear_bit(offset)
@@ -309,11 +793,6 @@ impl Board {
                         debug_assert!(self.pieces[offset + 7].is_some());
                         debug_assert_eq!(self.pieces[offset + 7].unwrap(), friendly_rook);
 
-                        // remove rook from start square
-                        self.zobrist_hash ^= self.zobrist_table[offset + 7][friendly_rook.idx()];
-                        // add rook to end square
-                        self.zobrist_hash ^= self.zobrist_table[offset + 5][friendly_rook.idx()];
-
                         // friendly rook bb
                         self.get_bb_mut(friendly_rook)
                             .clear_bit(offset + 7)
@@ -347,22 +826,13 @@ impl Board {
 
                 debug_assert_eq!(start_piece, friendly_pawn);
 
-                // decode the promotion piece
-                let promotion_piece = match piece {
-                    super::r#move::MOVE_PROMOTION_PIECE_KNIGHT => Pieces::knight(friendly_color),
-                    super::r#move::MOVE_PROMOTION_PIECE_BISHOP => Pieces::bishop(friendly_color),
-                    super::r#move::MOVE_PROMOTION_PIECE_ROOK => Pieces::rook(friendly_color),
-                    super::r#move::MOVE_PROMOTION_PIECE_QUEEN => Pieces::queen(friendly_color),
-                    _ => panic!("Couldn't match the promotion piece"),
-                };
+                let promotion_piece = Board::promotion_piece(piece, friendly_color);
 
                 // clear the end piece if this is a capture
                 if let Some(end_piece) = end_piece {
-                    // remove enemy piece from end square
-                    self.zobrist_hash ^= self.zobrist_table[end][end_piece.idx()];
-
                     // enemy piece bb
                     self.get_bb_mut(end_piece).clear_bit(end);
+                    self.material.remove(end_piece);
 
                     // enemy combined bb
                     self.get_combined_bb_mut(enemy_color).clear_bit(end);
@@ -378,13 +848,12 @@ impl Board {
                     // evaluator diff
                     info.evalutor_diff = Evaluator::promotion_diff(start, end, promotion_piece, None, friendly_color);
                 }
-                
-                // add promotion piece to end square
-                self.zobrist_hash ^= self.zobrist_table[end][promotion_piece.idx()];
 
                 // friendly piece bb for pawn and promotion piece
                 self.get_bb_mut(friendly_pawn).clear_bit(start);
                 self.get_bb_mut(promotion_piece).set_bit(end);
+                self.material.remove(friendly_pawn);
+                self.material.add(promotion_piece);
 
                 // friendly combined bb
                 self.get_combined_bb_mut(friendly_color)
@@ -404,11 +873,9 @@ impl Board {
                 };
 
                 if let Some(end_piece) = end_piece {
-                    // remove enemy piece from end square
-                    self.zobrist_hash ^= self.zobrist_table[end][end_piece.idx()];
-
                     // enemy piece bb
                     self.get_bb_mut(end_piece).clear_bit(end);
+                    self.material.remove(end_piece);
 
                     // enemy combined bb
                     self.get_combined_bb_mut(enemy_color).clear_bit(end);
@@ -435,9 +902,6 @@ impl Board {
                     self.disable_castle_from_sq(start);
                 }
 
-                // add friendly piece to end square
-                self.zobrist_hash ^= self.zobrist_table[end][start_piece.idx()];
-
                 // friendly piece bb
                 self.get_bb_mut(start_piece).clear_bit(start).set_bit(end);
 
@@ -453,9 +917,186 @@ impl Board {
         }
 
         self.current_color = self.current_color.enemy();
+
+        // update the hash from the pieces that changed, then from the side
+        // to move, castling rights and en passant square
+        self.zobrist_hash = self.apply_delta(self.zobrist_hash, &info.delta)
+            ^ self.state_hash_change(
+                (info.castling, info.en_passant),
+                (self.castling, self.en_passant),
+            );
+
+        if self.variant == Variant::ThreeCheck && self.is_king_attacked(self.current_color) {
+            let checks = &mut self.checks_given[friendly_color.idx()];
+            if *checks < 3 {
+                self.zobrist_hash ^= CHECK_KEYS[friendly_color.idx()][*checks as usize];
+                *checks += 1;
+                self.zobrist_hash ^= CHECK_KEYS[friendly_color.idx()][*checks as usize];
+            }
+        }
+    }
+
+    // Passes the move to the other side, for null move pruning. Nothing
+    // moves, but the en passant square is gone, and the fifty move counter
+    // starts again so that no position before the pass counts as a
+    // repetition of one after it.
+    pub fn make_null_move(&mut self, info: &mut UndoInfo) {
+        self.hash_history[self.half_move_count % HISTORY_LEN] = self.hash();
+        self.half_move_count += 1;
+
+        info.fifty_move = self.fifty_move;
+        info.en_passant = self.en_passant;
+        info.zobrist = self.zobrist_hash;
+
+        self.zobrist_hash ^= self.state_hash_change((self.castling, self.en_passant), (self.castling, None));
+        self.fifty_move = 0;
+        self.en_passant = None;
+        self.current_color = self.current_color.enemy();
+    }
+    pub fn undo_null_move(&mut self, info: &UndoInfo) {
+        self.half_move_count -= 1;
+        self.hash_history[self.half_move_count % HISTORY_LEN] = 0;
+
+        self.current_color = self.current_color.enemy();
+        self.fifty_move = info.fifty_move;
+        self.en_passant = info.en_passant;
+        self.zobrist_hash = info.zobrist;
+    }
+
+    fn promotion_piece(piece: u16, color: Color) -> Pieces {
+        match piece {
+            super::r#move::MOVE_PROMOTION_PIECE_KNIGHT => Pieces::knight(color),
+            super::r#move::MOVE_PROMOTION_PIECE_BISHOP => Pieces::bishop(color),
+            super::r#move::MOVE_PROMOTION_PIECE_ROOK => Pieces::rook(color),
+            super::r#move::MOVE_PROMOTION_PIECE_QUEEN => Pieces::queen(color),
+            _ => unreachable!(),
+        }
+    }
+
+    // the pieces a (legal) move would take off and put on the board, worked
+    // out without making it
+    pub fn move_delta(&self, my_move: Move) -> MoveDelta {
+        let start = my_move.get_move_start() as usize;
+        let end = my_move.get_move_end() as usize;
+        let piece = my_move.get_move_piece();
+
+        let friendly_color = self.friendly_color();
+        let start_piece = self.pieces[start].expect("no piece on the start square");
+
+        let mut delta = MoveDelta::default();
+        delta.remove(start_piece, start);
+
+        match my_move.get_move_type() {
+            super::r#move::MOVE_TYPE_EN_PASSANT => {
+                // the pawn lands on the en passant square and the captured
+                // pawn is on the end square
+                let en_passant_sq = self.en_passant.expect("en passant without a square").sq();
+                delta.add(start_piece, en_passant_sq);
+                delta.remove(Pieces::pawn(self.enemy_color()), end);
+            }
+            super::r#move::MOVE_TYPE_CASTLE => {
+                let friendly_rook = Pieces::rook(friendly_color);
+                let offset = start & 0b111000;
+                let (rook_start, rook_end) = match piece {
+                    super::r#move::MOVE_CASTLE_SIDE_QS => (offset, offset + 3),
+                    _ => (offset + 7, offset + 5),
+                };
+
+                delta.add(start_piece, end);
+                delta.remove(friendly_rook, rook_start);
+                delta.add(friendly_rook, rook_end);
+            }
+            move_type => {
+                if let Some(end_piece) = self.pieces[end] {
+                    delta.remove(end_piece, end);
+                }
+
+                if move_type == super::r#move::MOVE_TYPE_PROMOTION {
+                    delta.add(Board::promotion_piece(piece, friendly_color), end);
+                } else {
+                    delta.add(start_piece, end);
+                }
+            }
+        }
+
+        delta
+    }
+
+    fn apply_delta(&self, mut hash: u64, delta: &MoveDelta) -> u64 {
+        for &(piece, sq) in delta.removed().iter().chain(delta.added()) {
+            hash ^= self.zobrist.pieces[sq][piece.idx()];
+        }
+        hash
+    }
+
+    // the castling rights left after a move: moving or capturing on a king
+    // or rook square loses the rights that need that piece
+    fn castling_after(&self, delta: &MoveDelta) -> CastlingRights {
+        let mut castling = self.castling;
+        for &(_, sq) in delta.removed() {
+            match Square::from_usize(sq) {
+                Square::E1 => castling.remove_color(Color::White),
+                Square::E8 => castling.remove_color(Color::Black),
+                sq => {
+                    if let Some((color, side)) = Board::castle_rook_side(sq) {
+                        castling.remove(color, side);
+                    }
+                }
+            }
+        }
+        castling
+    }
+
+    // the en passant square after a move: set only by a double pawn push
+    fn en_passant_after(&self, my_move: Move) -> Option<Square> {
+        let start = my_move.get_move_start() as usize;
+        let end = my_move.get_move_end() as usize;
+
+        match self.pieces[start] {
+            Some(piece) if piece.is_pawn() && Board::distance(end, start) == 16 => {
+                Some(Square::from_usize((end + start) / 2))
+            }
+            _ => None,
+        }
+    }
+
+    // the hash of the position after a move, without making it (eg. to
+    // prefetch the child's hash table entry)
+    pub fn hash_after(&self, my_move: Move) -> u64 {
+        let delta = self.move_delta(my_move);
+        self.apply_delta(self.zobrist_hash, &delta)
+            ^ self.state_hash_change(
+                (self.castling, self.en_passant),
+                (self.castling_after(&delta), self.en_passant_after(my_move)),
+            )
     }
+    // True if a pseudo-legal move is illegal, found by making it and seeing
+    // whether the king is attacked. Castling out of or through check can't
+    // be seen that way, so those squares are checked first.
+    pub fn leaves_king_in_check(&mut self, my_move: Move, move_generator: &MoveGenerator) -> bool {
+        let color = self.friendly_color();
+
+        if my_move.get_move_type() == super::r#move::MOVE_TYPE_CASTLE {
+            let start = my_move.get_move_start() as usize;
+            let passed = (start + my_move.get_move_end() as usize) / 2;
+            if move_generator.is_sq_attacked(self, start, color) || move_generator.is_sq_attacked(self, passed, color) {
+                return true;
+            }
+        }
+
+        let mut info = UndoInfo::default();
+        self.make_move(my_move, &mut info);
+        let in_check = self
+            .try_king_square(color)
+            .is_some_and(|king| move_generator.is_sq_attacked(self, king.sq(), color));
+        self.undo_move(my_move, &info);
+
+        in_check
+    }
+
     pub fn undo_move(&mut self, my_move: Move, info: &UndoInfo) {
         self.half_move_count -= 1;
+        self.hash_history[self.half_move_count % HISTORY_LEN] = 0;
 
         // load data from move
         let start = my_move.get_move_start() as usize;
@@ -466,12 +1107,24 @@ impl Board {
         let friendly_color = self.enemy_color();
         let enemy_color = self.friendly_color();
 
+        // take the check counts, side to move, castling rights and en
+        // passant square out of the hash, then the pieces that changed
+        self.zobrist_hash ^= self.checks_hash();
+        self.checks_given = info.checks_given;
+        self.zobrist_hash ^= self.checks_hash();
+        self.zobrist_hash ^= self.state_hash_change(
+            (info.castling, info.en_passant),
+            (self.castling, self.en_passant),
+        );
+        self.zobrist_hash = self.apply_delta(self.zobrist_hash, &info.delta);
+        debug_assert_eq!(self.zobrist_hash, info.zobrist);
+
         // load previous state
         self.current_color = self.current_color.enemy();
         self.castling = info.castling;
         self.fifty_move = info.fifty_move;
         self.en_passant = info.en_passant;
-        self.zobrist_hash = info.zobrist;
+        self.material = info.material;
         let captured_piece = info.captured;
 
         // store end piece
@@ -627,82 +1280,244 @@ impl Board {
         }
     }
 
-    pub fn is_threefold_repetition(&self) -> bool {
-        let mut count = 0;
-        let mut i = self.half_move_count - self.fifty_move;
-        while i < self.half_move_count {
-            if self.zobrist_hash == self.hash_history[i] {
-                count += 1;
+    pub fn make_uci_move(
+        &mut self,
+        uci_move: &str,
+        move_generator: &MoveGenerator,
+        info: &mut UndoInfo,
+    ) -> std::result::Result<Move, MoveError> {
+        let my_move = self.find_uci_move(uci_move, move_generator)?;
+        self.make_move(my_move, info);
+        Ok(my_move)
+    }
+
+    // the legal move a move in UCI notation stands for, without making it
+    pub fn find_uci_move(
+        &mut self,
+        uci_move: &str,
+        move_generator: &MoveGenerator,
+    ) -> std::result::Result<Move, MoveError> {
+        let well_formed = matches!(uci_move.len(), 4 | 5)
+            && uci_move.is_char_boundary(2)
+            && uci_move.is_char_boundary(4)
+            && Square::from_notation(&uci_move[0..2]).is_some()
+            && Square::from_notation(&uci_move[2..4]).is_some()
+            && uci_move[4..].chars().all(|c| "nbrq".contains(c));
+        if !well_formed {
+            return Err(MoveError::InvalidNotation(uci_move.to_string()));
+        }
+
+        let mut move_list = MoveList::new();
+        move_generator.gen_moves(self, &mut move_list);
+
+        // castling may also be sent as the king taking its own rook, which
+        // can't be mistaken for any legal move
+        for i in 0..move_list.len() {
+            let my_move = move_list.at(i);
+            if my_move.move_to_string() == uci_move || my_move.move_to_uci(true) == uci_move {
+                return Ok(my_move);
             }
-            i += 1;
         }
-        count >= 3
-    }
-    pub fn is_low_material(&self) -> bool {
-        /*
-            king versus king
-            king and bishop versus king
-            king and knight versus king
-            king and bishop versus king and bishop with the bishops on the same color.
-        */
-        false
+
+        Err(MoveError::IllegalMove(uci_move.to_string()))
     }
-    pub fn is_draw_by_fifty_move(&self) -> bool {
-        self.fifty_move >= 100
+
+    // the number of moves made since the position was loaded, in half moves
+    #[inline(always)]
+    pub fn ply(&self) -> usize {
+        self.half_move_count
     }
-    pub fn is_draw(&self) -> bool {
-        self.is_draw_by_fifty_move() ||
-        self.is_threefold_repetition() ||
-        self.is_low_material()
+
+    // the first ply whose position could repeat: nothing before the last pawn
+    // move or capture, and nothing which has dropped out of the history
+    fn repetition_start(&self) -> usize {
+        let reversible = self.fifty_move.min(HISTORY_LEN);
+        self.half_move_count.saturating_sub(reversible)
     }
 
-    pub fn reset(&mut self) {
-        self.load_fen(STARTING_FEN).unwrap();
+    // the current position is the third occurrence if it has been seen
+    // twice before
+    // the hashes of the earlier positions which the game could still
+    // repeat, oldest first
+    pub fn repeatable_history(&self) -> impl Iterator<Item = u64> + '_ {
+        (self.repetition_start()..self.half_move_count).map(move |ply| self.hash_history[ply % HISTORY_LEN])
     }
 
+    pub fn is_threefold_repetition(&self) -> bool {
+        let count = (self.repetition_start()..self.half_move_count)
+            .filter(|&ply| self.hash_history[ply % HISTORY_LEN] == self.zobrist_hash)
+            .count();
+        count >= 2
+    }
     #[inline(always)]
-    fn disable_castle_for_color(&mut self, color: Color) {
-        if color.is_white() {
-            self.castling &= !WHITE_CASTLE;
+    pub fn material_key(&self) -> MaterialKey {
+        self.material
+    }
+    pub fn material_class(&self) -> MaterialClass {
+        // any number of bishops which all share a square color cannot mate
+        if self.material.is_bishops_only() {
+            let bishops = self.get_bb(Pieces::WhiteBishop) | self.get_bb(Pieces::BlackBishop);
+            if bishops & LIGHT_SQUARES == 0 || bishops & DARK_SQUARES == 0 {
+                return MaterialClass::DeadDraw;
+            }
+        }
+
+        self.material.classify()
+    }
+    pub fn is_low_material(&self) -> bool {
+        self.material_class().is_dead_draw()
+    }
+    pub fn is_draw_by_fifty_move(&self) -> bool {
+        self.fifty_move >= 100
+    }
+    pub fn is_draw(&self) -> bool {
+        self.is_draw_by_fifty_move() ||
+        self.is_threefold_repetition() ||
+        self.is_low_material()
+    }
+
+    pub fn game_state(&mut self, move_generator: &MoveGenerator) -> GameState {
+        let mut moves = MoveList::new();
+        let in_check = move_generator.gen_moves(self, &mut moves).checkers != 0;
+        self.game_state_with(in_check, moves.len())
+    }
+    // for callers which have already generated the legal moves
+    pub fn game_state_with(&self, in_check: bool, legal_moves: usize) -> GameState {
+        if let Some(state) = self.variant_state() {
+            state
+        } else if legal_moves == 0 {
+            if in_check {
+                GameState::Checkmate
+            } else {
+                GameState::Stalemate
+            }
+        } else if self.is_draw_by_fifty_move() {
+            GameState::FiftyMoveRule
+        } else if self.is_threefold_repetition() {
+            GameState::ThreefoldRepetition
+        } else if self.is_low_material() {
+            GameState::InsufficientMaterial
         } else {
-            self.castling &= !BLACK_CASTLE;
+            GameState::Ongoing
+        }
+    }
+
+    #[inline(always)]
+    pub fn variant(&self) -> Variant {
+        self.variant
+    }
+    // changes the rules for the current position, with no checks given yet
+    pub fn set_variant(&mut self, variant: Variant) {
+        self.variant = variant;
+        self.checks_given = [0; 2];
+        self.zobrist_hash = self.compute_hash();
+    }
+    #[inline(always)]
+    pub fn checks_given(&self, color: Color) -> u8 {
+        self.checks_given[color.idx()]
+    }
+
+    // Whether the side to move has lost by the variant's rules, which end
+    // the game as soon as the winning move is made, before checkmate or a
+    // draw is considered.
+    pub fn variant_state(&self) -> Option<GameState> {
+        match self.variant {
+            Variant::Standard => None,
+            Variant::ThreeCheck if self.checks_given[self.enemy_color().idx()] >= 3 => Some(GameState::ThirdCheck),
+            Variant::KingOfTheHill if self.get_bb(Pieces::king(self.enemy_color())) & CENTER_SQUARES != 0 => {
+                Some(GameState::KingOfTheHill)
+            }
+            _ => None,
+        }
+    }
+
+    // Whether the king of the color is attacked, worked out from the board
+    // alone as make_move has no MoveGenerator. Only three-check uses it.
+    fn is_king_attacked(&self, color: Color) -> bool {
+        let sq = match self.try_king_square(color) {
+            Some(king) => king.sq(),
+            None => return false,
+        };
+        let enemy = color.enemy();
+
+        if KNIGHT_ATTACKS[sq] & self.get_bb(Pieces::knight(enemy)) != 0
+            || KING_ATTACKS[sq] & self.get_bb(Pieces::king(enemy)) != 0
+            || PAWN_ATTACKS[color.idx()][sq] & self.get_bb(Pieces::pawn(enemy)) != 0
+        {
+            return true;
+        }
+
+        let queens = self.get_bb(Pieces::queen(enemy));
+        let rooks = self.get_bb(Pieces::rook(enemy)) | queens;
+        let bishops = self.get_bb(Pieces::bishop(enemy)) | queens;
+        let occupancy = self.get_occupancy();
+        let (rank, file) = ((sq / 8) as i16, (sq % 8) as i16);
+
+        for &(dr, df, sliders) in &[
+            (-1, 0, rooks), (1, 0, rooks), (0, -1, rooks), (0, 1, rooks),
+            (-1, -1, bishops), (-1, 1, bishops), (1, -1, bishops), (1, 1, bishops),
+        ] {
+            let (mut r, mut f) = (rank + dr, file + df);
+            while Square::valid_rf(r, f) {
+                let sq = (r * 8 + f) as usize;
+                if occupancy.is_bit_set(sq) {
+                    if sliders.is_bit_set(sq) {
+                        return true;
+                    }
+                    break;
+                }
+                r += dr;
+                f += df;
+            }
         }
+
+        false
+    }
+
+    // back to the starting position with no history, keeping the zobrist
+    // table so hashes can still be compared with earlier positions
+    pub fn reset(&mut self) {
+        self.load_fen(STARTING_FEN).unwrap();
+    }
+
+    #[inline(always)]
+    fn disable_castle_for_color(&mut self, color: Color) {
+        self.castling.remove_color(color);
     }
     #[inline(always)]
     fn disable_castle_from_sq(&mut self, sq: usize) {
-        match Square::from_usize(sq) {
-            Square::A1 => self.castling &= !WHITE_CASTLE_QS,
-            Square::H1 => self.castling &= !WHITE_CASTLE_KS,
-            Square::A8 => self.castling &= !BLACK_CASTLE_QS,
-            Square::H8 => self.castling &= !BLACK_CASTLE_KS,
-            _ => {}
+        if let Some((color, side)) = Board::castle_rook_side(Square::from_usize(sq)) {
+            self.castling.remove(color, side);
+        }
+    }
+    // the right which needs a rook on the square
+    #[inline(always)]
+    fn castle_rook_side(sq: Square) -> Option<(Color, CastleSide)> {
+        match sq {
+            Square::A1 => Some((Color::White, CastleSide::QueenSide)),
+            Square::H1 => Some((Color::White, CastleSide::KingSide)),
+            Square::A8 => Some((Color::Black, CastleSide::QueenSide)),
+            Square::H8 => Some((Color::Black, CastleSide::KingSide)),
+            _ => None,
         }
     }
 
+    #[inline(always)]
+    pub fn castling_rights(&self) -> CastlingRights {
+        self.castling
+    }
     #[inline(always)]
     pub fn can_castle_qs(&self, color: Color) -> bool {
-        if color.is_white() {
-            self.castling & WHITE_CASTLE_QS != 0
-        } else {
-            self.castling & BLACK_CASTLE_QS != 0
-        }
+        self.castling.allows(color, CastleSide::QueenSide)
     }
     #[inline(always)]
     pub fn can_castle_ks(&self, color: Color) -> bool {
-        if color.is_white() {
-            self.castling & WHITE_CASTLE_KS != 0
-        } else {
-            self.castling & BLACK_CASTLE_KS != 0
-        }
+        self.castling.allows(color, CastleSide::KingSide)
     }
 
     #[inline(always)]
     pub fn distance(a: usize, b: usize) -> usize {
-        if a > b {
-            a - b
-        } else {
-            b - a
-        }
+        a.abs_diff(b)
     }
 
     #[inline(always)]
@@ -714,6 +1529,18 @@ impl Board {
         self.current_color.enemy()
     }
 
+    // The square of the color's king, or None unless it has exactly one,
+    // which is only possible in a position built without validation or
+    // part way through editing one.
+    #[inline(always)]
+    pub fn try_king_square(&self, color: Color) -> Option<Square> {
+        let king = self.get_bb(Pieces::king(color));
+        match king != 0 && king & (king - 1) == 0 {
+            true => Some(Square::from_usize(king.lsb_idx())),
+            false => None,
+        }
+    }
+
     #[inline(always)]
     pub fn get_bb(&self, piece: Pieces) -> u64 {
         self.piece_bitboards[piece.idx()]
@@ -736,114 +1563,195 @@ impl Board {
     }
 
     pub fn to_fen(&self) -> String {
-        let mut result = String::new();
-        let mut square: usize = 0;
-        let mut rank = 0;
-        let mut file = 0;
-        let mut empty = 0;
+        let mut fen = String::with_capacity(90);
+        self.write_fen(&mut fen);
+        fen
+    }
 
-        // board
-        while rank < 8 {
-            debug_assert!(Square::valid_rf(rank, file));
-            debug_assert!(Square::valid_sq(square as i16));
+    // Appends the fen to `out`, so that a caller writing many can reuse one
+    // buffer rather than allocate a string for each
+    pub fn write_fen(&self, out: &mut String) {
+        let digit = |n: usize| char::from(b'0' + n as u8);
 
-            // process piece
-            if let Some(piece) = self.pieces[square] {
-                if empty != 0 {
-                    result.push_str(&empty.to_string());
+        for rank in 0..8 {
+            let mut empty = 0;
+            for piece in &self.pieces[rank * 8..rank * 8 + 8] {
+                match piece {
+                    Some(piece) => {
+                        if empty != 0 {
+                            out.push(digit(empty));
+                            empty = 0;
+                        }
+                        out.push(piece.notation());
+                    }
+                    None => empty += 1,
                 }
-                empty = 0;
-
-                result.push(piece.notation());
-            } else {
-                empty += 1;
             }
-
-            square += 1;
-            file += 1;
-
-            // reached end of row
-            if file > 7 {
-                if empty != 0 {
-                    result.push_str(&empty.to_string());
-                }
-                if rank < 7 {
-                    result.push('/');
-                }
-                rank += 1;
-                empty = 0;
-                file = 0;
+            if empty != 0 {
+                out.push(digit(empty));
+            }
+            if rank < 7 {
+                out.push('/');
             }
         }
 
-        result.push_str(&format!(" {} ", self.friendly_color().as_letter()));
-
-        if self.castling == 0 {
-            result.push('-');
-        } else {
-            if self.castling & WHITE_CASTLE_QS != 0 {
-                result.push('Q');
-            }
-            if self.castling & BLACK_CASTLE_QS != 0 {
-                result.push('q');
-            }
-            if self.castling & WHITE_CASTLE_KS != 0 {
-                result.push('K');
-            }
-            if self.castling & BLACK_CASTLE_KS != 0 {
-                result.push('k');
+        out.push(' ');
+        out.push(self.friendly_color().as_letter());
+        out.push(' ');
+        // writing to a string can't fail
+        write!(out, "{}", self.castling).unwrap();
+        out.push(' ');
+        match self.en_passant {
+            Some(sq) => {
+                out.push(char::from(b'a' + sq.file() as u8));
+                out.push(digit(8 - sq.rank()));
             }
+            None => out.push('-'),
         }
+        write!(out, " {} {}", self.fifty_move, self.full_move_count).unwrap();
+    }
 
-        result.push_str(&format!(
-            " {} {} {}",
-            if let Some(square) = self.en_passant {
-                square.notation()
-            } else {
-                "-".to_string()
-            },
-            self.fifty_move,
-            self.full_move_count
-        ));
+    pub fn new(fen: &str) -> std::result::Result<Board, FenError> {
+        let mut board = Board::blank();
+        board.zobrist = ZOBRIST_KEYS;
 
-        result
+        board.load_fen(fen)?;
+        Ok(board)
     }
 
-    pub fn new(fen: &str) -> std::result::Result<Board, String> {
-        let mut board = Board {
+    // an empty board, with no zobrist table
+    fn blank() -> Board {
+        Board {
             current_color: Color::White,
             fifty_move: 0,
             full_move_count: 0,
             half_move_count: 0,
-            castling: 0b1111,
+            castling: CastlingRights::ALL,
             en_passant: None,
             pieces: [None; 64],
             piece_bitboards: [0; 12],
             combined_bitboards: [0; 2],
-            zobrist_table: [[0; 12]; 64],
+            material: MaterialKey::default(),
+            zobrist: ZobristKeys::default(),
             zobrist_hash: 0,
-            hash_history: [0; 512],
-        };
+            hash_history: [0; HISTORY_LEN],
+            variant: Variant::Standard,
+            checks_given: [0; 2],
+        }
+    }
+}
 
-        // init zobrist table
-        board.rand_zobrist_table(&mut rand::thread_rng());
+// Sets up a position piece by piece, eg.
+//
+//     let board = BoardBuilder::new()
+//         .piece(Square::E1, Pieces::WhiteKing)
+//         .piece(Square::E8, Pieces::BlackKing)
+//         .piece(Square::D1, Pieces::WhiteQueen)
+//         .build()?;
+//
+// The position starts empty with white to move, no castling rights and the
+// counters of a new game.
+#[derive(Clone, Copy)]
+pub struct BoardBuilder {
+    pieces: [Option<Pieces>; 64],
+    side_to_move: Color,
+    castling: CastlingRights,
+    en_passant: Option<Square>,
+    halfmove: usize,
+    fullmove: usize,
+    // from the board the builder was made from, if any
+    zobrist: Option<ZobristKeys>,
+}
 
-        // load fen
-        match board.load_fen(fen) {
-            Ok(()) => {
-                // init zobrist hash
-                for sq in 0..64 {
-                    if let Some(piece) = board.pieces[sq] {
-                        board.zobrist_hash ^= board.zobrist_table[sq][piece.idx()]
-                    }
-                }
-                Ok(board)
-            },
-            Err(msg) => {
-                Err(msg)
-            }
+impl BoardBuilder {
+    pub fn new() -> BoardBuilder {
+        BoardBuilder {
+            pieces: [None; 64],
+            side_to_move: Color::White,
+            castling: CastlingRights::NONE,
+            en_passant: None,
+            halfmove: 0,
+            fullmove: 1,
+            zobrist: None,
         }
     }
+
+    // puts the piece on the square, replacing anything already there
+    pub fn piece(mut self, sq: Square, piece: Pieces) -> BoardBuilder {
+        self.pieces[sq.sq()] = Some(piece);
+        self
+    }
+    pub fn remove(mut self, sq: Square) -> BoardBuilder {
+        self.pieces[sq.sq()] = None;
+        self
+    }
+    pub fn side_to_move(mut self, color: Color) -> BoardBuilder {
+        self.side_to_move = color;
+        self
+    }
+    pub fn castling(mut self, castling: CastlingRights) -> BoardBuilder {
+        self.castling = castling;
+        self
+    }
+    // the square behind a pawn which has just moved two squares
+    pub fn en_passant(mut self, sq: Option<Square>) -> BoardBuilder {
+        self.en_passant = sq;
+        self
+    }
+    // half moves since the last pawn move or capture
+    pub fn halfmove(mut self, halfmove: usize) -> BoardBuilder {
+        self.halfmove = halfmove;
+        self
+    }
+    pub fn fullmove(mut self, fullmove: usize) -> BoardBuilder {
+        self.fullmove = fullmove;
+        self
+    }
+
+    pub fn build(self) -> std::result::Result<Board, FenError> {
+        Board::from_position(self)
+    }
+
+    fn is_valid_castling(&self) -> bool {
+        castling_fits(&self.pieces, self.castling)
+    }
+    fn is_valid_en_passant(&self) -> bool {
+        en_passant_fits(&self.pieces, self.side_to_move, self.en_passant)
+    }
+}
+
+// each castling right needs the king and rook on their starting squares
+fn castling_fits(pieces: &[Option<Pieces>; 64], castling: CastlingRights) -> bool {
+    castling.iter().all(|(color, side)| {
+        let (king_sq, rook_sq) = match (color, side) {
+            (Color::White, CastleSide::KingSide) => (Square::E1, Square::H1),
+            (Color::White, CastleSide::QueenSide) => (Square::E1, Square::A1),
+            (Color::Black, CastleSide::KingSide) => (Square::E8, Square::H8),
+            (Color::Black, CastleSide::QueenSide) => (Square::E8, Square::A8),
+        };
+        pieces[king_sq.sq()] == Some(Pieces::king(color)) && pieces[rook_sq.sq()] == Some(Pieces::rook(color))
+    })
+}
+
+// the square must be empty with the pawn that just double pushed in front of it
+fn en_passant_fits(pieces: &[Option<Pieces>; 64], side_to_move: Color, en_passant: Option<Square>) -> bool {
+    let sq = match en_passant {
+        Some(sq) => sq,
+        None => return true,
+    };
+    let (rank, pawn_sq, pawn) = if side_to_move.is_white() {
+        (2, sq.sq() + 8, Pieces::BlackPawn)
+    } else {
+        (5, sq.sq().wrapping_sub(8), Pieces::WhitePawn)
+    };
+
+    sq.rank() == rank && pieces.get(pawn_sq) == Some(&Some(pawn)) && pieces[sq.sq()].is_none()
+}
+
+impl Default for BoardBuilder {
+    fn default() -> BoardBuilder {
+        BoardBuilder::new()
+    }
 }
 
 impl Default for Board {
@@ -918,6 +1826,260 @@ mod tests {
         assert!(fen_test("8/8/8/8/k2Pp2Q/8/8/2K5 b - d3 0 1"));
     }
 
+    // the same position, ignoring the random zobrist tables
+    fn same_position(built: &Board, loaded: &Board) {
+        let mut built = built.clone();
+        built.zobrist = loaded.zobrist;
+        built.zobrist_hash = built.compute_hash();
+        assert!(built == *loaded, "{} != {}", built.to_fen(), loaded.to_fen());
+    }
+
+    #[test]
+    fn builder() {
+        let built = BoardBuilder::new()
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::H1, Pieces::WhiteRook)
+            .piece(Square::E8, Pieces::BlackKing)
+            .piece(Square::D4, Pieces::BlackPawn)
+            .piece(Square::E4, Pieces::WhitePawn)
+            .side_to_move(Color::Black)
+            .castling(CastlingRights::only(Color::White, CastleSide::KingSide))
+            .en_passant(Some(Square::E3))
+            .halfmove(0)
+            .fullmove(23)
+            .build()
+            .unwrap();
+        let fen = "4k3/8/8/8/3pP3/8/8/4K2R b K e3 0 23";
+        assert_eq!(built.to_fen(), fen);
+        same_position(&built, &Board::new(fen).unwrap());
+
+        // the starting position, from the fen's pieces
+        let start = Board::default();
+        let mut builder = BoardBuilder::new().castling(CastlingRights::ALL);
+        for sq in 0..64 {
+            if let Some(piece) = start.pieces[sq] {
+                builder = builder.piece(Square::from_usize(sq), piece);
+            }
+        }
+        same_position(&builder.build().unwrap(), &start);
+    }
+
+    #[test]
+    fn into_builder() {
+        // an unchanged position is identical, hash included
+        for fen in &[
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/8/8/2k5/2pP4/8/B7/4K3 b - d3 5 3",
+        ] {
+            let board = Board::new(fen).unwrap();
+            assert!(board.into_builder().build().unwrap() == board, "{}", fen);
+        }
+
+        // 1. d4 by hand, which can be compared with the board after the move
+        let mut board = Board::default();
+        let moved = board
+            .into_builder()
+            .remove(Square::D2)
+            .piece(Square::D4, Pieces::WhitePawn)
+            .side_to_move(Color::Black)
+            .en_passant(Some(Square::D3))
+            .build()
+            .unwrap();
+        let generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        board.make_uci_move("d2d4", &generator, &mut info).unwrap();
+        assert_eq!(moved.hash(), board.hash());
+        assert_eq!(moved.to_fen(), board.to_fen());
+    }
+
+    #[test]
+    fn king_squares() {
+        let board = Board::default();
+        assert!(board.try_king_square(Color::White) == Some(Square::E1));
+        assert!(board.try_king_square(Color::Black) == Some(Square::E8));
+
+        // the builder doesn't insist on one king each
+        let kingless = BoardBuilder::new().piece(Square::E8, Pieces::BlackKing).build().unwrap();
+        assert!(kingless.try_king_square(Color::White).is_none());
+        assert!(kingless.try_king_square(Color::Black) == Some(Square::E8));
+
+        let two_kings = BoardBuilder::new()
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::A1, Pieces::WhiteKing)
+            .piece(Square::E8, Pieces::BlackKing)
+            .build()
+            .unwrap();
+        assert!(two_kings.try_king_square(Color::White).is_none());
+        assert!(two_kings.try_king_square(Color::Black) == Some(Square::E8));
+    }
+
+    #[test]
+    fn builder_validation() {
+        let kings = BoardBuilder::new()
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::E8, Pieces::BlackKing);
+
+        // castling without the rook
+        let white_qs = CastlingRights::only(Color::White, CastleSide::QueenSide);
+        assert_eq!(kings.castling(white_qs).build().err(), Some(FenError::BadCastlingRights));
+        let rook = kings.piece(Square::A1, Pieces::WhiteRook);
+        assert!(rook.castling(white_qs).build().is_ok());
+
+        // en passant without a pawn to take, or on the wrong rank
+        assert_eq!(kings.en_passant(Some(Square::E6)).build().err(), Some(FenError::BadEnPassantSquare));
+        let pawn = kings.piece(Square::E5, Pieces::BlackPawn);
+        assert!(pawn.en_passant(Some(Square::E6)).build().is_ok());
+        assert_eq!(
+            pawn.side_to_move(Color::Black).en_passant(Some(Square::E6)).build().err(),
+            Some(FenError::BadEnPassantSquare)
+        );
+
+        // as from a fen
+        assert_eq!(
+            Board::new("4k3/8/8/8/8/8/8/4K3 w Q - 0 1").err(),
+            kings.castling(white_qs).build().err()
+        );
+    }
+
+    #[test]
+    fn editing() {
+        let start = Board::default();
+        let mut board = Board::default();
+
+        // taking every piece off leaves the castling rights, which now
+        // have nothing to castle with
+        for sq in (0..64).map(Square::from_usize) {
+            if let Some(piece) = start.pieces[sq.sq()] {
+                assert!(board.remove_piece(sq) == Ok(piece));
+                assert!(board.check_consistency().is_empty(), "{:?}", board.check_consistency());
+            }
+        }
+        assert_eq!(board.validate(), Err(PositionError::KingCount(Color::White, 0)));
+        assert_eq!(board.position_problems().len(), 3);
+        assert_eq!(board.remove_piece(Square::E1), Err(PositionError::SquareEmpty("e1".to_string())));
+
+        // and putting them back a piece at a time gives the same board
+        for sq in (0..64).map(Square::from_usize) {
+            if let Some(piece) = start.pieces[sq.sq()] {
+                board.put_piece(sq, piece).unwrap();
+                assert!(board.check_consistency().is_empty(), "{:?}", board.check_consistency());
+            }
+            match sq {
+                // the black pieces are on, but only one king
+                Square::H7 => assert_eq!(board.validate(), Err(PositionError::KingCount(Color::White, 0))),
+                // the white king is on but not the rook
+                Square::G1 => assert_eq!(board.validate(), Err(PositionError::BadCastlingRights)),
+                _ => {}
+            }
+        }
+        assert!(board == start);
+        assert_eq!(board.hash(), start.hash());
+        assert_eq!(board.validate(), Ok(()));
+        assert_eq!(
+            board.put_piece(Square::E4, Pieces::WhiteQueen).and(board.put_piece(Square::E4, Pieces::BlackQueen)),
+            Err(PositionError::SquareOccupied("e4".to_string()))
+        );
+
+        // from a cleared board, as from a fen
+        board.clear();
+        assert!(board.check_consistency().is_empty());
+        board.put_piece(Square::E1, Pieces::WhiteKing).unwrap();
+        board.put_piece(Square::E8, Pieces::BlackKing).unwrap();
+        board.put_piece(Square::A7, Pieces::WhitePawn).unwrap();
+        assert!(board == Board::new("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap());
+
+        // the invalid positions the fen parser lets through
+        board.put_piece(Square::E2, Pieces::BlackQueen).unwrap();
+        assert_eq!(board.validate(), Ok(()));
+        board.put_piece(Square::A8, Pieces::WhitePawn).unwrap();
+        board.put_piece(Square::D2, Pieces::BlackKing).unwrap();
+        assert_eq!(
+            board.position_problems(),
+            vec![PositionError::KingCount(Color::Black, 2), PositionError::PawnOnBackRank]
+        );
+        board.remove_piece(Square::A8).unwrap();
+        board.remove_piece(Square::D2).unwrap();
+        board.remove_piece(Square::E1).unwrap();
+        board.put_piece(Square::E7, Pieces::WhiteKing).unwrap();
+        assert_eq!(board.validate(), Err(PositionError::NotToMoveInCheck(Color::Black)));
+    }
+
+    #[test]
+    fn partial_fens() {
+        let full = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+
+        // the counters default to 0 and 1, and are always written out
+        for fen in &["4k3/8/8/3pP3/8/8/8/4K3 w - d6", "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0"] {
+            assert_eq!(Board::new(fen).unwrap().to_fen(), full);
+        }
+        let board = Board::new("4k3/8/8/8/8/8/8/4K3 b - - 7").unwrap();
+        assert_eq!(board.to_fen(), "4k3/8/8/8/8/8/8/4K3 b - - 7 1");
+        assert_eq!(board.fifty_move, 7);
+
+        let mut board = Board::default();
+        assert_eq!(board.load_fen(&format!("{} bm e5;", full)), Err(FenError::WrongFieldCount(8)));
+        assert_eq!(board.load_fen("4k3/8/8/8/8/8/8/4K3 w - - x"), Err(FenError::BadCounter));
+
+        // strict parsing wants every field
+        for fen in &["4k3/8/8/3pP3/8/8/8/4K3 w - d6", "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0"] {
+            assert_eq!(board.load_fen_with(fen, FenParsing::Strict), Err(FenError::MissingCounters));
+        }
+        assert_eq!(board.load_fen_with(full, FenParsing::Strict), Ok(()));
+        assert_eq!(board.load_fen_with("8/8/8 w", FenParsing::Strict), Err(FenError::WrongFieldCount(2)));
+
+        // lenient parsing skips EPD opcodes, with or without counters
+        for (epd, fen) in &[
+            (
+                "rnbqkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - bm c4; id \"test 1\";",
+                "rnbqkb1r/pppppppp/5n2/8/3P4/8/PPP1PPPP/RNBQKBNR w KQkq - 0 1",
+            ),
+            (
+                "4k3/8/8/8/8/8/8/4K3 b - - 12 40 id \"counters\";",
+                "4k3/8/8/8/8/8/8/4K3 b - - 12 40",
+            ),
+            ("4k3/8/8/8/8/8/8/4K3 b - - 3 bm Kd7;", "4k3/8/8/8/8/8/8/4K3 b - - 3 1"),
+        ] {
+            assert!(board.load_fen(epd).is_err(), "{}", epd);
+            board.load_fen_with(epd, FenParsing::Lenient).unwrap();
+            assert_eq!(board.to_fen(), Board::new(fen).unwrap().to_fen());
+        }
+        assert_eq!(
+            board.load_fen_with("4k3/8/8/8/8/8/8/4K3 b -", FenParsing::Lenient),
+            Err(FenError::WrongFieldCount(3))
+        );
+    }
+
+    #[test]
+    fn load_fen_unchanged_on_error() {
+        let mut board = Board::new("8/8/8/2k5/2pP4/8/B7/4K3 b - d3 5 3").unwrap();
+        let before = board.clone();
+        assert_eq!(
+            board.load_fen("4k3/8/8/8/8/8/8/4K3 w K - 0 1"),
+            Err(FenError::BadCastlingRights)
+        );
+        assert!(board == before);
+
+        // however far the fen gets before it fails
+        for fen in &[
+            "4k3/8/8/8/8/8/8/4K3 w -",
+            "4k3/8/8/8/8/8/8/4K2x w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3/k w - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 x - - 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - e9 0 1",
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 x",
+        ] {
+            assert!(board.load_fen(fen).is_err(), "{}", fen);
+            assert!(board == before, "{}", fen);
+        }
+        assert_eq!(
+            board.load_fen_with("4k3/8/8/8/8/8/8/4K3 w - -", FenParsing::Strict),
+            Err(FenError::MissingCounters)
+        );
+        assert!(board == before);
+    }
+
     fn undo_test(fen: &str) -> bool {
         let mut board = Board::new(fen).unwrap();
 
@@ -928,7 +2090,7 @@ mod tests {
 
         let mut info = UndoInfo::default();
         for i in 0..move_list.len() {
-            let mut test_board = board;
+            let mut test_board = board.clone();
 
             test_board.make_move(move_list.at(i), &mut info);
             test_board.undo_move(move_list.at(i), &info);
@@ -993,4 +2155,533 @@ mod tests {
             "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"
         ));
     }
+
+    #[test]
+    fn move_delta() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x1418);
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        let mut info = UndoInfo::default();
+
+        // between them these cover castling, en passant and promotions
+        for fen in &[
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        ] {
+            for _ in 0..20 {
+                let mut board = Board::new(fen).unwrap();
+
+                for _ in 0..40 {
+                    generator.gen_moves(&mut board, &mut move_list);
+                    if move_list.is_empty() {
+                        break;
+                    }
+
+                    let my_move = move_list.at(rng.gen_range(0..move_list.len()));
+                    let before = board.pieces;
+                    board.make_move(my_move, &mut info);
+
+                    // the delta is exactly the difference between the piece arrays
+                    let mut expected = before;
+                    for &(piece, sq) in info.delta.removed() {
+                        assert_eq!(expected[sq], Some(piece), "{}", my_move.move_to_string());
+                        expected[sq] = None;
+                    }
+                    for &(piece, sq) in info.delta.added() {
+                        assert_eq!(expected[sq], None, "{}", my_move.move_to_string());
+                        expected[sq] = Some(piece);
+                    }
+                    assert_eq!(expected, board.pieces, "{}", my_move.move_to_string());
+
+                    assert_eq!(board.hash(), board.compute_hash(), "{}", my_move.move_to_string());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn hash_after() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x1426);
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        let mut info = UndoInfo::default();
+
+        for fen in &[
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "8/8/8/1k6/3Pp3/8/8/4KQ2 b - d3 0 1",
+            "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+
+            for _ in 0..30 {
+                generator.gen_moves(&mut board, &mut move_list);
+                if move_list.is_empty() {
+                    break;
+                }
+
+                // every move agrees with actually making it
+                for i in 0..move_list.len() {
+                    let my_move = move_list.at(i);
+                    let expected = board.hash_after(my_move);
+
+                    board.make_move(my_move, &mut info);
+                    assert_eq!(board.hash(), expected, "{} in {}", my_move.move_to_string(), fen);
+                    board.undo_move(my_move, &info);
+                }
+
+                board.make_move(move_list.at(rng.gen_range(0..move_list.len())), &mut info);
+            }
+        }
+    }
+
+    #[test]
+    fn incremental_hash() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x1439);
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        let mut moves_made = 0;
+
+        // positions where castling rights are lost to rook captures and en
+        // passant squares come and go
+        let fens = [
+            STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1",
+            "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/8/8/1k6/3Pp3/8/8/4KQ2 b - d3 0 1",
+            "4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1",
+        ];
+
+        while moves_made < 40_000 {
+            let fen = fens[rng.gen_range(0..fens.len())];
+            let mut board = Board::new(fen).unwrap();
+            let mut played = Vec::new();
+
+            for _ in 0..rng.gen_range(1..120) {
+                generator.gen_moves(&mut board, &mut move_list);
+                if move_list.is_empty() {
+                    break;
+                }
+
+                let my_move = move_list.at(rng.gen_range(0..move_list.len()));
+                let mut info = UndoInfo::default();
+                let hash = board.hash();
+                board.make_move(my_move, &mut info);
+                assert_eq!(board.hash(), board.compute_hash(), "{} from {}", my_move.move_to_string(), fen);
+
+                played.push((my_move, info, hash));
+                moves_made += 1;
+            }
+
+            // undoing goes back through the same hashes
+            while let Some((my_move, info, hash)) = played.pop() {
+                board.undo_move(my_move, &info);
+                assert_eq!(board.hash(), hash, "undo {} from {}", my_move.move_to_string(), fen);
+                assert_eq!(board.hash(), board.compute_hash());
+            }
+        }
+    }
+
+    // to_fen as it was before write_fen, formatting each field separately
+    fn old_to_fen(board: &Board) -> String {
+        let mut result = String::new();
+        for rank in 0..8 {
+            let mut empty = 0;
+            for file in 0..8 {
+                match board.pieces[rank * 8 + file] {
+                    Some(piece) => {
+                        if empty != 0 {
+                            result.push_str(&empty.to_string());
+                        }
+                        empty = 0;
+                        result.push(piece.notation());
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty != 0 {
+                result.push_str(&empty.to_string());
+            }
+            if rank < 7 {
+                result.push('/');
+            }
+        }
+        result.push_str(&format!(" {} ", board.friendly_color().as_letter()));
+        result.push_str(&board.castling.to_string());
+        result.push_str(&format!(
+            " {} {} {}",
+            board.en_passant.map_or("-".to_string(), |sq| sq.notation()),
+            board.fifty_move,
+            board.full_move_count
+        ));
+        result
+    }
+
+    #[test]
+    fn write_fen_matches_old() {
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x1469);
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        let mut info = UndoInfo::default();
+        let mut fen = String::from("kept ");
+
+        for start in &[STARTING_FEN, "r3k2r/1P4P1/8/8/8/8/1p4p1/R3K2R w KQkq - 0 1", "8/8/8/1k6/3Pp3/8/8/4KQ2 b - d3 0 1"] {
+            let mut board = Board::new(start).unwrap();
+            for _ in 0..200 {
+                assert_eq!(board.to_fen(), old_to_fen(&board));
+
+                // write_fen appends to what is there
+                fen.truncate(5);
+                board.write_fen(&mut fen);
+                assert_eq!(fen[5..], old_to_fen(&board));
+
+                generator.gen_moves(&mut board, &mut move_list);
+                if move_list.is_empty() || board.fifty_move >= 100 {
+                    break;
+                }
+                board.make_move(move_list.at(rng.gen_range(0..move_list.len())), &mut info);
+            }
+        }
+    }
+
+    #[test]
+    fn position_key() {
+        // the same position reached by different moves, at different times
+        let generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        let mut a = Board::default();
+        let mut b = Board::default();
+        for uci_move in ["g1f3", "g8f6", "b1c3", "b8c6"] {
+            a.make_uci_move(uci_move, &generator, &mut info).unwrap();
+        }
+        for uci_move in ["b1c3", "b8c6", "g1f3", "g8f6"] {
+            b.make_uci_move(uci_move, &generator, &mut info).unwrap();
+        }
+        assert_eq!(a.position_key(), b.position_key());
+
+        let later = Board::new("r1bqkb1r/pppppppp/2n2n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R w KQkq - 12 40").unwrap();
+        assert_eq!(a.position_key(), later.position_key());
+
+        // but not the side to move, castling or en passant
+        for fen in [
+            "r1bqkb1r/pppppppp/2n2n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R b KQkq - 4 3",
+            "r1bqkb1r/pppppppp/2n2n2/8/8/2N2N2/PPPPPPPP/R1BQKB1R w Kkq - 4 3",
+        ] {
+            assert_ne!(a.position_key(), Board::new(fen).unwrap().position_key(), "{}", fen);
+        }
+        let double_push = Board::new("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1").unwrap();
+        let no_en_passant = Board::new("4k3/8/8/8/3pP3/8/8/4K3 b - - 0 1").unwrap();
+        assert_ne!(double_push.position_key(), no_en_passant.position_key());
+    }
+
+    #[test]
+    fn hash_includes_state() {
+        let board = Board::new("r3k2r/8/8/3pP3/8/8/8/R3K2R w KQkq d6 0 1").unwrap();
+        let hashes: Vec<u64> = vec![
+            board.into_builder(),
+            board.into_builder().en_passant(None),
+            board.into_builder().en_passant(None).side_to_move(Color::Black),
+            board.into_builder().castling(CastlingRights::color(Color::White)),
+            board.into_builder().castling(CastlingRights::NONE),
+        ]
+        .into_iter()
+        .map(|position| position.build().unwrap().hash())
+        .collect();
+
+        // the same pieces with a different side to move, castling rights or
+        // en passant square are different positions
+        for (i, a) in hashes.iter().enumerate() {
+            for b in &hashes[i + 1..] {
+                assert_ne!(a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn consistency() {
+        let generator = MoveGenerator::new();
+        let mut board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut moves = MoveList::new();
+        let mut info = UndoInfo::default();
+        assert_eq!(board.check_consistency(), Vec::<String>::new());
+
+        // castles, en passant and promotions all keep everything in step
+        generator.gen_moves(&mut board, &mut moves);
+        for i in 0..moves.len() {
+            board.make_move(moves.at(i), &mut info);
+            assert_eq!(board.check_consistency(), Vec::<String>::new(), "{}", moves.at(i).move_to_string());
+            board.undo_move(moves.at(i), &info);
+        }
+        assert_eq!(board.check_consistency(), Vec::<String>::new());
+
+        let mut corrupt = board.clone();
+        corrupt.zobrist_hash ^= 1;
+        assert_eq!(corrupt.check_consistency().len(), 1);
+
+        // a piece missing from the array, but not the bitboards or material
+        let mut corrupt = board.clone();
+        corrupt.pieces[Square::E1 as usize] = None;
+        let problems = corrupt.check_consistency();
+        assert!(problems.iter().any(|problem| problem.starts_with("WhiteKing bitboard")), "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("White bitboard")), "{:?}", problems);
+        assert!(problems.iter().any(|problem| problem.starts_with("hash")), "{:?}", problems);
+
+        let mut corrupt = board.clone();
+        corrupt.material.remove(Pieces::BlackQueen);
+        assert_eq!(corrupt.check_consistency(), vec!["material key doesn't match the pieces".to_string()]);
+    }
+
+    #[test]
+    fn fen_errors() {
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w -").err(), Some(FenError::WrongFieldCount(3)));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w - - 0 1 0").err(), Some(FenError::WrongFieldCount(7)));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/7x w - - 0 1").err(), Some(FenError::InvalidPieceChar('x')));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/7 w - - 0 1").err(), Some(FenError::TooFewSquares));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8/k w - - 0 1").err(), Some(FenError::TooManySquares));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 x - - 0 1").err(), Some(FenError::BadSideToMove));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w KX - 0 1").err(), Some(FenError::BadCastlingRights));
+
+        // malformed castling fields are rejected, even with every piece in
+        // place for the rights they name
+        for castling in ["K-q", "-K", "KK", "kqq"] {
+            let fen = format!("r3k2r/8/8/8/8/8/8/R3K2R w {} - 0 1", castling);
+            assert_eq!(Board::new(&fen).err(), Some(FenError::BadCastlingRights), "{}", fen);
+        }
+        let board = Board::new("r3k2r/8/8/8/8/8/8/R3K2R w kqKQ - 0 1").unwrap();
+        assert_eq!(board.castling_rights(), CastlingRights::ALL);
+        assert_eq!(board.to_fen(), "r3k2r/8/8/8/8/8/8/R3K2R w QqKk - 0 1");
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w - e9 0 1").err(), Some(FenError::BadEnPassantSquare));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w - e4 0 1").err(), Some(FenError::BadEnPassantSquare));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w - - x 1").err(), Some(FenError::BadCounter));
+        assert_eq!(Board::new("8/8/8/8/8/8/8/8 w - - 0 -1").err(), Some(FenError::BadCounter));
+    }
+
+    #[test]
+    fn uci_move_errors() {
+        let generator = MoveGenerator::new();
+        let mut board = Board::default();
+        let mut info = UndoInfo::default();
+
+        for bad in &["", "e2", "e2e9", "i2i4", "e2e4x", "e2e4qq", "é2e4"] {
+            assert_eq!(
+                board.make_uci_move(bad, &generator, &mut info),
+                Err(MoveError::InvalidNotation(bad.to_string()))
+            );
+        }
+        assert_eq!(
+            board.make_uci_move("e2e5", &generator, &mut info),
+            Err(MoveError::IllegalMove("e2e5".to_string()))
+        );
+        assert_eq!(board.to_fen(), Board::default().to_fen());
+
+        assert!(board.make_uci_move("e2e4", &generator, &mut info).is_ok());
+        assert_eq!(board.friendly_color(), Color::Black);
+    }
+
+    #[test]
+    fn reset() {
+        let generator = MoveGenerator::new();
+        let mut board = Board::default();
+        let start = board.clone();
+        let mut info = UndoInfo::default();
+
+        for uci_move in &["e2e4", "e7e5", "e1e2"] {
+            board.make_uci_move(uci_move, &generator, &mut info).unwrap();
+        }
+        board.reset();
+
+        assert_eq!(board.to_fen(), start.to_fen());
+        assert_eq!(board.hash(), start.hash());
+        // including the history, so no repetitions carry over
+        assert!(board == start);
+    }
+
+    #[test]
+    fn fixed_zobrist_keys() {
+        // every board hashes the same position the same way, in every run.
+        // Changing the keys changes these, and any stored hashes.
+        let start = Board::default();
+        assert_eq!(start.hash(), 0xe427883dfaa649c4);
+        // and not as Polyglot does, whose table would have to replace these
+        assert_ne!(start.hash(), 0x463b96181691fc9c);
+        assert_eq!(Board::new(STARTING_FEN).unwrap().hash(), start.hash());
+        let fen = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1";
+        assert_eq!(Board::new(fen).unwrap().hash(), 0x692e3aa6187bd8ce);
+
+        let built = BoardBuilder::new()
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::E8, Pieces::BlackKing)
+            .build()
+            .unwrap();
+        assert_eq!(built.hash(), Board::new("4k3/8/8/8/8/8/8/4K3 w - - 0 1").unwrap().hash());
+
+        // random keys are still there to opt into
+        let mut random = start.clone();
+        random.rand_zobrist_table(&mut rand::thread_rng());
+        assert_ne!(random.hash(), start.hash());
+        assert!(random.check_consistency().is_empty());
+    }
+
+    #[test]
+    fn long_game() {
+        let generator = MoveGenerator::new();
+        let mut board = Board::default();
+        let mut info = UndoInfo::default();
+        let shuffle = ["g1f3", "g8f6", "f3g1", "f6g8"];
+
+        // 2000 plies of knight moves, which wraps round the history. However
+        // long the game, a repetition check never looks further back than
+        // the history keeps (see the long_game bench for the time it takes)
+        for _ in 0..500 {
+            for uci_move in &shuffle {
+                board.make_uci_move(uci_move, &generator, &mut info).unwrap();
+                assert_eq!(board.repetition_start(), board.ply() - board.fifty_move.min(HISTORY_LEN));
+                assert_eq!(board.repeatable_history().count(), board.fifty_move.min(HISTORY_LEN));
+            }
+        }
+        assert_eq!(board.ply(), 2000);
+        assert_eq!(board.repeatable_history().count(), HISTORY_LEN);
+
+        // repetitions are still seen once the history has wrapped
+        assert!(board.is_threefold_repetition());
+
+        // but nothing before a pawn move can repeat (single pushes, so no en
+        // passant square tells the positions apart)
+        for uci_move in &["e2e3", "e7e6"] {
+            board.make_uci_move(uci_move, &generator, &mut info).unwrap();
+            assert!(!board.is_threefold_repetition());
+        }
+        for cycle in 1..=2 {
+            assert!(!board.is_threefold_repetition());
+            for uci_move in &shuffle {
+                board.make_uci_move(uci_move, &generator, &mut info).unwrap();
+            }
+            assert_eq!(board.is_threefold_repetition(), cycle == 2);
+        }
+    }
+
+    // plays the moves, checking that each can be undone, and returns the
+    // game state after each
+    fn play_variant(variant: Variant, moves: &[&str]) -> (Board, Vec<GameState>) {
+        let generator = MoveGenerator::new();
+        let mut board = Board::default();
+        board.set_variant(variant);
+        let mut states = Vec::new();
+
+        for uci_move in moves {
+            let before = board.clone();
+            let mut info = UndoInfo::default();
+            let my_move = board.make_uci_move(uci_move, &generator, &mut info).unwrap();
+            assert!(board.check_consistency().is_empty(), "{:?}", board.check_consistency());
+
+            let after = board.clone();
+            board.undo_move(my_move, &info);
+            assert!(board == before, "undoing {}", uci_move);
+            board = after;
+
+            states.push(board.game_state(&generator));
+        }
+
+        (board, states)
+    }
+
+    #[test]
+    fn three_check() {
+        let moves = ["e2e4", "d7d6", "f1b5", "c7c6", "b5c6", "b8c6", "d1h5", "a7a6", "h5f7"];
+        let (board, states) = play_variant(Variant::ThreeCheck, &moves);
+
+        assert_eq!(board.checks_given(Color::White), 3);
+        assert_eq!(board.checks_given(Color::Black), 0);
+        assert!(states[..8].iter().all(|&state| state == GameState::Ongoing));
+        assert_eq!(states[8], GameState::ThirdCheck);
+        assert_eq!(board.variant_state(), Some(GameState::ThirdCheck));
+
+        // the check counts are part of the hash
+        let mut same_position = Board::new(&board.to_fen()).unwrap();
+        same_position.set_variant(Variant::ThreeCheck);
+        assert_ne!(board.hash(), same_position.hash());
+    }
+
+    #[test]
+    fn king_of_the_hill() {
+        let moves = ["e2e3", "e7e6", "e1e2", "e8e7", "e2d3", "e7d6", "d3e4"];
+        let (board, states) = play_variant(Variant::KingOfTheHill, &moves);
+
+        assert!(states[..6].iter().all(|&state| state == GameState::Ongoing));
+        assert_eq!(states[6], GameState::KingOfTheHill);
+        assert_eq!(board.variant_state(), Some(GameState::KingOfTheHill));
+    }
+
+    #[test]
+    fn standard_unchanged_by_variants() {
+        let moves = ["e2e4", "d7d6", "f1b5", "c7c6", "b5c6", "b8c6", "d1h5", "a7a6", "h5f7"];
+        let (board, states) = play_variant(Variant::Standard, &moves);
+
+        // checks aren't counted, and the hash is the same as the position
+        // loaded from scratch
+        assert_eq!(board.checks_given(Color::White), 0);
+        assert!(states.iter().all(|&state| state == GameState::Ongoing));
+        assert_eq!(board.hash(), Board::new(&board.to_fen()).unwrap().hash());
+
+        // no checks hash the same as standard, whatever the variant
+        let mut koth = Board::default();
+        koth.set_variant(Variant::KingOfTheHill);
+        assert_eq!(koth.hash(), Board::default().hash());
+        let mut three_check = Board::default();
+        three_check.set_variant(Variant::ThreeCheck);
+        assert_eq!(three_check.hash(), Board::default().hash());
+
+        // the king on e4 is nothing special in standard chess
+        let (_, states) = play_variant(Variant::Standard, &["e2e3", "e7e6", "e1e2", "e8e7", "e2d3", "e7d6", "d3e4"]);
+        assert_eq!(states[6], GameState::Ongoing);
+    }
+
+    #[test]
+    fn null_move() {
+        // a pass hashes as the position with the other side to move and
+        // no en passant square, and undoing it puts everything back
+        let fen = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        let mut board = Board::new(fen).unwrap();
+        let mut info = UndoInfo::default();
+        board.make_null_move(&mut info);
+
+        assert_eq!(board.friendly_color(), Color::Black);
+        assert!(board.check_consistency().is_empty(), "{:?}", board.check_consistency());
+        let passed = Board::new("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR b KQkq - 0 3").unwrap();
+        assert_eq!(board.hash(), passed.hash());
+
+        board.undo_null_move(&info);
+        assert!(board == Board::new(fen).unwrap());
+        assert_eq!(board.hash(), Board::new(fen).unwrap().hash());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "making a move no position has: 0x934f")]
+    fn promotion_off_the_last_rank() {
+        use crate::engine::r#move::{MOVE_PROMOTION_PIECE_QUEEN, MOVE_TYPE_PROMOTION};
+
+        // e2e4=Q
+        let my_move = Move::new_move(
+            Square::E2.sq() as u16,
+            Square::E4.sq() as u16,
+            MOVE_TYPE_PROMOTION | MOVE_PROMOTION_PIECE_QUEEN,
+        );
+        Board::default().make_move(my_move, &mut UndoInfo::default());
+    }
 }
@@ -0,0 +1,178 @@
+// Castling rights: which sides each color may still castle on. They are
+// kept as four bits, one per right, so they index the zobrist keys and cost
+// a byte in the undo information.
+
+use std::fmt::{Display, Formatter};
+
+use crate::engine::error::FenError;
+use crate::engine::piece::Color;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CastleSide {
+    KingSide,
+    QueenSide,
+}
+
+// in the order the rights are written in a fen by to_fen
+const RIGHTS: [(Color, CastleSide, char); 4] = [
+    (Color::White, CastleSide::QueenSide, 'Q'),
+    (Color::Black, CastleSide::QueenSide, 'q'),
+    (Color::White, CastleSide::KingSide, 'K'),
+    (Color::Black, CastleSide::KingSide, 'k'),
+];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CastlingRights(u8);
+
+impl CastlingRights {
+    pub const NONE: CastlingRights = CastlingRights(0);
+    pub const ALL: CastlingRights = CastlingRights(0b1111);
+
+    // every combination, for tables indexed by bits()
+    pub const COUNT: usize = 16;
+
+    #[inline(always)]
+    const fn bit(color: Color, side: CastleSide) -> u8 {
+        match (color, side) {
+            (Color::Black, CastleSide::QueenSide) => 0b0001,
+            (Color::Black, CastleSide::KingSide) => 0b0010,
+            (Color::White, CastleSide::QueenSide) => 0b0100,
+            (Color::White, CastleSide::KingSide) => 0b1000,
+        }
+    }
+
+    // both sides for the color
+    pub const fn color(color: Color) -> CastlingRights {
+        CastlingRights(
+            CastlingRights::bit(color, CastleSide::KingSide) | CastlingRights::bit(color, CastleSide::QueenSide),
+        )
+    }
+
+    pub const fn only(color: Color, side: CastleSide) -> CastlingRights {
+        CastlingRights(CastlingRights::bit(color, side))
+    }
+
+    #[inline(always)]
+    pub fn bits(&self) -> u8 {
+        self.0
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    #[inline(always)]
+    pub fn allows(&self, color: Color, side: CastleSide) -> bool {
+        self.0 & CastlingRights::bit(color, side) != 0
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, color: Color, side: CastleSide) {
+        self.0 |= CastlingRights::bit(color, side);
+    }
+
+    #[inline(always)]
+    pub fn remove(&mut self, color: Color, side: CastleSide) {
+        self.0 &= !CastlingRights::bit(color, side);
+    }
+
+    #[inline(always)]
+    pub fn remove_color(&mut self, color: Color) {
+        self.0 &= !CastlingRights::color(color).0;
+    }
+
+    // the rights held, in the order they are written in a fen
+    pub fn iter(&self) -> impl Iterator<Item = (Color, CastleSide)> + '_ {
+        RIGHTS
+            .iter()
+            .filter(move |&&(color, side, _)| self.allows(color, side))
+            .map(|&(color, side, _)| (color, side))
+    }
+
+    // "-", or each right's letter once, in any order
+    pub fn from_fen(field: &str) -> Result<CastlingRights, FenError> {
+        if field == "-" {
+            return Ok(CastlingRights::NONE);
+        }
+        if field.is_empty() {
+            return Err(FenError::BadCastlingRights);
+        }
+
+        let mut rights = CastlingRights::NONE;
+        for c in field.chars() {
+            let &(color, side, _) = RIGHTS
+                .iter()
+                .find(|&&(_, _, letter)| letter == c)
+                .ok_or(FenError::BadCastlingRights)?;
+            if rights.allows(color, side) {
+                return Err(FenError::BadCastlingRights);
+            }
+            rights.add(color, side);
+        }
+        Ok(rights)
+    }
+}
+
+// as the castling field of a fen
+impl Display for CastlingRights {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.is_empty() {
+            return write!(f, "-");
+        }
+        for &(color, side, letter) in RIGHTS.iter() {
+            if self.allows(color, side) {
+                write!(f, "{}", letter)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_combination() {
+        for bits in 0..CastlingRights::COUNT as u8 {
+            let rights = CastlingRights(bits);
+            let field = rights.to_string();
+            assert_eq!(CastlingRights::from_fen(&field), Ok(rights), "{}", field);
+            assert_eq!(rights.iter().count(), bits.count_ones() as usize);
+
+            // the letters may come in any order
+            let reversed: String = field.chars().rev().collect();
+            assert_eq!(CastlingRights::from_fen(&reversed), Ok(rights), "{}", reversed);
+        }
+
+        assert_eq!(CastlingRights::from_fen("KQkq"), Ok(CastlingRights::ALL));
+        assert_eq!(CastlingRights::ALL.to_string(), "QqKk");
+        assert_eq!(CastlingRights::NONE.to_string(), "-");
+    }
+
+    #[test]
+    fn malformed() {
+        for field in ["", "K-q", "-K", "K-", "--", "KK", "KQkqK", "qq", "x", "KQkx", "kq-"] {
+            assert_eq!(
+                CastlingRights::from_fen(field),
+                Err(FenError::BadCastlingRights),
+                "'{}'",
+                field
+            );
+        }
+    }
+
+    #[test]
+    fn allows_and_remove() {
+        let mut rights = CastlingRights::ALL;
+        rights.remove(Color::White, CastleSide::KingSide);
+        assert!(!rights.allows(Color::White, CastleSide::KingSide));
+        assert!(rights.allows(Color::White, CastleSide::QueenSide));
+
+        rights.remove_color(Color::Black);
+        assert_eq!(rights.iter().collect::<Vec<_>>(), [(Color::White, CastleSide::QueenSide)]);
+        assert_eq!(rights, CastlingRights::only(Color::White, CastleSide::QueenSide));
+        assert_eq!(rights.to_string(), "Q");
+    }
+}
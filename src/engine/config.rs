@@ -0,0 +1,417 @@
+// Engine options read from a file at startup, so they don't have to be set
+// from a GUI every time. Options set with setoption still override them.
+//
+// The format is a small part of TOML: `key = value` lines, where a value is
+// a quoted string, an integer or true/false, and `#` starts a comment.
+//
+//     move_overhead = 50
+//     syzygy_path = "/home/me/syzygy"
+//
+// Unknown keys are warned about rather than rejected, so a file can be
+// shared with newer versions of the engine.
+
+use std::fmt::Write;
+
+use crate::engine::error::ConfigError;
+use crate::engine::search;
+use crate::engine::timeman;
+use crate::engine::tt;
+
+// looked for next to the executable when no file is given
+pub const DEFAULT_FILE_NAME: &str = "chess_rs.toml";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Kind {
+    Bool,
+    Integer { min: i64, max: i64 },
+    String,
+}
+
+impl Kind {
+    fn expected(&self) -> String {
+        match self {
+            Kind::Bool => "true or false".to_string(),
+            Kind::Integer { min, max } => format!("an integer from {} to {}", min, max),
+            Kind::String => "a quoted string".to_string(),
+        }
+    }
+}
+
+// a key which can be set in the file, and the uci option it sets
+struct Key {
+    name: &'static str,
+    option: &'static str,
+    kind: Kind,
+    default: &'static str,
+    about: &'static str,
+}
+
+const KEYS: &[Key] = &[
+    Key {
+        name: "show_wdl",
+        option: "UCI_ShowWDL",
+        kind: Kind::Bool,
+        default: "false",
+        about: "show win/draw/loss chances with each score",
+    },
+    Key {
+        name: "show_refutations",
+        option: "UCI_ShowRefutations",
+        kind: Kind::Bool,
+        default: "false",
+        about: "show the lines refuting each worse root move",
+    },
+    Key {
+        name: "show_currline",
+        option: "UCI_ShowCurrLine",
+        kind: Kind::Bool,
+        default: "false",
+        about: "show the line being searched every second",
+    },
+    Key {
+        name: "move_overhead",
+        option: "Move Overhead",
+        kind: Kind::Integer {
+            min: 0,
+            max: timeman::MAX_MOVE_OVERHEAD as i64,
+        },
+        default: "30",
+        about: "milliseconds kept back on every move for network and GUI lag",
+    },
+    Key {
+        name: "hash",
+        option: "Hash",
+        kind: Kind::Integer {
+            min: 1,
+            max: tt::MAX_HASH_MB as i64,
+        },
+        default: "64",
+        about: "megabytes for the transposition table",
+    },
+    Key {
+        name: "threads",
+        option: "Threads",
+        kind: Kind::Integer {
+            min: 1,
+            max: search::MAX_THREADS as i64,
+        },
+        default: "1",
+        about: "threads to search with",
+    },
+    Key {
+        name: "multi_pv",
+        option: "MultiPV",
+        kind: Kind::Integer {
+            min: 1,
+            max: search::MAX_MULTI_PV as i64,
+        },
+        default: "1",
+        about: "best lines to show after each iteration",
+    },
+    Key {
+        name: "overshoot_margin",
+        option: "Overshoot Margin",
+        kind: Kind::Integer {
+            min: 0,
+            max: timeman::MAX_OVERSHOOT_MARGIN as i64,
+        },
+        default: "10",
+        about: "milliseconds a search can run past its limit before it is reported",
+    },
+    Key {
+        name: "syzygy_path",
+        option: "SyzygyPath",
+        kind: Kind::String,
+        default: "\"\"",
+        about: "directories holding syzygy tablebases, separated by ':' (';' on windows)",
+    },
+    Key {
+        name: "log_file",
+        option: "Debug Log File",
+        kind: Kind::String,
+        default: "\"\"",
+        about: "log everything sent and received to this file",
+    },
+    Key {
+        name: "eval_file",
+        option: "EvalFile",
+        kind: Kind::String,
+        default: "\"\"",
+        about: "neural network to evaluate with, for builds with the nnue feature",
+    },
+];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Config {
+    // uci option names and values, in the order they appear in the file
+    options: Vec<(String, String)>,
+    warnings: Vec<String>,
+}
+
+impl Config {
+    pub fn load(path: &str) -> Result<Config, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Config::parse(&text),
+            Err(e) => Err(ConfigError::Io(path.to_string(), e.to_string())),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        let mut seen: Vec<&str> = Vec::new();
+
+        for (idx, line) in text.lines().enumerate() {
+            let line_number = idx + 1;
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (name, value) = match line.split_once('=') {
+                Some((name, value)) if !name.trim().is_empty() => (name.trim(), value.trim()),
+                _ => return Err(ConfigError::Syntax(line_number, line.to_string())),
+            };
+
+            if seen.contains(&name) {
+                return Err(ConfigError::DuplicateKey(line_number, name.to_string()));
+            }
+            seen.push(name);
+
+            let key = match KEYS.iter().find(|key| key.name == name) {
+                Some(key) => key,
+                None => {
+                    config.warnings.push(format!(
+                        "line {}: unknown key '{}' ignored",
+                        line_number, name
+                    ));
+                    continue;
+                }
+            };
+
+            match parse_value(value, key.kind) {
+                Some(value) => config.options.push((key.option.to_string(), value)),
+                None => {
+                    return Err(ConfigError::InvalidValue {
+                        line: line_number,
+                        key: name.to_string(),
+                        expected: key.kind.expected(),
+                    })
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    // the uci options to set, and their values
+    pub fn options(&self) -> impl Iterator<Item = (&str, &str)> + '_ {
+        self.options
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+
+    pub fn warnings(&self) -> &[String] {
+        &self.warnings
+    }
+}
+
+// a file with every key commented out at its default, for `chess_rs config
+// --write-default`
+pub fn default_template() -> String {
+    let mut template = String::new();
+    template
+        .push_str("# chess_rs settings, read at startup. Options set from a GUI override them.\n");
+    template.push_str("# Uncomment a line to change it.\n");
+
+    for key in KEYS {
+        let _ = write!(
+            template,
+            "\n# {} ({}, uci option '{}')\n# {} = {}\n",
+            key.about,
+            key.kind.expected(),
+            key.option,
+            key.name,
+            key.default
+        );
+    }
+
+    template
+}
+
+// everything before a # which isn't in a string
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (idx, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..idx],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+// the value as the uci option expects it, or None if it isn't of the kind
+fn parse_value(value: &str, kind: Kind) -> Option<String> {
+    match kind {
+        Kind::Bool => match value {
+            "true" | "false" => Some(value.to_string()),
+            _ => None,
+        },
+        Kind::Integer { min, max } => match value.parse::<i64>() {
+            Ok(n) if (min..=max).contains(&n) => Some(n.to_string()),
+            _ => None,
+        },
+        Kind::String => parse_string(value),
+    }
+}
+
+// a double quoted string with \" and \\ escapes
+fn parse_string(value: &str) -> Option<String> {
+    let inner = value.strip_prefix('"')?.strip_suffix('"')?;
+
+    let mut result = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c @ ('"' | '\\') => result.push(c),
+                _ => return None,
+            },
+            '"' => return None,
+            c => result.push(c),
+        }
+    }
+
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(text: &str) -> Vec<(String, String)> {
+        Config::parse(text).unwrap().options
+    }
+
+    #[test]
+    fn values() {
+        let text = "
+            # a comment
+            show_wdl = true
+            move_overhead = 250   # trailing comment
+            syzygy_path = \"/tb/a b # c:/tb/\\\"d\\\"\"
+        ";
+        assert_eq!(
+            options(text),
+            vec![
+                ("UCI_ShowWDL".to_string(), "true".to_string()),
+                ("Move Overhead".to_string(), "250".to_string()),
+                (
+                    "SyzygyPath".to_string(),
+                    "/tb/a b # c:/tb/\"d\"".to_string()
+                ),
+            ]
+        );
+        assert_eq!(options(""), vec![]);
+    }
+
+    #[test]
+    fn unknown_keys_warn() {
+        let config = Config::parse("ponder = true\nshow_wdl = false\ncontempt = \"lots\"\n").unwrap();
+        assert_eq!(config.options().count(), 1);
+        assert_eq!(
+            config.warnings(),
+            [
+                "line 1: unknown key 'ponder' ignored",
+                "line 3: unknown key 'contempt' ignored"
+            ]
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let error = |text: &str| Config::parse(text).unwrap_err();
+
+        assert_eq!(
+            error("show_wdl = true\n\nmove_overhead = \"30\"\n"),
+            ConfigError::InvalidValue {
+                line: 3,
+                key: "move_overhead".to_string(),
+                expected: format!("an integer from 0 to {}", timeman::MAX_MOVE_OVERHEAD),
+            }
+        );
+        assert!(matches!(
+            error("move_overhead = -1"),
+            ConfigError::InvalidValue { line: 1, .. }
+        ));
+        assert!(matches!(
+            error("show_wdl = yes"),
+            ConfigError::InvalidValue { line: 1, .. }
+        ));
+        assert!(matches!(
+            error("syzygy_path = /tb"),
+            ConfigError::InvalidValue { line: 1, .. }
+        ));
+        assert!(matches!(
+            error("syzygy_path = \"a\" \"b\""),
+            ConfigError::InvalidValue { line: 1, .. }
+        ));
+
+        assert_eq!(
+            error("\nshow_wdl\n"),
+            ConfigError::Syntax(2, "show_wdl".to_string())
+        );
+        assert_eq!(
+            error("= true"),
+            ConfigError::Syntax(1, "= true".to_string())
+        );
+        assert_eq!(
+            error("show_wdl = true\nshow_wdl = false"),
+            ConfigError::DuplicateKey(2, "show_wdl".to_string())
+        );
+
+        // the message says where the problem is
+        let message = error("# settings\nshow_wdl = 1").to_string();
+        assert!(
+            message.contains("line 2") && message.contains("show_wdl"),
+            "{}",
+            message
+        );
+    }
+
+    #[test]
+    fn missing_file() {
+        let path = std::env::temp_dir().join("chess_rs_config_missing.toml");
+        let path = path.to_str().unwrap();
+
+        assert!(matches!(Config::load(path), Err(ConfigError::Io(p, _)) if p == path));
+    }
+
+    #[test]
+    fn template() {
+        // everything is commented out
+        let template = default_template();
+        assert_eq!(Config::parse(&template).unwrap(), Config::default());
+
+        // and uncommenting it sets every option to its default
+        let uncommented: String = template
+            .lines()
+            .filter_map(|line| line.strip_prefix("# "))
+            .filter(|line| line.contains(" = "))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let config = Config::parse(&uncommented).unwrap();
+        assert!(config.warnings().is_empty());
+        assert_eq!(config.options().count(), KEYS.len());
+        let overhead = timeman::DEFAULT_MOVE_OVERHEAD.to_string();
+        assert!(config
+            .options()
+            .any(|option| option == ("Move Overhead", overhead.as_str())));
+    }
+}
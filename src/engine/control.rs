@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::engine::r#move::Move;
+use crate::engine::search::{IterationStats, PvLine};
+
+pub enum ProgressEvent {
+    // a root move of a perft has been fully counted
+    PerftRootMove { root_move: Move, nodes: u64 },
+    // an iteration of iterative deepening has completed
+    SearchIteration {
+        depth: usize,
+        best_move: Move,
+        score: i32,
+        millis: usize,
+        // the total for the search so far
+        nodes: u64,
+        // the best line, starting with best_move
+        pv: Vec<Move>,
+        // the best lines for MultiPV, best first, the first of them pv
+        lines: Vec<PvLine>,
+        // how well the moves were ordered
+        stats: IterationStats,
+    },
+    // a root move which is worse than the best, and the line refuting it
+    Refutation { refuted: Move, line: Vec<Move> },
+    // the moves from the root to the position being searched
+    CurrentLine { line: Vec<Move> },
+}
+
+type ProgressCallback = Box<dyn FnMut(ProgressEvent) + Send>;
+
+// Shared cancellation flag plus an optional progress callback for long
+// running operations (perft, search). The stop flag can be cloned out and
+// set from another thread.
+#[derive(Default)]
+pub struct ControlHandle {
+    stop: Arc<AtomicBool>,
+    // set while a search is pondering, when its clock hasn't started
+    ponder: Option<Arc<AtomicBool>>,
+    progress: Option<ProgressCallback>,
+}
+
+impl ControlHandle {
+    pub fn new() -> ControlHandle {
+        ControlHandle::default()
+    }
+
+    pub fn with_stop_flag(stop: Arc<AtomicBool>) -> ControlHandle {
+        ControlHandle {
+            stop,
+            ponder: None,
+            progress: None,
+        }
+    }
+
+    // the search's clock starts once the flag is cleared, as by ponderhit
+    pub fn with_ponder_flag(mut self, ponder: Arc<AtomicBool>) -> Self {
+        self.ponder = Some(ponder);
+        self
+    }
+
+    pub fn with_progress(mut self, progress: impl FnMut(ProgressEvent) + Send + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.stop)
+    }
+
+    pub fn ponder_flag(&self) -> Option<Arc<AtomicBool>> {
+        self.ponder.clone()
+    }
+
+    #[inline(always)]
+    pub fn stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+
+    #[inline(always)]
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn reset(&self) {
+        self.stop.store(false, Ordering::Relaxed);
+    }
+
+    pub fn report(&mut self, event: ProgressEvent) {
+        if let Some(progress) = &mut self.progress {
+            progress(event);
+        }
+    }
+}
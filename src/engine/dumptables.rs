@@ -0,0 +1,298 @@
+// `chess_rs dump-tables`: prints the move generator's tables, for checking a
+// port to another language or a change to how the tables are made. With
+// --piece and --square it prints that square's mask, magic, shift and
+// attacks, and with --checksum a hash of every table, which is the same for
+// any build whose tables are the same.
+
+use std::io::Write;
+
+use crate::engine::bitboard::BitBoardUtils;
+use crate::engine::error::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use crate::engine::movegen::MoveGenerator;
+use crate::engine::piece::Color;
+use crate::engine::square::Square;
+
+const USAGE: &str = "usage: chess_rs dump-tables (--piece <piece> --square <square> [--occupancy <hex>] | --checksum)";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TablePiece {
+    Pawn,
+    Knight,
+    Bishop,
+    Rook,
+    Queen,
+    King,
+}
+
+impl TablePiece {
+    pub fn from_name(name: &str) -> Option<TablePiece> {
+        match name {
+            "pawn" => Some(TablePiece::Pawn),
+            "knight" => Some(TablePiece::Knight),
+            "bishop" => Some(TablePiece::Bishop),
+            "rook" => Some(TablePiece::Rook),
+            "queen" => Some(TablePiece::Queen),
+            "king" => Some(TablePiece::King),
+            _ => None,
+        }
+    }
+}
+
+// 64 bit FNV-1a, which unlike the std hashers is the same in every build
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, value: u64) {
+        for byte in value.to_le_bytes().iter() {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+fn checksum<I: IntoIterator<Item = u64>>(values: I) -> u64 {
+    let mut hash = Fnv::new();
+    for value in values {
+        hash.write(value);
+    }
+    hash.0
+}
+
+// a checksum of each table by name, in a fixed order
+pub fn table_checksums(move_generator: &MoveGenerator) -> Vec<(&'static str, u64)> {
+    let attacks = move_generator.attacks();
+    let words = |table: &[usize]| checksum(table.iter().map(|&x| x as u64));
+
+    vec![
+        ("rook_masks", checksum(attacks.rook_masks().iter().copied())),
+        ("bishop_masks", checksum(attacks.bishop_masks().iter().copied())),
+        ("rook_magics", checksum(attacks.rook_magics().iter().copied())),
+        ("bishop_magics", checksum(attacks.bishop_magics().iter().copied())),
+        ("rook_magic_shifts", words(attacks.rook_magic_shifts())),
+        ("bishop_magic_shifts", words(attacks.bishop_magic_shifts())),
+        ("rook_offsets", words(attacks.rook_offsets())),
+        ("bishop_offsets", words(attacks.bishop_offsets())),
+        ("rook_moves", checksum(attacks.rook_table().iter().copied())),
+        ("bishop_moves", checksum(attacks.bishop_table().iter().copied())),
+        ("slider_range", checksum(attacks.slider_ranges().iter().flatten().copied())),
+        ("knight_attacks", checksum((0..64).map(|sq| attacks.knight_attacks(sq)))),
+        ("king_attacks", checksum((0..64).map(|sq| attacks.king_attacks(sq)))),
+        ("black_pawn_attacks", checksum((0..64).map(|sq| attacks.pawn_attacks(Color::Black, sq)))),
+        ("white_pawn_attacks", checksum((0..64).map(|sq| attacks.pawn_attacks(Color::White, sq)))),
+        ("ranks", checksum(move_generator.ranks().iter().copied())),
+        ("files", checksum(move_generator.files().iter().copied())),
+        ("not_ranks", checksum(move_generator.not_ranks().iter().copied())),
+        ("not_files", checksum(move_generator.not_files().iter().copied())),
+    ]
+}
+
+// one checksum covering every table
+pub fn tables_checksum(move_generator: &MoveGenerator) -> u64 {
+    checksum(table_checksums(move_generator).into_iter().map(|(_, hash)| hash))
+}
+
+pub fn checksum_text(move_generator: &MoveGenerator) -> String {
+    let mut text = String::new();
+    for (name, hash) in table_checksums(move_generator) {
+        text += &format!("{:<20} {:016x}\n", name, hash);
+    }
+    text + &format!("{:<20} {:016x}\n", "total", tables_checksum(move_generator))
+}
+
+fn bitboard(name: &str, bb: u64) -> String {
+    format!("{}: {:016x}\n{}\n", name, bb, bb.bb_to_string())
+}
+
+// The tables for a piece on a square, and the squares it attacks with the
+// occupancy given
+pub fn square_text(move_generator: &MoveGenerator, piece: TablePiece, sq: Square, occupancy: u64) -> String {
+    let attacks = move_generator.attacks();
+    let sq_idx = sq.sq();
+    let mut text = String::new();
+
+    let magic = match piece {
+        TablePiece::Rook => Some((
+            attacks.rook_masks()[sq_idx],
+            attacks.rook_magics()[sq_idx],
+            attacks.rook_magic_shifts()[sq_idx],
+            attacks.rook_offsets()[sq_idx],
+        )),
+        TablePiece::Bishop => Some((
+            attacks.bishop_masks()[sq_idx],
+            attacks.bishop_magics()[sq_idx],
+            attacks.bishop_magic_shifts()[sq_idx],
+            attacks.bishop_offsets()[sq_idx],
+        )),
+        _ => None,
+    };
+    if let Some((mask, magic, shift, offset)) = magic {
+        text += &format!("magic: {:016x}\nshift: {}\noffset: {}\n", magic, shift, offset);
+        text += &bitboard("mask", mask);
+    }
+
+    match piece {
+        TablePiece::Pawn => {
+            text += &bitboard("white attacks", attacks.pawn_attacks(Color::White, sq_idx));
+            text += &bitboard("black attacks", attacks.pawn_attacks(Color::Black, sq_idx));
+        }
+        TablePiece::Knight => text += &bitboard("attacks", attacks.knight_attacks(sq_idx)),
+        TablePiece::King => text += &bitboard("attacks", attacks.king_attacks(sq_idx)),
+        TablePiece::Bishop | TablePiece::Rook | TablePiece::Queen => {
+            let slider = |occupancy| match piece {
+                TablePiece::Bishop => attacks.bishop_attacks(sq_idx, occupancy),
+                TablePiece::Rook => attacks.rook_attacks(sq_idx, occupancy),
+                _ => attacks.queen_attacks(sq_idx, occupancy),
+            };
+            text += &bitboard("attacks on an empty board", slider(0));
+            if occupancy != 0 {
+                text += &bitboard("occupancy", occupancy);
+                text += &bitboard("attacks", slider(occupancy));
+            }
+        }
+    }
+
+    text
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text.trim_start_matches("0x"), 16).ok()
+}
+
+// args are those after `dump-tables`, the return value is the exit code
+pub fn dump_tables_command(args: &[String], output: &mut impl Write) -> i32 {
+    let mut piece = None;
+    let mut sq = None;
+    let mut occupancy = 0;
+    let mut checksum = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--piece" => match args.next().and_then(|name| TablePiece::from_name(name)) {
+                Some(name) => piece = Some(name),
+                None => {
+                    let _ = writeln!(output, "{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--square" => match args.next() {
+                Some(name) => match Square::from_notation(name) {
+                    Some(name) => sq = Some(name),
+                    None => {
+                        let _ = writeln!(output, "error: invalid square {}", name);
+                        return EXIT_INVALID;
+                    }
+                },
+                None => {
+                    let _ = writeln!(output, "{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--occupancy" => match args.next().and_then(|bb| parse_hex(bb)) {
+                Some(bb) => occupancy = bb,
+                None => {
+                    let _ = writeln!(output, "{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "--checksum" => checksum = true,
+            "-h" | "--help" => {
+                let _ = writeln!(output, "{}", USAGE);
+                return EXIT_OK;
+            }
+            _ => {
+                let _ = writeln!(output, "{}", USAGE);
+                return EXIT_USAGE;
+            }
+        }
+    }
+
+    let move_generator = MoveGenerator::new();
+    match (piece, sq, checksum) {
+        (None, None, true) => {
+            let _ = write!(output, "{}", checksum_text(&move_generator));
+        }
+        (Some(piece), Some(sq), false) => {
+            let _ = write!(output, "{}", square_text(&move_generator, piece, sq, occupancy));
+        }
+        _ => {
+            let _ = writeln!(output, "{}", USAGE);
+            return EXIT_USAGE;
+        }
+    }
+
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str]) -> (i32, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut output = Vec::new();
+        let code = dump_tables_command(&args, &mut output);
+        (code, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn checksums_stable() {
+        let first = MoveGenerator::new();
+        let second = MoveGenerator::new();
+
+        assert_eq!(table_checksums(&first), table_checksums(&second));
+        assert_eq!(checksum_text(&first), checksum_text(&second));
+
+        // every table is covered, and they are not all the same
+        let checksums = table_checksums(&first);
+        assert_eq!(checksums.len(), 19);
+        assert!(checksums.iter().any(|&(_, hash)| hash != checksums[0].1));
+
+        let (code, output) = run(&["--checksum"]);
+        assert_eq!(code, EXIT_OK);
+        assert!(output.ends_with(&format!("{:016x}\n", tables_checksum(&first))), "{}", output);
+    }
+
+    #[test]
+    fn rook_attacks_diagram() {
+        // a rook on e4 blocked by pieces on e6, c4 and e2, and by nothing
+        // towards the h file
+        let occupancy = (1u64 << Square::E6.sq()) | (1 << Square::C4.sq()) | (1 << Square::E2.sq());
+        let text = square_text(&MoveGenerator::new(), TablePiece::Rook, Square::E4, occupancy);
+
+        let diagram = "\
+attacks: 001010ec10100000
+    a b c d e f g h
+  ╭─────────────────╮
+8 │ . . . . . . . . │ 8
+7 │ . . . . . . . . │ 7
+6 │ . . . . x . . . │ 6
+5 │ . . . . x . . . │ 5
+4 │ . . x x . x x x │ 4
+3 │ . . . . x . . . │ 3
+2 │ . . . . x . . . │ 2
+1 │ . . . . . . . . │ 1
+  ╰─────────────────╯
+    a b c d e f g h
+";
+        assert!(text.ends_with(diagram), "{}", text);
+        assert!(text.starts_with("magic: "), "{}", text);
+    }
+
+    #[test]
+    fn usage() {
+        assert_eq!(run(&[]).0, EXIT_USAGE);
+        assert_eq!(run(&["--piece", "rook"]).0, EXIT_USAGE);
+        assert_eq!(run(&["--piece", "archbishop", "--square", "e4"]).0, EXIT_USAGE);
+        assert_eq!(run(&["--piece", "rook", "--square", "e9"]).0, EXIT_INVALID);
+        assert_eq!(run(&["--piece", "knight", "--square", "a1", "--checksum"]).0, EXIT_USAGE);
+
+        let (code, output) = run(&["--piece", "knight", "--square", "a1"]);
+        assert_eq!(code, EXIT_OK);
+        assert_eq!(output.matches('x').count(), 2, "{}", output);
+    }
+}
@@ -0,0 +1,153 @@
+// Names the opening a game is in, from a table of common lines and their
+// ECO (Encyclopaedia of Chess Openings) codes in eco.tsv. Each line is
+// played out once, the first time the table is needed, and the position it
+// reaches is keyed by Board::opening_key, so a game which transposes into a
+// line is recognised as well as one which follows it move for move.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result};
+use std::sync::OnceLock;
+
+use crate::engine::board::Board;
+use crate::engine::movegen::MoveGenerator;
+use crate::engine::r#move::UndoInfo;
+
+const ECO_TSV: &str = include_str!("eco.tsv");
+
+// a volume letter from A to E and a number from 00 to 99, as in C65
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EcoCode {
+    volume: u8,
+    number: u8,
+}
+
+impl EcoCode {
+    pub fn parse(code: &str) -> Option<EcoCode> {
+        let bytes = code.as_bytes();
+        match bytes {
+            &[volume @ b'A'..=b'E', tens @ b'0'..=b'9', units @ b'0'..=b'9'] => Some(EcoCode {
+                volume,
+                number: (tens - b'0') * 10 + (units - b'0'),
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Display for EcoCode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(f, "{}{:02}", self.volume as char, self.number)
+    }
+}
+
+struct Opening {
+    eco: EcoCode,
+    name: &'static str,
+    // the length of the line, so the deepest of several matches wins
+    plies: usize,
+}
+
+// The openings by the key of the position their line reaches. Where two
+// lines reach the same position the first in the file is kept.
+fn table() -> &'static HashMap<u64, Opening> {
+    static TABLE: OnceLock<HashMap<u64, Opening>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let move_generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        let mut table = HashMap::new();
+
+        for (number, line) in ECO_TSV.lines().enumerate() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let (eco, name, moves) = match fields[..] {
+                [eco, name, moves] => (EcoCode::parse(eco), name, moves),
+                _ => panic!("eco.tsv:{}: expected three tab separated fields", number + 1),
+            };
+            let eco = eco.unwrap_or_else(|| panic!("eco.tsv:{}: invalid ECO code", number + 1));
+
+            let mut board = Board::default();
+            let mut plies = 0;
+            for uci_move in moves.split_whitespace() {
+                if let Err(e) = board.make_uci_move(uci_move, &move_generator, &mut info) {
+                    panic!("eco.tsv:{}: {}", number + 1, e);
+                }
+                plies += 1;
+            }
+            table.entry(board.opening_key()).or_insert(Opening { eco, name, plies });
+        }
+        table
+    })
+}
+
+// The opening of the deepest line any of the positions reached, given
+// their opening keys in the order they were played. None if the game never
+// reached a position in the table.
+pub fn identify(history: &[u64]) -> Option<(EcoCode, &'static str)> {
+    let table = table();
+    history
+        .iter()
+        .filter_map(|key| table.get(key))
+        .max_by_key(|opening| opening.plies)
+        .map(|opening| (opening.eco, opening.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the opening keys of the starting position and after each move
+    fn keys(moves: &str) -> Vec<u64> {
+        let move_generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        let mut board = Board::default();
+
+        let mut keys = vec![board.opening_key()];
+        for uci_move in moves.split_whitespace() {
+            board.make_uci_move(uci_move, &move_generator, &mut info).unwrap();
+            keys.push(board.opening_key());
+        }
+        keys
+    }
+
+    fn opening(moves: &str) -> Option<(String, &'static str)> {
+        Board::identify_opening(&keys(moves)).map(|(eco, name)| (eco.to_string(), name))
+    }
+
+    #[test]
+    fn table_is_valid() {
+        // every line parses and is legal
+        let lines = ECO_TSV.lines().filter(|line| !line.is_empty() && !line.starts_with('#')).count();
+        assert!(table().len() > 50 && table().len() <= lines);
+    }
+
+    #[test]
+    fn named_openings() {
+        assert_eq!(opening("e2e4 e7e5 g1f3 b8c6 f1b5"), Some(("C60".to_string(), "Ruy Lopez")));
+        assert_eq!(
+            opening("e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6"),
+            Some(("B90".to_string(), "Sicilian Defence, Najdorf Variation"))
+        );
+
+        // the deepest line is named after leaving the table
+        assert_eq!(opening("e2e4 e7e5 g1f3 b8c6 f1b5 g8f6 d2d3 f8c5").unwrap().0, "C65");
+
+        // transpositions, including one which ends with a different double
+        // pawn push and so a different en passant square
+        assert_eq!(opening("g1f3 c7c5 e2e4").unwrap().0, "B27");
+        assert_eq!(opening("c2c4 g8f6 d2d4 e7e6 b1c3 f8b4").unwrap().0, "E20");
+    }
+
+    #[test]
+    fn unknown_positions() {
+        assert_eq!(opening(""), None);
+        assert_eq!(opening("h2h3 a7a6"), None);
+
+        let board = Board::new("8/5k2/8/3p4/8/2K5/8/8 w - - 0 1").unwrap();
+        assert_eq!(Board::identify_opening(&[board.opening_key()]), None);
+        assert_eq!(EcoCode::parse("F00"), None);
+        assert_eq!(EcoCode::parse("C5"), None);
+        assert_eq!(EcoCode::parse("C05").unwrap().to_string(), "C05");
+    }
+}
@@ -0,0 +1,468 @@
+use std::fmt::{Display, Formatter, Result};
+
+use crate::engine::piece::Color;
+
+// the exit codes of the command line tools: success, some of the input was
+// invalid, or the arguments were
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_INVALID: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum FenError {
+    WrongFieldCount(usize),
+    // fewer than six fields, when every field is required
+    MissingCounters,
+    InvalidPieceChar(char),
+    BadSideToMove,
+    BadCastlingRights,
+    BadEnPassantSquare,
+    BadCounter,
+    TooFewSquares,
+    TooManySquares,
+}
+
+impl Display for FenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            FenError::WrongFieldCount(count) => {
+                write!(f, "expected 4 to 6 whitespace delimited fields, found {}", count)
+            }
+            FenError::MissingCounters => write!(f, "missing halfmove or fullmove counter"),
+            FenError::InvalidPieceChar(c) => write!(f, "unrecognised character '{}' in board", c),
+            FenError::BadSideToMove => write!(f, "expected w/b for current player"),
+            FenError::BadCastlingRights => write!(f, "invalid castling rights"),
+            FenError::BadEnPassantSquare => write!(f, "invalid en passant square"),
+            FenError::BadCounter => write!(f, "invalid halfmove or fullmove counter"),
+            FenError::TooFewSquares => write!(f, "expected 64 squares, found fewer"),
+            FenError::TooManySquares => write!(f, "expected 64 squares, found more"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MoveError {
+    // the string is not valid coordinate notation
+    InvalidNotation(String),
+    // the move is well formed but not legal in the position
+    IllegalMove(String),
+}
+
+impl Display for MoveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            MoveError::InvalidNotation(s) => write!(f, "invalid move notation '{}'", s),
+            MoveError::IllegalMove(s) => write!(f, "illegal move '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for MoveError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SanError {
+    InvalidSyntax(String),
+    NoMatchingMove(String),
+    AmbiguousMove(String),
+}
+
+impl Display for SanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            SanError::InvalidSyntax(s) => write!(f, "invalid SAN '{}'", s),
+            SanError::NoMatchingMove(s) => write!(f, "no legal move matches '{}'", s),
+            SanError::AmbiguousMove(s) => write!(f, "ambiguous SAN '{}'", s),
+        }
+    }
+}
+
+impl std::error::Error for SanError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SearchError {
+    InvalidDepth(usize),
+    InvalidTime(String),
+    InvalidMovesToGo(String),
+    InvalidNodes(String),
+    InvalidMate(String),
+    InvalidPlayouts(String),
+    NoLegalMoves,
+}
+
+impl Display for SearchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            SearchError::InvalidDepth(depth) => write!(f, "invalid search depth {}", depth),
+            SearchError::InvalidTime(s) => write!(f, "invalid time '{}'", s),
+            SearchError::InvalidMovesToGo(s) => write!(f, "invalid movestogo '{}'", s),
+            SearchError::InvalidNodes(s) => write!(f, "invalid node limit '{}'", s),
+            SearchError::InvalidMate(s) => write!(f, "invalid mate '{}'", s),
+            SearchError::InvalidPlayouts(s) => write!(f, "invalid playouts '{}'", s),
+            SearchError::NoLegalMoves => write!(f, "no legal moves in the position"),
+        }
+    }
+}
+
+impl std::error::Error for SearchError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ConfigError {
+    // the path and why it couldn't be read
+    Io(String, String),
+    // a line which isn't `key = value`, by line number
+    Syntax(usize, String),
+    DuplicateKey(usize, String),
+    InvalidValue {
+        line: usize,
+        key: String,
+        expected: String,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "can't read '{}': {}", path, e),
+            ConfigError::Syntax(line, s) => write!(f, "line {}: expected 'key = value', found '{}'", line, s),
+            ConfigError::DuplicateKey(line, key) => write!(f, "line {}: '{}' is already set", line, key),
+            ConfigError::InvalidValue { line, key, expected } => {
+                write!(f, "line {}: '{}' should be {}", line, key, expected)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptError {
+    // the path and why it couldn't be read
+    Io(String, String),
+    // a sleep without a number of milliseconds, by line number
+    InvalidSleep(usize, String),
+    // a go infinite which would be waited for, and so never finish
+    InfiniteWait(usize),
+    // an analysis queue line without a position before its ';'
+    MissingPosition(usize),
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            ScriptError::Io(path, e) => write!(f, "can't read '{}': {}", path, e),
+            ScriptError::InvalidSleep(line, s) => write!(f, "line {}: expected 'sleep <ms>', found '{}'", line, s),
+            ScriptError::InfiniteWait(line) => {
+                write!(f, "line {}: an infinite or ponder search never finishes by itself, add nowait", line)
+            }
+            ScriptError::MissingPosition(line) => write!(f, "line {}: expected '<fen or startpos> ; <go limits>'", line),
+        }
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TtError {
+    // the path and why it couldn't be read or written
+    Io(String, String),
+    // not a saved table, or one this version can't read
+    BadFile(String),
+    // saved by an engine hashing positions differently, so none of the
+    // entries would be found
+    ZobristMismatch(String),
+}
+
+impl Display for TtError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            TtError::Io(path, e) => write!(f, "can't access '{}': {}", path, e),
+            TtError::BadFile(path) => write!(f, "'{}' is not a saved hash table", path),
+            TtError::ZobristMismatch(path) => write!(f, "'{}' was saved with different zobrist keys", path),
+        }
+    }
+}
+
+impl std::error::Error for TtError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SyzygyError {
+    // the path and why it couldn't be read
+    Io(String, String),
+    // not a WDL table, or one for other material than its name says
+    BadFile(String),
+}
+
+impl Display for SyzygyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            SyzygyError::Io(path, e) => write!(f, "can't access '{}': {}", path, e),
+            SyzygyError::BadFile(path) => write!(f, "'{}' is not a valid tablebase file", path),
+        }
+    }
+}
+
+impl std::error::Error for SyzygyError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OptionError {
+    // not one of the engine's uci options
+    Unknown(String),
+    // the option and a value it can't take
+    Invalid(String, String),
+}
+
+impl Display for OptionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            OptionError::Unknown(name) => write!(f, "unknown option '{}'", name),
+            OptionError::Invalid(name, value) => write!(f, "invalid {} '{}'", name, value),
+        }
+    }
+}
+
+impl std::error::Error for OptionError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PackError {
+    // a move which isn't legal at this ply, when encoding
+    IllegalMove(usize),
+    // a move index past the end of the legal moves at this ply (of which
+    // there are the last number)
+    BadIndex(usize, usize, usize),
+    // the stream ended in the middle of a move or header
+    Truncated,
+    // not a packed game, or one this version can't read
+    BadHeader,
+    // the header gives fewer moves than the stream holds
+    TrailingBytes(usize),
+}
+
+impl Display for PackError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            PackError::IllegalMove(ply) => write!(f, "ply {}: the move is not legal", ply),
+            PackError::BadIndex(ply, index, count) => {
+                write!(f, "ply {}: move index {} with only {} legal moves", ply, index, count)
+            }
+            PackError::Truncated => write!(f, "the stream ends part way through"),
+            PackError::BadHeader => write!(f, "not a packed game"),
+            PackError::TrailingBytes(count) => write!(f, "{} bytes left over after the last move", count),
+        }
+    }
+}
+
+impl std::error::Error for PackError {}
+
+// From editing a position, or a position which can't be searched. Squares
+// are in algebraic notation.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PositionError {
+    SquareOccupied(String),
+    SquareEmpty(String),
+    // the side has this many kings rather than one
+    KingCount(Color, usize),
+    PawnOnBackRank,
+    // the side which isn't to move is in check
+    NotToMoveInCheck(Color),
+    BadCastlingRights,
+    BadEnPassantSquare,
+}
+
+impl Display for PositionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            PositionError::SquareOccupied(sq) => write!(f, "there is already a piece on {}", sq),
+            PositionError::SquareEmpty(sq) => write!(f, "there is no piece on {}", sq),
+            PositionError::KingCount(color, kings) => write!(f, "{} has {} kings", color, kings),
+            PositionError::PawnOnBackRank => write!(f, "pawn on the first or eighth rank"),
+            PositionError::NotToMoveInCheck(color) => write!(f, "{} is in check but it is not their move", color),
+            PositionError::BadCastlingRights => write!(f, "castling rights without the king and rook in place"),
+            PositionError::BadEnPassantSquare => write!(f, "en passant square without a pawn which just moved past it"),
+        }
+    }
+}
+
+impl std::error::Error for PositionError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum PgnError {
+    // the path and why it couldn't be read or written
+    Io(String, String),
+    // a tag pair which isn't [Name "value"]
+    BadTag(String),
+    // a comment or variation still open at the end of the game
+    Unclosed(char),
+    // the FEN tag's position
+    BadFen(FenError),
+    // a move which can't be read or played, by its ply from the start
+    BadMove(usize, SanError),
+    NoMoves,
+}
+
+impl Display for PgnError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            PgnError::Io(path, e) => write!(f, "can't access '{}': {}", path, e),
+            PgnError::BadTag(tag) => write!(f, "invalid tag pair '{}'", tag),
+            PgnError::Unclosed(c) => write!(f, "'{}' is never closed", c),
+            PgnError::BadFen(e) => write!(f, "FEN tag: {}", e),
+            PgnError::BadMove(ply, e) => write!(f, "ply {}: {}", ply, e),
+            PgnError::NoMoves => write!(f, "the game has no moves"),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LogError {
+    // the path and why it couldn't be opened
+    Io(String, String),
+}
+
+impl Display for LogError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            LogError::Io(path, e) => write!(f, "can't open '{}': {}", path, e),
+        }
+    }
+}
+
+impl std::error::Error for LogError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Error {
+    Fen(FenError),
+    Move(MoveError),
+    San(SanError),
+    Search(SearchError),
+    Config(ConfigError),
+    Script(ScriptError),
+    Tt(TtError),
+    Syzygy(SyzygyError),
+    Option(OptionError),
+    Pack(PackError),
+    Position(PositionError),
+    Pgn(PgnError),
+    Log(LogError),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            Error::Fen(e) => write!(f, "fen error: {}", e),
+            Error::Move(e) => write!(f, "move error: {}", e),
+            Error::San(e) => write!(f, "san error: {}", e),
+            Error::Search(e) => write!(f, "search error: {}", e),
+            Error::Config(e) => write!(f, "config error: {}", e),
+            Error::Script(e) => write!(f, "script error: {}", e),
+            Error::Tt(e) => write!(f, "hash table error: {}", e),
+            Error::Syzygy(e) => write!(f, "tablebase error: {}", e),
+            Error::Option(e) => write!(f, "option error: {}", e),
+            Error::Pack(e) => write!(f, "packed game error: {}", e),
+            Error::Position(e) => write!(f, "position error: {}", e),
+            Error::Pgn(e) => write!(f, "pgn error: {}", e),
+            Error::Log(e) => write!(f, "log file error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fen(e) => Some(e),
+            Error::Move(e) => Some(e),
+            Error::San(e) => Some(e),
+            Error::Search(e) => Some(e),
+            Error::Config(e) => Some(e),
+            Error::Script(e) => Some(e),
+            Error::Tt(e) => Some(e),
+            Error::Syzygy(e) => Some(e),
+            Error::Option(e) => Some(e),
+            Error::Pack(e) => Some(e),
+            Error::Position(e) => Some(e),
+            Error::Pgn(e) => Some(e),
+            Error::Log(e) => Some(e),
+        }
+    }
+}
+
+impl From<FenError> for Error {
+    fn from(e: FenError) -> Error {
+        Error::Fen(e)
+    }
+}
+
+impl From<MoveError> for Error {
+    fn from(e: MoveError) -> Error {
+        Error::Move(e)
+    }
+}
+
+impl From<SanError> for Error {
+    fn from(e: SanError) -> Error {
+        Error::San(e)
+    }
+}
+
+impl From<SearchError> for Error {
+    fn from(e: SearchError) -> Error {
+        Error::Search(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Error {
+        Error::Config(e)
+    }
+}
+
+impl From<ScriptError> for Error {
+    fn from(e: ScriptError) -> Error {
+        Error::Script(e)
+    }
+}
+
+impl From<TtError> for Error {
+    fn from(e: TtError) -> Error {
+        Error::Tt(e)
+    }
+}
+
+impl From<SyzygyError> for Error {
+    fn from(e: SyzygyError) -> Error {
+        Error::Syzygy(e)
+    }
+}
+
+impl From<OptionError> for Error {
+    fn from(e: OptionError) -> Error {
+        Error::Option(e)
+    }
+}
+
+impl From<PackError> for Error {
+    fn from(e: PackError) -> Error {
+        Error::Pack(e)
+    }
+}
+
+impl From<PositionError> for Error {
+    fn from(e: PositionError) -> Error {
+        Error::Position(e)
+    }
+}
+
+impl From<PgnError> for Error {
+    fn from(e: PgnError) -> Error {
+        Error::Pgn(e)
+    }
+}
+
+impl From<LogError> for Error {
+    fn from(e: LogError) -> Error {
+        Error::Log(e)
+    }
+}
@@ -1,7 +1,14 @@
-use crate::engine::board::Board;
+use crate::engine::bitboard::BitBoardUtils;
+use crate::engine::board::{Board, Variant};
+use crate::engine::material::MaterialKey;
+use crate::engine::r#move::{Move, UndoInfo};
+use crate::engine::tune::{self, Param};
 
 use super::piece::{Color, Pieces};
 
+// the defaults of the tune piece values, which are read instead when built
+// with the `tune` feature
+#[cfg(not(feature = "tune"))]
 const PIECE_VALUE: [i32; 12] = [
     100,  // White Pawn
     315,  // White Knight
@@ -93,7 +100,56 @@ const SQ_VALUE: [&[i32; 64]; 6] = [
     &KING_SQ_VALUE,
 ];
 
-#[derive(Default)]
+// An evaluation function usable by the search. Scores are in centipawns
+// from the perspective of the side to move.
+//
+// The search calls `full` once on the root position, then `on_make` after
+// every `Board::make_move` and `on_undo` after every `Board::undo_move` (the
+// board passed is the position after the make or undo). An implementation
+// that keeps incremental state must ensure `evaluate` always returns the same
+// score that `full` would give for the current position.
+pub trait Evaluate {
+    // reset any incremental state from the board and return its score
+    fn full(&mut self, board: &Board) -> i32;
+
+    fn on_make(&mut self, board: &Board, my_move: Move, info: &UndoInfo);
+    fn on_undo(&mut self, board: &Board, my_move: Move, info: &UndoInfo);
+
+    // the score of the current position, from the side to move's point of
+    // view as negamax expects
+    fn evaluate(&self, board: &Board) -> i32;
+}
+
+// How far the incremental score has drifted from the score `full` gives for
+// the board, or None if it hasn't. Either way the evaluator is left reset to
+// the board, so any drift doesn't carry on into the next search.
+pub fn eval_drift(evaluator: &mut impl Evaluate, board: &Board) -> Option<i32> {
+    let incremental = evaluator.evaluate(board);
+    let full = evaluator.full(board);
+
+    if incremental == full {
+        None
+    } else {
+        Some(incremental - full)
+    }
+}
+
+// Logistic model of the chance of winning from a centipawn score: a score
+// of WDL_MIDPOINT wins half the time, and WDL_SPREAD sets how quickly that
+// changes. These should be recalibrated against game results whenever the
+// piece values are tuned.
+pub const WDL_MIDPOINT: f64 = 150.0;
+pub const WDL_SPREAD: f64 = 75.0;
+
+// knights, bishops, rooks and queens for both sides at the start of the game
+fn starting_non_pawn_material() -> i32 {
+    let value = |piece| Evaluator::piece_value(piece);
+    let white = Color::White;
+    2 * (2 * value(Pieces::knight(white)) + 2 * value(Pieces::bishop(white)) + 2 * value(Pieces::rook(white))
+        + value(Pieces::queen(white)))
+}
+
+#[derive(Clone, Default)]
 pub struct Evaluator {
     score: i32
 }
@@ -162,14 +218,50 @@ impl Evaluator {
         diff
     }
 
+    // The tables are from white's side, with a8 first, so black's pieces
+    // read them with the ranks flipped.
     fn sq_value(piece: Pieces, sq: usize) -> i32 {
         let piece_idx = piece.idx() % 6;
-        SQ_VALUE[piece_idx][sq] * if piece.color().is_white() { 1 } else { -1 }
+        if piece.color().is_white() {
+            SQ_VALUE[piece_idx][sq]
+        } else {
+            -SQ_VALUE[piece_idx][sq ^ 56]
+        }
     }
 
+    // The side ahead in material wants to trade pieces (but not pawns), so the
+    // material advantage is increased as the non pawn material comes off the
+    // board. With all the pieces still on it has no effect, which keeps it
+    // out of the middlegame.
+    pub fn simplification(material: MaterialKey) -> i32 {
+        let mut balance = 0;
+        let mut non_pawn_material = 0;
+
+        for idx in 0..12 {
+            let piece = Pieces::from_idx(idx);
+            let value = Evaluator::piece_value(piece) * material.count(piece) as i32;
+
+            balance += value;
+            if !piece.is_pawn() {
+                non_pawn_material += value.abs();
+            }
+        }
+
+        // the fraction of the material advantage gained once every piece is
+        // traded is one over the divisor
+        let starting = starting_non_pawn_material().max(1);
+        let traded = (starting - non_pawn_material).max(0);
+        balance * traded / (starting * tune::value(Param::SimplificationDivisor))
+    }
+
+    #[cfg(not(feature = "tune"))]
     pub fn piece_value(piece: Pieces) -> i32 {
         PIECE_VALUE[piece.idx()]
     }
+    #[cfg(feature = "tune")]
+    pub fn piece_value(piece: Pieces) -> i32 {
+        tune::piece_value(piece)
+    }
     
     pub fn update_score(&mut self, diff: i32) {
         self.score += diff;
@@ -186,11 +278,483 @@ impl Evaluator {
         }
     }
 
-    pub fn score(&self, color: Color) -> i32 {
-        if color.is_white() {
-            self.score
+    // the incremental score from white's point of view, for reports which
+    // always show it that way. The search wants score_relative.
+    pub fn white_score(&self) -> i32 {
+        self.score
+    }
+    // The score from the side to move's point of view, before scaling for
+    // the material left. This is the only place the score is negated for
+    // black, so everything the search sees agrees on the sign.
+    pub fn score_relative(&self, board: &Board) -> i32 {
+        let score = self.score + Evaluator::simplification(board.material_key());
+        if board.friendly_color().is_white() {
+            score
+        } else {
+            -score
+        }
+    }
+}
+
+impl Evaluate for Evaluator {
+    fn full(&mut self, board: &Board) -> i32 {
+        self.init_score(board);
+        self.evaluate(board)
+    }
+
+    #[inline(always)]
+    fn on_make(&mut self, _board: &Board, _my_move: Move, info: &UndoInfo) {
+        self.update_score(info.evalutor_diff);
+    }
+    #[inline(always)]
+    fn on_undo(&mut self, _board: &Board, _my_move: Move, info: &UndoInfo) {
+        self.update_score(-info.evalutor_diff);
+    }
+
+    // scaled towards a draw when the material left is unlikely to be enough
+    // to win
+    fn evaluate(&self, board: &Board) -> i32 {
+        board.material_class().scale(self.score_relative(board)) + variant_score(board) + mop_up_score(board)
+    }
+}
+
+// for each move the bare king is from the centre, and each the kings are
+// closer than the furthest apart they can be
+const MOP_UP_EDGE: i32 = 10;
+const MOP_UP_KINGS: i32 = 4;
+
+// With a queen or rook against a bare king the piece square tables give
+// nothing for making progress, so the search shuffles. The mate needs the
+// king on the edge with the other king close by, so that is what gets
+// rewarded, from the side to move's point of view. Cornering the king
+// this way is what sets up stalemates, which the search has to see.
+pub fn mop_up_score(board: &Board) -> i32 {
+    let material = board.material_key();
+
+    for strong in [Color::White, Color::Black] {
+        let weak = strong.enemy();
+        let heavy = material.count(Pieces::queen(strong)) + material.count(Pieces::rook(strong));
+        if heavy == 0 || !material.is_bare_king(weak) {
+            continue;
+        }
+        let (weak_king, strong_king) = match (board.try_king_square(weak), board.try_king_square(strong)) {
+            (Some(weak_king), Some(strong_king)) => (weak_king.sq(), strong_king.sq()),
+            _ => continue,
+        };
+
+        let centre_distance = |sq: usize| {
+            let (rank, file) = (sq / 8, sq % 8);
+            rank.clamp(3, 4).abs_diff(rank) + file.clamp(3, 4).abs_diff(file)
+        };
+        let kings_distance = (weak_king / 8).abs_diff(strong_king / 8) + (weak_king % 8).abs_diff(strong_king % 8);
+
+        let score = MOP_UP_EDGE * centre_distance(weak_king) as i32 + MOP_UP_KINGS * (14 - kings_distance as i32);
+        return if board.friendly_color() == strong { score } else { -score };
+    }
+    0
+}
+
+// for each check given so far in three-check, the third being a win
+const CHECK_BONUS: [i32; 3] = [0, 60, 200];
+// for a king that many king moves from the centre in king of the hill
+const CENTER_BONUS: [i32; 4] = [0, 80, 30, 10];
+
+// The variant's own terms from the side to move's point of view: checks
+// given in three-check, and kings near the centre in king of the hill.
+// Nothing for standard chess.
+pub fn variant_score(board: &Board) -> i32 {
+    let us = board.friendly_color();
+    let them = board.enemy_color();
+
+    match board.variant() {
+        Variant::Standard => 0,
+        Variant::ThreeCheck => {
+            let bonus = |color| CHECK_BONUS[(board.checks_given(color) as usize).min(2)];
+            bonus(us) - bonus(them)
+        }
+        Variant::KingOfTheHill => {
+            let bonus = |color| {
+                let king = board.get_bb(Pieces::king(color));
+                if king == 0 {
+                    return 0;
+                }
+                let sq = king.lsb_idx();
+                let (rank, file) = (sq / 8, sq % 8);
+                // the centre is ranks and files 3 and 4
+                let distance = rank.clamp(3, 4).abs_diff(rank).max(file.clamp(3, 4).abs_diff(file));
+                CENTER_BONUS[distance]
+            };
+            bonus(us) - bonus(them)
+        }
+    }
+}
+
+// Counts material and nothing else. Useful as a baseline, and for testing
+// the search without depending on the details of the real evaluation.
+// The chance of winning from a score, between 0 and 1
+fn win_rate(score: i32) -> f64 {
+    1.0 / (1.0 + ((WDL_MIDPOINT - score as f64) / WDL_SPREAD).exp())
+}
+
+// win, draw and loss chances in permille for the side the score is relative to
+pub fn wdl(score: i32) -> (u32, u32, u32) {
+    let win = (1000.0 * win_rate(score)).round() as u32;
+    let loss = (1000.0 * win_rate(-score)).round() as u32;
+
+    (win, 1000 - win - loss, loss)
+}
+
+// the expected result as a percentage, counting a draw as half a win
+pub fn win_percentage(score: i32) -> f64 {
+    100.0 * (win_rate(score) + (1.0 - win_rate(score) - win_rate(-score)) / 2.0)
+}
+
+#[derive(Clone, Default)]
+pub struct MaterialOnly;
+
+impl Evaluate for MaterialOnly {
+    fn full(&mut self, board: &Board) -> i32 {
+        self.evaluate(board)
+    }
+
+    fn on_make(&mut self, _board: &Board, _my_move: Move, _info: &UndoInfo) {}
+    fn on_undo(&mut self, _board: &Board, _my_move: Move, _info: &UndoInfo) {}
+
+    fn evaluate(&self, board: &Board) -> i32 {
+        let material = board.material_key();
+        let score: i32 = (0..12)
+            .map(Pieces::from_idx)
+            .map(|piece| Evaluator::piece_value(piece) * material.count(piece) as i32)
+            .sum();
+
+        if board.friendly_color().is_white() {
+            score
         } else {
-            -self.score
+            -score
+        }
+    }
+}
+
+// Another evaluator which sleeps for the delay before every evaluation, to
+// test searches which run late.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct SlowEvaluator<E> {
+    pub inner: E,
+    pub delay: Option<std::time::Duration>,
+}
+
+#[cfg(test)]
+impl<E> From<E> for SlowEvaluator<E> {
+    fn from(inner: E) -> SlowEvaluator<E> {
+        SlowEvaluator { inner, delay: None }
+    }
+}
+
+#[cfg(test)]
+impl<E: Evaluate> Evaluate for SlowEvaluator<E> {
+    fn full(&mut self, board: &Board) -> i32 {
+        self.inner.full(board)
+    }
+
+    fn on_make(&mut self, board: &Board, my_move: Move, info: &UndoInfo) {
+        self.inner.on_make(board, my_move, info);
+    }
+    fn on_undo(&mut self, board: &Board, my_move: Move, info: &UndoInfo) {
+        self.inner.on_undo(board, my_move, info);
+    }
+
+    fn evaluate(&self, board: &Board) -> i32 {
+        if let Some(delay) = self.delay {
+            std::thread::sleep(delay);
+        }
+        self.inner.evaluate(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(fen: &str) -> i32 {
+        let board = Board::new(fen).unwrap();
+        let mut evaluator = Evaluator::default();
+        evaluator.init_score(&board);
+        evaluator.evaluate(&board)
+    }
+
+    #[test]
+    fn simplification() {
+        // white is a knight up in both, the rooks are on squares worth nothing
+        let untraded = "4k3/pppp4/8/1r6/1R6/8/PPPP4/3NK3 w - - 0 1";
+        let traded = "4k3/pppp4/8/8/8/8/PPPP4/3NK3 w - - 0 1";
+
+        assert!(evaluate(traded) > evaluate(untraded));
+        assert!(evaluate(&traded.replace(" w ", " b ")) < evaluate(&untraded.replace(" w ", " b ")));
+
+        // no effect with everything still on the board
+        let board = Board::default();
+        assert_eq!(Evaluator::simplification(board.material_key()), 0);
+    }
+
+    #[test]
+    fn mop_up() {
+        let score = |fen: &str| mop_up_score(&Board::new(fen).unwrap());
+
+        // a cornered king is worth more than a central one, more so with
+        // the kings together
+        assert!(score("k7/8/8/8/8/8/8/Q6K w - - 0 1") > score("8/8/8/3k4/8/8/8/Q6K w - - 0 1"));
+        assert!(score("k7/2K5/8/8/8/8/8/Q7 w - - 0 1") > score("k7/8/8/8/8/8/8/Q6K w - - 0 1"));
+        assert_eq!(score("k7/8/8/8/8/8/8/Q6K b - - 0 1"), -score("k7/8/8/8/8/8/8/Q6K w - - 0 1"));
+        assert_eq!(score("q6k/8/8/8/8/8/8/K7 b - - 0 1"), score("k7/8/8/8/8/8/8/Q6K w - - 0 1"));
+
+        // nothing while the defender has anything but the king, or without
+        // a heavy piece to mate with
+        assert_eq!(score("k7/p7/8/8/8/8/8/Q6K w - - 0 1"), 0);
+        assert_eq!(score("k7/8/8/8/8/8/8/BN5K w - - 0 1"), 0);
+    }
+
+    #[test]
+    fn side_to_move() {
+        let fen = "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1";
+        let white = Board::new(fen).unwrap();
+        let black = Board::new(&fen.replace(" w ", " b ")).unwrap();
+
+        let mut evaluator = Evaluator::default();
+        evaluator.init_score(&white);
+        assert!(evaluator.white_score() < 0);
+        assert!(evaluator.score_relative(&white) < 0);
+        assert_eq!(evaluator.score_relative(&black), -evaluator.score_relative(&white));
+        assert_eq!(evaluator.evaluate(&black), -evaluator.evaluate(&white));
+    }
+
+    fn check_incremental(evaluator: &mut impl Evaluate) {
+        use crate::engine::movegen::{MoveGenerator, MoveList};
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x1416);
+        let generator = MoveGenerator::new();
+        let mut board =
+            Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut moves = MoveList::new();
+        let mut info = UndoInfo::default();
+
+        evaluator.full(&board);
+        for _ in 0..50 {
+            generator.gen_moves(&mut board, &mut moves);
+            if moves.is_empty() {
+                break;
+            }
+
+            let my_move = moves.at(rng.gen_range(0..moves.len()));
+            board.make_move(my_move, &mut info);
+            evaluator.on_make(&board, my_move, &info);
+
+            let incremental = evaluator.evaluate(&board);
+            assert_eq!(incremental, evaluator.full(&board), "{}", board.to_fen());
+
+            // undo and redo to check on_undo too
+            board.undo_move(my_move, &info);
+            evaluator.on_undo(&board, my_move, &info);
+            assert_eq!(evaluator.evaluate(&board), evaluator.full(&board));
+
+            board.make_move(my_move, &mut info);
+            evaluator.on_make(&board, my_move, &info);
+        }
+    }
+
+    #[test]
+    fn drift() {
+        let board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1").unwrap();
+        let mut evaluator = Evaluator::default();
+
+        evaluator.full(&board);
+        assert_eq!(eval_drift(&mut evaluator, &board), None);
+
+        // as if a move had been undone with the wrong difference. Black is
+        // to move, so the drift is the other way round.
+        evaluator.update_score(37);
+        assert_eq!(eval_drift(&mut evaluator, &board), Some(-37));
+        assert_eq!(eval_drift(&mut evaluator, &board), None);
+    }
+
+    #[test]
+    fn win_probability() {
+        assert!((win_percentage(0) - 50.0).abs() < 1e-9);
+        let (win, draw, loss) = wdl(0);
+        assert_eq!(win, loss);
+        assert_eq!(win + draw + loss, 1000);
+
+        assert!(win_percentage(5000) > 99.9);
+        assert!(win_percentage(-5000) < 0.1);
+        assert_eq!(wdl(i32::MAX / 2), (1000, 0, 0));
+
+        let mut last = 0.0;
+        for score in (-3000..=3000).step_by(10) {
+            let percentage = win_percentage(score);
+            assert!(percentage >= last, "not monotone at {}", score);
+            last = percentage;
+
+            let (win, draw, loss) = wdl(score);
+            assert_eq!(win + draw + loss, 1000);
+        }
+    }
+
+    #[test]
+    fn incremental_consistency() {
+        check_incremental(&mut Evaluator::default());
+        check_incremental(&mut MaterialOnly);
+    }
+
+    // The random games above may not reach every kind of move, so every
+    // move from positions which have castling both ways, en passant and
+    // promotions with and without a capture, for both sides, is checked too.
+    #[test]
+    fn diff_for_every_move_type() {
+        use crate::engine::movegen::{MoveGenerator, MoveList};
+        use crate::engine::r#move::MoveUtils;
+
+        let generator = MoveGenerator::new();
+        let mut moves = MoveList::new();
+        let mut info = UndoInfo::default();
+
+        for fen in &[
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 b kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "rnbqkbnr/pppp1ppp/8/8/3PpP2/8/PPP1P1PP/RNBQKBNR b KQkq f3 0 3",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut evaluator = Evaluator::default();
+            evaluator.init_score(&board);
+            let before = evaluator.white_score();
+
+            generator.gen_moves(&mut board, &mut moves);
+            for i in 0..moves.len() {
+                let my_move = moves.at(i);
+                board.make_move(my_move, &mut info);
+                evaluator.update_score(info.evalutor_diff);
+
+                let mut fresh = Evaluator::default();
+                fresh.init_score(&board);
+                assert_eq!(evaluator.white_score(), fresh.white_score(), "{} {}", fen, my_move.move_to_string());
+
+                board.undo_move(my_move, &info);
+                evaluator.update_score(-info.evalutor_diff);
+                assert_eq!(evaluator.white_score(), before);
+            }
+        }
+    }
+
+    // The diff from the diff function for the move's type, called with the
+    // squares and pieces worked out here rather than by make_move
+    fn direct_diff(board: &Board, my_move: Move) -> (&'static str, i32) {
+        use crate::engine::r#move::{MoveUtils, MOVE_CASTLE_SIDE_KS, MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT, MOVE_TYPE_PROMOTION};
+
+        let (start, end) = (my_move.get_move_start() as usize, my_move.get_move_end() as usize);
+        let color = board.friendly_color();
+        let piece = board.pieces[start].unwrap();
+        match my_move.get_move_type() {
+            MOVE_TYPE_CASTLE => {
+                let back_rank = start - start % 8;
+                let (rook_start, rook_end) = match my_move.get_move_piece() {
+                    MOVE_CASTLE_SIDE_KS => (back_rank + 7, back_rank + 5),
+                    _ => (back_rank, back_rank + 3),
+                };
+                ("castle", Evaluator::castle_diff(start, end, rook_start, rook_end, color))
+            }
+            // the move ends on the pawn taken, and the pawn taking it lands
+            // behind it
+            MOVE_TYPE_EN_PASSANT => {
+                let landing = if color.is_white() { end - 8 } else { end + 8 };
+                ("e.p.", Evaluator::en_passant_diff(start, landing, end, piece))
+            }
+            MOVE_TYPE_PROMOTION => {
+                let promoted = match my_move.get_move_piece() {
+                    0 => Pieces::knight(color),
+                    1 => Pieces::bishop(color),
+                    2 => Pieces::rook(color),
+                    _ => Pieces::queen(color),
+                };
+                ("promotion", Evaluator::promotion_diff(start, end, promoted, board.pieces[end], color))
+            }
+            _ if board.pieces[end].is_some() => ("capture", Evaluator::standard_diff(start, end, piece, board.pieces[end])),
+            _ => ("quiet", Evaluator::standard_diff(start, end, piece, None)),
+        }
+    }
+
+    // Each kind of move on a small position, for both sides: the change in
+    // init_score has to be what the diff functions give
+    #[test]
+    fn diff_functions() {
+        use crate::engine::movegen::MoveGenerator;
+
+        let cases: &[(&str, &[&str], &str)] = &[
+            ("4k3/8/8/8/8/8/8/4K1N1 w - - 0 1", &["g1f3"], "quiet"),
+            ("4k1n1/8/8/8/8/8/8/4K3 b - - 0 1", &["g8f6"], "quiet"),
+            ("4k3/8/8/3p4/8/2N5/8/4K3 w - - 0 1", &["c3d5"], "capture"),
+            ("4k3/8/2n5/8/3P4/8/8/4K3 b - - 0 1", &["c6d4"], "capture"),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", &["e2e4"], "quiet"),
+            ("4k3/3p4/8/8/8/8/8/4K3 b - - 0 1", &["d7d5"], "quiet"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &["e1g1", "e1c1"], "castle"),
+            ("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1", &["e8g8", "e8c8"], "castle"),
+            ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", &["e5d6"], "e.p."),
+            ("4k3/8/8/8/3pP3/8/8/4K3 b - e3 0 1", &["d4e3"], "e.p."),
+            (
+                "1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1",
+                &["a7a8q", "a7a8r", "a7a8b", "a7a8n", "a7b8q", "a7b8r", "a7b8b", "a7b8n"],
+                "promotion",
+            ),
+            (
+                "4k3/8/8/8/8/8/p7/1R2K3 b - - 0 1",
+                &["a2a1q", "a2a1r", "a2a1b", "a2a1n", "a2b1q", "a2b1r", "a2b1b", "a2b1n"],
+                "promotion",
+            ),
+        ];
+
+        // init_score itself has to be right for the comparison to mean
+        // anything: level at the start, and negated when the board is
+        // flipped and the colours swapped
+        let mut evaluator = Evaluator::default();
+        evaluator.init_score(&Board::default());
+        assert_eq!(evaluator.white_score(), 0);
+        let mirrored = [
+            ("4k3/8/8/3p4/8/2N5/8/4K3 w - - 0 1", "4k3/8/2n5/8/3P4/8/8/4K3 b - - 0 1"),
+            ("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "4k3/8/8/8/8/8/p7/1R2K3 b - - 0 1"),
+            (
+                "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 4 4",
+                "rnbqk2r/pppp1ppp/5n2/2b1p3/4P3/2N2N2/PPPP1PPP/R1BQKB1R b KQkq - 4 4",
+            ),
+        ];
+        for (fen, flipped) in mirrored {
+            evaluator.init_score(&Board::new(fen).unwrap());
+            let score = evaluator.white_score();
+            evaluator.init_score(&Board::new(flipped).unwrap());
+            assert_eq!(evaluator.white_score(), -score, "{}", fen);
+        }
+
+        let generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        for &(fen, moves, kind) in cases {
+            for uci_move in moves {
+                let mut board = Board::new(fen).unwrap();
+                let mut evaluator = Evaluator::default();
+                evaluator.init_score(&board);
+                let before = evaluator.white_score();
+
+                // the move is found on a copy, as the diff is of the board
+                // before it
+                let my_move = board.clone().make_uci_move(uci_move, &generator, &mut info).unwrap();
+                let (move_kind, diff) = direct_diff(&board, my_move);
+                assert_eq!(move_kind, kind, "{} {}", fen, uci_move);
+
+                board.make_move(my_move, &mut info);
+                evaluator.init_score(&board);
+                assert_eq!(evaluator.white_score() - before, diff, "{} {}", fen, uci_move);
+                assert_eq!(info.evalutor_diff, diff, "{} {}", fen, uci_move);
+            }
         }
     }
 }
@@ -0,0 +1,366 @@
+// `chess_rs fen-check`: validates FENs and describes the positions, so that
+// position databases can be linted from scripts. FENs are taken from the
+// command line, or one per line from stdin when none is given. The exit code
+// is nonzero if any of them is invalid. The counters may be left off unless
+// --strict is given, and --epd ignores any opcodes after the position.
+
+use std::io::{BufRead, Write};
+
+use crate::engine::board::{Board, FenParsing, GameState};
+use crate::engine::error::{Error, EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use crate::engine::eval::{Evaluate, Evaluator};
+use crate::engine::material::MaterialClass;
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::piece::Color;
+
+const USAGE: &str = "usage: chess_rs fen-check [--json] [--strict | --epd] [fen]";
+
+// The static evaluation split into its parts. Material and positional are
+// from white's point of view, the total from the side to move's.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EvalBreakdown {
+    pub material: i32,
+    pub positional: i32,
+    pub simplification: i32,
+    // sixteenths of the score kept by the material class
+    pub scale: i32,
+    pub total: i32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PositionReport {
+    pub side_to_move: Color,
+    pub in_check: bool,
+    pub legal_moves: usize,
+    pub state: GameState,
+    pub eval: EvalBreakdown,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FenReport {
+    pub fen: String,
+    // why the fen was rejected, if it was
+    pub problems: Vec<String>,
+    pub position: Option<PositionReport>,
+}
+
+impl FenReport {
+    pub fn is_valid(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![format!("fen: {}", self.fen)];
+
+        if !self.is_valid() {
+            lines.push("valid: no".to_string());
+            lines.extend(
+                self.problems
+                    .iter()
+                    .map(|problem| format!("error: {}", problem)),
+            );
+        }
+
+        if let Some(position) = &self.position {
+            let eval = &position.eval;
+            lines.push("valid: yes".to_string());
+            lines.push(format!("side to move: {}", position.side_to_move));
+            lines.push(format!(
+                "in check: {}",
+                if position.in_check { "yes" } else { "no" }
+            ));
+            lines.push(format!("legal moves: {}", position.legal_moves));
+            lines.push(format!("state: {}", position.state));
+            lines.push(format!("material: {}", eval.material));
+            lines.push(format!(
+                "eval: material {} positional {} simplification {} scale {}/16 total {}",
+                eval.material, eval.positional, eval.simplification, eval.scale, eval.total
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let problems: Vec<String> = self.problems.iter().map(|p| json_string(p)).collect();
+        let mut fields = vec![
+            format!("\"fen\":{}", json_string(&self.fen)),
+            format!("\"valid\":{}", self.is_valid()),
+            format!("\"errors\":[{}]", problems.join(",")),
+        ];
+
+        if let Some(position) = &self.position {
+            let eval = &position.eval;
+            let side = if position.side_to_move.is_white() {
+                "white"
+            } else {
+                "black"
+            };
+            fields.push(format!("\"side_to_move\":\"{}\"", side));
+            fields.push(format!("\"in_check\":{}", position.in_check));
+            fields.push(format!("\"legal_moves\":{}", position.legal_moves));
+            fields.push(format!("\"state\":\"{}\"", position.state));
+            fields.push(format!(
+                "\"eval\":{{\"material\":{},\"positional\":{},\"simplification\":{},\"scale\":{},\"total\":{}}}",
+                eval.material, eval.positional, eval.simplification, eval.scale, eval.total
+            ));
+        }
+
+        format!("{{{}}}", fields.join(","))
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut result = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn eval_breakdown(board: &Board) -> EvalBreakdown {
+    let mut evaluator = Evaluator::default();
+    evaluator.init_score(board);
+
+    let material = board
+        .pieces
+        .iter()
+        .flatten()
+        .map(|&piece| Evaluator::piece_value(piece))
+        .sum();
+    let scale = match board.material_class() {
+        MaterialClass::WinPossible => 16,
+        MaterialClass::DeadDraw => 0,
+        MaterialClass::Drawish(sixteenths) => sixteenths,
+    };
+
+    EvalBreakdown {
+        material,
+        positional: evaluator.white_score() - material,
+        simplification: Evaluator::simplification(board.material_key()),
+        scale,
+        total: evaluator.full(board),
+    }
+}
+
+pub fn check_fen(fen: &str, parsing: FenParsing, move_generator: &MoveGenerator) -> FenReport {
+    let fen = fen.trim().to_string();
+
+    let mut board = Board::default();
+    match board.load_fen_with(&fen, parsing) {
+        Ok(()) => {}
+        Err(e) => {
+            return FenReport {
+                fen,
+                problems: vec![Error::from(e).to_string()],
+                position: None,
+            }
+        }
+    };
+
+    // what the parser lets through, but the search can't cope with
+    let problems: Vec<String> = board.position_problems().iter().map(ToString::to_string).collect();
+    if !problems.is_empty() {
+        return FenReport {
+            fen,
+            problems,
+            position: None,
+        };
+    }
+
+    let mut moves = MoveList::new();
+    let in_check = move_generator.gen_moves(&mut board, &mut moves).checkers != 0;
+
+    FenReport {
+        fen,
+        problems,
+        position: Some(PositionReport {
+            side_to_move: board.friendly_color(),
+            in_check,
+            legal_moves: moves.len(),
+            state: board.game_state_with(in_check, moves.len()),
+            eval: eval_breakdown(&board),
+        }),
+    }
+}
+
+// args are those after `fen-check`, the return value is the exit code
+pub fn fen_check(args: &[String], input: impl BufRead, output: &mut impl Write) -> i32 {
+    let mut json = false;
+    let mut parsing = FenParsing::Standard;
+    let mut fen_args = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--strict" => parsing = FenParsing::Strict,
+            "--epd" => parsing = FenParsing::Lenient,
+            "-h" | "--help" => {
+                let _ = writeln!(output, "{}", USAGE);
+                return EXIT_OK;
+            }
+            _ => fen_args.push(arg.as_str()),
+        }
+    }
+
+    // the fen may be given as one argument or split over several
+    let fens: Vec<String> = if fen_args.is_empty() {
+        input
+            .lines()
+            .map_while(|line| line.ok())
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    } else {
+        vec![fen_args.join(" ")]
+    };
+
+    if fens.is_empty() {
+        let _ = writeln!(output, "{}", USAGE);
+        return EXIT_USAGE;
+    }
+
+    let move_generator = MoveGenerator::new();
+    let mut code = EXIT_OK;
+    for (i, fen) in fens.iter().enumerate() {
+        let report = check_fen(fen, parsing, &move_generator);
+        if !report.is_valid() {
+            code = EXIT_INVALID;
+        }
+
+        let _ = if json {
+            writeln!(output, "{}", report.to_json())
+        } else if i > 0 {
+            writeln!(output, "\n{}", report.to_text())
+        } else {
+            writeln!(output, "{}", report.to_text())
+        };
+    }
+
+    code
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(args: &[&str], input: &str) -> (i32, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut output = Vec::new();
+        let code = fen_check(&args, input.as_bytes(), &mut output);
+        (code, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn valid() {
+        let (code, output) = run(
+            &["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"],
+            "",
+        );
+        assert_eq!(code, EXIT_OK);
+        assert!(output.contains("valid: yes"));
+        assert!(output.contains("side to move: White"));
+        assert!(output.contains("in check: no"));
+        assert!(output.contains("legal moves: 20"));
+        assert!(output.contains("state: ongoing"));
+        assert!(output.contains("material: 0"));
+
+        // split over several arguments, as an unquoted fen would be
+        let split: Vec<&str> = "r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1".split(' ').collect();
+        let (code, output) = run(&split, "");
+        assert_eq!(code, EXIT_OK);
+        assert!(output.contains("legal moves: 26"));
+    }
+
+    #[test]
+    fn invalid() {
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1",
+            // no black king
+            "8/8/8/8/8/8/8/4K3 w - - 0 1",
+            // a pawn on the back rank
+            "P3k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            // black is in check with white to move
+            "4k3/4R3/8/8/8/8/8/4K3 w - - 0 1",
+        ] {
+            let (code, output) = run(&[fen], "");
+            assert_eq!(code, EXIT_INVALID, "{}", fen);
+            assert!(output.contains("valid: no"), "{}", fen);
+            assert!(output.contains("error: "), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn field_counts() {
+        let epd = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; id \"start\";";
+
+        // the counters are optional unless every field is asked for
+        let (code, output) = run(&["rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -"], "");
+        assert_eq!(code, EXIT_OK);
+        assert!(output.contains("legal moves: 20"));
+        let (code, output) = run(&["--strict", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -"], "");
+        assert_eq!(code, EXIT_INVALID);
+        assert!(output.contains("error: fen error: missing halfmove or fullmove counter"));
+
+        // EPD opcodes are only skipped when asked
+        assert_eq!(run(&[], epd).0, EXIT_INVALID);
+        let (code, output) = run(&["--epd"], &format!("{}\n4k3/8/8/8/8/8/8/3QK3 b - - 3 9\n", epd));
+        assert_eq!(code, EXIT_OK);
+        assert_eq!(output.matches("valid: yes").count(), 2);
+    }
+
+    #[test]
+    fn terminal() {
+        let checkmate = "rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3";
+        let report = check_fen(checkmate, FenParsing::Standard, &MoveGenerator::new());
+        let position = report.position.unwrap();
+        assert!(position.in_check);
+        assert_eq!(position.legal_moves, 0);
+        assert_eq!(position.state, GameState::Checkmate);
+
+        let stalemate = "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1";
+        let position = check_fen(stalemate, FenParsing::Standard, &MoveGenerator::new())
+            .position
+            .unwrap();
+        assert!(!position.in_check);
+        assert_eq!(position.state, GameState::Stalemate);
+
+        let (code, output) = run(
+            &[],
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1\n4k3/8/8/8/8/8/8/4K3 w - - 100 80\n",
+        );
+        assert_eq!(code, EXIT_OK);
+        assert!(output.contains("state: draw by insufficient material"));
+        assert!(output.contains("scale 0/16 total 0"));
+    }
+
+    #[test]
+    fn json() {
+        let (code, output) = run(&["--json"], "4k3/8/8/8/8/8/8/3QK3 b - - 0 1\nnot a fen\n");
+        assert_eq!(code, EXIT_INVALID);
+
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(
+            "{\"fen\":\"4k3/8/8/8/8/8/8/3QK3 b - - 0 1\",\"valid\":true,\"errors\":[]"
+        ));
+        assert!(lines[0].contains("\"side_to_move\":\"black\""));
+        assert!(lines[0].contains("\"legal_moves\":3"));
+        assert!(lines[0].contains("\"eval\":{\"material\":900,"));
+        assert_eq!(
+            lines[1],
+            "{\"fen\":\"not a fen\",\"valid\":false,\"errors\":[\"fen error: expected 4 to 6 whitespace delimited fields, found 3\"]}"
+        );
+    }
+
+    #[test]
+    fn usage() {
+        assert_eq!(run(&[], "").0, EXIT_USAGE);
+        assert_eq!(run(&["--json"], "\n\n").0, EXIT_USAGE);
+    }
+}
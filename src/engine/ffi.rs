@@ -0,0 +1,399 @@
+// A C interface for running the engine in-process, as GUIs not written in
+// Rust would rather than talking UCI over pipes. include/chess_rs.h
+// declares it. Build the library with
+//
+//     cargo rustc --release --lib --features cdylib --crate-type cdylib
+//
+// Everything goes through an opaque handle from engine_new, which the
+// caller owns until it is given back to engine_free. Strings are UTF-8 and
+// NUL terminated both ways. The engine only reads the caller's strings
+// during the call, and writes into the caller's buffers, never handing out
+// memory of its own. The functions returning int give 0 (or a count) on
+// success and one of the negative ENGINE_* codes otherwise.
+//
+// No panic crosses the boundary: each call runs inside catch_unwind and a
+// panic becomes ENGINE_PANIC. A handle whose call panicked may be left part
+// way through a change, and should only be freed.
+//
+// Safety: the handle must come from engine_new and not yet be freed, and
+// only be used from one thread at a time. The string arguments must be
+// NULL or valid NUL terminated strings, and each buffer must be writable
+// for the length given with it.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::engine::board::Board;
+use crate::engine::control::ControlHandle;
+use crate::engine::eval::{Evaluate, Evaluator};
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::piece::Pieces;
+use crate::engine::r#move::{MoveUtils, UndoInfo};
+use crate::engine::search::{self, SearchContext};
+use crate::engine::square::Square;
+
+pub const ENGINE_OK: c_int = 0;
+// a required pointer was NULL
+pub const ENGINE_NULL_ARGUMENT: c_int = -1;
+// a string wasn't valid UTF-8
+pub const ENGINE_INVALID_UTF8: c_int = -2;
+pub const ENGINE_INVALID_FEN: c_int = -3;
+pub const ENGINE_ILLEGAL_MOVE: c_int = -4;
+// the output didn't fit in the buffer, which is left empty
+pub const ENGINE_BUFFER_TOO_SMALL: c_int = -5;
+// a search depth of 0 or more than MAX_FFI_DEPTH
+pub const ENGINE_INVALID_DEPTH: c_int = -6;
+// the position is checkmate or stalemate
+pub const ENGINE_NO_LEGAL_MOVES: c_int = -7;
+pub const ENGINE_PANIC: c_int = -8;
+// a square or piece which doesn't parse, a piece put on an occupied square
+// or one taken from an empty square
+pub const ENGINE_INVALID_EDIT: c_int = -9;
+// an edited position which fails Board::validate
+pub const ENGINE_INVALID_POSITION: c_int = -10;
+
+pub const MAX_FFI_DEPTH: c_int = 64;
+
+pub struct FfiEngine {
+    board: Board,
+    move_generator: MoveGenerator,
+    evaluator: Evaluator,
+    context: SearchContext,
+}
+
+// runs the call, turning a panic into ENGINE_PANIC
+fn guard(call: impl FnOnce() -> Result<c_int, c_int>) -> c_int {
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(result)) => result,
+        Ok(Err(code)) => code,
+        Err(_) => ENGINE_PANIC,
+    }
+}
+
+unsafe fn engine_ref<'a>(engine: *mut FfiEngine) -> Result<&'a mut FfiEngine, c_int> {
+    engine.as_mut().ok_or(ENGINE_NULL_ARGUMENT)
+}
+
+// the engine, if its position can be searched
+unsafe fn valid_engine<'a>(engine: *mut FfiEngine) -> Result<&'a mut FfiEngine, c_int> {
+    let engine = engine_ref(engine)?;
+    engine.board.validate().map_err(|_| ENGINE_INVALID_POSITION)?;
+    Ok(engine)
+}
+
+unsafe fn read_square(square: *const c_char) -> Result<Square, c_int> {
+    match read_str(square)? {
+        Some(square) if square.len() == 2 => Square::from_notation(square).ok_or(ENGINE_INVALID_EDIT),
+        Some(_) => Err(ENGINE_INVALID_EDIT),
+        None => Err(ENGINE_NULL_ARGUMENT),
+    }
+}
+
+// the string, or None for NULL
+unsafe fn read_str<'a>(string: *const c_char) -> Result<Option<&'a str>, c_int> {
+    if string.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(string).to_str().map(Some).map_err(|_| ENGINE_INVALID_UTF8)
+}
+
+// copies the string and a NUL into the buffer, if they fit
+unsafe fn write_str(string: &str, out: *mut c_char, len: usize) -> Result<(), c_int> {
+    if out.is_null() {
+        return Err(ENGINE_NULL_ARGUMENT);
+    }
+    if string.len() >= len {
+        if len > 0 {
+            *out = 0;
+        }
+        return Err(ENGINE_BUFFER_TOO_SMALL);
+    }
+
+    std::ptr::copy_nonoverlapping(string.as_ptr() as *const c_char, out, string.len());
+    *out.add(string.len()) = 0;
+    Ok(())
+}
+
+// A new engine at the starting position, or NULL if it couldn't be made.
+#[no_mangle]
+pub extern "C" fn engine_new() -> *mut FfiEngine {
+    catch_unwind(|| {
+        Box::into_raw(Box::new(FfiEngine {
+            board: Board::default(),
+            move_generator: MoveGenerator::new(),
+            evaluator: Evaluator::default(),
+            context: SearchContext::new(),
+        }))
+    })
+    .unwrap_or(std::ptr::null_mut())
+}
+
+// Frees an engine from engine_new. NULL is ignored.
+#[no_mangle]
+pub unsafe extern "C" fn engine_free(engine: *mut FfiEngine) {
+    if !engine.is_null() {
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(engine))));
+    }
+}
+
+// Sets the position from the fen (NULL or "startpos" for the starting
+// position) and the moves played from it in UCI notation, separated by
+// commas (NULL or "" for none). The position is left as it was on error.
+#[no_mangle]
+pub unsafe extern "C" fn engine_set_position(
+    engine: *mut FfiEngine,
+    fen: *const c_char,
+    moves_csv: *const c_char,
+) -> c_int {
+    guard(|| {
+        let engine = engine_ref(engine)?;
+        let mut board = match read_str(fen)? {
+            None | Some("startpos") => Board::default(),
+            Some(fen) => Board::new(fen).map_err(|_| ENGINE_INVALID_FEN)?,
+        };
+
+        let mut info = UndoInfo::default();
+        let moves = read_str(moves_csv)?.unwrap_or("");
+        for token in moves.split(',').map(str::trim).filter(|token| !token.is_empty()) {
+            board
+                .make_uci_move(token, &engine.move_generator, &mut info)
+                .map_err(|_| ENGINE_ILLEGAL_MOVE)?;
+        }
+
+        engine.board = board;
+        Ok(ENGINE_OK)
+    })
+}
+
+// Puts the piece, given by its letter in a fen, on the empty square, given
+// in algebraic notation. Editing leaves the castling rights and en passant
+// square alone, so a position may be invalid until the editing is done.
+// Searching, listing moves from or evaluating an invalid one gives
+// ENGINE_INVALID_POSITION.
+#[no_mangle]
+pub unsafe extern "C" fn engine_put_piece(engine: *mut FfiEngine, square: *const c_char, piece: c_char) -> c_int {
+    guard(|| {
+        let engine = engine_ref(engine)?;
+        let square = read_square(square)?;
+        let piece = Pieces::from_notation(piece as u8 as char).ok_or(ENGINE_INVALID_EDIT)?;
+        engine.board.put_piece(square, piece).map_err(|_| ENGINE_INVALID_EDIT)?;
+        Ok(ENGINE_OK)
+    })
+}
+
+// Takes the piece off the square.
+#[no_mangle]
+pub unsafe extern "C" fn engine_remove_piece(engine: *mut FfiEngine, square: *const c_char) -> c_int {
+    guard(|| {
+        let engine = engine_ref(engine)?;
+        let square = read_square(square)?;
+        engine.board.remove_piece(square).map_err(|_| ENGINE_INVALID_EDIT)?;
+        Ok(ENGINE_OK)
+    })
+}
+
+// Empties the board, as Board::clear, to set up a position from scratch.
+#[no_mangle]
+pub unsafe extern "C" fn engine_clear(engine: *mut FfiEngine) -> c_int {
+    guard(|| {
+        engine_ref(engine)?.board.clear();
+        Ok(ENGINE_OK)
+    })
+}
+
+// Searches the position to the depth and writes the best move in UCI
+// notation into the buffer of len bytes, which 6 always fit.
+#[no_mangle]
+pub unsafe extern "C" fn engine_go_depth(
+    engine: *mut FfiEngine,
+    depth: c_int,
+    out_move: *mut c_char,
+    len: usize,
+) -> c_int {
+    guard(|| {
+        let engine = valid_engine(engine)?;
+        if !(1..=MAX_FFI_DEPTH).contains(&depth) {
+            return Err(ENGINE_INVALID_DEPTH);
+        }
+
+        let depth = depth as usize;
+        let mut board = engine.board.clone();
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let (best_move, _) = search::iterative_deepening(
+            depth,
+            &mut board,
+            &mut engine.evaluator,
+            &engine.move_generator,
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut ControlHandle::new(),
+            &mut engine.context,
+        )
+        .ok_or(ENGINE_NO_LEGAL_MOVES)?;
+
+        write_str(&best_move.move_to_string(), out_move, len)?;
+        Ok(ENGINE_OK)
+    })
+}
+
+// Writes the legal moves in UCI notation, separated by commas, into the
+// buffer of len bytes, and returns how many there are.
+#[no_mangle]
+pub unsafe extern "C" fn engine_legal_moves(engine: *mut FfiEngine, out_buf: *mut c_char, len: usize) -> c_int {
+    guard(|| {
+        let engine = valid_engine(engine)?;
+        let mut moves = MoveList::new();
+        engine.move_generator.gen_moves(&mut engine.board, &mut moves);
+        moves.sort_canonical();
+
+        let list: Vec<String> = (0..moves.len()).map(|i| moves.at(i).move_to_string()).collect();
+        write_str(&list.join(","), out_buf, len)?;
+        Ok(moves.len() as c_int)
+    })
+}
+
+// The static evaluation of the position in centipawns, from the side to
+// move's point of view, written to out_score.
+#[no_mangle]
+pub unsafe extern "C" fn engine_eval(engine: *mut FfiEngine, out_score: *mut c_int) -> c_int {
+    guard(|| {
+        let engine = valid_engine(engine)?;
+        let out_score = out_score.as_mut().ok_or(ENGINE_NULL_ARGUMENT)?;
+        engine.evaluator.full(&engine.board);
+        *out_score = engine.evaluator.evaluate(&engine.board);
+        Ok(ENGINE_OK)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    // the string the engine wrote into the buffer
+    fn read_buf(buf: &[c_char]) -> String {
+        unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn calls() {
+        let engine = engine_new();
+        assert!(!engine.is_null());
+        let mut buf = [0 as c_char; 256];
+
+        unsafe {
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), buf.len()), 20);
+            assert!(read_buf(&buf).starts_with("a2a4,a2a3,b2b4,"), "{}", read_buf(&buf));
+
+            let mut score = 0;
+            let mut evaluator = Evaluator::default();
+            assert_eq!(engine_eval(engine, &mut score), ENGINE_OK);
+            assert_eq!(score, evaluator.full(&Board::default()));
+
+            // mate in one
+            let fen = CString::new("6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1").unwrap();
+            assert_eq!(engine_set_position(engine, fen.as_ptr(), std::ptr::null()), ENGINE_OK);
+            assert_eq!(engine_go_depth(engine, 2, buf.as_mut_ptr(), 6), ENGINE_OK);
+            assert_eq!(read_buf(&buf), "d1d8");
+
+            let moves = CString::new("e2e4, e7e5,g1f3").unwrap();
+            assert_eq!(engine_set_position(engine, std::ptr::null(), moves.as_ptr()), ENGINE_OK);
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), buf.len()), 29);
+            assert_eq!(engine_eval(engine, &mut score), ENGINE_OK);
+            let board = Board::new("rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2").unwrap();
+            evaluator.full(&board);
+            assert_eq!(score, evaluator.evaluate(&board));
+
+            // checkmate
+            let moves = CString::new("f2f3,e7e5,g2g4,d8h4").unwrap();
+            assert_eq!(engine_set_position(engine, std::ptr::null(), moves.as_ptr()), ENGINE_OK);
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), buf.len()), 0);
+            assert_eq!(read_buf(&buf), "");
+            assert_eq!(engine_go_depth(engine, 3, buf.as_mut_ptr(), buf.len()), ENGINE_NO_LEGAL_MOVES);
+
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let engine = engine_new();
+        let mut buf = [0 as c_char; 256];
+
+        unsafe {
+            let bad_fen = CString::new("8/8/8 w").unwrap();
+            assert_eq!(engine_set_position(engine, bad_fen.as_ptr(), std::ptr::null()), ENGINE_INVALID_FEN);
+            let illegal = CString::new("e2e4,e2e4").unwrap();
+            assert_eq!(
+                engine_set_position(engine, std::ptr::null(), illegal.as_ptr()),
+                ENGINE_ILLEGAL_MOVE
+            );
+            let not_utf8 = [0xffu8 as c_char, 0];
+            assert_eq!(
+                engine_set_position(engine, not_utf8.as_ptr(), std::ptr::null()),
+                ENGINE_INVALID_UTF8
+            );
+
+            // the position is unchanged by the failures
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), buf.len()), 20);
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), 10), ENGINE_BUFFER_TOO_SMALL);
+            assert_eq!(read_buf(&buf), "");
+
+            assert_eq!(engine_go_depth(engine, 0, buf.as_mut_ptr(), buf.len()), ENGINE_INVALID_DEPTH);
+            assert_eq!(
+                engine_go_depth(engine, MAX_FFI_DEPTH + 1, buf.as_mut_ptr(), buf.len()),
+                ENGINE_INVALID_DEPTH
+            );
+            assert_eq!(engine_go_depth(engine, 1, std::ptr::null_mut(), 0), ENGINE_NULL_ARGUMENT);
+            assert_eq!(engine_go_depth(engine, 1, buf.as_mut_ptr(), 4), ENGINE_BUFFER_TOO_SMALL);
+            assert_eq!(engine_eval(engine, std::ptr::null_mut()), ENGINE_NULL_ARGUMENT);
+
+            assert_eq!(engine_legal_moves(std::ptr::null_mut(), buf.as_mut_ptr(), buf.len()), ENGINE_NULL_ARGUMENT);
+            engine_free(std::ptr::null_mut());
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn editing() {
+        let engine = engine_new();
+        let mut buf = [0 as c_char; 256];
+        let square = |name: &str| CString::new(name).unwrap();
+
+        unsafe {
+            assert_eq!(engine_clear(engine), ENGINE_OK);
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), buf.len()), ENGINE_INVALID_POSITION);
+
+            // a rook mate once both kings are on
+            assert_eq!(engine_put_piece(engine, square("g1").as_ptr(), b'K' as c_char), ENGINE_OK);
+            assert_eq!(engine_put_piece(engine, square("d1").as_ptr(), b'R' as c_char), ENGINE_OK);
+            assert_eq!(engine_go_depth(engine, 2, buf.as_mut_ptr(), buf.len()), ENGINE_INVALID_POSITION);
+            assert_eq!(engine_put_piece(engine, square("g8").as_ptr(), b'k' as c_char), ENGINE_OK);
+            for name in ["f7", "g7", "h7"] {
+                assert_eq!(engine_put_piece(engine, square(name).as_ptr(), b'p' as c_char), ENGINE_OK);
+            }
+            assert_eq!(engine_go_depth(engine, 2, buf.as_mut_ptr(), buf.len()), ENGINE_OK);
+            assert_eq!(read_buf(&buf), "d1d8");
+
+            assert_eq!(engine_put_piece(engine, square("d1").as_ptr(), b'Q' as c_char), ENGINE_INVALID_EDIT);
+            assert_eq!(engine_put_piece(engine, square("d2").as_ptr(), b'x' as c_char), ENGINE_INVALID_EDIT);
+            assert_eq!(engine_put_piece(engine, square("d9").as_ptr(), b'Q' as c_char), ENGINE_INVALID_EDIT);
+            assert_eq!(engine_put_piece(engine, std::ptr::null(), b'Q' as c_char), ENGINE_NULL_ARGUMENT);
+            assert_eq!(engine_remove_piece(engine, square("d2").as_ptr()), ENGINE_INVALID_EDIT);
+            assert_eq!(engine_remove_piece(engine, square("d1").as_ptr()), ENGINE_OK);
+            assert_eq!(engine_legal_moves(engine, buf.as_mut_ptr(), buf.len()), 5);
+
+            engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn panics_are_caught() {
+        assert_eq!(guard(|| panic!("inside the engine")), ENGINE_PANIC);
+        assert_eq!(guard(|| Ok(3)), 3);
+    }
+}
@@ -0,0 +1,376 @@
+// Bounded randomized robustness tests for everything that parses untrusted
+// text. Each run is seeded so failures reproduce; set CHESS_RS_FUZZ_ITERATIONS
+// to run more (or fewer) cases. Inputs that once caused problems are checked
+// in under fuzz/corpus and replayed on every run.
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::engine::board::Board;
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::movepick::{History, MovePicker};
+use crate::engine::r#move::{Move, MoveUtils, UndoInfo};
+use crate::engine::reference;
+use crate::engine::square::Square;
+use crate::engine::uci::{self, CapturedOutput, UciEngine, UciOutput};
+
+const FEN_CORPUS: &str = include_str!("../../fuzz/corpus/fen.txt");
+const UCI_CORPUS: &str = include_str!("../../fuzz/corpus/uci.txt");
+
+const DEFAULT_ITERATIONS: usize = 2000;
+// random playouts are restarted after this many moves
+const MAX_PLAYOUT_PLIES: usize = 200;
+
+const FEN_ALPHABET: &[u8] = b"pnbrqkPNBRQK12345678/ wb-KQkqabcdefgh0123456789x\xc3\xa9";
+const UCI_TOKENS: &[&str] = &[
+    "position", "startpos", "fen", "moves", "d", "isready", "ucinewgame", "uci", "stop", "e2e4",
+    "e7e5", "g1f3", "e1g1", "a7a8q", "a2a1n", "0000", "e2e9", "-", "w", "b", "KQkq", "0", "1",
+];
+
+fn iterations() -> usize {
+    std::env::var("CHESS_RS_FUZZ_ITERATIONS")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(DEFAULT_ITERATIONS)
+}
+
+fn corpus(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter(|line| !line.starts_with('#'))
+}
+
+fn random_input(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(0..80);
+
+    let bytes: Vec<u8> = if rng.gen_bool(0.5) {
+        // arbitrary bytes
+        (0..len).map(|_| rng.gen()).collect()
+    } else {
+        // bytes drawn from FEN-like characters
+        (0..len)
+            .map(|_| FEN_ALPHABET[rng.gen_range(0..FEN_ALPHABET.len())])
+            .collect()
+    };
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// a random mutation of a known good fen, which gets much deeper into the
+// parser than random bytes do
+fn mutated_fen(rng: &mut StdRng) -> String {
+    let valid: Vec<&str> = corpus(FEN_CORPUS)
+        .filter(|line| !line.starts_with('!'))
+        .collect();
+    let mut bytes = valid[rng.gen_range(0..valid.len())].as_bytes().to_vec();
+
+    for _ in 0..rng.gen_range(1..4) {
+        let c = FEN_ALPHABET[rng.gen_range(0..FEN_ALPHABET.len())];
+        let idx = rng.gen_range(0..=bytes.len());
+
+        match rng.gen_range(0..3) {
+            0 => bytes.insert(idx, c),
+            1 if idx < bytes.len() => bytes[idx] = c,
+            _ if idx < bytes.len() => {
+                bytes.remove(idx);
+            }
+            _ => {}
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn random_uci_line(rng: &mut StdRng) -> String {
+    let len = rng.gen_range(0..10);
+    let mut tokens: Vec<String> = Vec::new();
+
+    for _ in 0..len {
+        match rng.gen_range(0..8) {
+            0 => tokens.push(random_input(rng)),
+            1 => tokens.push(mutated_fen(rng)),
+            _ => tokens.push(UCI_TOKENS[rng.gen_range(0..UCI_TOKENS.len())].to_string()),
+        }
+    }
+
+    tokens.join(" ")
+}
+
+// any fen that loads must survive a round trip unchanged
+fn check_fen(fen: &str) -> bool {
+    match Board::new(fen) {
+        Ok(board) => {
+            let written = board.to_fen();
+            let reloaded = Board::new(&written)
+                .unwrap_or_else(|e| panic!("'{}' loaded but '{}' did not: {}", fen, written, e));
+            assert_eq!(reloaded.to_fen(), written, "fen round trip of '{}'", fen);
+
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+#[test]
+fn fen_corpus() {
+    for line in corpus(FEN_CORPUS) {
+        match line.strip_prefix('!') {
+            Some(fen) => assert!(!check_fen(fen), "'{}' should be rejected", fen),
+            None => assert!(check_fen(line), "'{}' should load", line),
+        }
+    }
+}
+
+#[test]
+fn fen_never_panics() {
+    let mut rng = StdRng::seed_from_u64(0x1411);
+
+    for _ in 0..iterations() {
+        let input = if rng.gen_bool(0.5) {
+            random_input(&mut rng)
+        } else {
+            mutated_fen(&mut rng)
+        };
+
+        check_fen(&input);
+
+        // a failed load may leave the board in any state, but must not panic
+        let mut board = Board::default();
+        let _ = board.load_fen(&input);
+    }
+}
+
+#[test]
+fn uci_move_never_panics() {
+    let mut rng = StdRng::seed_from_u64(0x1412);
+    let generator = MoveGenerator::new();
+    let mut info = UndoInfo::default();
+
+    for _ in 0..iterations() {
+        let input = random_input(&mut rng);
+
+        let _ = Square::from_notation(&input);
+
+        let mut board = Board::default();
+        if board.make_uci_move(&input, &generator, &mut info).is_err() {
+            assert_eq!(board.to_fen(), Board::default().to_fen());
+        }
+    }
+}
+
+#[test]
+fn uci_corpus() {
+    let captured = CapturedOutput::default();
+    let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+
+    for line in corpus(UCI_CORPUS) {
+        assert!(engine.handle_command(line));
+    }
+    assert!(!engine.handle_command("quit"));
+
+    assert!(captured.lines().iter().any(|line| line == "readyok"));
+}
+
+#[test]
+fn uci_commands_never_panic() {
+    let mut rng = StdRng::seed_from_u64(0x1413);
+    let mut engine = UciEngine::new(UciOutput::new(CapturedOutput::default()));
+
+    for _ in 0..iterations() {
+        let line = random_uci_line(&mut rng);
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        // searching random positions is too slow to do here, but the
+        // argument parsing is still exercised
+        let _ = uci::parse_go(&tokens);
+        if !matches!(tokens.first(), Some(&"quit") | Some(&"go")) {
+            engine.handle_command(&line);
+        }
+    }
+}
+
+// compares MoveGenerator with the reference generator over positions from
+// random playouts, which finds bugs that cancel out in perft totals. The
+// filtered pseudo-legal moves are checked against the legal ones too, and
+// the checkers found while generating against is_in_check.
+#[test]
+fn movegen_matches_reference() {
+    let mut rng = StdRng::seed_from_u64(0x1423);
+    let generator = MoveGenerator::new();
+    let starts: Vec<&str> = corpus(FEN_CORPUS)
+        .filter(|line| !line.starts_with('!'))
+        .collect();
+
+    let mut board = Board::default();
+    let mut plies = 0;
+    let mut moves = MoveList::new();
+    let mut pseudo_legal = MoveList::new();
+    let mut info = UndoInfo::default();
+
+    for _ in 0..iterations() {
+        let check_info = generator.gen_moves(&mut board, &mut moves);
+        assert_eq!(check_info, generator.checkers_and_pins(&board), "'{}'", board.to_fen());
+        assert_eq!(check_info.checkers != 0, generator.is_in_check(&mut board), "'{}'", board.to_fen());
+
+        let mut actual: Vec<String> = (0..moves.len()).map(|i| moves.at(i).move_to_string()).collect();
+        let mut expected = reference::legal_moves(&board);
+        actual.sort();
+        expected.sort();
+
+        if actual != expected {
+            let missing: Vec<&String> = expected.iter().filter(|m| !actual.contains(m)).collect();
+            let extra: Vec<&String> = actual.iter().filter(|m| !expected.contains(m)).collect();
+            panic!(
+                "move generation differs from the reference in '{}'\n  missing: {:?}\n  extra: {:?}\n  generated {} moves, expected {}",
+                board.to_fen(),
+                missing,
+                extra,
+                actual.len(),
+                expected.len()
+            );
+        }
+
+        // the pseudo-legal moves less those leaving the king in check are
+        // the same moves
+        generator.gen_pseudo_legal(&board, &mut pseudo_legal);
+        let mut filtered: Vec<Move> = (0..pseudo_legal.len())
+            .map(|i| pseudo_legal.at(i))
+            .filter(|&my_move| !board.leaves_king_in_check(my_move, &generator))
+            .collect();
+        let mut legal: Vec<Move> = (0..moves.len()).map(|i| moves.at(i)).collect();
+        filtered.sort_unstable();
+        legal.sort_unstable();
+        assert_eq!(filtered, legal, "pseudo-legal moves in '{}'", board.to_fen());
+
+        if moves.is_empty() || plies >= MAX_PLAYOUT_PLIES {
+            board = Board::new(starts[rng.gen_range(0..starts.len())]).unwrap();
+            plies = 0;
+        } else {
+            board.make_move(moves.at(rng.gen_range(0..moves.len())), &mut info);
+            plies += 1;
+        }
+    }
+}
+
+// A move which may or may not be legal in the position: one of its own, one
+// from the position before, or any encoding at all, the kinds of move a
+// picker is given from elsewhere.
+fn candidate_move(rng: &mut StdRng, own: &[Move], previous: &[Move]) -> Move {
+    match rng.gen_range(0..3) {
+        0 if !own.is_empty() => own[rng.gen_range(0..own.len())],
+        1 if !previous.is_empty() => previous[rng.gen_range(0..previous.len())],
+        _ => rng.gen(),
+    }
+}
+
+// checks is_pseudo_legal against the pseudo-legal generator, and that a
+// MovePicker given such moves returns each legal move exactly once
+#[test]
+fn move_picker_matches_movegen() {
+    let mut rng = StdRng::seed_from_u64(0x1446);
+    let generator = MoveGenerator::new();
+    let starts: Vec<&str> = corpus(FEN_CORPUS)
+        .filter(|line| !line.starts_with('!'))
+        .collect();
+
+    let mut board = Board::default();
+    let mut plies = 0;
+    let mut moves = MoveList::new();
+    let mut picked = MoveList::new();
+    let mut info = UndoInfo::default();
+    let mut previous: Vec<Move> = Vec::new();
+    let mut history = History::default();
+
+    for _ in 0..iterations() {
+        generator.gen_pseudo_legal(&board, &mut moves);
+        let pseudo_legal: Vec<Move> = (0..moves.len()).map(|i| moves.at(i)).collect();
+        generator.gen_moves(&mut board, &mut moves);
+        let mut legal: Vec<Move> = (0..moves.len()).map(|i| moves.at(i)).collect();
+
+        for _ in 0..20 {
+            let my_move = candidate_move(&mut rng, &pseudo_legal, &previous);
+            assert_eq!(
+                generator.is_pseudo_legal(&board, my_move),
+                pseudo_legal.contains(&my_move),
+                "is_pseudo_legal({}) in '{}'",
+                my_move.move_to_string(),
+                board.to_fen()
+            );
+        }
+
+        let tt_move = Some(candidate_move(&mut rng, &legal, &previous));
+        let killers = [
+            Some(candidate_move(&mut rng, &legal, &previous)),
+            Some(candidate_move(&mut rng, &legal, &previous)),
+        ];
+        let countermove = Some(candidate_move(&mut rng, &legal, &previous));
+        let mut picker = MovePicker::new(tt_move).with_refutations(killers, countermove);
+        let history_move = candidate_move(&mut rng, &legal, &previous);
+        history.update(board.friendly_color(), history_move, rng.gen_range(1..20));
+
+        let fen = board.to_fen();
+        let mut returned = Vec::new();
+        while let Some(my_move) = picker.next(&mut board, &generator, &mut picked, &history) {
+            returned.push(my_move);
+        }
+        assert_eq!(board.to_fen(), fen);
+        assert_eq!(picker.checkers(), Some(generator.checkers_and_pins(&board).checkers));
+
+        returned.sort_unstable();
+        legal.sort_unstable();
+        assert_eq!(returned, legal, "moves picked in '{}'", fen);
+
+        previous = pseudo_legal;
+        if moves.is_empty() || plies >= MAX_PLAYOUT_PLIES {
+            board = Board::new(starts[rng.gen_range(0..starts.len())]).unwrap();
+            plies = 0;
+        } else {
+            board.make_move(moves.at(rng.gen_range(0..moves.len())), &mut info);
+            plies += 1;
+        }
+    }
+}
+
+// Any u16 can come out of a corrupt table entry. The moves generated all
+// have shapes which pass, and a random encoding, or a legal move with its
+// flags changed, never panics on its way through the shape and legality
+// checks, or gets past them unless it is one of the position's moves.
+#[test]
+fn random_move_encodings() {
+    let mut rng = StdRng::seed_from_u64(0x1483);
+    let generator = MoveGenerator::new();
+    let starts: Vec<&str> = corpus(FEN_CORPUS)
+        .filter(|line| !line.starts_with('!'))
+        .collect();
+
+    let mut board = Board::default();
+    let mut plies = 0;
+    let mut moves = MoveList::new();
+    let mut info = UndoInfo::default();
+
+    for _ in 0..iterations() {
+        generator.gen_moves(&mut board, &mut moves);
+        let legal: Vec<Move> = (0..moves.len()).map(|i| moves.at(i)).collect();
+        assert!(legal.iter().all(|my_move| my_move.validate_shape()), "'{}'", board.to_fen());
+
+        for _ in 0..20 {
+            let my_move: Move = match rng.gen_bool(0.5) {
+                true if !legal.is_empty() => legal[rng.gen_range(0..legal.len())] ^ rng.gen_range(1..16),
+                _ => rng.gen(),
+            };
+            if !generator.is_pseudo_legal(&board, my_move) {
+                continue;
+            }
+            assert!(my_move.validate_shape());
+
+            let is_legal = !board.leaves_king_in_check(my_move, &generator);
+            assert_eq!(is_legal, legal.contains(&my_move), "{} in '{}'", my_move.move_to_string(), board.to_fen());
+        }
+
+        if legal.is_empty() || plies >= MAX_PLAYOUT_PLIES {
+            board = Board::new(starts[rng.gen_range(0..starts.len())]).unwrap();
+            plies = 0;
+        } else {
+            board.make_move(legal[rng.gen_range(0..legal.len())], &mut info);
+            plies += 1;
+        }
+    }
+}
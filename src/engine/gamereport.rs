@@ -0,0 +1,127 @@
+// A résumé of what the engine did over a game, for looking back at its
+// play from a GUI's log: how many times it was asked to move, how deep it
+// got, and how it spent its time. The UCI engine adds each search to it,
+// starts again at each new game, and sends it on quit or when asked with
+// the `stats` command.
+
+// what one go search did
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SearchRecord {
+    // the deepest iteration completed
+    pub depth: usize,
+    pub nodes: u64,
+    pub millis: u64,
+    // the hard time limit, for a search with one
+    pub allocated_millis: Option<u64>,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GameReport {
+    pub searches: usize,
+    pub depth_total: usize,
+    pub max_depth: usize,
+    pub nodes: u64,
+    pub millis: u64,
+    // the time given to the searches which had a limit, and what they used
+    pub allocated_millis: u64,
+    pub limited_millis: u64,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+}
+
+impl GameReport {
+    pub fn record(&mut self, search: &SearchRecord) {
+        self.searches += 1;
+        self.depth_total += search.depth;
+        self.max_depth = self.max_depth.max(search.depth);
+        self.nodes += search.nodes;
+        self.millis += search.millis;
+        if let Some(allocated) = search.allocated_millis {
+            self.allocated_millis += allocated;
+            self.limited_millis += search.millis;
+        }
+        self.tt_probes += search.tt_probes;
+        self.tt_hits += search.tt_hits;
+    }
+
+    pub fn average_depth(&self) -> f64 {
+        match self.searches {
+            0 => 0.0,
+            searches => self.depth_total as f64 / searches as f64,
+        }
+    }
+
+    // the percentage of looks in the table which found the position
+    pub fn tt_hit_rate(&self) -> f64 {
+        match self.tt_probes {
+            0 => 0.0,
+            probes => self.tt_hits as f64 * 100.0 / probes as f64,
+        }
+    }
+
+    // the summary, a line at a time
+    pub fn lines(&self) -> Vec<String> {
+        vec![
+            format!("game summary: {} searches", self.searches),
+            format!("depth: average {:.2} max {}", self.average_depth(), self.max_depth),
+            format!(
+                "nodes: {} nps {}",
+                self.nodes,
+                self.nodes * 1000 / self.millis.max(1)
+            ),
+            format!(
+                "time: {}ms, of which {}ms used of {}ms allocated",
+                self.millis, self.limited_millis, self.allocated_millis
+            ),
+            format!(
+                "tt: {} hits of {} probes ({:.1}%)",
+                self.tt_hits,
+                self.tt_probes,
+                self.tt_hit_rate()
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let mut report = GameReport::default();
+        assert_eq!((report.average_depth(), report.tt_hit_rate()), (0.0, 0.0));
+
+        report.record(&SearchRecord {
+            depth: 4,
+            nodes: 1000,
+            millis: 100,
+            allocated_millis: Some(300),
+            tt_probes: 400,
+            tt_hits: 100,
+        });
+        report.record(&SearchRecord {
+            depth: 6,
+            nodes: 3000,
+            millis: 900,
+            allocated_millis: None,
+            tt_probes: 600,
+            tt_hits: 300,
+        });
+
+        assert_eq!(report.average_depth(), 5.0);
+        assert_eq!(report.tt_hit_rate(), 40.0);
+        assert_eq!(
+            report.lines(),
+            [
+                "game summary: 2 searches",
+                "depth: average 5.00 max 6",
+                "nodes: 4000 nps 4000",
+                "time: 1000ms, of which 100ms used of 300ms allocated",
+                "tt: 400 hits of 1000 probes (40.0%)",
+            ]
+        );
+    }
+}
@@ -0,0 +1,231 @@
+// Verbatim log of the UCI conversation, for debugging problems with GUIs.
+//
+// Lines are prefixed with a UTC timestamp and `>> ` for input or `<< ` for
+// output. They are written by a separate thread so that a slow disk never
+// blocks the engine; if the thread falls too far behind, lines are dropped
+// and a warning is written in their place. The file is only created once
+// the first line is logged, and if that fails the error is kept for the
+// engine to report.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+
+use crate::engine::error::LogError;
+
+extern crate time;
+use time::OffsetDateTime;
+
+// lines which can be waiting to be written before any more are dropped
+const QUEUE_SIZE: usize = 1024;
+
+struct LogThread {
+    sender: Option<SyncSender<String>>,
+    handle: Option<JoinHandle<()>>,
+    dropped: Arc<AtomicUsize>,
+    errors: Arc<Mutex<Vec<LogError>>>,
+}
+
+impl Drop for LogThread {
+    fn drop(&mut self) {
+        // closing the channel ends the thread once it has written everything
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct IoLog {
+    thread: Arc<LogThread>,
+}
+
+impl IoLog {
+    pub fn new(path: &str) -> IoLog {
+        IoLog::with_queue_size(path, QUEUE_SIZE)
+    }
+
+    fn with_queue_size(path: &str, queue_size: usize) -> IoLog {
+        let (sender, receiver) = mpsc::sync_channel(queue_size);
+        let dropped = Arc::new(AtomicUsize::new(0));
+        let errors = Arc::new(Mutex::new(Vec::new()));
+
+        let path = path.to_string();
+        let thread_dropped = Arc::clone(&dropped);
+        let thread_errors = Arc::clone(&errors);
+        let handle = std::thread::spawn(move || write_lines(&path, receiver, &thread_dropped, &thread_errors));
+
+        IoLog {
+            thread: Arc::new(LogThread {
+                sender: Some(sender),
+                handle: Some(handle),
+                dropped,
+                errors,
+            }),
+        }
+    }
+
+    // Why the file couldn't be opened, if it couldn't, since the last call.
+    // It is opened by the thread writing the log, which has no way to tell
+    // the gui, so the failure is kept for the engine to report.
+    pub fn take_errors(&self) -> Vec<LogError> {
+        std::mem::take(&mut self.thread.errors.lock().unwrap())
+    }
+
+    // a line read from the gui
+    pub fn received(&self, line: &str) {
+        self.log(">>", line);
+    }
+    // a line sent to the gui
+    pub fn sent(&self, line: &str) {
+        self.log("<<", line);
+    }
+
+    fn log(&self, prefix: &str, line: &str) {
+        let now = OffsetDateTime::now_utc();
+        let line = format!(
+            "{:02}:{:02}:{:02}.{:03} {} {}",
+            now.hour(),
+            now.minute(),
+            now.second(),
+            now.millisecond(),
+            prefix,
+            line.trim_end()
+        );
+
+        if let Some(sender) = &self.thread.sender {
+            if let Err(TrySendError::Full(_)) = sender.try_send(line) {
+                self.thread.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+fn write_lines(path: &str, receiver: Receiver<String>, dropped: &AtomicUsize, errors: &Mutex<Vec<LogError>>) {
+    let mut file: Option<File> = None;
+
+    for line in receiver {
+        if file.is_none() {
+            file = match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(file),
+                Err(e) => {
+                    errors.lock().unwrap().push(LogError::Io(path.to_string(), e.to_string()));
+                    return;
+                }
+            };
+        }
+        let file = file.as_mut().unwrap();
+
+        let missed = dropped.swap(0, Ordering::Relaxed);
+        if missed > 0 {
+            let _ = writeln!(file, "warning: {} lines were dropped from the log", missed);
+        }
+
+        let _ = writeln!(file, "{}", line);
+        let _ = file.flush();
+    }
+
+    let missed = dropped.swap(0, Ordering::Relaxed);
+    if let (Some(file), true) = (file.as_mut(), missed > 0) {
+        let _ = writeln!(file, "warning: {} lines were dropped from the log", missed);
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn temp_path(name: &str) -> String {
+    let path = std::env::temp_dir().join(format!("chess_rs_{}_{}", std::process::id(), name));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_timestamps(contents: &str) -> Vec<&str> {
+        contents.lines().map(|line| &line[13..]).collect()
+    }
+
+    #[test]
+    fn lazy_creation() {
+        let path = temp_path("lazy.log");
+
+        let log = IoLog::new(&path);
+        drop(log);
+        assert!(std::fs::metadata(&path).is_err());
+
+        let log = IoLog::new(&path);
+        log.received("uci");
+        log.sent("uciok");
+        drop(log);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(strip_timestamps(&contents), vec![">> uci", "<< uciok"]);
+
+        // timestamps look like 12:34:56.789
+        let timestamp = &contents[..12];
+        assert_eq!(timestamp.as_bytes()[2], b':');
+        assert_eq!(timestamp.as_bytes()[8], b'.');
+        assert!(timestamp.chars().filter(|c| c.is_ascii_digit()).count() == 9);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn overflow_warns() {
+        let path = temp_path("overflow.log");
+
+        let log = IoLog::with_queue_size(&path, 1);
+        for i in 0..10000 {
+            log.sent(&i.to_string());
+        }
+        drop(log);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        let logged = lines.iter().filter(|line| line.contains(" << ")).count();
+
+        // dropped lines are counted in warnings, and the order is kept
+        let warned: usize = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("warning: "))
+            .map(|line| line.split(' ').next().unwrap().parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(logged + warned, 10000);
+
+        let numbers: Vec<usize> = lines
+            .iter()
+            .filter(|line| line.contains(" << "))
+            .map(|line| line[16..].parse().unwrap())
+            .collect();
+        assert!(numbers.windows(2).all(|w| w[0] < w[1]));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_error_kept() {
+        let path = temp_path("missing/open.log");
+
+        // the file is opened by the thread, so the error turns up later
+        let log = IoLog::new(&path);
+        log.received("uci");
+        let mut errors = log.take_errors();
+        for _ in 0..100 {
+            if !errors.is_empty() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            errors = log.take_errors();
+        }
+        assert!(matches!(&errors[..], [LogError::Io(error_path, _)] if *error_path == path), "{:?}", errors);
+
+        // and is only given once
+        log.sent("uciok");
+        assert!(log.take_errors().is_empty());
+    }
+}
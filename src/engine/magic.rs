@@ -0,0 +1,359 @@
+// Generates the sliding piece tables used by Attacks and MoveGenerator.
+// Normally build.rs runs this and writes the tables into the binary, so this
+// file is also compiled as part of the build script and must not depend on
+// the rest of the crate. With the `runtime_tables` feature they are generated
+// each time an Attacks is created instead.
+#![cfg_attr(not(feature = "runtime_tables"), allow(dead_code))]
+
+// Rook and bishop attacks are stored packed, one after the other, each square
+// using a slice of 2^(number of bits in its mask) entries
+pub struct SliderTables {
+    pub rook_masks: [u64; 64],
+    pub bishop_masks: [u64; 64],
+
+    pub rook_magic_shifts: [usize; 64],
+    pub bishop_magic_shifts: [usize; 64],
+
+    // where each square's slice of the attack tables starts
+    pub rook_offsets: [usize; 64],
+    pub bishop_offsets: [usize; 64],
+
+    pub rook_moves: Vec<u64>,
+    pub bishop_moves: Vec<u64>,
+
+    // the squares strictly between two squares on a line, or none
+    pub slider_range: [[u64; 64]; 64],
+
+    // indexed by a bitmask of Ranks/Files, all of those ranks or files
+    pub ranks: [u64; 256],
+    pub files: [u64; 256],
+
+    pub not_ranks: [u64; 256],
+    pub not_files: [u64; 256],
+}
+
+// ranks and files are numbered from a8, so rank 0 is the 8th rank
+fn sq(rank: i16, file: i16) -> usize {
+    (rank * 8 + file) as usize
+}
+
+fn valid(rank: i16, file: i16) -> bool {
+    (0..8).contains(&rank) && (0..8).contains(&file)
+}
+
+const ROOK_DIRECTIONS: [(i16, i16); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const BISHOP_DIRECTIONS: [(i16, i16); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+
+// the squares whose occupancy matters, which excludes the edge of the
+// board in each direction
+fn gen_mask(start: usize, directions: &[(i16, i16)]) -> u64 {
+    let mut result = 0;
+
+    for &(dr, df) in directions {
+        let mut r = (start / 8) as i16 + dr;
+        let mut f = (start % 8) as i16 + df;
+        while valid(r + dr, f + df) {
+            result |= 1 << sq(r, f);
+            r += dr;
+            f += df;
+        }
+    }
+
+    result
+}
+
+// the slow way of finding the attacks, for filling in the tables
+fn gen_moves(start: usize, occupancy: u64, directions: &[(i16, i16)]) -> u64 {
+    let mut result = 0;
+
+    for &(dr, df) in directions {
+        let mut r = (start / 8) as i16 + dr;
+        let mut f = (start % 8) as i16 + df;
+        while valid(r, f) {
+            let pos = sq(r, f);
+            result |= 1 << pos;
+            if occupancy & (1 << pos) != 0 {
+                break;
+            }
+            r += dr;
+            f += df;
+        }
+    }
+
+    result
+}
+
+pub fn gen_rook_moves(start: usize, occupancy: u64) -> u64 {
+    gen_moves(start, occupancy, &ROOK_DIRECTIONS)
+}
+pub fn gen_bishop_moves(start: usize, occupancy: u64) -> u64 {
+    gen_moves(start, occupancy, &BISHOP_DIRECTIONS)
+}
+
+// the idx'th subset of the mask, using the bits of idx in order
+pub fn idx_to_u64(idx: usize, mut mask: u64) -> u64 {
+    let mut result = 0;
+
+    let mut i = 0;
+    while idx != 0 && mask != 0 {
+        let pos = mask.trailing_zeros();
+        mask &= mask - 1;
+        if idx & (1 << i) != 0 {
+            result |= 1 << pos;
+        }
+        i += 1;
+    }
+
+    result
+}
+
+fn gen_attacks(
+    masks: &[u64; 64],
+    magics: &[u64; 64],
+    shifts: &[usize; 64],
+    offsets: &mut [usize; 64],
+    gen: fn(usize, u64) -> u64,
+) -> Vec<u64> {
+    let mut attacks = Vec::new();
+
+    for i in 0..64 {
+        offsets[i] = attacks.len();
+        attacks.resize(attacks.len() + (1 << masks[i].count_ones()), 0);
+
+        for idx in 0..(1 << masks[i].count_ones()) {
+            let occupancy = idx_to_u64(idx, masks[i]);
+            let key = u64::wrapping_mul(magics[i], occupancy) >> shifts[i];
+            attacks[offsets[i] + key as usize] = gen(i, occupancy);
+        }
+    }
+
+    attacks
+}
+
+fn gen_slider_range(start: usize, end: usize) -> u64 {
+    let (r1, f1) = ((start / 8) as i16, (start % 8) as i16);
+    let (r2, f2) = ((end / 8) as i16, (end % 8) as i16);
+    let (dr, df) = (r2 - r1, f2 - f1);
+
+    let on_line = (dr == 0 || df == 0 || dr.abs() == df.abs()) && start != end;
+    if !on_line {
+        return 0;
+    }
+
+    let (dr, df) = (dr.signum(), df.signum());
+    let mut result = 0;
+    let (mut r, mut f) = (r1 + dr, f1 + df);
+    while (r, f) != (r2, f2) {
+        result |= 1 << sq(r, f);
+        r += dr;
+        f += df;
+    }
+
+    result
+}
+
+impl SliderTables {
+    pub fn generate() -> SliderTables {
+        let mut tables = SliderTables {
+            rook_masks: [0; 64],
+            bishop_masks: [0; 64],
+            rook_magic_shifts: [0; 64],
+            bishop_magic_shifts: [0; 64],
+            rook_offsets: [0; 64],
+            bishop_offsets: [0; 64],
+            rook_moves: Vec::new(),
+            bishop_moves: Vec::new(),
+            slider_range: [[0; 64]; 64],
+            ranks: [0; 256],
+            files: [0; 256],
+            not_ranks: [0; 256],
+            not_files: [0; 256],
+        };
+
+        // ranks & files
+        for i in 0..8 {
+            tables.files[1 << i] = 0x0101010101010101 << i;
+            tables.ranks[1 << i] = 0xff << (8 * (7 - i));
+        }
+        for i in 0..256 {
+            for j in 0..8 {
+                if i & (1 << j) != 0 {
+                    tables.files[i] |= tables.files[1 << j];
+                    tables.ranks[i] |= tables.ranks[1 << j];
+                }
+            }
+            tables.not_files[i] = !tables.files[i];
+            tables.not_ranks[i] = !tables.ranks[i];
+        }
+
+        // masks and shifts
+        for i in 0..64 {
+            tables.rook_masks[i] = gen_mask(i, &ROOK_DIRECTIONS);
+            tables.bishop_masks[i] = gen_mask(i, &BISHOP_DIRECTIONS);
+
+            tables.rook_magic_shifts[i] = 64 - tables.rook_masks[i].count_ones() as usize;
+            tables.bishop_magic_shifts[i] = 64 - tables.bishop_masks[i].count_ones() as usize;
+        }
+
+        // move tables
+        tables.rook_moves = gen_attacks(
+            &tables.rook_masks,
+            &ROOK_MAGICS,
+            &tables.rook_magic_shifts,
+            &mut tables.rook_offsets,
+            gen_rook_moves,
+        );
+        tables.bishop_moves = gen_attacks(
+            &tables.bishop_masks,
+            &BISHOP_MAGICS,
+            &tables.bishop_magic_shifts,
+            &mut tables.bishop_offsets,
+            gen_bishop_moves,
+        );
+
+        // slider range
+        for start in 0..64 {
+            for end in 0..64 {
+                tables.slider_range[start][end] = gen_slider_range(start, end);
+            }
+        }
+
+        tables
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                            Rook & Bishop Magics                            */
+/* -------------------------------------------------------------------------- */
+pub const ROOK_MAGICS: [u64; 64] = [
+    72075735983988992u64,
+    162164771226042368u64,
+    2774234964794286080u64,
+    9295447227374240800u64,
+    7133704077631881220u64,
+    5404321769049293056u64,
+    13871089051341160576u64,
+    4647732546161868928u64,
+    1154188151204364296u64,
+    281623304421378u64,
+    9585349132126560768u64,
+    324399945019818112u64,
+    1266654575591552u64,
+    294422971669283848u64,
+    9228016932324638976u64,
+    422213622698112u64,
+    18019346383143456u64,
+    13519870926790656u64,
+    6917743432679031040u64,
+    4611968593184169992u64,
+    12170978542791720968u64,
+    144159173373870084u64,
+    73228578216739328u64,
+    2199036100765u64,
+    56330731617533952u64,
+    148619063654883328u64,
+    4625232012420055168u64,
+    14988261623278407680u64,
+    1478588125675784194u64,
+    577024260602875912u64,
+    2468254118020653568u64,
+    144256209032118404u64,
+    40577751509369480u64,
+    6917564213158219778u64,
+    9007478444400656u64,
+    20839044434890752u64,
+    4611976300242928640u64,
+    4617878489423415312u64,
+    11278859869620225u64,
+    288230653210657060u64,
+    576531123197214720u64,
+    844699816624161u64,
+    4616198431329755136u64,
+    1513221569692893216u64,
+    12125942013883416584u64,
+    4613005570896036100u64,
+    72066394459734032u64,
+    1765429764459462660u64,
+    342291713626218624u64,
+    22518273021051200u64,
+    9464597434109056u64,
+    613052534176650752u64,
+    20547690614100224u64,
+    140746078552192u64,
+    45044801233552384u64,
+    27028749086179840u64,
+    290556685111457u64,
+    288865903000617090u64,
+    1161084417409045u64,
+    289075918041778209u64,
+    2522578810537804930u64,
+    1298444514277720065u64,
+    1143496522109444u64,
+    2305843716071555138u64,
+];
+pub const BISHOP_MAGICS: [u64; 64] = [
+    1179020146311185u64,
+    145267478427205635u64,
+    4504158111531524u64,
+    9224516499644878888u64,
+    144680405855912002u64,
+    4619005622497574912u64,
+    1130315234418688u64,
+    5349125176573952u64,
+    6071010655858065920u64,
+    20310248111767713u64,
+    1297094009090539520u64,
+    4616233778910625860u64,
+    2305849615159678976u64,
+    74381998193642242u64,
+    1407684255942661u64,
+    2305862803678299144u64,
+    22535635693734016u64,
+    4503608284938884u64,
+    11259016393073153u64,
+    108650578499878976u64,
+    41095363813851170u64,
+    9232520132522148096u64,
+    70385943187776u64,
+    9227035893351617024u64,
+    1155182103739172867u64,
+    11530343153862181120u64,
+    2295791083930624u64,
+    1130297991168512u64,
+    281543712980996u64,
+    307513611096490433u64,
+    2289183226103316u64,
+    4612816874811392128u64,
+    4547891544985604u64,
+    3458958372559659520u64,
+    303473866573824u64,
+    1729558217427519744u64,
+    5633914760597520u64,
+    1441434463836899328u64,
+    20269028707403544u64,
+    149744981853258752u64,
+    2252933819802113u64,
+    1163074498090533888u64,
+    4681729134575680u64,
+    4621485970984798208u64,
+    367078571518203970u64,
+    72621098075685120u64,
+    1225544256278495744u64,
+    1411779381045761u64,
+    5333500077688291352u64,
+    4716913491968128u64,
+    148627764202701056u64,
+    1688850967695425u64,
+    17781710002178u64,
+    9243644149415084036u64,
+    218426849703891488u64,
+    9009415596316677u64,
+    1412882374067224u64,
+    279186509824u64,
+    20407489916899328u64,
+    4614113755159331840u64,
+    144119586390940160u64,
+    11547234118442230016u64,
+    5188151323463779840u64,
+    435758450535334272u64,
+];
@@ -0,0 +1,252 @@
+use crate::engine::piece::{Color, Pieces};
+
+// 4 bits for the count of each piece type, indexed by Pieces::idx. Kings are
+// never counted. 4 bits is enough as there are at most 10 of any piece type.
+const BITS_PER_PIECE: usize = 4;
+const COUNT_MASK: u64 = 0b1111;
+
+// a8 is a light square
+pub const LIGHT_SQUARES: u64 = 0xAA55AA55AA55AA55;
+pub const DARK_SQUARES: u64 = !LIGHT_SQUARES;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MaterialKey(u64);
+
+impl MaterialKey {
+    // counts are pawn, knight, bishop, rook, queen
+    pub const fn from_counts(white: [u8; 5], black: [u8; 5]) -> MaterialKey {
+        let mut key = 0;
+        let mut i = 0;
+
+        while i < 5 {
+            key |= (white[i] as u64) << (i * BITS_PER_PIECE);
+            key |= (black[i] as u64) << ((i + 6) * BITS_PER_PIECE);
+            i += 1;
+        }
+
+        MaterialKey(key)
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, piece: Pieces) {
+        if !piece.is_king() {
+            self.0 += 1 << (piece.idx() * BITS_PER_PIECE);
+        }
+    }
+    #[inline(always)]
+    pub fn remove(&mut self, piece: Pieces) {
+        if !piece.is_king() {
+            debug_assert!(self.count(piece) > 0);
+            self.0 -= 1 << (piece.idx() * BITS_PER_PIECE);
+        }
+    }
+
+    #[inline(always)]
+    pub fn count(&self, piece: Pieces) -> usize {
+        ((self.0 >> (piece.idx() * BITS_PER_PIECE)) & COUNT_MASK) as usize
+    }
+
+    // nothing but the king left for the color
+    pub fn is_bare_king(&self, color: Color) -> bool {
+        (0..5).all(|idx| self.count(Pieces::from_idx(idx + 6 * (1 - color.idx()))) == 0)
+    }
+
+    // Whether the color has a knight, bishop, rook or queen. Without one,
+    // being made to move can be all that loses, so the search doesn't try
+    // passing.
+    pub fn has_non_pawn_material(&self, color: Color) -> bool {
+        [Pieces::knight(color), Pieces::bishop(color), Pieces::rook(color), Pieces::queen(color)]
+            .iter()
+            .any(|&piece| self.count(piece) > 0)
+    }
+
+    // the same material with the colors swapped
+    pub fn mirrored(&self) -> MaterialKey {
+        const SIDE_BITS: usize = 6 * BITS_PER_PIECE;
+        const SIDE_MASK: u64 = (1 << SIDE_BITS) - 1;
+
+        MaterialKey(((self.0 & SIDE_MASK) << SIDE_BITS) | (self.0 >> SIDE_BITS))
+    }
+
+    // true if the only pieces left (besides kings) are bishops
+    pub fn is_bishops_only(&self) -> bool {
+        let bishops = MaterialKey::from_counts([0, 0, 15, 0, 0], [0, 0, 15, 0, 0]);
+        self.0 & !bishops.0 == 0
+    }
+
+    pub fn classify(&self) -> MaterialClass {
+        let mirrored = self.mirrored();
+
+        MATERIAL_TABLE
+            .iter()
+            .find(|(key, _)| key == self || *key == mirrored)
+            .map(|&(_, class)| class)
+            .unwrap_or(MaterialClass::WinPossible)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MaterialClass {
+    // either side may still be able to force a win
+    WinPossible,
+    // neither side can checkmate by any sequence of moves
+    DeadDraw,
+    // mate may be possible but cannot be forced (or almost never is), so
+    // the evaluation is scaled down by the given number of sixteenths
+    Drawish(i32),
+}
+
+impl MaterialClass {
+    pub fn is_dead_draw(&self) -> bool {
+        *self == MaterialClass::DeadDraw
+    }
+
+    // scale a static evaluation towards zero
+    pub fn scale(&self, score: i32) -> i32 {
+        match *self {
+            MaterialClass::WinPossible => score,
+            MaterialClass::DeadDraw => 0,
+            MaterialClass::Drawish(sixteenths) => score * sixteenths / 16,
+        }
+    }
+}
+
+// Known configurations, listed with the stronger side as white. Anything
+// not found here is assumed to be winnable. KB vs KB is listed as drawish;
+// the board upgrades it to a dead draw when the bishops share a color.
+const MATERIAL_TABLE: [(MaterialKey, MaterialClass); 12] = [
+    // K vs K
+    (MaterialKey::from_counts([0, 0, 0, 0, 0], [0, 0, 0, 0, 0]), MaterialClass::DeadDraw),
+    // KN vs K
+    (MaterialKey::from_counts([0, 1, 0, 0, 0], [0, 0, 0, 0, 0]), MaterialClass::DeadDraw),
+    // KB vs K
+    (MaterialKey::from_counts([0, 0, 1, 0, 0], [0, 0, 0, 0, 0]), MaterialClass::DeadDraw),
+    // KNN vs K, mate only with the defender's help
+    (MaterialKey::from_counts([0, 2, 0, 0, 0], [0, 0, 0, 0, 0]), MaterialClass::Drawish(1)),
+    // KN vs KN
+    (MaterialKey::from_counts([0, 1, 0, 0, 0], [0, 1, 0, 0, 0]), MaterialClass::Drawish(1)),
+    // KB vs KN
+    (MaterialKey::from_counts([0, 0, 1, 0, 0], [0, 1, 0, 0, 0]), MaterialClass::Drawish(1)),
+    // KB vs KB
+    (MaterialKey::from_counts([0, 0, 1, 0, 0], [0, 0, 1, 0, 0]), MaterialClass::Drawish(1)),
+    // KNN vs KN, KNN vs KB
+    (MaterialKey::from_counts([0, 2, 0, 0, 0], [0, 1, 0, 0, 0]), MaterialClass::Drawish(2)),
+    (MaterialKey::from_counts([0, 2, 0, 0, 0], [0, 0, 1, 0, 0]), MaterialClass::Drawish(2)),
+    // KR vs KN, KR vs KB
+    (MaterialKey::from_counts([0, 0, 0, 1, 0], [0, 1, 0, 0, 0]), MaterialClass::Drawish(4)),
+    (MaterialKey::from_counts([0, 0, 0, 1, 0], [0, 0, 1, 0, 0]), MaterialClass::Drawish(4)),
+    // KR vs KR
+    (MaterialKey::from_counts([0, 0, 0, 1, 0], [0, 0, 0, 1, 0]), MaterialClass::Drawish(2)),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::board::Board;
+
+    fn class(fen: &str) -> MaterialClass {
+        Board::new(fen).unwrap().material_class()
+    }
+
+    #[test]
+    fn dead_draws() {
+        for fen in &[
+            "4k3/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4KN2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4KB2 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/4K3 b - - 0 1",
+            "4kb2/8/8/8/8/8/8/4K3 b - - 0 1",
+            // same colored bishops
+            "4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/B1B1K1B1 w - - 0 1",
+        ] {
+            assert_eq!(class(fen), MaterialClass::DeadDraw, "{}", fen);
+            assert!(Board::new(fen).unwrap().is_low_material(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn drawish() {
+        for fen in &[
+            // KNN vs K has no forced mate
+            "4k3/8/8/8/8/8/8/3NKN2 w - - 0 1",
+            "3nkn2/8/8/8/8/8/8/4K3 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/4KN2 w - - 0 1",
+            "4kn2/8/8/8/8/8/8/4KB2 w - - 0 1",
+            // opposite colored bishops
+            "4kb2/8/8/8/8/8/8/4KB2 w - - 0 1",
+            "4kb2/8/8/8/8/8/8/4KR2 w - - 0 1",
+            "4kr2/8/8/8/8/8/8/4KR2 w - - 0 1",
+        ] {
+            assert!(matches!(class(fen), MaterialClass::Drawish(_)), "{}", fen);
+            assert!(!Board::new(fen).unwrap().is_low_material(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn win_possible() {
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            // KBN vs K is a forced mate
+            "4k3/8/8/8/8/8/8/3NKB2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/4KR2 w - - 0 1",
+            "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1",
+            "4k3/8/8/8/8/8/8/2B1KB2 w - - 0 1",
+        ] {
+            assert_eq!(class(fen), MaterialClass::WinPossible, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn incremental() {
+        use crate::engine::board::BoardBuilder;
+        use crate::engine::movegen::{MoveGenerator, MoveList};
+        use crate::engine::piece::Color;
+        use crate::engine::r#move::UndoInfo;
+        use crate::engine::square::Square;
+
+        // captures, promotions and en passant
+        let mut board = BoardBuilder::new()
+            .piece(Square::A8, Pieces::BlackRook)
+            .piece(Square::E8, Pieces::BlackKing)
+            .piece(Square::B7, Pieces::WhitePawn)
+            .piece(Square::D4, Pieces::BlackPawn)
+            .piece(Square::E4, Pieces::WhitePawn)
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::H1, Pieces::WhiteRook)
+            .side_to_move(Color::Black)
+            .en_passant(Some(Square::E3))
+            .build()
+            .unwrap();
+        let generator = MoveGenerator::new();
+        let mut moves = MoveList::new();
+        let mut info = UndoInfo::default();
+        let before = board.material_key();
+
+        generator.gen_moves(&mut board, &mut moves);
+        for i in 0..moves.len() {
+            board.make_move(moves.at(i), &mut info);
+
+            let mut reply_moves = MoveList::new();
+            let mut reply_info = UndoInfo::default();
+            generator.gen_moves(&mut board, &mut reply_moves);
+            for j in 0..reply_moves.len() {
+                board.make_move(reply_moves.at(j), &mut reply_info);
+                assert_eq!(board.material_key(), Board::new(&board.to_fen()).unwrap().material_key());
+                board.undo_move(reply_moves.at(j), &reply_info);
+            }
+
+            assert_eq!(board.material_key(), Board::new(&board.to_fen()).unwrap().material_key());
+            board.undo_move(moves.at(i), &info);
+        }
+
+        assert_eq!(board.material_key(), before);
+    }
+
+    #[test]
+    fn scale() {
+        assert_eq!(MaterialClass::WinPossible.scale(320), 320);
+        assert_eq!(MaterialClass::DeadDraw.scale(320), 0);
+        assert_eq!(MaterialClass::Drawish(4).scale(-320), -80);
+    }
+}
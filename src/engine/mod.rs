@@ -1,10 +1,43 @@
+pub(crate) mod attacks;
+pub(crate) mod bench;
 pub mod bitboard;
 pub mod board;
+pub mod castling;
+pub mod config;
+pub(crate) mod dumptables;
+pub mod eco;
+pub mod control;
+pub mod error;
 pub mod eval;
+pub(crate) mod fencheck;
+pub(crate) mod gamereport;
+#[cfg(feature = "cdylib")]
+pub mod ffi;
+#[cfg(test)]
+mod fuzz;
+pub(crate) mod iolog;
+mod magic;
+pub mod material;
 pub mod r#move;
 pub mod movegen;
+pub mod movepack;
+pub(crate) mod movepick;
+pub(crate) mod movetable;
+#[cfg(feature = "nnue")]
+pub mod nnue;
 pub mod perft;
+pub mod pgn;
 pub mod piece;
+pub(crate) mod playout;
+#[cfg(test)]
+mod reference;
+pub mod san;
+pub(crate) mod script;
 pub mod search;
 pub mod square;
-pub mod uci;
+pub mod syzygy;
+pub(crate) mod timeman;
+pub mod tt;
+pub(crate) mod tune;
+pub(crate) mod uci;
+pub(crate) mod ucioptions;
@@ -1,4 +1,5 @@
-use crate::engine::{piece::Pieces, square::Square};
+use crate::engine::castling::CastlingRights;
+use crate::engine::{material::MaterialKey, piece::Pieces, square::Square};
 
 pub const MOVE_TYPE_CASTLE: u16 = 0b0000000000000100;
 pub const MOVE_TYPE_EN_PASSANT: u16 = 0b0000000000001000;
@@ -19,13 +20,20 @@ pub const MOVE_MASK_END: u16 = 0b1111110000000000;
 
 pub type Move = u16;
 
+// Passing the move, which is never legal but lets the search ask how good
+// the position would be if the other side could move again. No real move
+// goes from a square to itself, and UCI writes it as 0000.
+pub const NULL_MOVE: Move = 0;
+
 pub trait MoveUtils {
     fn get_move_type(&self) -> u16;
     fn get_move_piece(&self) -> u16;
     fn get_move_start(&self) -> u16;
     fn get_move_end(&self) -> u16;
     fn move_to_string(&self) -> String;
+    fn move_to_uci(&self, chess960: bool) -> String;
     fn new_move(start: u16, end: u16, flags: u16) -> Move;
+    fn validate_shape(&self) -> bool;
 }
 
 impl MoveUtils for Move {
@@ -50,6 +58,10 @@ impl MoveUtils for Move {
     }
 
     fn move_to_string(&self) -> String {
+        if *self == NULL_MOVE {
+            return "0000".to_string();
+        }
+
         let start = self.get_move_start();
         let end = self.get_move_end();
         let move_type = self.get_move_type();
@@ -74,18 +86,161 @@ impl MoveUtils for Move {
         result
     }
 
+    // In Chess960 castling is written as the king taking its own rook, so
+    // e1h1 rather than e1g1. Every other move is the same either way.
+    fn move_to_uci(&self, chess960: bool) -> String {
+        if !chess960 || self.get_move_type() != MOVE_TYPE_CASTLE {
+            return self.move_to_string();
+        }
+
+        let start_sq = Square::from_usize(self.get_move_start() as usize);
+        let rook_file = if self.get_move_piece() == MOVE_CASTLE_SIDE_KS { 7 } else { 0 };
+        let rook_sq = Square::from_rf(start_sq.rank(), rook_file);
+        format!("{}{}", start_sq.notation(), rook_sq.notation())
+    }
+
     #[inline(always)]
     fn new_move(start: u16, end: u16, flags: u16) -> Move {
         (end << 10) | (start << 4) | flags
     }
+
+    // False if no position has the move, whatever the pieces: the flags
+    // have to fit the squares. A promotion goes from the seventh rank to
+    // the eighth (or the second to the first), an en passant capture ends
+    // on the pawn it takes, beside where it started, and castling takes
+    // the king from e1 or e8 two squares towards the side in its flags. The
+    // piece bits of any other move are clear. This says nothing about
+    // whether the move is legal, but a move from outside the search, such
+    // as one out of the transposition table, which fails it would corrupt
+    // the board if made.
+    fn validate_shape(&self) -> bool {
+        let start = self.get_move_start() as usize;
+        let end = self.get_move_end() as usize;
+        let (start_rank, end_rank) = (start / 8, end / 8);
+        let file_distance = (start % 8).abs_diff(end % 8);
+
+        match self.get_move_type() {
+            MOVE_TYPE_PROMOTION => {
+                file_distance <= 1 && matches!((start_rank, end_rank), (1, 0) | (6, 7))
+            }
+            MOVE_TYPE_EN_PASSANT => {
+                self.get_move_piece() == 0
+                    && file_distance == 1
+                    && start_rank == end_rank
+                    && matches!(start_rank, 3 | 4)
+            }
+            MOVE_TYPE_CASTLE => {
+                let side = if end % 8 == 6 { MOVE_CASTLE_SIDE_KS } else { MOVE_CASTLE_SIDE_QS };
+                (start == Square::E1.sq() || start == Square::E8.sq())
+                    && start_rank == end_rank
+                    && file_distance == 2
+                    && self.get_move_piece() == side
+            }
+            _ => self.get_move_piece() == 0 && start != end,
+        }
+    }
+}
+
+// The (piece, square) pairs a move takes off and puts on the board. A
+// capture removes the captured piece, castling moves the rook as well as
+// the king, and a promotion removes the pawn and adds the new piece.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MoveDelta {
+    removed: [(Pieces, usize); 3],
+    added: [(Pieces, usize); 2],
+    removed_count: usize,
+    added_count: usize,
+}
+
+impl Default for MoveDelta {
+    fn default() -> MoveDelta {
+        MoveDelta {
+            removed: [(Pieces::WhitePawn, 0); 3],
+            added: [(Pieces::WhitePawn, 0); 2],
+            removed_count: 0,
+            added_count: 0,
+        }
+    }
+}
+
+impl MoveDelta {
+    #[inline(always)]
+    pub fn clear(&mut self) {
+        self.removed_count = 0;
+        self.added_count = 0;
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, piece: Pieces, sq: usize) {
+        self.added[self.added_count] = (piece, sq);
+        self.added_count += 1;
+    }
+    #[inline(always)]
+    pub fn remove(&mut self, piece: Pieces, sq: usize) {
+        self.removed[self.removed_count] = (piece, sq);
+        self.removed_count += 1;
+    }
+
+    #[inline(always)]
+    pub fn added(&self) -> &[(Pieces, usize)] {
+        &self.added[..self.added_count]
+    }
+    #[inline(always)]
+    pub fn removed(&self) -> &[(Pieces, usize)] {
+        &self.removed[..self.removed_count]
+    }
 }
 
 #[derive(Default)]
 pub struct UndoInfo {
-    pub castling: u8,
+    pub castling: CastlingRights,
     pub fifty_move: usize,
     pub en_passant: Option<Square>,
     pub captured: Option<Pieces>,
     pub zobrist: u64,
+    pub material: MaterialKey,
     pub evalutor_diff: i32,
+    pub delta: MoveDelta,
+    // the checks each side had given, for three-check
+    pub checks_given: [u8; 2],
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // every combination of squares and flags encodes and decodes the same
+    // way, and the shapes which pass are the ones some position has
+    #[test]
+    fn encoding() {
+        let mut valid = [0; 4];
+        for start in 0..64 {
+            for end in 0..64 {
+                for move_type in [0, MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT, MOVE_TYPE_PROMOTION] {
+                    for piece in 0..4 {
+                        let my_move = Move::new_move(start, end, move_type | piece);
+                        assert_eq!(
+                            (my_move.get_move_start(), my_move.get_move_end()),
+                            (start, end)
+                        );
+                        assert_eq!((my_move.get_move_type(), my_move.get_move_piece()), (move_type, piece));
+
+                        if my_move.validate_shape() {
+                            valid[(move_type >> 2) as usize] += 1;
+                            assert_eq!(my_move.move_to_string().len(), 4 + (move_type == MOVE_TYPE_PROMOTION) as usize);
+                        }
+                    }
+                }
+            }
+        }
+
+        // the 16 bits are all used, so that was every u16
+        assert_eq!((0..=u16::MAX).filter(|my_move| my_move.validate_shape()).count(), valid.iter().sum());
+
+        // any two different squares, four castles, 14 en passant captures on
+        // each side's rank, and 22 pawn moves onto each side's last rank, each
+        // promoting to one of four pieces
+        assert_eq!(valid, [64 * 63, 4, 2 * 14, 2 * 22 * 4]);
+        assert!(!NULL_MOVE.validate_shape());
+    }
 }
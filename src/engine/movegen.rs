@@ -1,13 +1,26 @@
+use std::cmp::Reverse;
 use std::fmt::{Display, Formatter, Result};
 
+#[cfg(not(feature = "runtime_tables"))]
+use crate::engine::attacks::tables;
+use crate::engine::attacks::{Attacks, Table, KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
 use crate::engine::bitboard::BitBoardUtils;
 use crate::engine::board::Board;
+#[cfg(feature = "runtime_tables")]
+use crate::engine::magic::SliderTables;
 use crate::engine::piece::{Color, Pieces};
 use crate::engine::r#move::*;
 use crate::engine::square::Square;
 
 use super::{bitboard::FULL_BB, eval::Evaluator};
 
+// The groups MoveList::score_moves puts moves in, best first. Captures
+// which win material (or trade evenly) come first, then promotions, then
+// captures which lose material on the face of it, then quiet moves at 0.
+const WINNING_CAPTURE: i32 = 30_000;
+const PROMOTION: i32 = 20_000;
+const LOSING_CAPTURE: i32 = 10_000;
+
 trait PlayerTrait {
     fn color() -> Color;
     fn enemy() -> Color;
@@ -157,6 +170,8 @@ impl Files {
 
 pub struct MoveList {
     moves: Vec<(Move, i32)>,
+    // moves before this have been returned by pick_next
+    picked: usize,
 }
 
 impl MoveList {
@@ -192,13 +207,22 @@ impl MoveList {
         self.moves.len()
     }
 
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.moves.is_empty()
+    }
+
     #[inline(always)]
     pub fn clear(&mut self) {
         self.moves.clear();
+        self.picked = 0;
     }
 
     pub fn new() -> MoveList {
-        let mut result = MoveList { moves: Vec::new() };
+        let mut result = MoveList {
+            moves: Vec::new(),
+            picked: 0,
+        };
         result.moves.reserve(256);
         result
     }
@@ -208,496 +232,219 @@ impl MoveList {
         self.moves[idx].0
     }
 
-    pub fn order_moves(&mut self, board: &Board) {
-        // score the moves
-        for item in &mut self.moves {
-            let my_move = &item.0;
-
-            // score the promotion piece
-            if my_move.get_move_type() == super::r#move::MOVE_TYPE_PROMOTION {
-                item.1 += match my_move.get_move_piece() {
-                    super::r#move::MOVE_PROMOTION_PIECE_KNIGHT => 315,
-                    super::r#move::MOVE_PROMOTION_PIECE_BISHOP => 325,
-                    super::r#move::MOVE_PROMOTION_PIECE_ROOK => 500,
-                    super::r#move::MOVE_PROMOTION_PIECE_QUEEN => 900,
-                    _ => panic!("Couldn't match the promotion piece"),
-                };
-            }
+    // Scores the moves for pick_next, without sorting them. Searching
+    // usually stops at the first or second move, so sorting the rest would
+    // be wasted. Captures are ordered most valuable victim first, then least
+    // valuable attacker.
+    pub fn score_moves(&mut self, board: &Board) {
+        self.picked = 0;
 
-            // score a capture
+        let value = |piece: Pieces| Evaluator::piece_value(piece).abs();
+        for item in &mut self.moves {
+            let my_move = item.0;
+            let start = my_move.get_move_start() as usize;
             let end = my_move.get_move_end() as usize;
-            if let Some(piece) = board.pieces[end] {
-                let start = my_move.get_move_start() as usize;
-                item.1 += Evaluator::piece_value(piece)
-                    - Evaluator::piece_value(board.pieces[start].unwrap());
-            }
-        }
-
-        // sort the moves
-        self.moves.sort_by(|&a, &b| a.1.cmp(&b.1));
-    }
-}
-
-impl Display for MoveList {
-    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
-        let mut result = String::new();
-
-        for (i, &my_move) in self.moves.iter().enumerate() {
-            result.push_str(&format!("{}: {}\n", i + 1, my_move.0.move_to_string()));
-        }
 
-        write!(f, "{}", result)
-    }
-}
-
-pub struct MoveGenerator {
-    rook_masks: [u64; 64],
-    bishop_masks: [u64; 64],
-
-    rook_magic_shifts: [usize; 64],
-    bishop_magic_shifts: [usize; 64],
-
-    rook_moves: Box<[[u64; 4096]]>,
-    bishop_moves: Box<[[u64; 4096]]>,
-
-    knight_moves: [u64; 64],
-    king_moves: [u64; 64],
-
-    pawn_attacks: [[u64; 64]; 2],
-
-    slider_range: [[u64; 64]; 64],
-
-    ranks: [u64; 256],
-    files: [u64; 256],
-
-    not_ranks: [u64; 256],
-    not_files: [u64; 256],
-}
-
-impl MoveGenerator {
-    /* -------------------------------------------------------------------------- */
-    /*                                    Setup                                   */
-    /* -------------------------------------------------------------------------- */
-    fn gen_rook_mask(&self, start: Square) -> u64 {
-        let rank = start.rank() as i16;
-        let file = start.file() as i16;
-        let mut result = 0;
-
-        let mut r = rank - 1;
-        let mut f = file;
-
-        while r >= 1 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            r -= 1;
-        }
-
-        r = rank + 1;
-        while r <= 6 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            r += 1;
-        }
-
-        r = rank;
-        f = file - 1;
-        while f >= 1 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            f -= 1;
-        }
-
-        f = file + 1;
-        while f <= 6 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            f += 1;
+            // en passant moves end on the captured pawn, and castling moves
+            // end on an empty square
+            let victim = board.pieces[end].map_or(0, value);
+            let attacker = value(board.pieces[start].unwrap());
+
+            item.1 = if my_move.get_move_type() == MOVE_TYPE_PROMOTION {
+                PROMOTION + victim + match my_move.get_move_piece() {
+                    MOVE_PROMOTION_PIECE_KNIGHT => 315,
+                    MOVE_PROMOTION_PIECE_BISHOP => 325,
+                    MOVE_PROMOTION_PIECE_ROOK => 500,
+                    _ => 900,
+                }
+            } else if victim == 0 {
+                0
+            } else if victim >= attacker {
+                WINNING_CAPTURE + 8 * victim - attacker
+            } else {
+                LOSING_CAPTURE + 8 * victim - attacker
+            };
         }
-
-        result
     }
-    fn gen_bishop_mask(&self, start: Square) -> u64 {
-        let rank = start.rank() as i16;
-        let file = start.file() as i16;
-        let mut result = 0;
-
-        let mut r = rank - 1;
-        let mut f = file - 1;
-        while r >= 1 && f >= 1 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            r -= 1;
-            f -= 1;
-        }
-
-        r = rank + 1;
-        f = file - 1;
-        while r <= 6 && f >= 1 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            r += 1;
-            f -= 1;
-        }
-
-        r = rank - 1;
-        f = file + 1;
-        while r >= 1 && f <= 6 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            r -= 1;
-            f += 1;
-        }
 
-        r = rank + 1;
-        f = file + 1;
-        while r <= 6 && f <= 6 {
-            result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            r += 1;
-            f += 1;
-        }
-
-        result
+    // Returns the best scored move not yet picked, or None once every move
+    // has been. Each call looks through the remaining moves once, so a node
+    // which cuts off early only pays for the moves it searched.
+    pub fn pick_next(&mut self) -> Option<Move> {
+        let (my_move, _) = self.best_to(self.picked)?;
+        self.picked += 1;
+        Some(my_move)
     }
 
-    fn gen_rook_moves(&self, start: Square, occupancy: u64) -> u64 {
-        let rank = start.rank() as i16;
-        let file = start.file() as i16;
-        let mut result = 0;
-
-        let mut r = rank - 1;
-        let mut f = file;
-
-        while r >= 0 {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
+    // Moves the best scored of the moves from idx on to idx, and returns it
+    // with its score. The first of the best is taken, and moved there by
+    // rotating rather than swapping, so equal moves stay in generation order
+    // and searches are reproducible.
+    pub fn best_to(&mut self, idx: usize) -> Option<(Move, i32)> {
+        let remaining = self.moves.get_mut(idx..)?;
+
+        let mut best = 0;
+        for (idx, &(_, score)) in remaining.iter().enumerate().skip(1) {
+            if score > remaining[best].1 {
+                best = idx;
             }
-            r -= 1;
         }
+        remaining.get(best)?;
+        remaining[..=best].rotate_right(1);
 
-        r = rank + 1;
-        while r <= 7 {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
-            }
-            r += 1;
-        }
+        Some(remaining[0])
+    }
 
-        r = rank;
-        f = file - 1;
-        while f >= 0 {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
-            }
-            f -= 1;
-        }
+    // Moves my_move to idx, as best_to does, if it is there or after it.
+    pub fn find_to(&mut self, idx: usize, my_move: Move) -> bool {
+        let remaining = match self.moves.get_mut(idx..) {
+            Some(remaining) => remaining,
+            None => return false,
+        };
 
-        f = file + 1;
-        while f <= 7 {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
+        match remaining.iter().position(|&(other, _)| other == my_move) {
+            Some(found) => {
+                remaining[..=found].rotate_right(1);
+                true
             }
-            f += 1;
+            None => false,
         }
-
-        result
     }
-    fn gen_bishop_moves(&self, start: Square, occupancy: u64) -> u64 {
-        let rank = start.rank() as i16;
-        let file = start.file() as i16;
-        let mut result = 0;
-
-        let mut r = rank - 1;
-        let mut f = file - 1;
-        while Square::valid_rf(r, f) {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
-            }
-            r -= 1;
-            f -= 1;
-        }
 
-        r = rank + 1;
-        f = file - 1;
-        while Square::valid_rf(r, f) {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
-            }
-            r += 1;
-            f -= 1;
-        }
-
-        r = rank - 1;
-        f = file + 1;
-        while Square::valid_rf(r, f) {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
-            }
-            r -= 1;
-            f += 1;
+    // scores every move, for best_to and pick_next
+    pub fn score_with(&mut self, mut score: impl FnMut(Move) -> i32) {
+        self.picked = 0;
+        for item in &mut self.moves {
+            item.1 = score(item.0);
         }
+    }
 
-        r = rank + 1;
-        f = file + 1;
-        while Square::valid_rf(r, f) {
-            let pos = Square::from_rf(r as usize, f as usize).sq();
-            result.set_bit(pos);
-            if occupancy.is_bit_set(pos) {
-                break;
-            }
-            r += 1;
-            f += 1;
-        }
+    pub fn order_moves(&mut self, board: &Board) {
+        self.score_moves(board);
 
-        result
+        // best first, keeping equal moves in generation order so that
+        // searches are reproducible. The sort is stable and in place.
+        self.moves.sort_by_key(|&(_, score)| Reverse(score));
     }
-    fn gen_king_moves(&self, start: Square) -> u64 {
-        let mut result = 0;
-
-        let rank = start.rank() as i16;
-        let file = start.file() as i16;
-
-        const MOVE_VECTORS: [[i16; 2]; 8] = [
-            [-1, -1],
-            [-1, 0],
-            [-1, 1],
-            [0, -1],
-            [0, 1],
-            [1, -1],
-            [1, 0],
-            [1, 1],
-        ];
-
-        for &vector in &MOVE_VECTORS {
-            let f = file + vector[0];
-            let r = rank + vector[1];
-            if Square::valid_rf(r, f) {
-                result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            }
-        }
 
-        result
+    // Puts the moves in a fixed order, which (unlike the order they are
+    // generated in) won't change between versions: by start square, then
+    // the square the piece moves to, both numbered from a8 (0) to h1 (63) as
+    // on the Board, then promotions to a queen, rook, bishop and knight.
+    pub fn sort_canonical(&mut self) {
+        self.moves.sort_by_key(|&(my_move, _)| canonical_key(my_move));
     }
-    fn gen_knight_moves(&self, start: Square) -> u64 {
-        let mut result = 0;
-
-        let rank = start.rank() as i16;
-        let file = start.file() as i16;
-
-        const MOVE_VECTORS: [[i16; 2]; 8] = [
-            [-1, 2],
-            [-2, 1],
-            [1, 2],
-            [2, 1],
-            [-1, -2],
-            [-2, -1],
-            [1, -2],
-            [2, -1],
-        ];
-
-        for &vector in &MOVE_VECTORS {
-            let f = file + vector[0];
-            let r = rank + vector[1];
-            if Square::valid_rf(r, f) {
-                result.set_bit(Square::from_rf(r as usize, f as usize).sq());
-            }
-        }
+}
 
-        result
+fn canonical_key(my_move: Move) -> (u16, u16, Reverse<u16>) {
+    let mut end = my_move.get_move_end();
+    // en passant moves end on the captured pawn, beside the destination
+    if my_move.get_move_type() == MOVE_TYPE_EN_PASSANT {
+        end = if end / 8 == 3 { end - 8 } else { end + 8 };
     }
 
-    fn idx_to_u64(&self, idx: usize, mut mask: u64) -> u64 {
-        let mut result = 0;
+    let promotion = if my_move.get_move_type() == MOVE_TYPE_PROMOTION {
+        my_move.get_move_piece()
+    } else {
+        0
+    };
 
-        let mut i = 0;
-        while idx != 0 && mask != 0 {
-            let pos = mask.pop_lsb();
-            if idx & (1 << i) != 0 {
-                result.set_bit(pos);
-            }
-            i += 1;
-        }
+    (my_move.get_move_start(), end, Reverse(promotion))
+}
 
-        result
+impl Default for MoveList {
+    fn default() -> MoveList {
+        MoveList::new()
     }
+}
 
-    fn init(&mut self) {
-        // init ranks & files
-        for i in 0..8 {
-            self.files[1 << i] = 0x0101010101010101 << i;
-            self.ranks[1 << i] = 0xff << (8 * (7 - i));
-        }
-        for i in 0..256 {
-            for j in 0..8 {
-                if i & (1 << j) != 0 {
-                    self.files[i] |= self.files[1 << j];
-                    self.ranks[i] |= self.ranks[1 << j];
-                }
-            }
-            self.not_files[i] = !self.files[i];
-            self.not_ranks[i] = !self.ranks[i];
-        }
-
-        // masks, shifts and move tables
-        for i in 0..64 {
-            let sq = Square::from_usize(i);
+impl Display for MoveList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let mut result = String::new();
 
-            // rook & bishop masks
-            self.rook_masks[i] = self.gen_rook_mask(sq);
-            self.bishop_masks[i] = self.gen_bishop_mask(sq);
+        for (i, &my_move) in self.moves.iter().enumerate() {
+            result.push_str(&format!("{}: {}\n", i + 1, my_move.0.move_to_string()));
+        }
 
-            // rook & bishop shifts
-            self.rook_magic_shifts[i] = 64 - self.rook_masks[i].count_1s();
-            self.bishop_magic_shifts[i] = 64 - self.bishop_masks[i].count_1s();
+        write!(f, "{}", result)
+    }
+}
 
-            // rook & bishop move tables
-            for idx in 0..(1 << self.rook_masks[i].count_1s()) {
-                let indexed_mask = self.idx_to_u64(idx, self.rook_masks[i]);
-                let key =
-                    u64::wrapping_mul(ROOK_MAGICS[i], indexed_mask) >> self.rook_magic_shifts[i];
-                self.rook_moves[i][key as usize] = self.gen_rook_moves(sq, indexed_mask);
-            }
-            for idx in 0..(1 << self.bishop_masks[i].count_1s()) {
-                let indexed_mask = self.idx_to_u64(idx, self.bishop_masks[i]);
-                let key = u64::wrapping_mul(BISHOP_MAGICS[i], indexed_mask)
-                    >> self.bishop_magic_shifts[i];
-                self.bishop_moves[i][key as usize] = self.gen_bishop_moves(sq, indexed_mask);
-            }
+// checking pieces, pinned friendly pieces and the enemy sliders pinning them,
+// all for the side to move
+#[derive(Clone, Copy, Default, Debug, PartialEq)]
+pub struct CheckInfo {
+    pub checkers: u64,
+    pub pinned: u64,
+    pub pinners: u64,
+}
 
-            // knight moves
-            self.knight_moves[i] = self.gen_knight_moves(sq);
+pub struct MoveGenerator {
+    attacks: Attacks,
 
-            // king moves
-            self.king_moves[i] = self.gen_king_moves(sq);
+    ranks: Table<[u64; 256]>,
+    files: Table<[u64; 256]>,
 
-            // pawn attacks
-            if sq.rank() != 0 {
-                if sq.file() != 7 {
-                    self.pawn_attacks[Color::White.idx()][i].set_bit(i - 7);
-                }
-                if sq.file() != 0 {
-                    self.pawn_attacks[Color::White.idx()][i].set_bit(i - 9);
-                }
-            }
-            if sq.rank() != 7 {
-                if sq.file() != 7 {
-                    self.pawn_attacks[Color::Black.idx()][i].set_bit(i + 9);
-                }
-                if sq.file() != 0 {
-                    self.pawn_attacks[Color::Black.idx()][i].set_bit(i + 7);
-                }
-            }
-        }
+    not_ranks: Table<[u64; 256]>,
+    not_files: Table<[u64; 256]>,
+}
 
-        // slider range
-        for start in 0..64 {
-            for end in 0..64 {
-                let min_sq = Square::from_usize(std::cmp::min(start, end));
-                let max_sq = Square::from_usize(std::cmp::max(start, end));
+impl MoveGenerator {
+    /* -------------------------------------------------------------------------- */
+    /*                                    Setup                                   */
+    /* -------------------------------------------------------------------------- */
+    #[cfg(not(feature = "runtime_tables"))]
+    pub fn new() -> MoveGenerator {
+        MoveGenerator {
+            attacks: Attacks::new(),
 
-                self.slider_range[start][end] = 0;
+            ranks: &tables::RANKS,
+            files: &tables::FILES,
 
-                // same rank
-                if min_sq.rank() == max_sq.rank() {
-                    for f in min_sq.file() + 1..max_sq.file() {
-                        self.slider_range[start][end]
-                            .set_bit(Square::from_rf(min_sq.rank(), f).sq());
-                    }
-                }
-                // same file
-                else if min_sq.file() == max_sq.file() {
-                    for r in min_sq.rank() + 1..max_sq.rank() {
-                        self.slider_range[start][end]
-                            .set_bit(Square::from_rf(r, min_sq.file()).sq());
-                    }
-                } else {
-                    let rank_diff = max_sq.rank() as i16 - min_sq.rank() as i16;
-                    let file_diff = max_sq.file() as i16 - min_sq.file() as i16;
-
-                    // diagonal
-                    if rank_diff.abs() == file_diff.abs() {
-                        // negative gradient
-                        if rank_diff == file_diff {
-                            let mut r = min_sq.rank() + 1;
-                            let mut f = min_sq.file() + 1;
-                            while r < max_sq.rank() && f < max_sq.file() {
-                                self.slider_range[start][end].set_bit(Square::from_rf(r, f).sq());
-                                r += 1;
-                                f += 1;
-                            }
-                        }
-                        // positive gradient
-                        else {
-                            let mut r = min_sq.rank() + 1;
-                            let mut f = min_sq.file() - 1;
-                            while r < max_sq.rank() && f > max_sq.file() {
-                                self.slider_range[start][end].set_bit(Square::from_rf(r, f).sq());
-                                r += 1;
-                                f -= 1;
-                            }
-                        }
-                    }
-                }
-            }
+            not_ranks: &tables::NOT_RANKS,
+            not_files: &tables::NOT_FILES,
         }
     }
 
+    #[cfg(feature = "runtime_tables")]
     pub fn new() -> MoveGenerator {
-        let mut result = MoveGenerator {
-            rook_masks: [0; 64],
-            bishop_masks: [0; 64],
-
-            rook_magic_shifts: [0; 64],
-            bishop_magic_shifts: [0; 64],
-
-            rook_moves: vec![[0; 4096]; 64].into_boxed_slice(),
-            bishop_moves: vec![[0; 4096]; 64].into_boxed_slice(),
-
-            knight_moves: [0; 64],
-            king_moves: [0; 64],
-
-            pawn_attacks: [[0; 64]; 2],
-
-            slider_range: [[0; 64]; 64],
-
-            ranks: [0; 256],
-            files: [0; 256],
+        let tables = SliderTables::generate();
 
-            not_ranks: [0; 256],
-            not_files: [0; 256],
-        };
+        MoveGenerator {
+            attacks: Attacks::from_tables(&tables),
 
-        result.init();
+            ranks: Box::new(tables.ranks),
+            files: Box::new(tables.files),
 
-        result
+            not_ranks: Box::new(tables.not_ranks),
+            not_files: Box::new(tables.not_files),
+        }
     }
 
-    /* -------------------------------------------------------------------------- */
-    /*                               Move Generation                              */
-    /* -------------------------------------------------------------------------- */
+    // the attack tables the generator uses
     #[inline(always)]
-    fn magic_bishop_moves(&self, sq: usize, mut occupancy: u64) -> u64 {
-        occupancy &= self.bishop_masks[sq];
-        let idx = u64::wrapping_mul(BISHOP_MAGICS[sq], occupancy) >> self.bishop_magic_shifts[sq];
-        self.bishop_moves[sq][idx as usize]
+    pub fn attacks(&self) -> &Attacks {
+        &self.attacks
     }
-    #[inline(always)]
-    fn magic_rook_moves(&self, sq: usize, mut occupancy: u64) -> u64 {
-        occupancy &= self.rook_masks[sq];
-        let idx = u64::wrapping_mul(ROOK_MAGICS[sq], occupancy) >> self.rook_magic_shifts[sq];
-        self.rook_moves[sq][idx as usize]
+
+    // indexed by a bitmask of ranks or files, every square on them (or not
+    // on them)
+    pub fn ranks(&self) -> &[u64] {
+        &self.ranks[..]
     }
-    #[inline(always)]
-    fn magic_queen_moves(&self, sq: usize, occupancy: u64) -> u64 {
-        self.magic_bishop_moves(sq, occupancy) | self.magic_rook_moves(sq, occupancy)
+    pub fn files(&self) -> &[u64] {
+        &self.files[..]
+    }
+    pub fn not_ranks(&self) -> &[u64] {
+        &self.not_ranks[..]
+    }
+    pub fn not_files(&self) -> &[u64] {
+        &self.not_files[..]
     }
 
+    /* -------------------------------------------------------------------------- */
+    /*                               Move Generation                              */
+    /* -------------------------------------------------------------------------- */
     #[inline(always)]
     fn find_enemy_attackers<P: PlayerTrait>(
         &self,
@@ -710,34 +457,34 @@ impl MoveGenerator {
         let rook_queen =
             board.get_bb(Pieces::rook(P::enemy())) | board.get_bb(Pieces::queen(P::enemy()));
 
-        let mut attacking_pieces = (self.magic_bishop_moves(sq, occupancy) & bishop_queen)
-            | (self.magic_rook_moves(sq, occupancy) & rook_queen);
+        let mut attacking_pieces = (self.attacks.bishop_attacks(sq, occupancy) & bishop_queen)
+            | (self.attacks.rook_attacks(sq, occupancy) & rook_queen);
 
         // knights
-        attacking_pieces |= (self.knight_moves[sq] & board.get_bb(Pieces::knight(P::enemy())))
-            | (self.king_moves[sq] & board.get_bb(Pieces::king(P::enemy())))
-            | (self.pawn_attacks[P::color().idx()][sq] & board.get_bb(Pieces::pawn(P::enemy())));
+        attacking_pieces |= (KNIGHT_ATTACKS[sq] & board.get_bb(Pieces::knight(P::enemy())))
+            | (KING_ATTACKS[sq] & board.get_bb(Pieces::king(P::enemy())))
+            | (PAWN_ATTACKS[P::color().idx()][sq] & board.get_bb(Pieces::pawn(P::enemy())));
 
         attacking_pieces
     }
     #[inline(always)]
     fn is_sq_under_attack<P: PlayerTrait>(&self, sq: usize, board: &Board, occupancy: u64) -> bool {
-        if self.knight_moves[sq] & board.get_bb(Pieces::knight(P::enemy())) != 0
-            || self.king_moves[sq] & board.get_bb(Pieces::king(P::enemy())) != 0
-            || self.pawn_attacks[P::color().idx()][sq] & board.get_bb(Pieces::pawn(P::enemy())) != 0
+        if KNIGHT_ATTACKS[sq] & board.get_bb(Pieces::knight(P::enemy())) != 0
+            || KING_ATTACKS[sq] & board.get_bb(Pieces::king(P::enemy())) != 0
+            || PAWN_ATTACKS[P::color().idx()][sq] & board.get_bb(Pieces::pawn(P::enemy())) != 0
         {
             return true;
         }
 
         let bishop_queen =
             board.get_bb(Pieces::bishop(P::enemy())) | board.get_bb(Pieces::queen(P::enemy()));
-        if self.magic_bishop_moves(sq, occupancy) & bishop_queen != 0 {
+        if self.attacks.bishop_attacks(sq, occupancy) & bishop_queen != 0 {
             return true;
         }
 
         let rook_queen =
             board.get_bb(Pieces::rook(P::enemy())) | board.get_bb(Pieces::queen(P::enemy()));
-        self.magic_rook_moves(sq, occupancy) & rook_queen != 0
+        self.attacks.rook_attacks(sq, occupancy) & rook_queen != 0
     }
 
     #[inline(always)]
@@ -789,6 +536,36 @@ impl MoveGenerator {
         result
     }
 
+    // the squares the unpinned pawns attack on one side, whatever is on them
+    #[inline(always)]
+    fn pawn_capture_squares<P: PlayerTrait, C: CaptureSideTrait>(&self, board: &Board, pinned: u64) -> u64 {
+        let pawns_bb = board.get_bb(Pieces::pawn(P::color())) & !pinned;
+
+        let excluded_file = if C::is_left() { Files::A } else { Files::H };
+        let file_mask = self.not_files[excluded_file as usize];
+        if P::is_white() {
+            (pawns_bb & file_mask) >> (if C::is_left() { 9 } else { 7 })
+        } else {
+            (pawns_bb & file_mask) << (if C::is_left() { 7 } else { 9 })
+        }
+    }
+    #[inline(always)]
+    fn add_pawn_capture_moves<P: PlayerTrait, C: CaptureSideTrait>(&self, move_list: &mut MoveList, captures: u64) {
+        let offset = P::capture_offset(C::is_left());
+        let back_rank = P::opposite_back_rank();
+
+        let mut non_promotion_caps = captures & self.not_ranks[back_rank as usize];
+        while non_promotion_caps != 0 {
+            let end = non_promotion_caps.pop_lsb() as i16;
+            move_list.add_move((offset + end) as usize, end as usize);
+        }
+
+        let mut promotion_caps = captures & self.ranks[back_rank as usize];
+        while promotion_caps != 0 {
+            let end = promotion_caps.pop_lsb() as i16;
+            move_list.add_promotion((offset + end) as usize, end as usize);
+        }
+    }
     fn add_pawn_captures<P: PlayerTrait, C: CaptureSideTrait>(
         &self,
         move_list: &mut MoveList,
@@ -799,17 +576,8 @@ impl MoveGenerator {
         king_pos: usize,
     ) {
         let enemy_bb = board.get_combined_bb(P::enemy());
-        let pawns_bb = board.get_bb(Pieces::pawn(P::color())) & !pinned;
         let offset = P::capture_offset(C::is_left());
-
-        let back_rank = P::opposite_back_rank();
-        let excluded_file = if C::is_left() { Files::A } else { Files::H };
-        let file_mask = self.not_files[excluded_file as usize];
-        let mut captures = if P::is_white() {
-            (pawns_bb & file_mask) >> (if C::is_left() { 9 } else { 7 })
-        } else {
-            (pawns_bb & file_mask) << (if C::is_left() { 7 } else { 9 })
-        };
+        let mut captures = self.pawn_capture_squares::<P, C>(board, pinned);
 
         if board.en_passant.is_some() {
             let en_passant = board.en_passant.unwrap().sq();
@@ -823,18 +591,7 @@ impl MoveGenerator {
         }
 
         captures &= legal_captures & enemy_bb;
-
-        let mut non_promotion_caps = captures & self.not_ranks[back_rank as usize];
-        while non_promotion_caps != 0 {
-            let end = non_promotion_caps.pop_lsb() as i16;
-            move_list.add_move((offset + end) as usize, end as usize);
-        }
-
-        let mut promotion_caps = captures & self.ranks[back_rank as usize];
-        while promotion_caps != 0 {
-            let end = promotion_caps.pop_lsb() as i16;
-            move_list.add_promotion((offset + end) as usize, end as usize);
-        }
+        self.add_pawn_capture_moves::<P, C>(move_list, captures);
     }
     fn add_pawn_pushes<P: PlayerTrait>(
         &self,
@@ -886,6 +643,7 @@ impl MoveGenerator {
     /*                              Non-Pinned Pieces                             */
     /* -------------------------------------------------------------------------- */
     #[inline(always)]
+    #[allow(clippy::too_many_arguments)]
     fn add_pawn_moves<P: PlayerTrait>(
         &self,
         move_list: &mut MoveList,
@@ -927,7 +685,7 @@ impl MoveGenerator {
 
         while knights_bb != 0 {
             let start = knights_bb.pop_lsb();
-            let mut knight_moves = self.knight_moves[start] & mask;
+            let mut knight_moves = KNIGHT_ATTACKS[start] & mask;
 
             while knight_moves != 0 {
                 move_list.add_move(start, knight_moves.pop_lsb());
@@ -948,7 +706,7 @@ impl MoveGenerator {
 
         while bishops_bb != 0 {
             let start = bishops_bb.pop_lsb();
-            let mut bishop_moves = self.magic_bishop_moves(start, occupancy) & mask;
+            let mut bishop_moves = self.attacks.bishop_attacks(start, occupancy) & mask;
 
             while bishop_moves != 0 {
                 move_list.add_move(start, bishop_moves.pop_lsb());
@@ -969,7 +727,7 @@ impl MoveGenerator {
 
         while rooks_bb != 0 {
             let start = rooks_bb.pop_lsb();
-            let mut rook_moves = self.magic_rook_moves(start, occupancy) & mask;
+            let mut rook_moves = self.attacks.rook_attacks(start, occupancy) & mask;
 
             while rook_moves != 0 {
                 move_list.add_move(start, rook_moves.pop_lsb());
@@ -990,7 +748,7 @@ impl MoveGenerator {
 
         while queens_bb != 0 {
             let start = queens_bb.pop_lsb();
-            let mut queen_moves = self.magic_queen_moves(start, occupancy) & mask;
+            let mut queen_moves = self.attacks.queen_attacks(start, occupancy) & mask;
 
             while queen_moves != 0 {
                 move_list.add_move(start, queen_moves.pop_lsb());
@@ -1003,12 +761,13 @@ impl MoveGenerator {
         move_list: &mut MoveList,
         board: &Board,
         mut occupancy: u64,
+        targets: u64,
     ) {
         let king_bb = board.get_bb(Pieces::king(P::color()));
         let start = king_bb.lsb_idx();
         occupancy &= !king_bb;
 
-        let mut king_moves = self.king_moves[start] & !board.get_combined_bb(P::color());
+        let mut king_moves = KING_ATTACKS[start] & targets & !board.get_combined_bb(P::color());
 
         while king_moves != 0 {
             let end = king_moves.pop_lsb();
@@ -1018,21 +777,33 @@ impl MoveGenerator {
             }
         }
     }
+    // Castling with the rights and an empty path between king and rook.
+    //
+    // The king may not castle out of, through or into check. E1 is covered
+    // by only castling when not in check; the square the king passes over
+    // and the one it lands on (D1 and C1, or F1 and G1) are tested here if
+    // check_attacks is set. B1 only has to be empty, it may be attacked.
+    // As with the king's other moves, the attack tests are made with the
+    // king off the board, so it never hides an attack on those squares.
     #[inline(always)]
     fn add_castling_moves<P: PlayerTrait>(
         &self,
         move_list: &mut MoveList,
         board: &Board,
         occupancy: u64,
+        check_attacks: bool,
     ) {
         let file_mask_qs = self.files[Files::B.idx() | Files::C.idx() | Files::D.idx()];
         let file_mask_ks = self.files[Files::F.idx() | Files::G.idx()];
 
+        let without_king = occupancy & !board.get_bb(Pieces::king(P::color()));
+        let is_attacked = |sq: Square| check_attacks && self.is_sq_under_attack::<P>(sq.sq(), board, without_king);
+
         if P::is_white() {
             if board.can_castle_qs(Color::White)
                 && occupancy & self.ranks[Ranks::One as usize] & file_mask_qs == 0
-                && !self.is_sq_under_attack::<P>(Square::D1.sq(), board, occupancy)
-                && !self.is_sq_under_attack::<P>(Square::C1.sq(), board, occupancy)
+                && !is_attacked(Square::D1)
+                && !is_attacked(Square::C1)
             {
                 move_list.add_move_with_flags(
                     Square::E1.sq(),
@@ -1042,8 +813,8 @@ impl MoveGenerator {
             }
             if board.can_castle_ks(Color::White)
                 && occupancy & self.ranks[Ranks::One as usize] & file_mask_ks == 0
-                && !self.is_sq_under_attack::<P>(Square::F1.sq(), board, occupancy)
-                && !self.is_sq_under_attack::<P>(Square::G1.sq(), board, occupancy)
+                && !is_attacked(Square::F1)
+                && !is_attacked(Square::G1)
             {
                 move_list.add_move_with_flags(
                     Square::E1.sq(),
@@ -1054,8 +825,8 @@ impl MoveGenerator {
         } else {
             if board.can_castle_qs(Color::Black)
                 && occupancy & self.ranks[Ranks::Eight as usize] & file_mask_qs == 0
-                && !self.is_sq_under_attack::<P>(Square::D8.sq(), board, occupancy)
-                && !self.is_sq_under_attack::<P>(Square::C8.sq(), board, occupancy)
+                && !is_attacked(Square::D8)
+                && !is_attacked(Square::C8)
             {
                 move_list.add_move_with_flags(
                     Square::E8.sq(),
@@ -1065,8 +836,8 @@ impl MoveGenerator {
             }
             if board.can_castle_ks(Color::Black)
                 && occupancy & self.ranks[Ranks::Eight as usize] & file_mask_ks == 0
-                && !self.is_sq_under_attack::<P>(Square::F8.sq(), board, occupancy)
-                && !self.is_sq_under_attack::<P>(Square::G8.sq(), board, occupancy)
+                && !is_attacked(Square::F8)
+                && !is_attacked(Square::G8)
             {
                 move_list.add_move_with_flags(
                     Square::E8.sq(),
@@ -1168,6 +939,7 @@ impl MoveGenerator {
             }
         }
     }
+    #[allow(clippy::too_many_arguments)]
     fn add_pinned_moves<P: PlayerTrait>(
         &self,
         move_list: &mut MoveList,
@@ -1180,7 +952,7 @@ impl MoveGenerator {
         attacker_pos: usize,
     ) {
         let moves_mask = legal_captures | blockers;
-        let pin_move_mask = self.slider_range[attacker_pos][king_pos]
+        let pin_move_mask = self.attacks.between(attacker_pos, king_pos)
             & !board.get_combined_bb(P::color())
             | (1u64 << attacker_pos);
 
@@ -1230,7 +1002,7 @@ impl MoveGenerator {
         } else {
             if piece.is_bishop() || piece.is_queen() {
                 let mut moves =
-                    self.magic_bishop_moves(pinned_pos, occupancy) & moves_mask & pin_move_mask;
+                    self.attacks.bishop_attacks(pinned_pos, occupancy) & moves_mask & pin_move_mask;
 
                 while moves != 0 {
                     move_list.add_move(pinned_pos, moves.pop_lsb());
@@ -1239,7 +1011,7 @@ impl MoveGenerator {
 
             if piece.is_rook() || piece.is_queen() {
                 let mut moves =
-                    self.magic_rook_moves(pinned_pos, occupancy) & moves_mask & pin_move_mask;
+                    self.attacks.rook_attacks(pinned_pos, occupancy) & moves_mask & pin_move_mask;
 
                 while moves != 0 {
                     move_list.add_move(pinned_pos, moves.pop_lsb());
@@ -1248,17 +1020,47 @@ impl MoveGenerator {
         }
         // king cannot be pinned, knight cannot move if pinned
     }
-    fn gen_pin_attackers<P: PlayerTrait>(
+    #[allow(clippy::too_many_arguments)]
+    fn gen_pinned_moves<P: PlayerTrait>(
         &self,
         move_list: &mut MoveList,
         board: &mut Board,
         occupancy: u64,
         king_pos: usize,
-        checkers: u64,
+        check_info: &CheckInfo,
         legal_captures: u64,
         blockers: u64,
+    ) {
+        let mut pinners = check_info.pinners;
+
+        while pinners != 0 {
+            let attacker_pos = pinners.pop_lsb();
+            let pinned_pos =
+                (self.attacks.between(attacker_pos, king_pos) & check_info.pinned).lsb_idx();
+
+            self.add_pinned_moves::<P>(
+                move_list,
+                board,
+                occupancy,
+                legal_captures,
+                blockers,
+                king_pos,
+                pinned_pos,
+                attacker_pos,
+            );
+        }
+    }
+
+    /* -------------------------------------------------------------------------- */
+    /*                               Checks & Pins                                */
+    /* -------------------------------------------------------------------------- */
+    fn gen_pins<P: PlayerTrait>(
+        &self,
+        board: &Board,
+        king_pos: usize,
+        checkers: u64,
         is_bishop: bool,
-    ) -> u64 {
+    ) -> (u64, u64) {
         let enemy_color = P::enemy();
         let piece_mask = board.get_bb(Pieces::queen(enemy_color))
             | board.get_bb(if is_bishop {
@@ -1266,90 +1068,89 @@ impl MoveGenerator {
             } else {
                 Pieces::rook(enemy_color)
             });
+
+        // x-ray through friendly pieces by only using the enemy pieces as blockers
         let mut attackers = if is_bishop {
-            self.magic_bishop_moves(king_pos, board.get_combined_bb(P::enemy()))
+            self.attacks.bishop_attacks(king_pos, board.get_combined_bb(enemy_color))
         } else {
-            self.magic_rook_moves(king_pos, board.get_combined_bb(P::enemy()))
+            self.attacks.rook_attacks(king_pos, board.get_combined_bb(enemy_color))
         } & piece_mask
             & !checkers;
-        let mut pinned_pieces = 0;
+
+        let mut pinned = 0;
+        let mut pinners = 0;
 
         while attackers != 0 {
             let attacker_pos = attackers.pop_lsb();
-            let mut occupied =
-                self.slider_range[attacker_pos][king_pos] & board.get_combined_bb(P::color());
-            if occupied == 0 {
-                continue;
-            }
-
-            let pinned_pos = occupied.pop_lsb();
+            let occupied =
+                self.attacks.between(attacker_pos, king_pos) & board.get_combined_bb(P::color());
 
             // only one piece blocking therefore there is a pin
-            if occupied == 0 {
-                self.add_pinned_moves::<P>(
-                    move_list,
-                    board,
-                    occupancy,
-                    legal_captures,
-                    blockers,
-                    king_pos,
-                    pinned_pos,
-                    attacker_pos,
-                );
-                pinned_pieces.set_bit(pinned_pos);
+            if occupied.count_1s() == 1 {
+                pinned |= occupied;
+                pinners.set_bit(attacker_pos);
             }
         }
 
-        pinned_pieces
+        (pinned, pinners)
     }
-    fn gen_pinned_pieces<P: PlayerTrait>(
-        &self,
-        move_list: &mut MoveList,
-        board: &mut Board,
-        occupancy: u64,
-        king_pos: usize,
-        checkers: u64,
-        legal_captures: u64,
-        blockers: u64,
-    ) -> u64 {
-        self.gen_pin_attackers::<P>(
-            move_list,
-            board,
-            occupancy,
-            king_pos,
-            checkers,
-            legal_captures,
-            blockers,
-            true,
-        ) | self.gen_pin_attackers::<P>(
-            move_list,
-            board,
-            occupancy,
-            king_pos,
+    fn checkers_and_pins_for_player<P: PlayerTrait>(&self, board: &Board) -> CheckInfo {
+        let king_pos = match board.try_king_square(P::color()) {
+            Some(king) => king.sq(),
+            None => return CheckInfo::default(),
+        };
+
+        let occupancy = board.get_occupancy();
+
+        let checkers = self.find_enemy_attackers::<P>(king_pos, board, occupancy);
+        let (bishop_pinned, bishop_pinners) = self.gen_pins::<P>(board, king_pos, checkers, true);
+        let (rook_pinned, rook_pinners) = self.gen_pins::<P>(board, king_pos, checkers, false);
+
+        CheckInfo {
             checkers,
-            legal_captures,
-            blockers,
-            false,
-        )
+            pinned: bishop_pinned | rook_pinned,
+            pinners: bishop_pinners | rook_pinners,
+        }
     }
 
-    fn gen_moves_for_player<P: PlayerTrait>(&self, board: &mut Board, move_list: &mut MoveList) {
+    // With captures_only, the pieces may only move onto enemy pieces and the
+    // pawns may only push to promote (en passant is a capture as usual), and
+    // there is no castling. The checks and pins are dealt with the same way.
+    fn gen_moves_for_player<P: PlayerTrait>(
+        &self,
+        board: &mut Board,
+        move_list: &mut MoveList,
+        captures_only: bool,
+    ) -> CheckInfo {
         move_list.clear();
 
+        // no legal moves without exactly one king (only reachable from an
+        // invalid position, see gen_moves)
+        let king_pos = match board.try_king_square(P::color()) {
+            Some(king) => king.sq(),
+            None => return CheckInfo::default(),
+        };
+
         let occupancy = board.get_occupancy();
-        let king_pos = board.get_bb(Pieces::king(P::color())).lsb_idx();
+
+        // the squares pieces and pawn pushes may end on
+        let (targets, push_targets) = if captures_only {
+            (
+                board.get_combined_bb(P::enemy()),
+                self.ranks[P::opposite_back_rank() as usize],
+            )
+        } else {
+            (FULL_BB, FULL_BB)
+        };
 
         // always generate king moves first
-        self.add_king_moves::<P>(move_list, board, occupancy);
+        self.add_king_moves::<P>(move_list, board, occupancy, targets);
 
-        // calculate pieces giving check
-        let attacking_king = self.find_enemy_attackers::<P>(king_pos, board, occupancy);
+        // calculate pieces giving check and pinned pieces
+        let check_info = self.checkers_and_pins_for_player::<P>(board);
+        let attacking_king = check_info.checkers;
 
         match attacking_king.count_1s() {
-            // double check
-            2 => {
-                // king moves are the only option, already calculated
-            }
             // single check
             1 => {
                 let attacker_pos = attacking_king.lsb_idx();
@@ -1360,18 +1161,19 @@ impl MoveGenerator {
                     // a knight cannot move out of a pin
                     0
                 } else {
-                    self.slider_range[king_pos][attacker_pos]
+                    self.attacks.between(king_pos, attacker_pos)
                 };
 
-                let move_mask = attacking_king | blockers;
-                let pinned = self.gen_pinned_pieces::<P>(
+                let move_mask = (attacking_king | blockers) & targets;
+                let pinned = check_info.pinned;
+                self.gen_pinned_moves::<P>(
                     move_list,
                     board,
                     occupancy,
                     king_pos,
+                    &check_info,
                     attacking_king,
-                    attacking_king,
-                    blockers,
+                    blockers & targets,
                 );
 
                 self.add_pawn_moves::<P>(
@@ -1380,7 +1182,7 @@ impl MoveGenerator {
                     occupancy,
                     pinned,
                     attacking_king,
-                    blockers,
+                    blockers & push_targets,
                     king_pos,
                 );
                 self.add_knight_moves::<P>(move_list, board, pinned, move_mask);
@@ -1390,178 +1192,551 @@ impl MoveGenerator {
             }
             // not in check - standard move generation
             0 => {
-                let pinned = self.gen_pinned_pieces::<P>(
-                    move_list, board, occupancy, king_pos, 0, FULL_BB, FULL_BB,
+                let pinned = check_info.pinned;
+                // a pawn pinned along a file never reaches the last rank, the
+                // pinner or the king is in the way
+                self.gen_pinned_moves::<P>(
+                    move_list,
+                    board,
+                    occupancy,
+                    king_pos,
+                    &check_info,
+                    targets,
+                    targets,
                 );
 
-                self.add_castling_moves::<P>(move_list, board, occupancy);
+                if !captures_only {
+                    self.add_castling_moves::<P>(move_list, board, occupancy, true);
+                }
                 self.add_pawn_moves::<P>(
-                    move_list, board, occupancy, pinned, FULL_BB, FULL_BB, king_pos,
+                    move_list, board, occupancy, pinned, FULL_BB, push_targets, king_pos,
                 );
-                self.add_knight_moves::<P>(move_list, board, pinned, FULL_BB);
-                self.add_bishop_moves::<P>(move_list, board, occupancy, pinned, FULL_BB);
-                self.add_rook_moves::<P>(move_list, board, occupancy, pinned, FULL_BB);
-                self.add_queen_moves::<P>(move_list, board, occupancy, pinned, FULL_BB);
+                self.add_knight_moves::<P>(move_list, board, pinned, targets);
+                self.add_bishop_moves::<P>(move_list, board, occupancy, pinned, targets);
+                self.add_rook_moves::<P>(move_list, board, occupancy, pinned, targets);
+                self.add_queen_moves::<P>(move_list, board, occupancy, pinned, targets);
+            }
+            // double check (or more in an illegal position)
+            _ => {
+                // king moves are the only option, already calculated
+            }
+        }
+
+        check_info
+    }
+
+    fn gen_pseudo_legal_for_player<P: PlayerTrait>(&self, board: &Board, move_list: &mut MoveList) {
+        move_list.clear();
+
+        // as for legal moves, nothing is generated without exactly one king
+        let start = match board.try_king_square(P::color()) {
+            Some(king) => king.sq(),
+            None => return,
+        };
+
+        let occupancy = board.get_occupancy();
+
+        let mut king_moves = KING_ATTACKS[start] & !board.get_combined_bb(P::color());
+        while king_moves != 0 {
+            move_list.add_move(start, king_moves.pop_lsb());
+        }
+        self.add_castling_moves::<P>(move_list, board, occupancy, false);
+
+        self.add_pseudo_legal_pawn_captures::<P, LeftCapture>(move_list, board);
+        self.add_pseudo_legal_pawn_captures::<P, RightCapture>(move_list, board);
+        self.add_pawn_pushes::<P>(move_list, board, occupancy, 0, FULL_BB);
+        self.add_knight_moves::<P>(move_list, board, 0, FULL_BB);
+        self.add_bishop_moves::<P>(move_list, board, occupancy, 0, FULL_BB);
+        self.add_rook_moves::<P>(move_list, board, occupancy, 0, FULL_BB);
+        self.add_queen_moves::<P>(move_list, board, occupancy, 0, FULL_BB);
+    }
+    fn is_pseudo_legal_for_player<P: PlayerTrait>(&self, board: &Board, my_move: Move) -> bool {
+        let start = my_move.get_move_start() as usize;
+        let end = my_move.get_move_end() as usize;
+        let piece = match board.pieces[start] {
+            Some(piece) if piece.color() == P::color() => piece,
+            _ => return false,
+        };
+
+        let occupancy = board.get_occupancy();
+        let enemy_bb = board.get_combined_bb(P::enemy());
+        let targets = !board.get_combined_bb(P::color());
+        let back_rank = self.ranks[P::opposite_back_rank() as usize];
+
+        // the squares a pawn on start can move to, ignoring promotions
+        let pawn_targets = || {
+            let single = (start as i16 - P::forward_offset()) as usize;
+            let mut pawn_targets = PAWN_ATTACKS[P::color().idx()][start] & enemy_bb;
+            if !occupancy.is_bit_set(single) {
+                pawn_targets.set_bit(single);
+
+                let double = (single as i16 - P::forward_offset()) as usize;
+                if self.ranks[P::en_passant_rank() as usize].is_bit_set(single) && !occupancy.is_bit_set(double) {
+                    pawn_targets.set_bit(double);
+                }
+            }
+            pawn_targets
+        };
+
+        match my_move.get_move_type() {
+            MOVE_TYPE_CASTLE => {
+                // only the generator knows the rules, and there are at most two
+                let mut castles = MoveList::new();
+                self.add_castling_moves::<P>(&mut castles, board, occupancy, false);
+                castles.moves.iter().any(|&(castle, _)| castle == my_move)
             }
+            MOVE_TYPE_EN_PASSANT => match board.en_passant {
+                Some(en_passant) => {
+                    let en_passant = en_passant.sq();
+                    piece.is_pawn()
+                        && PAWN_ATTACKS[P::enemy().idx()][en_passant].is_bit_set(start)
+                        && end == (en_passant as i16 + P::forward_offset()) as usize
+                        && my_move.get_move_piece() == 0
+                }
+                None => false,
+            },
+            MOVE_TYPE_PROMOTION => piece.is_pawn() && back_rank.is_bit_set(end) && pawn_targets().is_bit_set(end),
             _ => {
-                panic!("Invalid number of attackers on the king");
+                let reachable = if piece.is_pawn() {
+                    pawn_targets() & !back_rank
+                } else if piece.is_knight() {
+                    KNIGHT_ATTACKS[start]
+                } else if piece.is_bishop() {
+                    self.attacks.bishop_attacks(start, occupancy)
+                } else if piece.is_rook() {
+                    self.attacks.rook_attacks(start, occupancy)
+                } else if piece.is_queen() {
+                    self.attacks.queen_attacks(start, occupancy)
+                } else {
+                    KING_ATTACKS[start]
+                };
+                my_move.get_move_piece() == 0 && (reachable & targets).is_bit_set(end)
             }
         }
     }
+    fn add_pseudo_legal_pawn_captures<P: PlayerTrait, C: CaptureSideTrait>(&self, move_list: &mut MoveList, board: &Board) {
+        let captures = self.pawn_capture_squares::<P, C>(board, 0);
+
+        if let Some(en_passant) = board.en_passant {
+            let en_passant = en_passant.sq() as i16;
+            if captures.is_bit_set(en_passant as usize) {
+                move_list.add_move_with_flags(
+                    (en_passant + P::capture_offset(C::is_left())) as usize,
+                    (en_passant + P::forward_offset()) as usize,
+                    MOVE_TYPE_EN_PASSANT,
+                );
+            }
+        }
+
+        self.add_pawn_capture_moves::<P, C>(move_list, captures & board.get_combined_bb(P::enemy()));
+    }
+
+    // The moves in the position ignoring checks and pins, so some may leave
+    // the king attacked; castling only needs the rights and an empty path.
+    // Use Board::leaves_king_in_check to filter out the illegal ones.
+    // Everything else uses the legal generator, this is for comparing the
+    // two approaches.
+    pub fn gen_pseudo_legal(&self, board: &Board, move_list: &mut MoveList) {
+        if board.friendly_color().is_white() {
+            self.gen_pseudo_legal_for_player::<WhitePlayer>(board, move_list)
+        } else {
+            self.gen_pseudo_legal_for_player::<BlackPlayer>(board, move_list)
+        }
+    }
+
+    // True if the move is one gen_pseudo_legal would generate, found
+    // without generating anything, for moves from elsewhere such as an
+    // earlier search. Board::leaves_king_in_check then says if it's legal.
+    // A move whose flags don't fit its squares is turned away first, so a
+    // corrupt one never reaches the checks by type.
+    pub fn is_pseudo_legal(&self, board: &Board, my_move: Move) -> bool {
+        if !my_move.validate_shape() {
+            false
+        } else if board.friendly_color().is_white() {
+            self.is_pseudo_legal_for_player::<WhitePlayer>(board, my_move)
+        } else {
+            self.is_pseudo_legal_for_player::<BlackPlayer>(board, my_move)
+        }
+    }
+
+    // true if the enemies of color attack the square
+    pub fn is_sq_attacked(&self, board: &Board, sq: usize, color: Color) -> bool {
+        let occupancy = board.get_occupancy();
+        if color.is_white() {
+            self.is_sq_under_attack::<WhitePlayer>(sq, board, occupancy)
+        } else {
+            self.is_sq_under_attack::<BlackPlayer>(sq, board, occupancy)
+        }
+    }
+
+    // The legal moves in the position. The order they are generated in is
+    // not part of the API, use MoveList::sort_canonical for a stable one.
+    // The checkers and pins are found along the way, and returned so that
+    // callers needn't look for them again.
+    //
+    // A side without exactly one king has no moves. A missing king is
+    // expected now and then, as searches of positions which were illegal to
+    // begin with capture it, but a second one is a bug in whatever made the
+    // position, so a debug build stops there.
+    pub fn gen_moves(&self, board: &mut Board, move_list: &mut MoveList) -> CheckInfo {
+        debug_assert!(
+            board.get_bb(Pieces::king(board.friendly_color())).count_1s() <= 1,
+            "generating moves for a side with more than one king: {}",
+            board.to_fen()
+        );
+        if board.friendly_color().is_white() {
+            self.gen_moves_for_player::<WhitePlayer>(board, move_list, false)
+        } else {
+            self.gen_moves_for_player::<BlackPlayer>(board, move_list, false)
+        }
+    }
+
+    // The legal captures (en passant included) and promotions, for the
+    // quiescence search. As with gen_moves the checkers and pins are
+    // returned; when in check, the quiet evasions are left out too.
+    pub fn gen_captures(&self, board: &mut Board, move_list: &mut MoveList) -> CheckInfo {
+        if board.friendly_color().is_white() {
+            self.gen_moves_for_player::<WhitePlayer>(board, move_list, true)
+        } else {
+            self.gen_moves_for_player::<BlackPlayer>(board, move_list, true)
+        }
+    }
 
-    pub fn gen_moves(&self, board: &mut Board, move_list: &mut MoveList) {
+    pub fn checkers_and_pins(&self, board: &Board) -> CheckInfo {
         if board.friendly_color().is_white() {
-            self.gen_moves_for_player::<WhitePlayer>(board, move_list)
+            self.checkers_and_pins_for_player::<WhitePlayer>(board)
         } else {
-            self.gen_moves_for_player::<BlackPlayer>(board, move_list)
+            self.checkers_and_pins_for_player::<BlackPlayer>(board)
         }
     }
 
+    // whether the side to move's king is attacked, false if it doesn't have
+    // exactly one
     pub fn is_in_check(&self, board: &mut Board) -> bool {
         let occupancy = board.get_occupancy();
+        let king_pos = match board.try_king_square(board.friendly_color()) {
+            Some(king) => king.sq(),
+            None => return false,
+        };
 
         if board.friendly_color().is_white() {
-            let king_pos = board.get_bb(Pieces::king(Color::White)).lsb_idx();
             self.find_enemy_attackers::<WhitePlayer>(king_pos, board, occupancy) != 0
         } else {
-            let king_pos = board.get_bb(Pieces::king(Color::Black)).lsb_idx();
             self.find_enemy_attackers::<BlackPlayer>(king_pos, board, occupancy) != 0
         }
     }
 }
 
-/* -------------------------------------------------------------------------- */
-/*                            Rook & Bishop Magics                            */
-/* -------------------------------------------------------------------------- */
-const ROOK_MAGICS: [u64; 64] = [
-    72075735983988992u64,
-    162164771226042368u64,
-    2774234964794286080u64,
-    9295447227374240800u64,
-    7133704077631881220u64,
-    5404321769049293056u64,
-    13871089051341160576u64,
-    4647732546161868928u64,
-    1154188151204364296u64,
-    281623304421378u64,
-    9585349132126560768u64,
-    324399945019818112u64,
-    1266654575591552u64,
-    294422971669283848u64,
-    9228016932324638976u64,
-    422213622698112u64,
-    18019346383143456u64,
-    13519870926790656u64,
-    6917743432679031040u64,
-    4611968593184169992u64,
-    12170978542791720968u64,
-    144159173373870084u64,
-    73228578216739328u64,
-    2199036100765u64,
-    56330731617533952u64,
-    148619063654883328u64,
-    4625232012420055168u64,
-    14988261623278407680u64,
-    1478588125675784194u64,
-    577024260602875912u64,
-    2468254118020653568u64,
-    144256209032118404u64,
-    40577751509369480u64,
-    6917564213158219778u64,
-    9007478444400656u64,
-    20839044434890752u64,
-    4611976300242928640u64,
-    4617878489423415312u64,
-    11278859869620225u64,
-    288230653210657060u64,
-    576531123197214720u64,
-    844699816624161u64,
-    4616198431329755136u64,
-    1513221569692893216u64,
-    12125942013883416584u64,
-    4613005570896036100u64,
-    72066394459734032u64,
-    1765429764459462660u64,
-    342291713626218624u64,
-    22518273021051200u64,
-    9464597434109056u64,
-    613052534176650752u64,
-    20547690614100224u64,
-    140746078552192u64,
-    45044801233552384u64,
-    27028749086179840u64,
-    290556685111457u64,
-    288865903000617090u64,
-    1161084417409045u64,
-    289075918041778209u64,
-    2522578810537804930u64,
-    1298444514277720065u64,
-    1143496522109444u64,
-    2305843716071555138u64,
-];
-const BISHOP_MAGICS: [u64; 64] = [
-    1179020146311185u64,
-    145267478427205635u64,
-    4504158111531524u64,
-    9224516499644878888u64,
-    144680405855912002u64,
-    4619005622497574912u64,
-    1130315234418688u64,
-    5349125176573952u64,
-    6071010655858065920u64,
-    20310248111767713u64,
-    1297094009090539520u64,
-    4616233778910625860u64,
-    2305849615159678976u64,
-    74381998193642242u64,
-    1407684255942661u64,
-    2305862803678299144u64,
-    22535635693734016u64,
-    4503608284938884u64,
-    11259016393073153u64,
-    108650578499878976u64,
-    41095363813851170u64,
-    9232520132522148096u64,
-    70385943187776u64,
-    9227035893351617024u64,
-    1155182103739172867u64,
-    11530343153862181120u64,
-    2295791083930624u64,
-    1130297991168512u64,
-    281543712980996u64,
-    307513611096490433u64,
-    2289183226103316u64,
-    4612816874811392128u64,
-    4547891544985604u64,
-    3458958372559659520u64,
-    303473866573824u64,
-    1729558217427519744u64,
-    5633914760597520u64,
-    1441434463836899328u64,
-    20269028707403544u64,
-    149744981853258752u64,
-    2252933819802113u64,
-    1163074498090533888u64,
-    4681729134575680u64,
-    4621485970984798208u64,
-    367078571518203970u64,
-    72621098075685120u64,
-    1225544256278495744u64,
-    1411779381045761u64,
-    5333500077688291352u64,
-    4716913491968128u64,
-    148627764202701056u64,
-    1688850967695425u64,
-    17781710002178u64,
-    9243644149415084036u64,
-    218426849703891488u64,
-    9009415596316677u64,
-    1412882374067224u64,
-    279186509824u64,
-    20407489916899328u64,
-    4614113755159331840u64,
-    144119586390940160u64,
-    11547234118442230016u64,
-    5188151323463779840u64,
-    435758450535334272u64,
-];
+impl Default for MoveGenerator {
+    fn default() -> MoveGenerator {
+        MoveGenerator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn check_info(fen: &str, generator: &MoveGenerator) -> CheckInfo {
+        let board = Board::new(fen).unwrap();
+        generator.checkers_and_pins(&board)
+    }
+
+    fn moves_from(fen: &str, sq: Square, generator: &MoveGenerator) -> usize {
+        let mut board = Board::new(fen).unwrap();
+        let mut move_list = MoveList::new();
+        generator.gen_moves(&mut board, &mut move_list);
+
+        (0..move_list.len())
+            .filter(|&i| move_list.at(i).get_move_start() as usize == sq.sq())
+            .count()
+    }
+
+    #[test]
+    fn kingless_boards() {
+        use crate::engine::board::BoardBuilder;
+
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+
+        // white has no king to be in check or to move, though its rook
+        // attacks the black king
+        let mut board = BoardBuilder::new()
+            .piece(Square::A1, Pieces::WhiteRook)
+            .piece(Square::A8, Pieces::BlackKing)
+            .build()
+            .unwrap();
+        assert!(!generator.is_in_check(&mut board));
+        assert_eq!(generator.gen_moves(&mut board, &mut move_list), CheckInfo::default());
+        assert!(move_list.is_empty());
+        generator.gen_pseudo_legal(&board, &mut move_list);
+        assert!(move_list.is_empty());
+
+        let rook_move = board.make_uci_move("a1a2", &generator, &mut UndoInfo::default());
+        assert!(rook_move.is_err());
+
+        // black does, and is in check
+        let mut board = board.into_builder().side_to_move(Color::Black).build().unwrap();
+        assert!(generator.is_in_check(&mut board));
+        generator.gen_moves(&mut board, &mut move_list);
+        assert_eq!(move_list.len(), 2);
+
+        // with two kings the side to move is never in check
+        let mut board = BoardBuilder::new()
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::E2, Pieces::WhiteKing)
+            .piece(Square::E8, Pieces::BlackRook)
+            .piece(Square::A8, Pieces::BlackKing)
+            .build()
+            .unwrap();
+        assert!(!generator.is_in_check(&mut board));
+        assert_eq!(generator.checkers_and_pins(&board), CheckInfo::default());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "generating moves for a side with more than one king: 4k3/8/8/8/8/8/8/3KK3 w - - 0 1")]
+    fn two_kings() {
+        use crate::engine::board::BoardBuilder;
+
+        let mut board = BoardBuilder::new()
+            .piece(Square::D1, Pieces::WhiteKing)
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::E8, Pieces::BlackKing)
+            .build()
+            .unwrap();
+        MoveGenerator::new().gen_moves(&mut board, &mut MoveList::new());
+    }
+
+    #[test]
+    fn checkers_and_pins() {
+        let generator = MoveGenerator::new();
+
+        // no checks or pins
+        let info = check_info(crate::engine::board::STARTING_FEN, &generator);
+        assert_eq!(info, CheckInfo::default());
+
+        // knight on e2 pinned by the rook on e8
+        let info = check_info("4r1k1/8/8/8/8/8/4N3/4K3 w - - 0 1", &generator);
+        assert_eq!(info.checkers, 0);
+        assert_eq!(info.pinned, 1 << Square::E2.sq());
+        assert_eq!(info.pinners, 1 << Square::E8.sq());
+        assert_eq!(moves_from("4r1k1/8/8/8/8/8/4N3/4K3 w - - 0 1", Square::E2, &generator), 0);
+
+        // two friendly pieces between the rook and king, so neither is pinned
+        let info = check_info("4r1k1/8/8/8/4B3/8/4N3/4K3 w - - 0 1", &generator);
+        assert_eq!(info.pinned, 0);
+        assert_eq!(info.pinners, 0);
+
+        // bishop pinned diagonally can only move along the pin
+        let info = check_info("6k1/8/8/8/q7/8/2B5/3K4 w - - 0 1", &generator);
+        assert_eq!(info.pinned, 1 << Square::C2.sq());
+        assert_eq!(info.pinners, 1 << Square::A4.sq());
+        assert_eq!(moves_from("6k1/8/8/8/q7/8/2B5/3K4 w - - 0 1", Square::C2, &generator), 2);
+
+        // enemy piece in the way of the x-ray means no pin
+        let info = check_info("4r1k1/8/8/4n3/8/8/4N3/4K3 w - - 0 1", &generator);
+        assert_eq!(info.pinned, 0);
+
+        // single check from a rook, with a pinned rook elsewhere
+        let info = check_info("3k4/8/8/b7/8/8/3R4/r3K3 w - - 0 1", &generator);
+        assert_eq!(info.checkers, 1 << Square::A1.sq());
+        assert_eq!(info.pinned, 1 << Square::D2.sq());
+        assert_eq!(info.pinners, 1 << Square::A5.sq());
+        assert_eq!(moves_from("3k4/8/8/b7/8/8/3R4/r3K3 w - - 0 1", Square::D2, &generator), 0);
+
+        // double check
+        let info = check_info("4r1k1/8/8/8/8/5n2/8/4K3 w - - 0 1", &generator);
+        assert_eq!(info.checkers.count_1s(), 2);
+    }
+
+    #[test]
+    fn checkers_match_is_in_check() {
+        let generator = MoveGenerator::new();
+
+        for fen in &[
+            crate::engine::board::STARTING_FEN,
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/4r3/8/8/4Q3/8/2K5 b - - 0 1",
+            "8/8/8/8/k2Pp2Q/8/8/2K5 b - d3 0 1",
+            "4k3/6N1/5b2/4R3/8/8/8/4K3 b - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let info = generator.checkers_and_pins(&board);
+            assert_eq!(info.checkers != 0, generator.is_in_check(&mut board));
+        }
+    }
+
+    // testdata/canonical_moves.txt holds positions, each followed by their
+    // moves in canonical order
+    #[test]
+    fn canonical_order() {
+        let generator = MoveGenerator::new();
+        let golden = include_str!("../../testdata/canonical_moves.txt");
+        let mut lines = golden.lines().filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let mut positions = 0;
+
+        while let Some(fen) = lines.next() {
+            let expected = lines.next().unwrap_or_default();
+            let mut board = Board::new(fen).unwrap();
+            let mut move_list = MoveList::new();
+            generator.gen_moves(&mut board, &mut move_list);
+
+            // whatever order they start in
+            move_list.moves.reverse();
+            move_list.sort_canonical();
+            let moves: Vec<String> = (0..move_list.len()).map(|i| move_list.at(i).move_to_string()).collect();
+            assert_eq!(moves.join(" "), expected, "{}", fen);
+            positions += 1;
+        }
+
+        assert_eq!(positions, 8);
+    }
+
+    #[test]
+    fn castling_through_attacks() {
+        let generator = MoveGenerator::new();
+
+        // (position, the castling moves which are legal)
+        for (fen, castles) in &[
+            ("7k/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1"),
+            // attacks on the rooks and b1 don't matter
+            ("r6k/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1"),
+            ("1r5k/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1"),
+            ("6kr/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1"),
+            // but c1, d1, e1, f1 and g1 do
+            ("2r4k/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"),
+            ("3r3k/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1g1"),
+            ("4r2k/8/8/8/8/8/8/R3K2R w KQ - 0 1", ""),
+            ("5r1k/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1"),
+            ("k5r1/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1"),
+            // by any piece
+            ("7k/8/8/7b/8/8/8/R3K2R w KQ - 0 1", "e1g1"),
+            ("7k/8/8/8/8/1n6/8/R3K2R w KQ - 0 1", "e1g1"),
+            ("7k/8/8/8/8/8/4p3/R3K2R w KQ - 0 1", ""),
+            // and the same for black
+            ("r3k2r/8/8/8/8/8/8/1R5K b kq - 0 1", "e8c8 e8g8"),
+            ("r3k2r/8/8/8/8/8/8/2R4K b kq - 0 1", "e8g8"),
+            ("r3k2r/8/8/8/8/8/8/K5R1 b kq - 0 1", "e8c8"),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut move_list = MoveList::new();
+            generator.gen_moves(&mut board, &mut move_list);
+            move_list.sort_canonical();
+
+            let found: Vec<String> = (0..move_list.len())
+                .map(|i| move_list.at(i))
+                .filter(|my_move| my_move.get_move_type() == MOVE_TYPE_CASTLE)
+                .map(|my_move| my_move.move_to_string())
+                .collect();
+            assert_eq!(found.join(" "), *castles, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn pseudo_legal_castling() {
+        let generator = MoveGenerator::new();
+
+        // (position, castling moves generated, those which are legal)
+        for (fen, generated, legal) in &[
+            ("4k3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1", "e1c1 e1g1"),
+            // through check on d1
+            ("3rk3/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1", "e1g1"),
+            // out of check
+            ("4r1k1/8/8/8/8/8/8/R3K2R w KQ - 0 1", "e1c1 e1g1", ""),
+            // into check on g8
+            ("r3k2r/8/8/8/8/8/8/6RK b kq - 0 1", "e8c8 e8g8", "e8c8"),
+            // blocked
+            ("r3k2r/8/8/8/8/8/8/1N2K1N1 w - - 0 1", "", ""),
+            ("rn2k1nr/8/8/8/8/8/8/4K3 b kq - 0 1", "", ""),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut move_list = MoveList::new();
+            generator.gen_pseudo_legal(&board, &mut move_list);
+
+            let castles: Vec<Move> = (0..move_list.len())
+                .map(|i| move_list.at(i))
+                .filter(|my_move| my_move.get_move_type() == MOVE_TYPE_CASTLE)
+                .collect();
+            let to_string = |moves: &[Move]| moves.iter().map(|m| m.move_to_string()).collect::<Vec<_>>().join(" ");
+            assert_eq!(to_string(&castles), *generated, "{}", fen);
+
+            let kept: Vec<Move> = castles
+                .into_iter()
+                .filter(|&my_move| !board.leaves_king_in_check(my_move, &generator))
+                .collect();
+            assert_eq!(to_string(&kept), *legal, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn order_moves() {
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        let is_capture = |board: &Board, my_move: Move| board.pieces[my_move.get_move_end() as usize].is_some();
+
+        // the bishop on a6 is the most valuable piece white can take
+        let mut board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        generator.gen_moves(&mut board, &mut move_list);
+        move_list.order_moves(&board);
+        assert_eq!(move_list.at(0).move_to_string(), "e2a6");
+
+        // captures before quiet moves, and nothing lost
+        let captures = (0..move_list.len()).take_while(|&i| is_capture(&board, move_list.at(i))).count();
+        assert_eq!(captures, 8);
+        assert!((captures..move_list.len()).all(|i| !is_capture(&board, move_list.at(i))));
+        assert_eq!(move_list.len(), 48);
+
+        // the same for black, whose piece values are negative
+        let mut board = Board::new("4k3/8/8/3Qr3/2p5/3P4/8/4K3 b - - 0 1").unwrap();
+        generator.gen_moves(&mut board, &mut move_list);
+        move_list.order_moves(&board);
+        let order: Vec<String> = (0..2).map(|i| move_list.at(i).move_to_string()).collect();
+        assert_eq!(order, vec!["e5d5", "c4d3"]);
+
+        // promotions come after winning captures, but before losing ones
+        let mut board = Board::new("4k3/1P6/8/8/8/8/3n4/3QK1Rb w - - 0 1").unwrap();
+        generator.gen_moves(&mut board, &mut move_list);
+        move_list.order_moves(&board);
+        let order: Vec<String> = (0..7).map(|i| move_list.at(i).move_to_string()).collect();
+        assert_eq!(order, vec!["e1d2", "b7b8q", "b7b8r", "b7b8b", "b7b8n", "g1h1", "d1d2"]);
+    }
+
+    #[test]
+    fn pick_next() {
+        let generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "8/8/8/8/8/8/8/k1K5 w - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            generator.gen_moves(&mut board, &mut move_list);
+            let mut generated: Vec<Move> = (0..move_list.len()).map(|i| move_list.at(i)).collect();
+
+            move_list.score_moves(&board);
+            let scores: HashMap<Move, i32> = move_list.moves.iter().copied().collect();
+            let mut picked = Vec::new();
+            while let Some(my_move) = move_list.pick_next() {
+                picked.push(my_move);
+            }
+            assert_eq!(move_list.pick_next(), None);
+
+            // best first
+            let scores: Vec<i32> = picked.iter().map(|my_move| scores[my_move]).collect();
+            assert!(scores.windows(2).all(|pair| pair[0] >= pair[1]), "{}: {:?}", fen, scores);
+
+            // and every move exactly once
+            picked.sort_unstable();
+            generated.sort_unstable();
+            assert_eq!(picked, generated, "{}", fen);
+        }
+
+        // nothing to pick
+        move_list.clear();
+        assert_eq!(move_list.pick_next(), None);
+    }
+}
@@ -0,0 +1,267 @@
+// Games packed into about a byte a move, for datasets too big to keep as
+// PGN, such as opening book sources and self-play games. Each move is
+// stored as its index in the legal moves of the position it was played in,
+// sorted as by MoveList::sort_canonical, so the stream means nothing
+// without the position it starts from and is decoded by replaying it.
+//
+// No position has 255 legal moves, but an index of 255 or more would be
+// written as 255 followed by the index less 255.
+//
+// A packed game is:
+//
+//     "CRMV"                   magic
+//     u16 (little endian)      length of the starting fen
+//     fen                      the starting position, in ascii
+//     u32 (little endian)      number of moves
+//     moves                    as above
+
+use crate::engine::board::Board;
+use crate::engine::error::{Error, PackError};
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::r#move::{Move, UndoInfo};
+
+const GAME_MAGIC: &[u8; 4] = b"CRMV";
+
+// an index at least this large takes a second byte
+const ESCAPE: u8 = u8::MAX;
+
+// the legal moves from the position, in their canonical order
+fn sorted_moves(board: &mut Board, move_generator: &MoveGenerator, moves: &mut MoveList) {
+    move_generator.gen_moves(board, moves);
+    moves.sort_canonical();
+}
+
+// Packs the moves played from the board, one byte each. Fails if any move
+// isn't legal when it is played.
+pub fn encode_moves(board: &Board, moves: &[Move], move_generator: &MoveGenerator) -> Result<Vec<u8>, Error> {
+    let mut board = board.clone();
+    let mut legal = MoveList::new();
+    let mut info = UndoInfo::default();
+    let mut bytes = Vec::with_capacity(moves.len());
+
+    for (ply, &my_move) in moves.iter().enumerate() {
+        sorted_moves(&mut board, move_generator, &mut legal);
+        let index = (0..legal.len())
+            .find(|&i| legal.at(i) == my_move)
+            .ok_or(PackError::IllegalMove(ply))?;
+
+        if index < ESCAPE as usize {
+            bytes.push(index as u8);
+        } else {
+            bytes.push(ESCAPE);
+            bytes.push((index - ESCAPE as usize) as u8);
+        }
+        board.make_move(my_move, &mut info);
+    }
+
+    Ok(bytes)
+}
+
+// Unpacks moves packed by encode_moves from the same board. A stream which
+// doesn't describe a game of legal moves is an error.
+pub fn decode_moves(board: &Board, bytes: &[u8], move_generator: &MoveGenerator) -> Result<Vec<Move>, Error> {
+    let mut board = board.clone();
+    let mut legal = MoveList::new();
+    let mut info = UndoInfo::default();
+    let mut moves = Vec::with_capacity(bytes.len());
+
+    let mut bytes = bytes.iter();
+    while let Some(&byte) = bytes.next() {
+        let index = match byte {
+            ESCAPE => ESCAPE as usize + *bytes.next().ok_or(PackError::Truncated)? as usize,
+            byte => byte as usize,
+        };
+
+        sorted_moves(&mut board, move_generator, &mut legal);
+        if index >= legal.len() {
+            return Err(PackError::BadIndex(moves.len(), index, legal.len()).into());
+        }
+
+        let my_move = legal.at(index);
+        board.make_move(my_move, &mut info);
+        moves.push(my_move);
+    }
+
+    Ok(moves)
+}
+
+// Packs the game with its starting position.
+pub fn encode_game(board: &Board, moves: &[Move], move_generator: &MoveGenerator) -> Result<Vec<u8>, Error> {
+    let fen = board.to_fen();
+    let packed = encode_moves(board, moves, move_generator)?;
+
+    let mut bytes = Vec::with_capacity(GAME_MAGIC.len() + 2 + fen.len() + 4 + packed.len());
+    bytes.extend_from_slice(GAME_MAGIC);
+    bytes.extend_from_slice(&(fen.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(fen.as_bytes());
+    bytes.extend_from_slice(&(moves.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&packed);
+    Ok(bytes)
+}
+
+// Unpacks a game packed by encode_game, giving its starting position and
+// moves.
+pub fn decode_game(bytes: &[u8], move_generator: &MoveGenerator) -> Result<(Board, Vec<Move>), Error> {
+    let (magic, rest) = split(bytes, GAME_MAGIC.len())?;
+    if magic != GAME_MAGIC {
+        return Err(PackError::BadHeader.into());
+    }
+
+    let (length, rest) = split(rest, 2)?;
+    let (fen, rest) = split(rest, u16::from_le_bytes([length[0], length[1]]) as usize)?;
+    let fen = std::str::from_utf8(fen).map_err(|_| PackError::BadHeader)?;
+    let board = Board::new(fen)?;
+
+    let (count, rest) = split(rest, 4)?;
+    let count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize;
+    let moves = decode_moves(&board, rest, move_generator)?;
+    match moves.len() {
+        len if len < count => Err(PackError::Truncated.into()),
+        len if len > count => Err(PackError::TrailingBytes(rest.len() - encoded_len(rest, count)).into()),
+        _ => Ok((board, moves)),
+    }
+}
+
+fn split(bytes: &[u8], at: usize) -> Result<(&[u8], &[u8]), PackError> {
+    match bytes.len() >= at {
+        true => Ok(bytes.split_at(at)),
+        false => Err(PackError::Truncated),
+    }
+}
+
+// the bytes taken by the first count moves of a stream
+fn encoded_len(bytes: &[u8], count: usize) -> usize {
+    let mut len = 0;
+    for _ in 0..count {
+        len += if bytes[len] == ESCAPE { 2 } else { 1 };
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::error::FenError;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // a game of random legal moves from the board, until it ends or reaches
+    // the number of plies
+    fn random_game(board: &Board, plies: usize, rng: &mut StdRng, move_generator: &MoveGenerator) -> Vec<Move> {
+        let mut board = board.clone();
+        let mut legal = MoveList::new();
+        let mut info = UndoInfo::default();
+        let mut moves = Vec::new();
+
+        while moves.len() < plies {
+            move_generator.gen_moves(&mut board, &mut legal);
+            if legal.is_empty() {
+                break;
+            }
+            let my_move = legal.at(rng.gen_range(0..legal.len()));
+            board.make_move(my_move, &mut info);
+            moves.push(my_move);
+        }
+        moves
+    }
+
+    #[test]
+    fn round_trip() {
+        let move_generator = MoveGenerator::new();
+        let mut rng = StdRng::seed_from_u64(1474);
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        ];
+
+        for fen in fens.iter() {
+            let board = Board::new(fen).unwrap();
+            for _ in 0..50 {
+                let moves = random_game(&board, 300, &mut rng, &move_generator);
+                let packed = encode_moves(&board, &moves, &move_generator).unwrap();
+                assert_eq!(packed.len(), moves.len());
+                assert_eq!(decode_moves(&board, &packed, &move_generator).unwrap(), moves);
+
+                let game = encode_game(&board, &moves, &move_generator).unwrap();
+                let (start, decoded) = decode_game(&game, &move_generator).unwrap();
+                assert_eq!(start.to_fen(), board.to_fen());
+                assert_eq!(decoded, moves);
+            }
+        }
+    }
+
+    #[test]
+    fn illegal_moves() {
+        let move_generator = MoveGenerator::new();
+        let board = Board::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut moves = random_game(&board, 10, &mut rng, &move_generator);
+
+        // a move played twice in a row is the other side's turn
+        moves.insert(4, moves[3]);
+        assert_eq!(
+            encode_moves(&board, &moves, &move_generator),
+            Err(Error::from(PackError::IllegalMove(4)))
+        );
+    }
+
+    #[test]
+    fn corrupted_streams() {
+        let move_generator = MoveGenerator::new();
+        let board = Board::default();
+        let mut rng = StdRng::seed_from_u64(7);
+        let moves = random_game(&board, 80, &mut rng, &move_generator);
+        let game = encode_game(&board, &moves, &move_generator).unwrap();
+
+        // 20 moves from the start position
+        assert_eq!(
+            decode_moves(&board, &[20], &move_generator),
+            Err(Error::from(PackError::BadIndex(0, 20, 20)))
+        );
+        assert_eq!(
+            decode_moves(&board, &[0, ESCAPE], &move_generator),
+            Err(Error::from(PackError::Truncated))
+        );
+        assert_eq!(
+            decode_moves(&board, &[0, ESCAPE, 0], &move_generator),
+            Err(Error::from(PackError::BadIndex(1, 255, 20)))
+        );
+
+        // cut short anywhere, the game is an error
+        for len in 0..game.len() {
+            assert!(decode_game(&game[..len], &move_generator).is_err(), "{}", len);
+        }
+
+        let mut extra = game.clone();
+        extra.push(0);
+        assert_eq!(
+            decode_game(&extra, &move_generator).err(),
+            Some(Error::from(PackError::TrailingBytes(1)))
+        );
+
+        let mut bad_magic = game.clone();
+        bad_magic[0] = b'X';
+        assert_eq!(
+            decode_game(&bad_magic, &move_generator).err(),
+            Some(Error::from(PackError::BadHeader))
+        );
+
+        let mut bad_fen = game.clone();
+        bad_fen[6] = b'X';
+        assert_eq!(
+            decode_game(&bad_fen, &move_generator).err(),
+            Some(Error::from(FenError::InvalidPieceChar('X')))
+        );
+
+        // any byte changed gives another game or an error, never a panic
+        for i in 0..game.len() {
+            for &value in [0, 1, 37, 254, 255].iter() {
+                let mut corrupted = game.clone();
+                corrupted[i] = value;
+                let _ = decode_game(&corrupted, &move_generator);
+            }
+        }
+    }
+}
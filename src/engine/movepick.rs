@@ -0,0 +1,526 @@
+// Hands out the moves at a node in the order they are most likely to cause
+// a cutoff, generating them as late as possible:
+//
+//  1. the transposition table move, checked without generating anything
+//  2. captures which don't lose material, most valuable victim first
+//  3. the killer moves and the countermove, if they are legal here
+//  4. quiet moves, by history
+//  5. captures which lose material, and under-promotions
+//
+// A node which cuts off on the first move never generates the others. On
+// the line the previous iteration found best, its move is passed as the
+// table move instead. Quiet moves with the same history keep the order they
+// were generated in.
+
+use std::cmp::max;
+
+use crate::engine::attacks::Attacks;
+use crate::engine::bitboard::BitBoardUtils;
+use crate::engine::board::Board;
+use crate::engine::eval::Evaluator;
+use crate::engine::movegen::{CheckInfo, MoveGenerator, MoveList};
+use crate::engine::piece::{Color, Pieces};
+use crate::engine::r#move::*;
+
+// The scores given to generated moves put each in its stage, and order the
+// moves within it. Good captures score above GOOD_CAPTURE, bad captures
+// below BAD_CAPTURE, and quiet moves in between.
+const GOOD_CAPTURE: i32 = 1 << 20;
+const BAD_CAPTURE: i32 = -(1 << 20);
+const STAGE_MARGIN: i32 = 1 << 19;
+
+// more than everything else on the board, so a king is never exchanged
+const KING_VALUE: i32 = 20_000;
+
+// history scores are halved when one reaches this, well inside the quiet
+// moves' stage
+const HISTORY_MAX: i32 = 1 << 16;
+
+// How often each quiet move (by color, start and end square) has caused a
+// cutoff, weighted towards the deeper searches, to order the quiet moves
+// which aren't killers.
+pub struct History {
+    table: Box<[[[i32; 64]; 64]; 2]>,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History {
+            table: Box::new([[[0; 64]; 64]; 2]),
+        }
+    }
+}
+
+impl History {
+    pub fn score(&self, color: Color, my_move: Move) -> i32 {
+        self.table[color.idx()][my_move.get_move_start() as usize][my_move.get_move_end() as usize]
+    }
+
+    // the move caused a cutoff, searched to depth
+    pub fn update(&mut self, color: Color, my_move: Move, depth: usize) {
+        let bonus = (depth * depth).min(HISTORY_MAX as usize) as i32;
+        let entry = &mut self.table[color.idx()][my_move.get_move_start() as usize][my_move.get_move_end() as usize];
+        *entry += bonus;
+
+        if *entry >= HISTORY_MAX {
+            self.age();
+        }
+    }
+
+    // Halves every score, between searches so that what the last one found
+    // counts for less than what this one finds, but the order is kept.
+    pub fn age(&mut self) {
+        for score in self.table.iter_mut().flatten().flatten() {
+            *score /= 2;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for score in self.table.iter_mut().flatten().flatten() {
+            *score = 0;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Stage {
+    TtMove,
+    Generate,
+    GoodCaptures,
+    Killers,
+    Quiets,
+    BadCaptures,
+    Done,
+}
+
+pub struct MovePicker {
+    stage: Stage,
+    tt_move: Option<Move>,
+    tt_move_picked: bool,
+    // the killers then the countermove, tried in that order
+    refutations: [Option<Move>; 3],
+    next_refutation: usize,
+    // the moves before this in the move list have been returned
+    picked: usize,
+    // whether captures losing material wait until after the quiet moves
+    split_captures: bool,
+    // from the move generator, once it has run
+    check_info: Option<CheckInfo>,
+}
+
+impl MovePicker {
+    pub fn new(tt_move: Option<Move>) -> MovePicker {
+        MovePicker {
+            stage: Stage::TtMove,
+            tt_move,
+            tt_move_picked: false,
+            refutations: [None; 3],
+            next_refutation: 0,
+            picked: 0,
+            split_captures: true,
+            check_info: None,
+        }
+    }
+
+    // quiet moves which caused cutoffs at this ply, and the move which
+    // caused one after the last move played, tried after the good captures
+    pub fn with_refutations(
+        mut self,
+        killers: [Option<Move>; 2],
+        countermove: Option<Move>,
+    ) -> MovePicker {
+        self.refutations = [killers[0], killers[1], countermove];
+        self
+    }
+
+    // If false, every capture is a good capture. A search which stops in the
+    // middle of exchanges doesn't see the recapture, so to it a capture
+    // which loses material is often the best move.
+    pub fn with_split_captures(mut self, split_captures: bool) -> MovePicker {
+        self.split_captures = split_captures;
+        self
+    }
+
+    // The next move to search, or None when every legal move has been
+    // returned. The move list holds the generated moves between calls, so
+    // must not be changed until the picker is finished with.
+    pub fn next(
+        &mut self,
+        board: &mut Board,
+        move_generator: &MoveGenerator,
+        move_list: &mut MoveList,
+        history: &History,
+    ) -> Option<Move> {
+        loop {
+            match self.stage {
+                Stage::TtMove => {
+                    self.stage = Stage::Generate;
+
+                    if let Some(tt_move) = self.tt_move {
+                        if move_generator.is_pseudo_legal(board, tt_move)
+                            && !board.leaves_king_in_check(tt_move, move_generator)
+                        {
+                            self.tt_move_picked = true;
+                            return Some(tt_move);
+                        }
+                    }
+                }
+                Stage::Generate => {
+                    self.stage = Stage::GoodCaptures;
+
+                    self.check_info = Some(move_generator.gen_moves(board, move_list));
+                    let attacks = move_generator.attacks();
+                    let split_captures = self.split_captures;
+                    move_list.score_with(|my_move| score(board, attacks, history, my_move, split_captures));
+
+                    // it has already been searched, so goes before the rest
+                    if self.tt_move_picked {
+                        let found = move_list.find_to(0, self.tt_move.unwrap());
+                        debug_assert!(found, "tt move not generated in {}", board.to_fen());
+                        self.picked = 1;
+                    }
+                }
+                Stage::GoodCaptures => match move_list.best_to(self.picked) {
+                    Some((my_move, score)) if score >= GOOD_CAPTURE - STAGE_MARGIN => {
+                        return Some(self.take(my_move))
+                    }
+                    _ => self.stage = Stage::Killers,
+                },
+                Stage::Killers => match self.refutations.get(self.next_refutation) {
+                    Some(&refutation) => {
+                        self.next_refutation += 1;
+
+                        // only if it is legal and hasn't been returned
+                        if let Some(my_move) = refutation {
+                            if move_list.find_to(self.picked, my_move) {
+                                return Some(self.take(my_move));
+                            }
+                        }
+                    }
+                    None => self.stage = Stage::Quiets,
+                },
+                Stage::Quiets => match move_list.best_to(self.picked) {
+                    Some((my_move, score)) if score > BAD_CAPTURE + STAGE_MARGIN => {
+                        return Some(self.take(my_move))
+                    }
+                    _ => self.stage = Stage::BadCaptures,
+                },
+                Stage::BadCaptures => match move_list.best_to(self.picked) {
+                    Some((my_move, _)) => return Some(self.take(my_move)),
+                    None => self.stage = Stage::Done,
+                },
+                Stage::Done => {
+                    // every move is returned once, as each stage only picks
+                    // from those not yet returned
+                    debug_assert_eq!(self.picked, move_list.len());
+                    return None;
+                }
+            }
+        }
+    }
+
+    // The pieces giving check, once the moves have been generated. They
+    // always have been when next has returned None, so a node with no moves
+    // can tell checkmate from stalemate without looking again.
+    pub fn checkers(&self) -> Option<u64> {
+        self.check_info.map(|check_info| check_info.checkers)
+    }
+
+    fn take(&mut self, my_move: Move) -> Move {
+        self.picked += 1;
+        my_move
+    }
+}
+
+// the value of a piece in an exchange, whatever its color
+fn value(piece: Pieces) -> i32 {
+    if piece.is_king() {
+        KING_VALUE
+    } else {
+        Evaluator::piece_value(piece).abs()
+    }
+}
+
+fn promotion_value(my_move: Move, color: Color) -> i32 {
+    value(match my_move.get_move_piece() {
+        MOVE_PROMOTION_PIECE_KNIGHT => Pieces::knight(color),
+        MOVE_PROMOTION_PIECE_BISHOP => Pieces::bishop(color),
+        MOVE_PROMOTION_PIECE_ROOK => Pieces::rook(color),
+        _ => Pieces::queen(color),
+    })
+}
+
+// orders captures of the same piece, the least valuable attacker first
+fn attacker_rank(piece: Pieces) -> i32 {
+    piece.idx() as i32 % 6
+}
+
+// the stage of a generated move, and its place in the stage
+fn score(board: &Board, attacks: &Attacks, history: &History, my_move: Move, split_captures: bool) -> i32 {
+    let start = my_move.get_move_start() as usize;
+    let end = my_move.get_move_end() as usize;
+    let attacker = board.pieces[start].unwrap();
+    let quiet = history.score(attacker.color(), my_move);
+
+    let mut gain = match my_move.get_move_type() {
+        MOVE_TYPE_CASTLE => return quiet,
+        MOVE_TYPE_EN_PASSANT => value(Pieces::pawn(attacker.color())),
+        _ => board.pieces[end].map_or(0, value),
+    };
+
+    if my_move.get_move_type() == MOVE_TYPE_PROMOTION {
+        if my_move.get_move_piece() != MOVE_PROMOTION_PIECE_QUEEN {
+            return BAD_CAPTURE + gain;
+        }
+        gain += promotion_value(my_move, attacker.color()) - value(attacker);
+    } else if gain == 0 {
+        return quiet;
+    }
+
+    // most valuable victim, then least valuable attacker
+    let mvv_lva = 8 * gain - attacker_rank(attacker);
+    if !split_captures || see(board, attacks, my_move) >= 0 {
+        GOOD_CAPTURE + mvv_lva
+    } else {
+        BAD_CAPTURE + mvv_lva
+    }
+}
+
+// the pieces of both colors attacking the square, of those in occupancy
+fn attackers_to(board: &Board, attacks: &Attacks, sq: usize, occupancy: u64) -> u64 {
+    let bb = |piece: fn(Color) -> Pieces| {
+        board.get_bb(piece(Color::White)) | board.get_bb(piece(Color::Black))
+    };
+    let diagonal = bb(Pieces::bishop) | bb(Pieces::queen);
+    let straight = bb(Pieces::rook) | bb(Pieces::queen);
+
+    let attackers = (attacks.pawn_attacks(Color::Black, sq) & board.get_bb(Pieces::WhitePawn))
+        | (attacks.pawn_attacks(Color::White, sq) & board.get_bb(Pieces::BlackPawn))
+        | (attacks.knight_attacks(sq) & bb(Pieces::knight))
+        | (attacks.king_attacks(sq) & bb(Pieces::king))
+        | (attacks.bishop_attacks(sq, occupancy) & diagonal)
+        | (attacks.rook_attacks(sq, occupancy) & straight);
+
+    attackers & occupancy
+}
+
+// Static exchange evaluation: the material the side making the move wins
+// (or loses, if negative) when both sides keep recapturing on the square
+// with their least valuable piece, each able to stop when going on would
+// lose more. Pieces behind the capturers join in as the line opens. Pins
+// and checks are ignored.
+pub fn see(board: &Board, attacks: &Attacks, my_move: Move) -> i32 {
+    let start = my_move.get_move_start() as usize;
+    let mut target = my_move.get_move_end() as usize;
+    let mover = match board.pieces[start] {
+        Some(piece) => piece,
+        None => return 0,
+    };
+
+    let mut occupancy = board.get_occupancy();
+    occupancy.clear_bit(start);
+
+    // gains[depth] is the material won by the side making the capture at
+    // that depth, if the other side stops there
+    let mut gains = [0; 32];
+    let mut on_target = value(mover);
+    match my_move.get_move_type() {
+        MOVE_TYPE_CASTLE => return 0,
+        MOVE_TYPE_EN_PASSANT => {
+            // the captured pawn is beside the square the capture lands on
+            gains[0] = value(Pieces::pawn(mover.color().enemy()));
+            occupancy.clear_bit(target);
+            target = board.en_passant.unwrap().sq();
+        }
+        MOVE_TYPE_PROMOTION => {
+            gains[0] = board.pieces[target].map_or(0, value)
+                + promotion_value(my_move, mover.color())
+                - value(mover);
+            on_target = promotion_value(my_move, mover.color());
+        }
+        _ => gains[0] = board.pieces[target].map_or(0, value),
+    }
+
+    let mut color = mover.color().enemy();
+    let mut depth = 0;
+    while depth + 1 < gains.len() {
+        let attackers = attackers_to(board, attacks, target, occupancy);
+        let own = attackers & board.get_combined_bb(color);
+
+        // the least valuable attacker recaptures
+        let least_valuable = [
+            Pieces::pawn,
+            Pieces::knight,
+            Pieces::bishop,
+            Pieces::rook,
+            Pieces::queen,
+            Pieces::king,
+        ]
+        .iter()
+        .map(|piece| piece(color))
+        .find(|&piece| own & board.get_bb(piece) != 0);
+        let piece = match least_valuable {
+            Some(piece) => piece,
+            None => break,
+        };
+        let sq = (own & board.get_bb(piece)).lsb_idx();
+
+        // a king can't capture onto a defended square
+        if piece.is_king() && attackers & board.get_combined_bb(color.enemy()) != 0 {
+            break;
+        }
+
+        depth += 1;
+        gains[depth] = on_target - gains[depth - 1];
+        on_target = value(piece);
+        occupancy.clear_bit(sq);
+        color = color.enemy();
+    }
+
+    // each side only carries on capturing if it gains from it
+    while depth > 0 {
+        gains[depth - 1] = -max(-gains[depth - 1], gains[depth]);
+        depth -= 1;
+    }
+
+    gains[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::square::Square;
+
+    // a move between two squares, whether or not it is legal
+    fn raw_move(uci_move: &str) -> Move {
+        let sq = |notation: &str| Square::from_notation(notation).unwrap().sq() as u16;
+        Move::new_move(sq(&uci_move[..2]), sq(&uci_move[2..4]), 0)
+    }
+
+    fn see_of(fen: &str, uci_move: &str) -> i32 {
+        let mut board = Board::new(fen).unwrap();
+        let move_generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        move_generator.gen_moves(&mut board, &mut move_list);
+
+        let my_move = (0..move_list.len())
+            .map(|i| move_list.at(i))
+            .find(|my_move| my_move.move_to_string() == uci_move)
+            .unwrap();
+        see(&board, move_generator.attacks(), my_move)
+    }
+
+    #[test]
+    fn static_exchange() {
+        // an undefended pawn
+        assert_eq!(
+            see_of("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5"),
+            100
+        );
+        // a pawn defended by a pawn, so the rook is lost
+        assert_eq!(see_of("4k3/8/3p4/4p3/8/8/8/4RK2 w - - 0 1", "e1e5"), -400);
+        // the pieces lined up behind the rook and bishop join in, and the
+        // knight is lost for the pawn
+        assert_eq!(
+            see_of(
+                "1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1",
+                "d3e5"
+            ),
+            -215
+        );
+        assert_eq!(see_of("3r2k1/8/8/3p4/8/8/3R4/3Q2K1 w - - 0 1", "d2d5"), 100);
+        // the king can take back the rook, unless the other rook defends it
+        assert_eq!(see_of("4k3/8/8/3r4/8/8/3P4/4K3 b - - 0 1", "d5d2"), -400);
+        assert_eq!(see_of("3rk3/8/8/3r4/8/8/3P4/4K3 b - - 0 1", "d5d2"), 100);
+        assert_eq!(see_of("4k3/8/8/8/8/8/3q4/4K3 w - - 0 1", "e1d2"), 900);
+        // a quiet move to an attacked square loses the piece
+        assert_eq!(see_of("4k3/8/8/3p4/8/4N3/8/4K3 w - - 0 1", "e3c4"), -315);
+        // en passant, and promotions with and without being taken back
+        assert_eq!(see_of("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6"), 100);
+        assert_eq!(see_of("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7c8q"), -100);
+        assert_eq!(see_of("3rk3/2P5/8/8/8/8/8/4K3 w - - 0 1", "c7d8q"), 400);
+    }
+
+    fn picked(board: &mut Board, picker: &mut MovePicker) -> Vec<String> {
+        let move_generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+
+        let mut moves = Vec::new();
+        while let Some(my_move) = picker.next(board, &move_generator, &mut move_list, &History::default()) {
+            moves.push(my_move.move_to_string());
+        }
+        moves
+    }
+
+    #[test]
+    fn stages() {
+        // Bxf4 wins a knight and bxc4 trades pawns, Qxd5 loses the queen
+        let mut board = Board::new("3rk3/8/8/3p4/2p2n2/1P6/7B/3QK1N1 w - - 0 1").unwrap();
+        let mut move_list = MoveList::new();
+        MoveGenerator::new().gen_moves(&mut board, &mut move_list);
+        let mut generated: Vec<String> = (0..move_list.len())
+            .map(|i| move_list.at(i).move_to_string())
+            .collect();
+
+        // the countermove was the tt move and the second killer is illegal,
+        // as the knight covers e2, so neither is returned
+        let mut picker = MovePicker::new(Some(raw_move("e1f2"))).with_refutations(
+            [Some(raw_move("g1f3")), Some(raw_move("e1e2"))],
+            Some(raw_move("e1f2")),
+        );
+        let mut moves = picked(&mut board, &mut picker);
+        assert_eq!(moves[..4], ["e1f2", "h2f4", "b3c4", "g1f3"]);
+        assert_eq!(moves.last().unwrap(), "d1d5");
+
+        // and every legal move is returned once
+        moves.sort();
+        generated.sort();
+        assert_eq!(moves, generated);
+
+        // or the losing capture goes with the others
+        let moves = picked(
+            &mut board,
+            &mut MovePicker::new(None).with_split_captures(false),
+        );
+        assert_eq!(moves[..3], ["h2f4", "b3c4", "d1d5"]);
+
+        // an impossible tt move is ignored
+        let moves = picked(&mut board, &mut MovePicker::new(Some(raw_move("g1g3"))));
+        assert_eq!(moves[..2], ["h2f4", "b3c4"]);
+        assert_eq!(moves.len(), generated.len());
+
+        // with no moves there is nothing to pick, whatever the tt move
+        let mut mated = Board::new("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+        assert!(picked(&mut mated, &mut MovePicker::new(Some(raw_move("g8h8")))).is_empty());
+    }
+
+    #[test]
+    fn history() {
+        let mut history = History::default();
+        history.update(Color::White, raw_move("g1f3"), 2);
+        history.update(Color::White, raw_move("e1f2"), 3);
+        history.update(Color::Black, raw_move("d8d7"), 5);
+        assert_eq!(history.score(Color::White, raw_move("e1f2")), 9);
+        assert_eq!(history.score(Color::Black, raw_move("e1f2")), 0);
+
+        // quiet moves by history, after the captures
+        let mut board = Board::new("3rk3/8/8/3p4/2p2n2/1P6/7B/3QK1N1 w - - 0 1").unwrap();
+        let move_generator = MoveGenerator::new();
+        let mut move_list = MoveList::new();
+        let mut picker = MovePicker::new(None);
+        let moves: Vec<String> = std::iter::from_fn(|| picker.next(&mut board, &move_generator, &mut move_list, &history))
+            .map(|my_move| my_move.move_to_string())
+            .collect();
+        assert_eq!(moves[..4], ["h2f4", "b3c4", "e1f2", "g1f3"]);
+
+        // aging keeps the order, and a score reaching the limit ages them all
+        history.age();
+        assert_eq!(history.score(Color::White, raw_move("e1f2")), 4);
+        history.update(Color::Black, raw_move("a7a5"), 1 << 10);
+        assert_eq!(history.score(Color::Black, raw_move("a7a5")), HISTORY_MAX / 2);
+        assert_eq!(history.score(Color::White, raw_move("e1f2")), 2);
+
+        history.clear();
+        assert_eq!(history.score(Color::Black, raw_move("a7a5")), 0);
+    }
+}
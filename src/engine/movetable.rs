@@ -0,0 +1,137 @@
+// The `moves` command: every legal move in the position with its SAN, what
+// kind of move it is, and how the static evaluation changes if it is made,
+// best first. For seeing why the engine likes or dislikes a move, and for
+// checking the move generator, SAN and the evaluation against each other.
+
+use crate::engine::board::Board;
+use crate::engine::eval::Evaluate;
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::r#move::{
+    Move, MoveUtils, UndoInfo, MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT, MOVE_TYPE_PROMOTION,
+};
+use crate::engine::san;
+
+pub struct MoveRow {
+    pub my_move: Move,
+    pub san: String,
+    pub capture: bool,
+    pub check: bool,
+    // the evaluation after the move less the evaluation before, both from
+    // the point of view of the side making it
+    pub eval_delta: i32,
+}
+
+impl MoveRow {
+    // castle, e.p. or promotion, from the move's flags
+    pub fn kind(&self) -> &'static str {
+        match self.my_move.get_move_type() {
+            MOVE_TYPE_CASTLE => "castle",
+            MOVE_TYPE_EN_PASSANT => "e.p.",
+            MOVE_TYPE_PROMOTION => "promotion",
+            _ => "-",
+        }
+    }
+}
+
+// The legal moves, the best for the side to move first, then in coordinate
+// order. The board is left as it was, and the evaluator set up for it.
+pub fn move_rows<E: Evaluate>(board: &mut Board, move_generator: &MoveGenerator, evaluator: &mut E) -> Vec<MoveRow> {
+    let mut moves = MoveList::new();
+    let mut replies = MoveList::new();
+    let mut info = UndoInfo::default();
+    move_generator.gen_moves(board, &mut moves);
+    let before = evaluator.full(board);
+
+    let mut rows = Vec::with_capacity(moves.len());
+    for i in 0..moves.len() {
+        let my_move = moves.at(i);
+        let san = san::move_to_san(board, move_generator, my_move);
+        let capture = my_move.get_move_type() == MOVE_TYPE_EN_PASSANT
+            || (my_move.get_move_type() != MOVE_TYPE_CASTLE && board.pieces[my_move.get_move_end() as usize].is_some());
+
+        board.make_move(my_move, &mut info);
+        evaluator.on_make(board, my_move, &info);
+        let after = -evaluator.evaluate(board);
+        let check = move_generator.gen_moves(board, &mut replies).checkers != 0;
+        board.undo_move(my_move, &info);
+        evaluator.on_undo(board, my_move, &info);
+
+        rows.push(MoveRow {
+            my_move,
+            san,
+            capture,
+            check,
+            eval_delta: after - before,
+        });
+    }
+
+    rows.sort_by_key(|row| (-row.eval_delta, row.my_move.move_to_uci(false)));
+    rows
+}
+
+// a fixed width table of the rows, with a header
+pub fn move_table(rows: &[MoveRow], chess960: bool) -> String {
+    let yes_no = |yes| if yes { "yes" } else { "-" };
+    let mut table = format!(
+        "{:<6} {:<8} {:<9} {:<7} {:<5} {:>6}\n",
+        "move", "san", "type", "capture", "check", "delta"
+    );
+    for row in rows {
+        table += &format!(
+            "{:<6} {:<8} {:<9} {:<7} {:<5} {:>+6}\n",
+            row.my_move.move_to_uci(chess960),
+            row.san,
+            row.kind(),
+            yes_no(row.capture),
+            yes_no(row.check),
+            row.eval_delta
+        );
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::eval::Evaluator;
+
+    fn table(fen: &str) -> String {
+        let mut board = Board::new(fen).unwrap();
+        let rows = move_rows(&mut board, &MoveGenerator::new(), &mut Evaluator::default());
+        assert_eq!(board.to_fen(), fen);
+        move_table(&rows, false)
+    }
+
+    #[test]
+    fn forced_capture() {
+        // the king has to take the queen
+        assert_eq!(
+            table("7k/8/8/8/8/8/6q1/7K w - - 0 1"),
+            "\
+move   san      type      capture check  delta
+h1g2   Kxg2     -         yes     -      +1084
+"
+        );
+    }
+
+    #[test]
+    fn promotions() {
+        // a bishop or knight alone can't win, so promoting to one throws
+        // away the pawn
+        assert_eq!(
+            table("4k3/P7/8/8/8/8/8/4K3 w - - 0 1"),
+            "\
+move   san      type      capture check  delta
+a7a8q  a8=Q+    promotion -       yes     +872
+a7a8r  a8=R+    promotion -       yes     +453
+e1f1   Kf1      -         -       -        +10
+e1d1   Kd1      -         -       -         +0
+e1d2   Kd2      -         -       -         +0
+e1e2   Ke2      -         -       -         +0
+e1f2   Kf2      -         -       -         +0
+a7a8b  a8=B     promotion -       -       -162
+a7a8n  a8=N     promotion -       -       -162
+"
+        );
+    }
+}
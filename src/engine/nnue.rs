@@ -0,0 +1,417 @@
+// An experimental neural network evaluation.
+//
+// The network has one input per (piece, square) pair, where the piece
+// includes its color, so 768 inputs. These feed a single hidden layer with a
+// clipped relu activation, followed by a scalar output. The hidden layer
+// values before activation (the accumulator) are updated incrementally as
+// pieces are added to and removed from the board.
+//
+// File format, all values little endian:
+//
+//   magic           4 bytes, "CRNN"
+//   version         u32, currently 1
+//   inputs          u32, must be 768
+//   hidden          u32, 1 to MAX_HIDDEN
+//   feature weights i16 * inputs * hidden, the column for each input in turn
+//   feature biases  i16 * hidden
+//   output weights  i16 * hidden
+//   output bias     i32
+//
+// The output is white relative, with OUTPUT_SCALE centipawns for a fully
+// active neuron whose output weight is WEIGHT_SCALE.
+
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+use crate::engine::board::Board;
+use crate::engine::eval::{Evaluate, Evaluator};
+use crate::engine::piece::{Color, Pieces};
+use crate::engine::r#move::{Move, UndoInfo};
+
+const MAGIC: &[u8; 4] = b"CRNN";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 16;
+
+pub const INPUTS: usize = 768;
+pub const MAX_HIDDEN: usize = 1024;
+
+// clipped relu range for the accumulator
+const ACTIVATION_MAX: i32 = 255;
+// output weights are fixed point with this many units per 1.0
+const WEIGHT_SCALE: i32 = 64;
+// centipawns for an output of 1.0
+const OUTPUT_SCALE: i32 = 400;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetworkError {
+    Io(String),
+    BadMagic,
+    UnsupportedVersion(u32),
+    WrongInputCount(u32),
+    BadHiddenSize(u32),
+    WrongLength { expected: usize, found: usize },
+}
+
+impl Display for NetworkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NetworkError::Io(e) => write!(f, "failed to read network: {}", e),
+            NetworkError::BadMagic => write!(f, "not a network file"),
+            NetworkError::UnsupportedVersion(v) => write!(f, "unsupported network version {}", v),
+            NetworkError::WrongInputCount(n) => {
+                write!(f, "expected {} network inputs, found {}", INPUTS, n)
+            }
+            NetworkError::BadHiddenSize(n) => write!(f, "invalid hidden layer size {}", n),
+            NetworkError::WrongLength { expected, found } => {
+                write!(f, "expected {} bytes of network, found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+#[derive(Debug, PartialEq)]
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_biases: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i32,
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]])
+}
+
+fn read_i16s(bytes: &[u8], offset: usize, count: usize) -> Vec<i16> {
+    bytes[offset..offset + 2 * count]
+        .chunks_exact(2)
+        .map(|c| i16::from_le_bytes([c[0], c[1]]))
+        .collect()
+}
+
+impl Network {
+    pub fn load(path: &str) -> Result<Network, NetworkError> {
+        let bytes = std::fs::read(path).map_err(|e| NetworkError::Io(e.to_string()))?;
+        Network::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Network, NetworkError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(NetworkError::WrongLength {
+                expected: HEADER_SIZE,
+                found: bytes.len(),
+            });
+        }
+        if &bytes[0..4] != MAGIC {
+            return Err(NetworkError::BadMagic);
+        }
+
+        let version = read_u32(bytes, 4);
+        if version != VERSION {
+            return Err(NetworkError::UnsupportedVersion(version));
+        }
+        let inputs = read_u32(bytes, 8);
+        if inputs as usize != INPUTS {
+            return Err(NetworkError::WrongInputCount(inputs));
+        }
+        let hidden = read_u32(bytes, 12);
+        if hidden == 0 || hidden as usize > MAX_HIDDEN {
+            return Err(NetworkError::BadHiddenSize(hidden));
+        }
+        let hidden = hidden as usize;
+
+        let expected = HEADER_SIZE + 2 * (INPUTS * hidden + 2 * hidden) + 4;
+        if bytes.len() != expected {
+            return Err(NetworkError::WrongLength {
+                expected,
+                found: bytes.len(),
+            });
+        }
+
+        let mut offset = HEADER_SIZE;
+        let feature_weights = read_i16s(bytes, offset, INPUTS * hidden);
+        offset += 2 * INPUTS * hidden;
+        let feature_biases = read_i16s(bytes, offset, hidden);
+        offset += 2 * hidden;
+        let output_weights = read_i16s(bytes, offset, hidden);
+        offset += 2 * hidden;
+        let output_bias = read_u32(bytes, offset) as i32;
+
+        Ok(Network {
+            hidden,
+            feature_weights,
+            feature_biases,
+            output_weights,
+            output_bias,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + 2 * (INPUTS + 2) * self.hidden + 4);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(INPUTS as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        for value in self
+            .feature_weights
+            .iter()
+            .chain(&self.feature_biases)
+            .chain(&self.output_weights)
+        {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+
+        bytes
+    }
+
+    #[inline(always)]
+    fn feature(piece: Pieces, sq: usize) -> usize {
+        piece.idx() * 64 + sq
+    }
+
+    #[inline(always)]
+    fn column(&self, piece: Pieces, sq: usize) -> &[i16] {
+        let start = Network::feature(piece, sq) * self.hidden;
+        &self.feature_weights[start..start + self.hidden]
+    }
+
+    fn output(&self, accumulator: &[i32]) -> i32 {
+        let sum = accumulator
+            .iter()
+            .zip(&self.output_weights)
+            .map(|(&a, &w)| a.clamp(0, ACTIVATION_MAX) * w as i32)
+            .sum::<i32>()
+            + self.output_bias;
+
+        sum * OUTPUT_SCALE / (ACTIVATION_MAX * WEIGHT_SCALE)
+    }
+}
+
+#[derive(Clone)]
+pub struct NnueEvaluator {
+    network: Arc<Network>,
+    accumulator: Vec<i32>,
+}
+
+impl NnueEvaluator {
+    pub fn new(network: Arc<Network>) -> NnueEvaluator {
+        let accumulator = vec![0; network.hidden];
+        NnueEvaluator { network, accumulator }
+    }
+
+    #[inline(always)]
+    fn update(&mut self, piece: Pieces, sq: usize, added: bool) {
+        let sign = if added { 1 } else { -1 };
+        for (a, &w) in self.accumulator.iter_mut().zip(self.network.column(piece, sq)) {
+            *a += sign * w as i32;
+        }
+    }
+
+    fn refresh(&mut self, board: &Board) {
+        for (a, &b) in self.accumulator.iter_mut().zip(&self.network.feature_biases) {
+            *a = b as i32;
+        }
+        for sq in 0..64 {
+            if let Some(piece) = board.pieces[sq] {
+                self.update(piece, sq, true);
+            }
+        }
+    }
+
+    fn relative(score: i32, color: Color) -> i32 {
+        if color.is_white() {
+            score
+        } else {
+            -score
+        }
+    }
+}
+
+impl Evaluate for NnueEvaluator {
+    fn full(&mut self, board: &Board) -> i32 {
+        self.refresh(board);
+        self.evaluate(board)
+    }
+
+    fn on_make(&mut self, _board: &Board, _my_move: Move, info: &UndoInfo) {
+        for &(piece, sq) in info.delta.removed() {
+            self.update(piece, sq, false);
+        }
+        for &(piece, sq) in info.delta.added() {
+            self.update(piece, sq, true);
+        }
+    }
+    fn on_undo(&mut self, _board: &Board, _my_move: Move, info: &UndoInfo) {
+        for &(piece, sq) in info.delta.added() {
+            self.update(piece, sq, false);
+        }
+        for &(piece, sq) in info.delta.removed() {
+            self.update(piece, sq, true);
+        }
+    }
+
+    fn evaluate(&self, board: &Board) -> i32 {
+        NnueEvaluator::relative(self.network.output(&self.accumulator), board.friendly_color())
+    }
+}
+
+// The network when one has been loaded, otherwise the classical evaluation
+#[derive(Clone)]
+pub enum Backend {
+    Classical(Evaluator),
+    Network(NnueEvaluator),
+}
+
+impl Default for Backend {
+    fn default() -> Backend {
+        Backend::Classical(Evaluator::default())
+    }
+}
+
+impl Backend {
+    // an empty path switches back to the classical evaluation
+    pub fn load(path: &str) -> Result<Backend, NetworkError> {
+        if path.is_empty() {
+            return Ok(Backend::default());
+        }
+
+        let network = Network::load(path)?;
+        Ok(Backend::Network(NnueEvaluator::new(Arc::new(network))))
+    }
+}
+
+impl Evaluate for Backend {
+    fn full(&mut self, board: &Board) -> i32 {
+        match self {
+            Backend::Classical(e) => e.full(board),
+            Backend::Network(e) => e.full(board),
+        }
+    }
+
+    #[inline(always)]
+    fn on_make(&mut self, board: &Board, my_move: Move, info: &UndoInfo) {
+        match self {
+            Backend::Classical(e) => e.on_make(board, my_move, info),
+            Backend::Network(e) => e.on_make(board, my_move, info),
+        }
+    }
+    #[inline(always)]
+    fn on_undo(&mut self, board: &Board, my_move: Move, info: &UndoInfo) {
+        match self {
+            Backend::Classical(e) => e.on_undo(board, my_move, info),
+            Backend::Network(e) => e.on_undo(board, my_move, info),
+        }
+    }
+
+    #[inline(always)]
+    fn evaluate(&self, board: &Board) -> i32 {
+        match self {
+            Backend::Classical(e) => e.evaluate(board),
+            Backend::Network(e) => e.evaluate(board),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::movegen::{MoveGenerator, MoveList};
+    use rand::{Rng, SeedableRng};
+
+    // 8 hidden neurons with weights drawn uniformly from -64..64
+    const TINY_NET: &[u8] = include_bytes!("../../nets/tiny.nnue");
+
+    fn tiny() -> Arc<Network> {
+        Arc::new(Network::from_bytes(TINY_NET).unwrap())
+    }
+
+    #[test]
+    fn load() {
+        let network = tiny();
+        assert_eq!(network.hidden, 8);
+        assert_eq!(network.to_bytes(), TINY_NET);
+
+        let mut bytes = TINY_NET.to_vec();
+        bytes[0] = b'X';
+        assert_eq!(Network::from_bytes(&bytes), Err(NetworkError::BadMagic));
+
+        let mut bytes = TINY_NET.to_vec();
+        bytes[4] = 2;
+        assert_eq!(Network::from_bytes(&bytes), Err(NetworkError::UnsupportedVersion(2)));
+
+        let mut bytes = TINY_NET.to_vec();
+        bytes[8..12].copy_from_slice(&767u32.to_le_bytes());
+        assert_eq!(Network::from_bytes(&bytes), Err(NetworkError::WrongInputCount(767)));
+
+        let mut bytes = TINY_NET.to_vec();
+        bytes[12..16].copy_from_slice(&0u32.to_le_bytes());
+        assert_eq!(Network::from_bytes(&bytes), Err(NetworkError::BadHiddenSize(0)));
+
+        let bytes = &TINY_NET[..TINY_NET.len() - 1];
+        assert!(matches!(Network::from_bytes(bytes), Err(NetworkError::WrongLength { .. })));
+
+        assert!(matches!(Network::load("does/not/exist.nnue"), Err(NetworkError::Io(_))));
+    }
+
+    #[test]
+    fn incremental_matches_full() {
+        let network = tiny();
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0x1417);
+        let generator = MoveGenerator::new();
+        let mut moves = MoveList::new();
+
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r2q1rk1/pP1p2pp/Q4n2/bbp1p3/Np6/1B3NBn/pPPP1PPP/R3K2R b KQ - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut evaluator = NnueEvaluator::new(Arc::clone(&network));
+            let mut fresh = NnueEvaluator::new(Arc::clone(&network));
+            let mut history = Vec::new();
+
+            evaluator.full(&board);
+            for _ in 0..60 {
+                generator.gen_moves(&mut board, &mut moves);
+                if moves.is_empty() {
+                    break;
+                }
+
+                let my_move = moves.at(rng.gen_range(0..moves.len()));
+                let mut info = UndoInfo::default();
+                board.make_move(my_move, &mut info);
+                evaluator.on_make(&board, my_move, &info);
+
+                assert_eq!(evaluator.evaluate(&board), fresh.full(&board), "{}", board.to_fen());
+                assert_eq!(evaluator.accumulator, fresh.accumulator);
+
+                history.push((my_move, info));
+            }
+
+            // unwind back to the start
+            while let Some((my_move, info)) = history.pop() {
+                board.undo_move(my_move, &info);
+                evaluator.on_undo(&board, my_move, &info);
+
+                assert_eq!(evaluator.evaluate(&board), fresh.full(&board), "{}", board.to_fen());
+            }
+        }
+    }
+
+    #[test]
+    fn backend() {
+        let board = Board::default();
+
+        let mut classical = Backend::load("").unwrap();
+        assert!(matches!(classical, Backend::Classical(_)));
+        assert_eq!(classical.full(&board), Evaluator::default().full(&board));
+
+        let mut network = Backend::Network(NnueEvaluator::new(tiny()));
+        assert_eq!(network.full(&board), NnueEvaluator::new(tiny()).full(&board));
+    }
+}
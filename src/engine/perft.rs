@@ -1,70 +1,488 @@
+use std::io::Write;
+
 use crate::engine::board::Board;
-use crate::engine::r#move::{UndoInfo,MoveUtils};
+use crate::engine::control::{ControlHandle, ProgressEvent};
+use crate::engine::error::{EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use crate::engine::r#move::{Move,UndoInfo,MoveUtils};
 use crate::engine::movegen::{MoveList,MoveGenerator};
 
-pub fn perft(depth: usize, board: &mut Board, move_generator: &MoveGenerator, move_lists: &mut Vec<MoveList>) -> u64 {
-    move_generator.gen_moves(board, &mut move_lists[depth - 1]);
+// One move list for each ply of a perft, indexed by the depth remaining,
+// so the recursion never allocates. Grows if a deeper perft needs it.
+pub struct PerftStack {
+    move_lists: Vec<MoveList>,
+}
+
+impl PerftStack {
+    pub fn new(depth: usize) -> PerftStack {
+        PerftStack {
+            move_lists: (0..depth).map(|_| MoveList::new()).collect(),
+        }
+    }
+
+    #[inline(always)]
+    fn at(&mut self, depth: usize) -> &mut MoveList {
+        while self.move_lists.len() < depth {
+            self.move_lists.push(MoveList::new());
+        }
+        &mut self.move_lists[depth - 1]
+    }
+}
+
+pub fn perft(depth: usize, board: &mut Board, move_generator: &MoveGenerator) -> u64 {
+    perft_with_stack(depth, board, move_generator, &mut PerftStack::new(depth))
+}
+
+pub fn perft_with_stack(depth: usize, board: &mut Board, move_generator: &MoveGenerator, stack: &mut PerftStack) -> u64 {
+    // the position itself is the only leaf of a zero depth tree
+    if depth == 0 {
+        return 1;
+    }
+
+    move_generator.gen_moves(board, stack.at(depth));
 
     if depth <= 1 {
-        return move_lists[depth - 1].len() as u64;
+        return stack.at(depth).len() as u64;
     }
 
     let mut nodes = 0;
     let mut info = UndoInfo::default();
 
-    for i in 0..move_lists[depth - 1].len() {
-        let current_move = move_lists[depth - 1].at(i);
+    for i in 0..stack.at(depth).len() {
+        let current_move = stack.at(depth).at(i);
 
         board.make_move(current_move, &mut info);
-        nodes += perft(depth - 1, board, move_generator, move_lists);
+        nodes += perft_with_stack(depth - 1, board, move_generator, stack);
         board.undo_move(current_move, &info);
     }
 
     nodes
 }
 
-pub fn perft_divide(depth: usize, board: &mut Board) -> u64 {
-    let move_generator = MoveGenerator::new();
-    let mut move_lists = Vec::new();
+// The same count as perft, from the pseudo-legal generator with each move
+// checked by making it. Only for comparing the speed of the two.
+pub fn perft_pseudo_legal(depth: usize, board: &mut Board, move_generator: &MoveGenerator) -> u64 {
+    perft_pseudo_legal_with_stack(depth, board, move_generator, &mut PerftStack::new(depth))
+}
 
-    for _ in 0..depth {
-        move_lists.push(MoveList::new());
+fn perft_pseudo_legal_with_stack(depth: usize, board: &mut Board, move_generator: &MoveGenerator, stack: &mut PerftStack) -> u64 {
+    if depth == 0 {
+        return 1;
+    }
+
+    move_generator.gen_pseudo_legal(board, stack.at(depth));
+
+    let mut nodes = 0;
+    let mut info = UndoInfo::default();
+
+    for i in 0..stack.at(depth).len() {
+        let current_move = stack.at(depth).at(i);
+
+        if board.leaves_king_in_check(current_move, move_generator) {
+            continue;
+        }
+
+        if depth <= 1 {
+            nodes += 1;
+        } else {
+            board.make_move(current_move, &mut info);
+            nodes += perft_pseudo_legal_with_stack(depth - 1, board, move_generator, stack);
+            board.undo_move(current_move, &info);
+        }
+    }
+
+    nodes
+}
+
+pub(crate) fn perft_stoppable(depth: usize, board: &mut Board, move_generator: &MoveGenerator, stack: &mut PerftStack, control: &ControlHandle) -> Option<u64> {
+    if control.is_stopped() {
+        return None;
+    }
+    if depth == 0 {
+        return Some(1);
     }
 
-    move_generator.gen_moves(board, &mut move_lists[depth - 1]);
+    move_generator.gen_moves(board, stack.at(depth));
+
+    if depth <= 1 {
+        return Some(stack.at(depth).len() as u64);
+    }
 
     let mut nodes = 0;
     let mut info = UndoInfo::default();
 
-    for i in 0..move_lists[depth - 1].len() {
-        let current_move = move_lists[depth - 1].at(i);
+    for i in 0..stack.at(depth).len() {
+        let current_move = stack.at(depth).at(i);
 
         board.make_move(current_move, &mut info);
-        let inner_nodes = if depth <= 1 { 1 } else { perft(depth - 1, board, &move_generator, &mut move_lists) };
-        println!("{}: {}", current_move.move_to_string(), inner_nodes);
+        let inner_nodes = perft_stoppable(depth - 1, board, move_generator, stack, control);
         board.undo_move(current_move, &info);
 
-        nodes += inner_nodes;
+        nodes += inner_nodes?;
     }
 
-    println!("\nNodes searched: {}", nodes);
+    Some(nodes)
+}
 
-    nodes
+pub struct PerftResult {
+    pub nodes: u64,
+    pub divide: Vec<(Move, u64)>,
+    // the number of legal moves in the position
+    pub root_moves: usize,
+    // false if the perft was stopped before every root move was counted
+    pub complete: bool,
+}
+
+// how the counts for each root move are written out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DivideFormat {
+    // "<move>: <count>" in the order the moves were generated, then a blank
+    // line and "Nodes searched: <total>", as go perft prints
+    Default,
+    // "<move> <count>" sorted by the move, then a blank line and the bare
+    // total, which perftree and other divide-diff scripts expect
+    Perftree,
+}
+
+impl DivideFormat {
+    pub fn from_name(name: &str) -> Option<DivideFormat> {
+        match name {
+            "default" => Some(DivideFormat::Default),
+            "perftree" => Some(DivideFormat::Perftree),
+            _ => None,
+        }
+    }
+}
+
+impl PerftResult {
+    // every line of the divide, each ending in a newline
+    pub fn to_text(&self, format: DivideFormat) -> String {
+        let mut divide: Vec<(String, u64)> = self
+            .divide
+            .iter()
+            .map(|&(root_move, nodes)| (root_move.move_to_string(), nodes))
+            .collect();
+
+        let (separator, total) = match format {
+            DivideFormat::Default => (": ", format!("Nodes searched: {}", self.nodes)),
+            DivideFormat::Perftree => {
+                divide.sort();
+                (" ", self.nodes.to_string())
+            }
+        };
+
+        let mut text = String::new();
+        for (root_move, nodes) in divide {
+            text += &format!("{}{}{}\n", root_move, separator, nodes);
+        }
+        text + &format!("\n{}\n", total)
+    }
+}
+
+pub fn perft_divide_with_control(depth: usize, board: &mut Board, move_generator: &MoveGenerator, control: &mut ControlHandle) -> PerftResult {
+    let mut stack = PerftStack::new(depth);
+    move_generator.gen_moves(board, stack.at(depth));
+
+    let mut result = PerftResult {
+        nodes: 0,
+        divide: Vec::new(),
+        root_moves: stack.at(depth).len(),
+        complete: true,
+    };
+    let mut info = UndoInfo::default();
+
+    for i in 0..stack.at(depth).len() {
+        let current_move = stack.at(depth).at(i);
+
+        board.make_move(current_move, &mut info);
+        let inner_nodes = if depth <= 1 { Some(1) } else { perft_stoppable(depth - 1, board, move_generator, &mut stack, control) };
+        board.undo_move(current_move, &info);
+
+        // a partially counted root move is discarded
+        match inner_nodes {
+            Some(inner_nodes) if !control.is_stopped() => {
+                result.nodes += inner_nodes;
+                result.divide.push((current_move, inner_nodes));
+                control.report(ProgressEvent::PerftRootMove { root_move: current_move, nodes: inner_nodes });
+            }
+            _ => {
+                result.complete = false;
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+pub fn perft_divide(depth: usize, board: &mut Board) -> u64 {
+    let move_generator = MoveGenerator::new();
+    let mut control = ControlHandle::new()
+        .with_progress(|event| {
+            if let ProgressEvent::PerftRootMove { root_move, nodes } = event {
+                println!("{}: {}", root_move.move_to_string(), nodes);
+            }
+        });
+
+    let result = perft_divide_with_control(depth, board, &move_generator, &mut control);
+
+    println!("\nNodes searched: {}", result.nodes);
+
+    result.nodes
+}
+
+const USAGE: &str = "usage: chess_rs perft [--format default|perftree] <depth> [fen] [moves]";
+
+// `chess_rs perft`: a divide of the position after the moves (given in one
+// argument separated by spaces, or as several), which is how perftree
+// drives an engine. args are those after `perft`, the return value is the
+// exit code.
+pub fn perft_command(args: &[String], output: &mut impl Write) -> i32 {
+    let mut format = DivideFormat::Default;
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => match args.next().and_then(|name| DivideFormat::from_name(name)) {
+                Some(name) => format = name,
+                None => {
+                    let _ = writeln!(output, "{}", USAGE);
+                    return EXIT_USAGE;
+                }
+            },
+            "-h" | "--help" => {
+                let _ = writeln!(output, "{}", USAGE);
+                return EXIT_OK;
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    let depth = match positional.first().map(|depth| depth.parse::<usize>()) {
+        Some(Ok(depth)) if depth >= 1 => depth,
+        _ => {
+            let _ = writeln!(output, "{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let fen = positional.get(1).copied().unwrap_or(crate::engine::board::STARTING_FEN);
+    let mut board = match Board::new(fen) {
+        Ok(board) => board,
+        Err(e) => {
+            let _ = writeln!(output, "error: {}", crate::Error::from(e));
+            return EXIT_INVALID;
+        }
+    };
+
+    let move_generator = MoveGenerator::new();
+    let mut info = UndoInfo::default();
+    let moves = positional.iter().skip(2).flat_map(|moves| moves.split_whitespace());
+    for uci_move in moves {
+        if let Err(e) = board.make_uci_move(uci_move, &move_generator, &mut info) {
+            let _ = writeln!(output, "error: {}", crate::Error::from(e));
+            return EXIT_INVALID;
+        }
+    }
+
+    let result = perft_divide_with_control(depth, &mut board, &move_generator, &mut ControlHandle::new());
+    let _ = write!(output, "{}", result.to_text(format));
+
+    EXIT_OK
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::engine::r#move::{MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT, MOVE_TYPE_PROMOTION};
 
     fn perft_test(fen: &str, depth: usize, move_generator: &MoveGenerator) -> u64 {
-        let mut move_lists = Vec::new();
         let mut board = Board::new(fen).unwrap();
-    
-        for _ in 0..depth {
-            move_lists.push(MoveList::new());
+        perft(depth, &mut board, move_generator)
+    }
+
+    #[test]
+    fn perft_stack_grows() {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::default();
+
+        // a stack made for a shallower perft is extended rather than indexed out of bounds
+        let mut stack = PerftStack::new(1);
+        assert_eq!(perft_with_stack(3, &mut board, &move_generator, &mut stack), 8902);
+        assert_eq!(perft_with_stack(2, &mut board, &move_generator, &mut stack), 400);
+    }
+    #[test]
+    fn perft_depth_zero() {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::default();
+
+        // nothing is generated, so an empty stack isn't indexed at all
+        assert_eq!(perft(0, &mut board, &move_generator), 1);
+        assert_eq!(perft_with_stack(0, &mut board, &move_generator, &mut PerftStack::new(0)), 1);
+        assert_eq!(perft_pseudo_legal(0, &mut board, &move_generator), 1);
+        assert_eq!(perft_stoppable(0, &mut board, &move_generator, &mut PerftStack::new(0), &ControlHandle::new()), Some(1));
+        assert_eq!(board.to_fen(), Board::default().to_fen());
+    }
+    #[test]
+    fn perft_cancel() {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::default();
+
+        // stop as soon as the first root move has been counted
+        let mut control = ControlHandle::new();
+        let stop = control.stop_flag();
+        control = control.with_progress(move |_| stop.store(true, std::sync::atomic::Ordering::Relaxed));
+
+        let start = std::time::Instant::now();
+        let result = perft_divide_with_control(6, &mut board, &move_generator, &mut control);
+
+        assert!(!result.complete);
+        assert_eq!(result.divide.len(), 1);
+        assert_eq!(result.nodes, result.divide[0].1);
+        assert!(start.elapsed() < std::time::Duration::from_secs(60));
+        assert_eq!(board.to_fen(), Board::default().to_fen());
+    }
+    #[test]
+    fn perft_divide_complete() {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::default();
+        let mut control = ControlHandle::new();
+
+        let result = perft_divide_with_control(3, &mut board, &move_generator, &mut control);
+
+        assert!(result.complete);
+        assert_eq!(result.divide.len(), 20);
+        assert_eq!(result.nodes, 8902);
+    }
+    fn run(args: &[&str]) -> (i32, String) {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut output = Vec::new();
+        let code = perft_command(&args, &mut output);
+        (code, String::from_utf8(output).unwrap())
+    }
+
+    #[test]
+    fn perftree_output() {
+        let startpos = crate::engine::board::STARTING_FEN;
+        let golden = "\
+a2a3 20
+a2a4 20
+b1a3 20
+b1c3 20
+b2b3 20
+b2b4 20
+c2c3 20
+c2c4 20
+d2d3 20
+d2d4 20
+e2e3 20
+e2e4 20
+f2f3 20
+f2f4 20
+g1f3 20
+g1h3 20
+g2g3 20
+g2g4 20
+h2h3 20
+h2h4 20
+
+400
+";
+        assert_eq!(run(&["--format", "perftree", "2", startpos]), (EXIT_OK, golden.to_string()));
+        assert_eq!(run(&["--format", "perftree", "2", startpos, ""]), (EXIT_OK, golden.to_string()));
+
+        // the moves are made before dividing
+        let (code, output) = run(&["--format", "perftree", "1", startpos, "e2e4 e7e5 g1f3"]);
+        assert_eq!(code, EXIT_OK);
+        assert!(output.starts_with("a7a5 1\na7a6 1\nb7b5 1\n"), "{}", output);
+        assert!(output.ends_with("\n\n29\n"), "{}", output);
+
+        // generation order, as go perft prints
+        let (code, output) = run(&["1"]);
+        assert_eq!(code, EXIT_OK);
+        assert!(output.starts_with("a2a3: 1\n"), "{}", output);
+        assert!(output.ends_with("\n\nNodes searched: 20\n"), "{}", output);
+
+        assert_eq!(run(&["0"]).0, EXIT_USAGE);
+        assert_eq!(run(&["--format", "csv", "1"]).0, EXIT_USAGE);
+        assert_eq!(run(&["1", "not a fen"]).0, EXIT_INVALID);
+        assert_eq!(run(&["1", startpos, "e2e5"]).0, EXIT_INVALID);
+    }
+
+    #[test]
+    fn perft_pseudo_legal_matches() {
+        let move_generator = MoveGenerator::new();
+
+        for (fen, depth) in &[
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 4),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 3),
+            ("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 4),
+            ("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", 3),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            assert_eq!(
+                perft_pseudo_legal(*depth, &mut board, &move_generator),
+                perft_test(fen, *depth, &move_generator),
+                "{}",
+                fen
+            );
+        }
+    }
+    // Walks the tree to depth, checking gen_captures against the captures,
+    // en passant captures and promotions in gen_moves at every node, and
+    // returns how many there were in total.
+    fn perft_captures(depth: usize, board: &mut Board, move_generator: &MoveGenerator) -> u64 {
+        let mut moves = MoveList::new();
+        let mut captures = MoveList::new();
+        move_generator.gen_moves(board, &mut moves);
+        move_generator.gen_captures(board, &mut captures);
+
+        let is_capture = |m: Move| match m.get_move_type() {
+            MOVE_TYPE_CASTLE => false,
+            MOVE_TYPE_EN_PASSANT | MOVE_TYPE_PROMOTION => true,
+            _ => board.pieces[m.get_move_end() as usize].is_some(),
+        };
+        let mut expected: Vec<Move> = (0..moves.len()).map(|i| moves.at(i)).filter(|&m| is_capture(m)).collect();
+        let mut found: Vec<Move> = (0..captures.len()).map(|i| captures.at(i)).collect();
+        expected.sort_unstable();
+        found.sort_unstable();
+        assert_eq!(found, expected, "{}", board.to_fen());
+
+        let mut count = found.len() as u64;
+        if depth > 1 {
+            let mut info = UndoInfo::default();
+            for i in 0..moves.len() {
+                let m = moves.at(i);
+                board.make_move(m, &mut info);
+                count += perft_captures(depth - 1, board, move_generator);
+                board.undo_move(m, &info);
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn perft_captures_match() {
+        let move_generator = MoveGenerator::new();
+
+        for (fen, depth) in &[
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 3),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 3),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 3),
+            ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 3),
+            ("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 3),
+            ("r3k2r/8/3Q4/8/8/5q2/8/R3K2R b KQkq - 0 1", 3),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            perft_captures(*depth, &mut board, &move_generator);
         }
 
-        perft(depth, &mut board, &move_generator, &mut move_lists)
+        let mut board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert_eq!(perft_captures(1, &mut board, &move_generator), 8);
     }
 
     #[test]
@@ -129,6 +547,19 @@ mod tests {
         assert_eq!(perft_test("r3k2r/p1ppqpb1/bn1Ppnp1/4N3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1", 4, &move_generator), 3835265);
     }
     #[test]
+    fn perft_castling() {
+        // castling through check, into check and with rooks attacked or
+        // captured, where a mistake in any of them changes the totals
+        let move_generator = MoveGenerator::new();
+        assert_eq!(perft_test("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", 4, &move_generator), 314346);
+        assert_eq!(perft_test("r3k2r/8/8/8/8/8/8/1R2K2R w Kkq - 0 1", 4, &move_generator), 328965);
+        assert_eq!(perft_test("r3k2r/8/8/8/8/8/8/2R1K2R w Kkq - 0 1", 4, &move_generator), 312835);
+        assert_eq!(perft_test("r3k2r/8/8/8/8/8/8/R3K1R1 w Qkq - 0 1", 4, &move_generator), 316214);
+        assert_eq!(perft_test("1r2k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1", 4, &move_generator), 334705);
+        assert_eq!(perft_test("2r1k2r/8/8/8/8/8/8/R3K2R w KQk - 0 1", 4, &move_generator), 317324);
+        assert_eq!(perft_test("r3k1r1/8/8/8/8/8/8/R3K2R w KQq - 0 1", 4, &move_generator), 320792);
+    }
+    #[test]
     fn perft_depth_5() {
         let move_generator = MoveGenerator::new();
         assert_eq!(perft_test("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 5, &move_generator), 15833292);
@@ -0,0 +1,526 @@
+// Games in PGN, read and written, and `chess_rs annotate`, which searches
+// every position of a game and writes it out again with the engine's view
+// of each move as a comment: {+0.34/14} when the move was the one the
+// search liked best, {-0.80/14, best was 12...Nf6} when it wasn't.
+//
+// Only the first game of a file is read. Variations, NAGs and the other
+// games' moves are skipped, and the comments kept are those after a move.
+
+use std::fmt::{Display, Formatter};
+
+use crate::engine::board::Board;
+use crate::engine::control::ControlHandle;
+use crate::engine::error::{Error, PgnError, EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use crate::engine::eval::Evaluator;
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::r#move::{Move, UndoInfo};
+use crate::engine::san;
+use crate::engine::search::{self, SearchContext, MATE_SCORE};
+
+const USAGE: &str = "usage: chess_rs annotate <game.pgn> [--movetime <ms>] [--depth <plies>] [--out <file>]";
+
+// the time for each position when neither it nor a depth is given
+pub const DEFAULT_MOVE_TIME_MILLIS: usize = 500;
+// how deep a search with only a time limit can go
+pub const MAX_ANNOTATE_DEPTH: usize = 64;
+
+const RESULTS: &[&str] = &["1-0", "0-1", "1/2-1/2", "*"];
+
+// movetext lines are wrapped before this many characters
+const LINE_LENGTH: usize = 80;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Game {
+    // the tag pairs, in the order given
+    pub tags: Vec<(String, String)>,
+    pub moves: Vec<Move>,
+    // the comment after each move, if it has one
+    pub comments: Vec<Option<String>>,
+    // 1-0, 0-1, 1/2-1/2 or *
+    pub result: String,
+}
+
+impl Game {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    // the position from the FEN tag, or the start position
+    pub fn start_position(&self) -> Result<Board, PgnError> {
+        match self.tag("FEN") {
+            Some(fen) => Board::new(fen).map_err(PgnError::BadFen),
+            None => Ok(Board::default()),
+        }
+    }
+
+    // the number of the first move, which a FEN tag may give
+    fn first_move_number(&self) -> usize {
+        self.tag("FEN")
+            .and_then(|fen| fen.split_whitespace().nth(5))
+            .and_then(|number| number.parse().ok())
+            .unwrap_or(1)
+    }
+}
+
+// the first game in the text
+pub fn parse_game(text: &str, move_generator: &MoveGenerator) -> Result<Game, PgnError> {
+    let mut game = Game::default();
+    let mut board = None;
+    let mut info = UndoInfo::default();
+    let mut chars = text.char_indices().peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        match c {
+            _ if c.is_whitespace() => {
+                chars.next();
+            }
+            // a tag once the moves have started belongs to the next game
+            '[' if board.is_some() => break,
+            '[' => {
+                let end = text[start..].find(']').ok_or(PgnError::Unclosed('['))?;
+                game.tags.push(parse_tag(&text[start + 1..start + end])?);
+                chars.nth(text[start..start + end].chars().count());
+            }
+            '{' | ';' => {
+                let close = if c == '{' { '}' } else { '\n' };
+                let end = match text[start..].find(close) {
+                    Some(end) => end,
+                    None if c == ';' => text.len() - start,
+                    None => return Err(PgnError::Unclosed('{')),
+                };
+                let comment = text[start + 1..start + end].split_whitespace().collect::<Vec<_>>().join(" ");
+                if let Some(last) = game.comments.last_mut() {
+                    *last = Some(match last.take() {
+                        Some(earlier) => format!("{} {}", earlier, comment),
+                        None => comment,
+                    });
+                }
+                chars.nth(text[start..(start + end + 1).min(text.len())].chars().count() - 1);
+            }
+            '(' => {
+                let mut depth = 0;
+                for (_, c) in chars.by_ref() {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    }
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                if depth != 0 {
+                    return Err(PgnError::Unclosed('('));
+                }
+            }
+            _ => {
+                // a stray ) or } is a token by itself, which won't read as a move
+                let end = text[start..]
+                    .find(|c: char| c.is_whitespace() || "[]{}();".contains(c))
+                    .map_or(text.len(), |end| start + end)
+                    .max(start + c.len_utf8());
+                let token = &text[start..end];
+                chars.nth(token.chars().count() - 1);
+
+                if RESULTS.contains(&token) {
+                    game.result = token.to_string();
+                    break;
+                }
+                if token.starts_with('$') {
+                    continue;
+                }
+
+                // a move number, which may be joined to the move (1.e4)
+                let san = match token.starts_with(|c: char| c.is_ascii_digit()) {
+                    true => token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.'),
+                    false => token,
+                };
+                if san.is_empty() {
+                    continue;
+                }
+
+                if board.is_none() {
+                    board = Some(game.start_position()?);
+                }
+                let board = board.as_mut().unwrap();
+                let ply = game.moves.len() + 1;
+                let my_move =
+                    san::san_to_move(board, move_generator, san).map_err(|e| PgnError::BadMove(ply, e))?;
+                board.make_move(my_move, &mut info);
+                game.moves.push(my_move);
+                game.comments.push(None);
+            }
+        }
+    }
+
+    if game.result.is_empty() {
+        game.result = game.tag("Result").unwrap_or("*").to_string();
+    }
+    Ok(game)
+}
+
+// the inside of [Name "value"]
+fn parse_tag(tag: &str) -> Result<(String, String), PgnError> {
+    let bad_tag = || PgnError::BadTag(tag.to_string());
+    let (name, value) = tag.trim().split_once(char::is_whitespace).ok_or_else(bad_tag)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .ok_or_else(bad_tag)?;
+    Ok((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+// The game in PGN, with its comments. A black move is numbered when it
+// starts the game or follows a comment, as in 12...Nf6.
+pub fn write_game(game: &Game, move_generator: &MoveGenerator) -> Result<String, PgnError> {
+    let mut pgn = String::new();
+    for (name, value) in &game.tags {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    if !game.tags.is_empty() {
+        pgn.push('\n');
+    }
+
+    let mut board = game.start_position()?;
+    let mut info = UndoInfo::default();
+    let mut tokens = Vec::new();
+    let mut number = game.first_move_number();
+    let mut numbered = false;
+    for (&my_move, comment) in game.moves.iter().zip(&game.comments) {
+        let white = board.friendly_color().is_white();
+        if white {
+            tokens.push(format!("{}.", number));
+        } else if !numbered {
+            tokens.push(format!("{}...", number));
+        }
+        tokens.push(san::move_to_san(&mut board, move_generator, my_move));
+        board.make_move(my_move, &mut info);
+
+        numbered = white && comment.is_none();
+        if let Some(comment) = comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        if !white {
+            number += 1;
+        }
+    }
+    tokens.push(game.result.clone());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() >= LINE_LENGTH {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+    Ok(pgn)
+}
+
+// What the search made of a move, as written in its comment
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    // the played move's score from White's side, as PGN evaluations are
+    pub score: i32,
+    pub depth: usize,
+    // the move the search preferred, numbered, if it wasn't the one played
+    pub best: Option<String>,
+}
+
+impl Annotation {
+    // reads back a comment written for an annotation
+    pub fn parse(comment: &str) -> Option<Annotation> {
+        let (evaluation, best) = match comment.split_once(", best was ") {
+            Some((evaluation, best)) => (evaluation, Some(best.to_string())),
+            None => (comment, None),
+        };
+        let (score, depth) = evaluation.split_once('/')?;
+
+        let score = match score.strip_prefix('#') {
+            // a mate in that many moves, which may be a ply quicker than
+            // the one the search found but is written the same
+            Some(moves) => match moves.parse::<i32>().ok()? {
+                moves if moves > 0 => MATE_SCORE - (2 * moves - 1),
+                moves => -(MATE_SCORE + 2 * moves),
+            },
+            None => {
+                let pawns = score.parse::<f64>().ok()?;
+                (pawns * 100.0).round() as i32
+            }
+        };
+
+        Some(Annotation {
+            score,
+            depth: depth.parse().ok()?,
+            best,
+        })
+    }
+}
+
+// +0.34/14 or #-3/9, then the better move if there was one
+impl Display for Annotation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match search::mate_in(self.score) {
+            Some(moves) => write!(f, "#{}/{}", moves, self.depth)?,
+            None => write!(f, "{:+.2}/{}", self.score as f64 / 100.0, self.depth)?,
+        }
+        match &self.best {
+            Some(best) => write!(f, ", best was {}", best),
+            None => Ok(()),
+        }
+    }
+}
+
+// Searches the position before each move of the game to the depth, or
+// until the time (in milliseconds) runs out. Every root move is searched
+// with a full window, so the played move's score is known even when it
+// isn't the best.
+pub fn annotate_game(
+    game: &Game,
+    depth: usize,
+    move_time_millis: usize,
+    move_generator: &MoveGenerator,
+    context: &mut SearchContext,
+) -> Result<Vec<Annotation>, PgnError> {
+    let mut board = game.start_position()?;
+    let mut evaluator = Evaluator::default();
+    let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+    let mut info = UndoInfo::default();
+    let mut number = game.first_move_number();
+    let mut annotations = Vec::new();
+
+    for &played in &game.moves {
+        let white = board.friendly_color().is_white();
+        search::iterative_deepening(
+            depth,
+            &mut board,
+            &mut evaluator,
+            move_generator,
+            &mut move_lists,
+            usize::MAX,
+            move_time_millis,
+            &mut ControlHandle::new(),
+            context,
+        );
+
+        let lines = context.best_lines(usize::MAX);
+        let score = lines.iter().find(|line| line.pv[0] == played).map_or(0, |line| line.score);
+        let best = lines.first().map(|line| line.pv[0]).filter(|&best| best != played).map(|best| {
+            let dots = if white { "." } else { "..." };
+            format!("{}{}{}", number, dots, san::move_to_san(&mut board, move_generator, best))
+        });
+        annotations.push(Annotation {
+            score: if white { score } else { -score },
+            depth: context.stats().last().map_or(0, |last| last.depth),
+            best,
+        });
+
+        board.make_move(played, &mut info);
+        if !white {
+            number += 1;
+        }
+    }
+
+    Ok(annotations)
+}
+
+// the game with each move's comment replaced by its annotation
+pub fn annotated(game: &Game, annotations: &[Annotation]) -> Game {
+    Game {
+        comments: annotations.iter().map(|annotation| Some(annotation.to_string())).collect(),
+        ..game.clone()
+    }
+}
+
+// args are those after `annotate`, the return value is the exit code
+pub fn annotate_command(args: &[String]) -> i32 {
+    let mut path = None;
+    let mut out = None;
+    let (mut move_time, mut depth) = (None, None);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let parsed = match arg.as_str() {
+            "--movetime" => args.next().and_then(|millis| millis.parse().ok()).map(|millis| move_time = Some(millis)),
+            "--depth" => args
+                .next()
+                .and_then(|plies| plies.parse().ok())
+                .filter(|&plies| (1..=MAX_ANNOTATE_DEPTH).contains(&plies))
+                .map(|plies| depth = Some(plies)),
+            "--out" => args.next().map(|file| out = Some(file.clone())),
+            file if !file.starts_with('-') && path.is_none() => {
+                path = Some(file.to_string());
+                Some(())
+            }
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    }
+    let path = match path {
+        Some(path) => path,
+        None => {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    // a depth alone searches to it, however long it takes
+    let move_time = match (move_time, depth) {
+        (Some(millis), _) => millis,
+        (None, Some(_)) => usize::MAX,
+        (None, None) => DEFAULT_MOVE_TIME_MILLIS,
+    };
+    let depth = depth.unwrap_or(MAX_ANNOTATE_DEPTH);
+
+    let move_generator = MoveGenerator::new();
+    let annotate = || -> Result<(), PgnError> {
+        let text = std::fs::read_to_string(&path).map_err(|e| PgnError::Io(path.clone(), e.to_string()))?;
+        let game = parse_game(&text, &move_generator)?;
+        if game.moves.is_empty() {
+            return Err(PgnError::NoMoves);
+        }
+
+        let mut context = SearchContext::new();
+        let annotations = annotate_game(&game, depth, move_time, &move_generator, &mut context)?;
+        let pgn = write_game(&annotated(&game, &annotations), &move_generator)?;
+        match &out {
+            Some(file) => std::fs::write(file, pgn).map_err(|e| PgnError::Io(file.clone(), e.to_string())),
+            None => {
+                print!("{}", pgn);
+                Ok(())
+            }
+        }
+    };
+
+    match annotate() {
+        Ok(()) => EXIT_OK,
+        Err(e) => {
+            eprintln!("{}", Error::from(e));
+            EXIT_INVALID
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::error::SanError;
+    use crate::engine::r#move::MoveUtils;
+
+    // Scholar's mate, after 3...Nf6?? which lets it happen
+    const SCHOLARS_MATE: &str = r#"[Event "Casual game"]
+[White "A \"quoted\" name"]
+[Result "1-0"]
+
+1. e4 e5 {the most common reply} 2. Bc4 (2. Nf3 Nc6 (2... d6) 3. Bb5) Nc6 3. Qh5 $2 Nf6?? 4. Qxf7# 1-0
+
+[Event "The next game"]
+1. d4 *
+"#;
+
+    #[test]
+    fn parsing() {
+        let move_generator = MoveGenerator::new();
+        let game = parse_game(SCHOLARS_MATE, &move_generator).unwrap();
+
+        assert_eq!(game.tag("White"), Some("A \"quoted\" name"));
+        assert_eq!(game.result, "1-0");
+        let moves: Vec<String> = game.moves.iter().map(|my_move| my_move.move_to_string()).collect();
+        assert_eq!(moves, ["e2e4", "e7e5", "f1c4", "b8c6", "d1h5", "g8f6", "h5f7"]);
+        assert_eq!(game.comments[1].as_deref(), Some("the most common reply"));
+        assert!(game.comments.iter().enumerate().all(|(idx, comment)| idx == 1 || comment.is_none()));
+
+        // written out again as it came in, but for the variations and NAGs
+        let written = write_game(&game, &move_generator).unwrap();
+        assert_eq!(
+            written,
+            "[Event \"Casual game\"]\n[White \"A \\\"quoted\\\" name\"]\n[Result \"1-0\"]\n\n\
+             1. e4 e5 {the most common reply} 2. Bc4 Nc6 3. Qh5 Nf6 4. Qxf7# 1-0\n"
+        );
+        assert_eq!(parse_game(&written, &move_generator).unwrap(), game);
+
+        // a FEN tag starts the game elsewhere, with its move number
+        let from_fen = "[FEN \"4k3/8/8/8/8/8/8/R3K3 b Q - 0 30\"]\n\n30... Kd7 31. O-O-O+ *";
+        let game = parse_game(from_fen, &move_generator).unwrap();
+        assert_eq!(game.moves.len(), 2);
+        assert!(write_game(&game, &move_generator).unwrap().ends_with("\n30... Kd7 31. O-O-O+ *\n"));
+
+        assert_eq!(
+            parse_game("1. e4 e5 2. Ke3", &move_generator),
+            Err(PgnError::BadMove(3, SanError::NoMatchingMove("Ke3".to_string())))
+        );
+        assert_eq!(parse_game("1. e4 {unclosed", &move_generator), Err(PgnError::Unclosed('{')));
+        assert_eq!(parse_game("1. e4 (1. d4", &move_generator), Err(PgnError::Unclosed('(')));
+        assert_eq!(parse_game("[Event]", &move_generator), Err(PgnError::BadTag("Event".to_string())));
+        assert_eq!(
+            parse_game("1. e4 ) e5", &move_generator),
+            Err(PgnError::BadMove(2, SanError::InvalidSyntax(")".to_string())))
+        );
+    }
+
+    #[test]
+    fn annotations() {
+        for comment in ["+0.34/14", "-1.20/9, best was 12...Nf6", "#3/5", "#-2/6, best was 40.Kh1"] {
+            assert_eq!(Annotation::parse(comment).unwrap().to_string(), comment);
+        }
+        assert_eq!(Annotation::parse("+0.34/14").unwrap().score, 34);
+        assert_eq!(Annotation::parse("the most common reply"), None);
+    }
+
+    #[test]
+    fn annotate_game() {
+        let move_generator = MoveGenerator::new();
+        let game = parse_game(SCHOLARS_MATE, &move_generator).unwrap();
+        let annotations = super::annotate_game(&game, 3, usize::MAX, &move_generator, &mut SearchContext::new()).unwrap();
+        let written = write_game(&annotated(&game, &annotations), &move_generator).unwrap();
+
+        // every move's comment reads back, with a score
+        let reread = parse_game(&written, &move_generator).unwrap();
+        assert_eq!(reread.moves, game.moves);
+        for (comment, annotation) in reread.comments.iter().zip(&annotations) {
+            let parsed = Annotation::parse(comment.as_deref().unwrap()).unwrap();
+            assert_eq!(parsed.to_string(), annotation.to_string());
+            assert_eq!(parsed.depth, 3);
+        }
+
+        // Nf6 allows mate where a queen move or g6 would stop it, and Qxf7
+        // mates
+        assert!(annotations[5].best.as_deref().is_some_and(|best| best.starts_with("3...")));
+        assert!(annotations[6].best.is_none());
+        assert!(written.contains(" Nf6 {#1/3, best was 3..."), "{}", written);
+        assert!(written.ends_with(" 4. Qxf7# {#1/3} 1-0\n"), "{}", written);
+    }
+
+    #[test]
+    fn annotate_command() {
+        let dir = std::env::temp_dir();
+        let game = dir.join(format!("chess_rs_annotate_{}.pgn", std::process::id()));
+        let out = dir.join(format!("chess_rs_annotated_{}.pgn", std::process::id()));
+        std::fs::write(&game, "1. e4 e5 2. Nf3 Nc6 *\n").unwrap();
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        let (game_path, out_path) = (game.to_str().unwrap(), out.to_str().unwrap());
+
+        let code = super::annotate_command(&args(&[game_path, "--movetime", "50", "--out", out_path]));
+        let annotated = std::fs::read_to_string(&out).unwrap();
+        let _ = std::fs::remove_file(&game);
+        let _ = std::fs::remove_file(&out);
+        assert_eq!(code, EXIT_OK);
+
+        let reread = parse_game(&annotated, &MoveGenerator::new()).unwrap();
+        assert_eq!(reread.moves.len(), 4);
+        assert!(reread.comments.iter().all(|comment| comment.as_deref().and_then(Annotation::parse).is_some()));
+
+        assert_eq!(super::annotate_command(&args(&[])), EXIT_USAGE);
+        assert_eq!(super::annotate_command(&args(&[game_path, "--depth", "0"])), EXIT_USAGE);
+        assert_eq!(super::annotate_command(&args(&["no_such_game.pgn", "--depth", "1"])), EXIT_INVALID);
+    }
+}
@@ -42,6 +42,36 @@ impl Display for Color {
     }
 }
 
+// A piece without its color, in the same order as Pieces
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PieceType {
+    Pawn = 0,
+    Knight = 1,
+    Bishop = 2,
+    Rook = 3,
+    Queen = 4,
+    King = 5,
+}
+
+impl PieceType {
+    pub fn notation(&self) -> char {
+        match *self {
+            PieceType::Pawn => 'P',
+            PieceType::Knight => 'N',
+            PieceType::Bishop => 'B',
+            PieceType::Rook => 'R',
+            PieceType::Queen => 'Q',
+            PieceType::King => 'K',
+        }
+    }
+
+    #[inline(always)]
+    pub fn idx(&self) -> usize {
+        *self as usize
+    }
+}
+
 #[repr(usize)]
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Pieces {
@@ -78,6 +108,17 @@ impl Pieces {
         }
     }
 
+    pub fn piece_type(&self) -> PieceType {
+        match *self {
+            Pieces::WhitePawn | Pieces::BlackPawn => PieceType::Pawn,
+            Pieces::WhiteKnight | Pieces::BlackKnight => PieceType::Knight,
+            Pieces::WhiteBishop | Pieces::BlackBishop => PieceType::Bishop,
+            Pieces::WhiteRook | Pieces::BlackRook => PieceType::Rook,
+            Pieces::WhiteQueen | Pieces::BlackQueen => PieceType::Queen,
+            Pieces::WhiteKing | Pieces::BlackKing => PieceType::King,
+        }
+    }
+
     pub fn notation(&self) -> char {
         match *self {
             Pieces::WhitePawn => 'P',
@@ -96,6 +137,11 @@ impl Pieces {
         }
     }
 
+    // the piece from its letter in a fen
+    pub fn from_notation(c: char) -> Option<Pieces> {
+        (0..12).map(Pieces::from_idx).find(|piece| piece.notation() == c)
+    }
+
     pub fn symbol(&self) -> char {
         match *self {
             Pieces::WhitePawn => '♙',
@@ -119,6 +165,24 @@ impl Pieces {
         *self as usize
     }
 
+    pub fn from_idx(idx: usize) -> Pieces {
+        match idx {
+            0 => Pieces::WhitePawn,
+            1 => Pieces::WhiteKnight,
+            2 => Pieces::WhiteBishop,
+            3 => Pieces::WhiteRook,
+            4 => Pieces::WhiteQueen,
+            5 => Pieces::WhiteKing,
+            6 => Pieces::BlackPawn,
+            7 => Pieces::BlackKnight,
+            8 => Pieces::BlackBishop,
+            9 => Pieces::BlackRook,
+            10 => Pieces::BlackQueen,
+            11 => Pieces::BlackKing,
+            _ => panic!("Invalid piece index {}", idx),
+        }
+    }
+
     #[inline(always)]
     pub fn is_pawn(&self) -> bool {
         matches!(*self, Pieces::WhitePawn | Pieces::BlackPawn)
@@ -0,0 +1,303 @@
+// `go playouts N`: estimates a position by playing N games to the end from
+// it, each starting with one of the root moves in turn. The moves are mostly
+// the best by the static evaluation one ply ahead, with a random move some
+// of the time, so the games are quick but not senseless. It is no substitute
+// for the search, but a rough second opinion and a testbed.
+//
+// Each thread plays on its own board, making and undoing moves, so a
+// playout costs no copies. With a seed the games are the same every time.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::engine::board::{Board, GameState};
+use crate::engine::eval::{Evaluate, Evaluator};
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::r#move::{Move, UndoInfo};
+
+// A game still going after this many moves by each side is adjudicated a
+// draw. The UCI option Playout Move Limit sets it, up to the maximum, so
+// the games stay short whatever the position.
+pub const DEFAULT_PLAYOUT_MOVES: usize = 100;
+pub const MAX_PLAYOUT_MOVES: usize = 1024;
+
+// the chance of playing a random move rather than the best looking one
+const RANDOM_MOVE_CHANCE: f64 = 0.25;
+
+// how the games starting with a root move went, for the side to move at
+// the root
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PlayoutStats {
+    pub root_move: Move,
+    pub wins: u64,
+    pub draws: u64,
+    pub losses: u64,
+}
+
+impl PlayoutStats {
+    fn new(root_move: Move) -> PlayoutStats {
+        PlayoutStats {
+            root_move,
+            wins: 0,
+            draws: 0,
+            losses: 0,
+        }
+    }
+
+    pub fn playouts(&self) -> u64 {
+        self.wins + self.draws + self.losses
+    }
+
+    // a draw counts as half a win
+    pub fn win_rate(&self) -> f64 {
+        match self.playouts() {
+            0 => 0.0,
+            playouts => (self.wins as f64 + self.draws as f64 / 2.0) / playouts as f64,
+        }
+    }
+
+    fn add(&mut self, other: &PlayoutStats) {
+        self.wins += other.wins;
+        self.draws += other.draws;
+        self.losses += other.losses;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Outcome {
+    Win,
+    Draw,
+    Loss,
+}
+
+// a worker's board and everything it reuses between playouts
+struct Worker<'a> {
+    board: Board,
+    evaluator: Evaluator,
+    move_generator: &'a MoveGenerator,
+    moves: MoveList,
+    played: Vec<Move>,
+    undo: Vec<UndoInfo>,
+    // the game is a draw once this many plies have been played
+    max_plies: usize,
+    rng: StdRng,
+}
+
+impl<'a> Worker<'a> {
+    fn new(board: &Board, move_generator: &'a MoveGenerator, max_moves: usize, rng: StdRng) -> Worker<'a> {
+        let board = board.clone();
+        let max_plies = 2 * max_moves;
+        let mut evaluator = Evaluator::default();
+        evaluator.full(&board);
+
+        Worker {
+            board,
+            evaluator,
+            move_generator,
+            moves: MoveList::new(),
+            played: Vec::with_capacity(max_plies + 1),
+            undo: (0..=max_plies).map(|_| UndoInfo::default()).collect(),
+            max_plies,
+            rng,
+        }
+    }
+
+    fn make(&mut self, my_move: Move) {
+        let info = &mut self.undo[self.played.len()];
+        self.board.make_move(my_move, info);
+        self.evaluator.on_make(&self.board, my_move, info);
+        self.played.push(my_move);
+    }
+
+    fn undo(&mut self) {
+        let my_move = self.played.pop().unwrap();
+        let info = &self.undo[self.played.len()];
+        self.board.undo_move(my_move, info);
+        self.evaluator.on_undo(&self.board, my_move, info);
+    }
+
+    // the move which leaves the side to move best off by the static
+    // evaluation
+    fn greedy_move(&mut self) -> Move {
+        let mut best = (self.moves.at(0), i32::MIN);
+        for i in 0..self.moves.len() {
+            let my_move = self.moves.at(i);
+            self.make(my_move);
+            let score = -self.evaluator.evaluate(&self.board);
+            self.undo();
+
+            if score > best.1 {
+                best = (my_move, score);
+            }
+        }
+        best.0
+    }
+
+    // plays a game on from the root move, and takes it back again
+    fn playout(&mut self, root_move: Move) -> Outcome {
+        let root_color = self.board.friendly_color();
+        self.make(root_move);
+
+        let outcome = loop {
+            let in_check = self.move_generator.gen_moves(&mut self.board, &mut self.moves).checkers != 0;
+            let state = self.board.game_state_with(in_check, self.moves.len());
+
+            match state {
+                GameState::Ongoing if self.played.len() < self.max_plies => {}
+                // the side to move has lost
+                GameState::Checkmate | GameState::ThirdCheck | GameState::KingOfTheHill => {
+                    break match self.board.friendly_color() == root_color {
+                        true => Outcome::Loss,
+                        false => Outcome::Win,
+                    };
+                }
+                _ => break Outcome::Draw,
+            }
+
+            let my_move = if self.rng.gen_bool(RANDOM_MOVE_CHANCE) {
+                self.moves.at(self.rng.gen_range(0..self.moves.len()))
+            } else {
+                self.greedy_move()
+            };
+            self.make(my_move);
+        };
+
+        while !self.played.is_empty() {
+            self.undo();
+        }
+        outcome
+    }
+}
+
+// Plays `count` games from the position on `threads` threads, the root
+// moves taking turns to start them, until done or stopped. A game is a draw
+// after max_moves moves by each side, counting the root move. Thread t plays
+// games t, t + threads, ... with its own generator seeded from the seed, so
+// a seeded run always plays the same games. There are no statistics for a
+// position without legal moves.
+pub fn playouts(
+    board: &Board,
+    move_generator: &MoveGenerator,
+    count: u64,
+    max_moves: usize,
+    threads: usize,
+    seed: Option<u64>,
+    stop: &AtomicBool,
+) -> Vec<PlayoutStats> {
+    let mut root_moves = MoveList::new();
+    move_generator.gen_moves(&mut board.clone(), &mut root_moves);
+    let root_moves: Vec<Move> = (0..root_moves.len()).map(|i| root_moves.at(i)).collect();
+    if root_moves.is_empty() {
+        return Vec::new();
+    }
+
+    let threads = threads.max(1) as u64;
+    let results: Vec<Vec<PlayoutStats>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let root_moves = &root_moves;
+                scope.spawn(move || {
+                    let rng = match seed {
+                        Some(seed) => StdRng::seed_from_u64(seed.wrapping_add(thread)),
+                        None => StdRng::from_entropy(),
+                    };
+                    let mut worker = Worker::new(board, move_generator, max_moves, rng);
+                    let mut stats: Vec<PlayoutStats> = root_moves.iter().map(|&m| PlayoutStats::new(m)).collect();
+
+                    let mut game = thread;
+                    while game < count && !stop.load(Ordering::Relaxed) {
+                        let idx = (game % root_moves.len() as u64) as usize;
+                        match worker.playout(root_moves[idx]) {
+                            Outcome::Win => stats[idx].wins += 1,
+                            Outcome::Draw => stats[idx].draws += 1,
+                            Outcome::Loss => stats[idx].losses += 1,
+                        }
+                        game += threads;
+                    }
+                    stats
+                })
+            })
+            .collect();
+
+        workers.into_iter().map(|worker| worker.join().expect("playout thread panicked")).collect()
+    });
+
+    let mut stats: Vec<PlayoutStats> = root_moves.iter().map(|&m| PlayoutStats::new(m)).collect();
+    for thread_stats in &results {
+        for (total, thread) in stats.iter_mut().zip(thread_stats) {
+            total.add(thread);
+        }
+    }
+    stats
+}
+
+// the root move with the best win rate, the first of any tied
+pub fn best_move(stats: &[PlayoutStats]) -> Option<Move> {
+    let mut best: Option<&PlayoutStats> = None;
+    for stat in stats {
+        if best.is_none_or(|best| stat.win_rate() > best.win_rate()) {
+            best = Some(stat);
+        }
+    }
+    best.map(|stat| stat.root_move)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::r#move::MoveUtils;
+
+    #[test]
+    fn mate_in_one_dominates() {
+        let board = Board::new("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap();
+        let stats = playouts(&board, &MoveGenerator::new(), 1000, DEFAULT_PLAYOUT_MOVES, 2, Some(1464), &AtomicBool::new(false));
+
+        assert_eq!(stats.iter().map(|stat| stat.playouts()).sum::<u64>(), 1000);
+        let mate = stats.iter().find(|stat| stat.root_move.move_to_uci(false) == "a1a8").unwrap();
+        assert!(mate.playouts() > 0 && mate.wins == mate.playouts(), "{:?}", mate);
+        assert_eq!(best_move(&stats), Some(mate.root_move));
+        assert!(stats.iter().all(|stat| stat.losses == 0));
+    }
+
+    #[test]
+    fn seeded_runs_repeat() {
+        let board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let run = |seed| playouts(&board, &move_generator, 100, DEFAULT_PLAYOUT_MOVES, 2, Some(seed), &AtomicBool::new(false));
+
+        assert_eq!(run(7), run(7));
+        assert_eq!(board.to_fen(), Board::default().to_fen());
+    }
+
+    #[test]
+    fn stopped_and_game_over() {
+        let board = Board::default();
+        let stats = playouts(&board, &MoveGenerator::new(), 1000, DEFAULT_PLAYOUT_MOVES, 1, None, &AtomicBool::new(true));
+        assert_eq!(stats.len(), 20);
+        assert!(stats.iter().all(|stat| stat.playouts() == 0));
+
+        let mated = Board::new("7k/6Q1/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(playouts(&mated, &MoveGenerator::new(), 10, DEFAULT_PLAYOUT_MOVES, 1, None, &AtomicBool::new(false)).is_empty());
+    }
+
+    #[test]
+    fn move_limit() {
+        // the quickest mate takes two moves each, so with a limit of one
+        // every game is adjudicated a draw
+        let board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let stats = playouts(&board, &move_generator, 1000, 1, 2, Some(1425), &AtomicBool::new(false));
+        assert!(stats.iter().all(|stat| stat.draws == stat.playouts()), "{:?}", stats);
+
+        // a pawn race is only decided once a pawn queens, so a short limit
+        // cuts off games which would have ended in a result
+        let board = Board::new("4k3/pppppppp/8/8/8/8/PPPPPPPP/4K3 w - - 0 1").unwrap();
+        let decided = |max_moves| {
+            let stats = playouts(&board, &move_generator, 200, max_moves, 2, Some(1425), &AtomicBool::new(false));
+            stats.iter().map(|stat| stat.wins + stat.losses).sum::<u64>()
+        };
+        assert!(decided(DEFAULT_PLAYOUT_MOVES) > decided(10));
+    }
+}
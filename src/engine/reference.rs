@@ -0,0 +1,309 @@
+// A deliberately simple legal move generator, used to check MoveGenerator.
+// It only looks at board.pieces, scanning outwards from each square, and
+// finds legal moves by making each pseudo legal move on a copy of the
+// squares and rejecting it if the king can then be captured. It shares no
+// code with the real generator, so it is slow but makes different mistakes.
+
+use crate::engine::board::Board;
+use crate::engine::piece::{Color, Pieces};
+use crate::engine::square::Square;
+
+type Squares = [Option<Pieces>; 64];
+
+const KNIGHT_OFFSETS: [(i16, i16); 8] = [
+    (-2, -1),
+    (-2, 1),
+    (-1, -2),
+    (-1, 2),
+    (1, -2),
+    (1, 2),
+    (2, -1),
+    (2, 1),
+];
+const KING_OFFSETS: [(i16, i16); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+const ROOK_DIRECTIONS: [(i16, i16); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const BISHOP_DIRECTIONS: [(i16, i16); 4] = [(-1, -1), (-1, 1), (1, -1), (1, 1)];
+
+struct Candidate {
+    start: usize,
+    end: usize,
+    promotion: Option<char>,
+    en_passant: bool,
+    // the rook's start and end squares
+    castle: Option<(usize, usize)>,
+}
+
+impl Candidate {
+    fn new(start: usize, end: usize) -> Candidate {
+        Candidate {
+            start,
+            end,
+            promotion: None,
+            en_passant: false,
+            castle: None,
+        }
+    }
+
+    fn notation(&self) -> String {
+        let mut result =
+            Square::from_usize(self.start).notation() + &Square::from_usize(self.end).notation();
+        result.extend(self.promotion);
+        result
+    }
+}
+
+// the square reached from sq by moving the given number of ranks and files,
+// rank 0 being the 8th rank
+fn offset(sq: usize, (dr, df): (i16, i16)) -> Option<usize> {
+    let r = (sq / 8) as i16 + dr;
+    let f = (sq % 8) as i16 + df;
+    if Square::valid_rf(r, f) {
+        Some((r * 8 + f) as usize)
+    } else {
+        None
+    }
+}
+
+fn is_color(squares: &Squares, sq: usize, color: Color) -> bool {
+    matches!(squares[sq], Some(piece) if piece.color() == color)
+}
+
+fn is_piece(squares: &Squares, sq: Option<usize>, piece: Pieces) -> bool {
+    matches!(sq, Some(sq) if squares[sq] == Some(piece))
+}
+
+// the direction pawns of the color move in, in ranks
+fn pawn_direction(color: Color) -> i16 {
+    if color.is_white() {
+        -1
+    } else {
+        1
+    }
+}
+
+fn is_attacked(squares: &Squares, sq: usize, by: Color) -> bool {
+    let (pawn, knight, bishop, rook, queen, king) = if by.is_white() {
+        (
+            Pieces::WhitePawn,
+            Pieces::WhiteKnight,
+            Pieces::WhiteBishop,
+            Pieces::WhiteRook,
+            Pieces::WhiteQueen,
+            Pieces::WhiteKing,
+        )
+    } else {
+        (
+            Pieces::BlackPawn,
+            Pieces::BlackKnight,
+            Pieces::BlackBishop,
+            Pieces::BlackRook,
+            Pieces::BlackQueen,
+            Pieces::BlackKing,
+        )
+    };
+
+    // a pawn attacks sq from one rank behind it, from its own point of view
+    let dr = -pawn_direction(by);
+    if is_piece(squares, offset(sq, (dr, -1)), pawn) || is_piece(squares, offset(sq, (dr, 1)), pawn)
+    {
+        return true;
+    }
+
+    if KNIGHT_OFFSETS
+        .iter()
+        .any(|&o| is_piece(squares, offset(sq, o), knight))
+        || KING_OFFSETS
+            .iter()
+            .any(|&o| is_piece(squares, offset(sq, o), king))
+    {
+        return true;
+    }
+
+    let slides = |directions: &[(i16, i16)], pieces: [Pieces; 2]| {
+        directions.iter().any(|&direction| {
+            let mut current = sq;
+            while let Some(next) = offset(current, direction) {
+                match squares[next] {
+                    Some(piece) => return pieces.contains(&piece),
+                    None => current = next,
+                }
+            }
+            false
+        })
+    };
+
+    slides(&ROOK_DIRECTIONS, [rook, queen]) || slides(&BISHOP_DIRECTIONS, [bishop, queen])
+}
+
+fn pseudo_legal(board: &Board) -> Vec<Candidate> {
+    let squares = &board.pieces;
+    let color = board.friendly_color();
+    let mut candidates = Vec::new();
+
+    for start in 0..64 {
+        let piece = match squares[start] {
+            Some(piece) if piece.color() == color => piece,
+            _ => continue,
+        };
+
+        // empty or enemy squares
+        let can_land = |end: usize| !is_color(squares, end, color);
+
+        if piece.is_pawn() {
+            let dr = pawn_direction(color);
+            let promotion_rank = if color.is_white() { 0 } else { 7 };
+            let home_rank = if color.is_white() { 6 } else { 1 };
+
+            let mut targets = Vec::new();
+            if let Some(end) = offset(start, (dr, 0)).filter(|&end| squares[end].is_none()) {
+                targets.push((end, false));
+
+                if start / 8 == home_rank {
+                    if let Some(end) = offset(end, (dr, 0)).filter(|&end| squares[end].is_none()) {
+                        targets.push((end, false));
+                    }
+                }
+            }
+            for &df in &[-1, 1] {
+                if let Some(end) = offset(start, (dr, df)) {
+                    if is_color(squares, end, color.enemy()) {
+                        targets.push((end, false));
+                    } else if board.en_passant.map(|sq| sq.sq()) == Some(end) {
+                        targets.push((end, true));
+                    }
+                }
+            }
+
+            for (end, en_passant) in targets {
+                if end / 8 == promotion_rank {
+                    for promotion in "nbrq".chars() {
+                        let mut candidate = Candidate::new(start, end);
+                        candidate.promotion = Some(promotion);
+                        candidates.push(candidate);
+                    }
+                } else {
+                    let mut candidate = Candidate::new(start, end);
+                    candidate.en_passant = en_passant;
+                    candidates.push(candidate);
+                }
+            }
+        } else if piece.is_knight() || piece.is_king() {
+            let offsets = if piece.is_knight() {
+                &KNIGHT_OFFSETS
+            } else {
+                &KING_OFFSETS
+            };
+            for &o in offsets {
+                if let Some(end) = offset(start, o).filter(|&end| can_land(end)) {
+                    candidates.push(Candidate::new(start, end));
+                }
+            }
+        } else {
+            let mut directions = Vec::new();
+            if !piece.is_bishop() {
+                directions.extend_from_slice(&ROOK_DIRECTIONS);
+            }
+            if !piece.is_rook() {
+                directions.extend_from_slice(&BISHOP_DIRECTIONS);
+            }
+
+            for direction in directions {
+                let mut current = start;
+                while let Some(end) = offset(current, direction) {
+                    if can_land(end) {
+                        candidates.push(Candidate::new(start, end));
+                    }
+                    if squares[end].is_some() {
+                        break;
+                    }
+                    current = end;
+                }
+            }
+        }
+    }
+
+    // castling: the king and rook are on their starting squares, the squares
+    // between them are empty and the king does not pass through check
+    let back_rank = if color.is_white() { 56 } else { 0 };
+    let king = back_rank + 4;
+    let (own_king, own_rook) = if color.is_white() {
+        (Pieces::WhiteKing, Pieces::WhiteRook)
+    } else {
+        (Pieces::BlackKing, Pieces::BlackRook)
+    };
+    let sides = [
+        (
+            board.can_castle_ks(color),
+            7,
+            &[5, 6][..],
+            &[4, 5, 6][..],
+            6,
+            5,
+        ),
+        (
+            board.can_castle_qs(color),
+            0,
+            &[1, 2, 3][..],
+            &[4, 3, 2][..],
+            2,
+            3,
+        ),
+    ];
+
+    for &(allowed, rook_file, empty, safe, king_file, rook_end_file) in &sides {
+        if allowed
+            && squares[king] == Some(own_king)
+            && squares[back_rank + rook_file] == Some(own_rook)
+            && empty.iter().all(|&f| squares[back_rank + f].is_none())
+            && safe
+                .iter()
+                .all(|&f| !is_attacked(squares, back_rank + f, color.enemy()))
+        {
+            let mut candidate = Candidate::new(king, back_rank + king_file);
+            candidate.castle = Some((back_rank + rook_file, back_rank + rook_end_file));
+            candidates.push(candidate);
+        }
+    }
+
+    candidates
+}
+
+// the legal moves in the position, in uci notation
+pub(crate) fn legal_moves(board: &Board) -> Vec<String> {
+    let color = board.friendly_color();
+
+    pseudo_legal(board)
+        .into_iter()
+        .filter(|candidate| {
+            let mut squares = board.pieces;
+
+            let piece = squares[candidate.start].take();
+            squares[candidate.end] = piece;
+            if candidate.en_passant {
+                // the captured pawn is beside the start square
+                squares[candidate.start / 8 * 8 + candidate.end % 8] = None;
+            }
+            if let Some((rook_start, rook_end)) = candidate.castle {
+                squares[rook_end] = squares[rook_start].take();
+            }
+
+            let king = squares.iter().position(
+                |&sq| matches!(sq, Some(piece) if piece.is_king() && piece.color() == color),
+            );
+            match king {
+                Some(king) => !is_attacked(&squares, king, color.enemy()),
+                None => true,
+            }
+        })
+        .map(|candidate| candidate.notation())
+        .collect()
+}
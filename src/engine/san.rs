@@ -0,0 +1,308 @@
+// Standard algebraic notation for moves, as used in PGN and by most GUIs
+// when showing moves to people: Nbd2, exd6, e8=Q+, O-O-O and so on. SAN is
+// written for people, and read back from PGN games.
+
+use crate::engine::board::Board;
+use crate::engine::error::SanError;
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::piece::PieceType;
+use crate::engine::r#move::{
+    Move, MoveUtils, UndoInfo, MOVE_CASTLE_SIDE_KS, MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT,
+    MOVE_TYPE_PROMOTION,
+};
+use crate::engine::square::Square;
+
+// The move must be legal in the position. The board is left as it was.
+pub fn move_to_san(board: &mut Board, move_generator: &MoveGenerator, my_move: Move) -> String {
+    let mut san = piece_and_squares(board, move_generator, my_move);
+
+    let mut info = UndoInfo::default();
+    let mut replies = MoveList::new();
+    board.make_move(my_move, &mut info);
+    if move_generator.gen_moves(board, &mut replies).checkers != 0 {
+        san.push(if replies.is_empty() { '#' } else { '+' });
+    }
+    board.undo_move(my_move, &info);
+
+    san
+}
+
+// The legal move the SAN stands for. Check and mate signs, and annotations
+// such as !? are ignored, as is whether a capture is marked. Castling may
+// be written with zeros, a promotion without its '=', and a piece may be
+// given more of its start square than it needs, but not less.
+pub fn san_to_move(board: &mut Board, move_generator: &MoveGenerator, san: &str) -> Result<Move, SanError> {
+    let invalid = || SanError::InvalidSyntax(san.to_string());
+    let text = san.trim_end_matches(['+', '#', '!', '?']);
+
+    let mut moves = MoveList::new();
+    move_generator.gen_moves(board, &mut moves);
+    let moves = (0..moves.len()).map(|idx| moves.at(idx));
+
+    let matching: Vec<Move> = match text {
+        "O-O" | "0-0" => moves
+            .filter(|m| m.get_move_type() == MOVE_TYPE_CASTLE && m.get_move_piece() == MOVE_CASTLE_SIDE_KS)
+            .collect(),
+        "O-O-O" | "0-0-0" => moves
+            .filter(|m| m.get_move_type() == MOVE_TYPE_CASTLE && m.get_move_piece() != MOVE_CASTLE_SIDE_KS)
+            .collect(),
+        _ => {
+            if !text.is_ascii() {
+                return Err(invalid());
+            }
+
+            // [piece] [file] [rank] [x] square [[=] promotion]
+            let (piece_type, rest) = match text.chars().next().and_then(|c| "NBRQK".find(c)) {
+                Some(idx) => (PIECE_TYPES[idx + 1], &text[1..]),
+                None => (PieceType::Pawn, text),
+            };
+            let (rest, promotion) = match rest.char_indices().last() {
+                Some((idx, c)) if piece_type == PieceType::Pawn && "NBRQ".contains(c) => {
+                    (rest[..idx].trim_end_matches('='), Some("NBRQ".find(c).unwrap() as u16))
+                }
+                _ => (rest, None),
+            };
+            if rest.len() < 2 {
+                return Err(invalid());
+            }
+            let end = Square::from_notation(&rest[rest.len() - 2..]).ok_or_else(invalid)?;
+            let from = rest[..rest.len() - 2].trim_end_matches('x');
+            let (from_file, from_rank) = match from.as_bytes() {
+                [] => (None, None),
+                [file @ b'a'..=b'h'] => (Some(file), None),
+                [rank @ b'1'..=b'8'] => (None, Some(rank)),
+                [file @ b'a'..=b'h', rank @ b'1'..=b'8'] => (Some(file), Some(rank)),
+                _ => return Err(invalid()),
+            };
+
+            moves
+                .filter(|&m| {
+                    let start = Square::from_usize(m.get_move_start() as usize);
+                    let start_notation = start.notation();
+                    let start_notation = start_notation.as_bytes();
+                    let is_promotion = m.get_move_type() == MOVE_TYPE_PROMOTION;
+
+                    board.pieces[start.sq()].map(|piece| piece.piece_type()) == Some(piece_type)
+                        && landing_square(m) == end
+                        && from_file.is_none_or(|&file| file == start_notation[0])
+                        && from_rank.is_none_or(|&rank| rank == start_notation[1])
+                        && m.get_move_type() != MOVE_TYPE_CASTLE
+                        && match promotion {
+                            Some(piece) => is_promotion && m.get_move_piece() == piece,
+                            None => !is_promotion,
+                        }
+                })
+                .collect()
+        }
+    };
+
+    match matching.as_slice() {
+        [] => Err(SanError::NoMatchingMove(san.to_string())),
+        [my_move] => Ok(*my_move),
+        _ => Err(SanError::AmbiguousMove(san.to_string())),
+    }
+}
+
+const PIECE_TYPES: [PieceType; 6] = [
+    PieceType::Pawn,
+    PieceType::Knight,
+    PieceType::Bishop,
+    PieceType::Rook,
+    PieceType::Queen,
+    PieceType::King,
+];
+
+// where the moving piece ends up, which for en passant isn't the end of the
+// move but the square behind the captured pawn
+fn landing_square(my_move: Move) -> Square {
+    let end_sq = Square::from_usize(my_move.get_move_end() as usize);
+    if my_move.get_move_type() == MOVE_TYPE_EN_PASSANT {
+        let end_r = if end_sq.rank() == 3 { 2 } else { 5 };
+        Square::from_rf(end_r, end_sq.file())
+    } else {
+        end_sq
+    }
+}
+
+// everything but the check or mate suffix
+fn piece_and_squares(board: &mut Board, move_generator: &MoveGenerator, my_move: Move) -> String {
+    let move_type = my_move.get_move_type();
+    if move_type == MOVE_TYPE_CASTLE {
+        return match my_move.get_move_piece() {
+            MOVE_CASTLE_SIDE_KS => String::from("O-O"),
+            _ => String::from("O-O-O"),
+        };
+    }
+
+    let start = my_move.get_move_start() as usize;
+    let end = my_move.get_move_end() as usize;
+    let piece = board.pieces[start].expect("no piece on the start square");
+    let start_sq = Square::from_usize(start);
+    let end_sq = landing_square(my_move);
+    let is_capture = move_type == MOVE_TYPE_EN_PASSANT || board.pieces[end].is_some();
+
+    let mut san = String::new();
+    if piece.is_pawn() {
+        if is_capture {
+            san.push_str(&start_sq.notation()[..1]);
+            san.push('x');
+        }
+        san.push_str(&end_sq.notation());
+        if move_type == MOVE_TYPE_PROMOTION {
+            san.push('=');
+            san.push(['N', 'B', 'R', 'Q'][my_move.get_move_piece() as usize]);
+        }
+        return san;
+    }
+
+    san.push(piece.piece_type().notation());
+    if piece.piece_type() != PieceType::King {
+        san.push_str(&disambiguation(board, move_generator, my_move));
+    }
+    if is_capture {
+        san.push('x');
+    }
+    san.push_str(&end_sq.notation());
+    san
+}
+
+// the file, rank or both of the start square, when another piece of the same
+// type could also move to the end square
+fn disambiguation(board: &mut Board, move_generator: &MoveGenerator, my_move: Move) -> String {
+    let start = my_move.get_move_start() as usize;
+    let end = my_move.get_move_end() as usize;
+    let start_sq = Square::from_usize(start);
+
+    let mut moves = MoveList::new();
+    move_generator.gen_moves(board, &mut moves);
+
+    let rivals: Vec<Square> = (0..moves.len())
+        .map(|idx| moves.at(idx))
+        .filter(|m| m.get_move_end() as usize == end && m.get_move_start() as usize != start)
+        .map(|m| m.get_move_start() as usize)
+        .filter(|&sq| board.pieces[sq] == board.pieces[start])
+        .map(Square::from_usize)
+        .collect();
+
+    let notation = start_sq.notation();
+    if rivals.is_empty() {
+        String::new()
+    } else if rivals.iter().all(|sq| sq.file() != start_sq.file()) {
+        notation[..1].to_string()
+    } else if rivals.iter().all(|sq| sq.rank() != start_sq.rank()) {
+        notation[1..].to_string()
+    } else {
+        notation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn san(fen: &str, uci: &str) -> String {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::new(fen).unwrap();
+        let before = board.clone();
+
+        let mut moves = MoveList::new();
+        move_generator.gen_moves(&mut board, &mut moves);
+        let my_move = (0..moves.len())
+            .map(|idx| moves.at(idx))
+            .find(|m| m.move_to_string() == uci)
+            .unwrap_or_else(|| panic!("{} is not legal", uci));
+
+        let san = move_to_san(&mut board, &move_generator, my_move);
+        assert!(board == before);
+        san
+    }
+
+    #[test]
+    fn pieces_and_pawns() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(san(start, "e2e4"), "e4");
+        assert_eq!(san(start, "g1f3"), "Nf3");
+
+        let exchange = "rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 0 2";
+        assert_eq!(san(exchange, "e4d5"), "exd5");
+
+        let en_passant = "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3";
+        assert_eq!(san(en_passant, "e5f6"), "exf6");
+    }
+
+    #[test]
+    fn castling_and_promotion() {
+        let castles = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(san(castles, "e1g1"), "O-O");
+        assert_eq!(san(castles, "e1c1"), "O-O-O");
+
+        let promotion = "8/P6k/8/8/8/8/8/K7 w - - 0 1";
+        assert_eq!(san(promotion, "a7a8q"), "a8=Q");
+        assert_eq!(san(promotion, "a7a8n"), "a8=N");
+    }
+
+    #[test]
+    fn disambiguation() {
+        // knights on b1 and f3 can both reach d2
+        let files = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(san(files, "b1d2"), "Nbd2");
+
+        // rooks on a1 and a5 share a file
+        let ranks = "4k3/8/8/R7/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(ranks, "a1a3"), "R1a3");
+
+        // queens on e4, h4 and h1 can all reach e1
+        let both = "8/8/1k6/8/4Q2Q/8/8/K6Q w - - 0 1";
+        assert_eq!(san(both, "h4e1"), "Qh4e1");
+    }
+
+    #[test]
+    fn check_and_mate() {
+        let check = "4k3/8/8/8/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(san(check, "a1a8"), "Ra8+");
+
+        let mate = "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1";
+        assert_eq!(san(mate, "a1a8"), "Ra8#");
+    }
+
+    fn read(fen: &str, san: &str) -> Result<String, SanError> {
+        let mut board = Board::new(fen).unwrap();
+        san_to_move(&mut board, &MoveGenerator::new(), san).map(|my_move| my_move.move_to_string())
+    }
+
+    #[test]
+    fn reading() {
+        // every move reads back from the SAN written for it
+        let move_generator = MoveGenerator::new();
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/8/1k6/8/4Q2Q/8/8/K6Q w - - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut moves = MoveList::new();
+            move_generator.gen_moves(&mut board, &mut moves);
+            for my_move in (0..moves.len()).map(|idx| moves.at(idx)) {
+                let san = move_to_san(&mut board, &move_generator, my_move);
+                assert_eq!(san_to_move(&mut board, &move_generator, &san), Ok(my_move), "{} in {}", san, fen);
+            }
+        }
+
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        assert_eq!(read(start, "Nf3!?").as_deref(), Ok("g1f3"));
+        assert_eq!(read(start, "Ngf3").as_deref(), Ok("g1f3"));
+        let castles = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        assert_eq!(read(castles, "0-0-0+").as_deref(), Ok("e1c1"));
+        let promotion = "8/P6k/8/8/8/8/8/K7 w - - 0 1";
+        assert_eq!(read(promotion, "a8Q").as_deref(), Ok("a7a8q"));
+
+        let files = "4k3/8/8/8/8/5N2/8/1N2K3 w - - 0 1";
+        assert_eq!(read(files, "Nd2"), Err(SanError::AmbiguousMove("Nd2".to_string())));
+        assert_eq!(read(start, "e5"), Err(SanError::NoMatchingMove("e5".to_string())));
+        assert_eq!(read(start, "a8=Q"), Err(SanError::NoMatchingMove("a8=Q".to_string())));
+        for bad in ["", "N", "Nz3", "Nbbd2", "é4"] {
+            assert_eq!(read(start, bad), Err(SanError::InvalidSyntax(bad.to_string())));
+        }
+    }
+}
@@ -0,0 +1,318 @@
+// `chess_rs script <file>`: runs a UCI session from a file, one command per
+// line, writing the engine's responses to stdout. This makes sessions easy
+// to repeat and to test from the shell.
+//
+// Lines starting with `#` are comments, and `sleep <ms>` pauses the script.
+// Each go (or bench) waits for its search to finish before the next line is
+// read, unless the line ends in `nowait`, as a `go infinite` or `go ponder`
+// must so that a later stop (or ponderhit) can end it. A ponderhit waits
+// too, as it turns the ponder search into one which ends on time. The
+// script ends at quit or the end of the file,
+// either of which stops any search still running.
+
+use crate::engine::config::Config;
+use crate::engine::error::{Error, ScriptError, EXIT_INVALID, EXIT_OK, EXIT_USAGE};
+use crate::engine::uci::{UciEngine, UciOutput};
+
+const USAGE: &str = "usage: chess_rs script <file>";
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Step {
+    // a UCI command, and whether to wait for the search it starts
+    Command { line: String, wait: bool },
+    Sleep(u64),
+}
+
+// the steps of a script, checked before any of them are run
+pub fn parse(script: &str) -> Result<Vec<Step>, ScriptError> {
+    let mut steps = Vec::new();
+
+    for (idx, line) in script.lines().enumerate() {
+        let line = line.trim();
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        match tokens.as_slice() {
+            [] => {}
+            [first, ..] if first.starts_with('#') => {}
+            ["sleep", rest @ ..] => match rest {
+                [millis] if millis.parse::<u64>().is_ok() => {
+                    steps.push(Step::Sleep(millis.parse().unwrap()))
+                }
+                _ => return Err(ScriptError::InvalidSleep(idx + 1, line.to_string())),
+            },
+            [command @ .., "nowait"] => steps.push(Step::Command {
+                line: command.join(" "),
+                wait: false,
+            }),
+            [first, rest @ ..] => {
+                let wait = matches!(*first, "go" | "bench" | "ponderhit");
+                if wait && (rest.contains(&"infinite") || rest.contains(&"ponder")) {
+                    return Err(ScriptError::InfiniteWait(idx + 1));
+                }
+                steps.push(Step::Command {
+                    line: line.to_string(),
+                    wait,
+                });
+            }
+        }
+    }
+
+    Ok(steps)
+}
+
+// A position of an analysis queue (the UCI extension `analysequeue
+// <file>`), as the position and go commands which analyse it
+#[derive(Clone, Debug, PartialEq)]
+pub struct QueueEntry {
+    pub position: String,
+    pub go: String,
+}
+
+// An analysis queue has a line per position, `<position> ; <go limits>`,
+// where the position is `startpos` or a fen, either followed by moves, and
+// the limits are those of a go command (eg. `movetime 1000`). Without
+// limits the position gets a plain go. Comments and blank lines are as in a
+// script, and as there every search has to finish by itself.
+pub fn parse_queue(queue: &str) -> Result<Vec<QueueEntry>, ScriptError> {
+    let mut entries = Vec::new();
+
+    for (idx, line) in queue.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (position, limits) = line.split_once(';').unwrap_or((line, ""));
+        let (position, limits) = (position.trim(), limits.trim());
+        let position = match position.split_whitespace().next() {
+            None => return Err(ScriptError::MissingPosition(idx + 1)),
+            Some("startpos") | Some("fen") => format!("position {}", position),
+            Some(_) => format!("position fen {}", position),
+        };
+
+        let limits: Vec<&str> = limits.split_whitespace().collect();
+        if limits.contains(&"infinite") || limits.contains(&"ponder") {
+            return Err(ScriptError::InfiniteWait(idx + 1));
+        }
+        let go = match limits.is_empty() {
+            true => "go".to_string(),
+            false => format!("go {}", limits.join(" ")),
+        };
+
+        entries.push(QueueEntry { position, go });
+    }
+
+    Ok(entries)
+}
+
+// runs the steps until quit or the last one, then quits
+pub fn run(engine: &mut UciEngine, steps: &[Step]) {
+    for step in steps {
+        match step {
+            Step::Command { line, wait } => {
+                if !engine.handle_command(line) {
+                    return;
+                }
+                if *wait {
+                    engine.wait_for_search();
+                }
+            }
+            Step::Sleep(millis) => std::thread::sleep(std::time::Duration::from_millis(*millis)),
+        }
+    }
+
+    engine.quit();
+}
+
+// args are those after `script`, the return value is the exit code
+pub fn script_command(args: &[String], config: &Config) -> i32 {
+    let path = match args {
+        [path] if path != "-h" && path != "--help" => path,
+        _ => {
+            eprintln!("{}", USAGE);
+            return EXIT_USAGE;
+        }
+    };
+
+    let steps = std::fs::read_to_string(path)
+        .map_err(|e| ScriptError::Io(path.clone(), e.to_string()))
+        .and_then(|script| parse(&script));
+    let steps = match steps {
+        Ok(steps) => steps,
+        Err(e) => {
+            eprintln!("{}", Error::from(e));
+            return EXIT_INVALID;
+        }
+    };
+
+    let mut engine = UciEngine::new(UciOutput::stdout());
+    engine.apply_config(config);
+    run(&mut engine, &steps);
+
+    EXIT_OK
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::uci::CapturedOutput;
+
+    fn run_script(script: &str) -> Vec<String> {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        run(&mut engine, &parse(script).unwrap());
+        captured.lines()
+    }
+
+    #[test]
+    fn queue_parsing() {
+        let entries = parse_queue(
+            "# positions\nstartpos moves e2e4 ; movetime 100\n\n  8/8/8/8/8/8/k7/4K2R w K - 0 1;depth 3 \nfen 4k3/8/8/8/8/8/8/4K2R w K - 0 1\n",
+        )
+        .unwrap();
+        let entry = |position: &str, go: &str| QueueEntry {
+            position: position.to_string(),
+            go: go.to_string(),
+        };
+        assert_eq!(
+            entries,
+            vec![
+                entry("position startpos moves e2e4", "go movetime 100"),
+                entry("position fen 8/8/8/8/8/8/k7/4K2R w K - 0 1", "go depth 3"),
+                entry("position fen 4k3/8/8/8/8/8/8/4K2R w K - 0 1", "go"),
+            ]
+        );
+
+        assert_eq!(parse_queue("startpos\n ; depth 2"), Err(ScriptError::MissingPosition(2)));
+        assert_eq!(parse_queue("startpos ; infinite"), Err(ScriptError::InfiniteWait(1)));
+    }
+
+    #[test]
+    fn parsing() {
+        let steps =
+            parse("# a comment\n\n  isready  \ngo depth 2\nsleep 50\ngo infinite nowait\nstop\n")
+                .unwrap();
+        assert_eq!(
+            steps,
+            vec![
+                Step::Command {
+                    line: "isready".to_string(),
+                    wait: false
+                },
+                Step::Command {
+                    line: "go depth 2".to_string(),
+                    wait: true
+                },
+                Step::Sleep(50),
+                Step::Command {
+                    line: "go infinite".to_string(),
+                    wait: false
+                },
+                Step::Command {
+                    line: "stop".to_string(),
+                    wait: false
+                },
+            ]
+        );
+
+        assert_eq!(
+            parse("isready\nsleep\n"),
+            Err(ScriptError::InvalidSleep(2, "sleep".to_string()))
+        );
+        assert_eq!(
+            parse("sleep soon"),
+            Err(ScriptError::InvalidSleep(1, "sleep soon".to_string()))
+        );
+        assert_eq!(
+            parse("position startpos\ngo infinite"),
+            Err(ScriptError::InfiniteWait(2))
+        );
+        assert_eq!(
+            parse("go wtime 1000 btime 1000 ponder"),
+            Err(ScriptError::InfiniteWait(1))
+        );
+    }
+
+    #[test]
+    fn analyse() {
+        let lines = run_script(include_str!("../../scripts/analyse.uci"));
+
+        // every search finishes before the next position is set up
+        let bestmoves: Vec<&String> = lines
+            .iter()
+            .filter(|line| line.starts_with("bestmove "))
+            .collect();
+        assert_eq!(bestmoves.len(), 3, "{:?}", lines);
+        assert!(bestmoves[1].starts_with("bestmove d1d5"), "{}", bestmoves[1]);
+        assert_eq!(lines.iter().filter(|line| *line == "readyok").count(), 1);
+        // followed by the report from quit, of the last position, which
+        // was taken to be a new game
+        let report = lines.iter().position(|line| line.starts_with("info string game summary: 1 searches"));
+        assert_eq!(report.map(|report| &lines[report - 1]), Some(bestmoves[2]), "{:?}", lines);
+    }
+
+    #[test]
+    fn infinite() {
+        let lines = run_script(include_str!("../../scripts/infinite.uci"));
+
+        // searched until the stop, then nothing after quit but the report
+        assert!(
+            lines.iter().any(|line| line.starts_with("info score ")),
+            "{:?}",
+            lines
+        );
+        let moved = lines.iter().rev().find(|line| !line.starts_with("info string "));
+        assert!(
+            moved.unwrap().starts_with("bestmove "),
+            "{:?}",
+            lines
+        );
+    }
+
+    #[test]
+    fn ponder() {
+        let lines = run_script(include_str!("../../scripts/ponder.uci"));
+        let find = |prefix: &str| {
+            lines
+                .iter()
+                .enumerate()
+                .filter(|(_, line)| line.starts_with(prefix))
+                .map(|(i, _)| i)
+                .collect::<Vec<_>>()
+        };
+
+        // searching while pondering, but the move only comes after the
+        // ponderhit, once the clock has run
+        let (readyok, bestmove) = (find("readyok"), find("bestmove "));
+        assert_eq!((readyok.len(), bestmove.len()), (2, 1), "{:?}", lines);
+        assert!(find("info score ")[0] < readyok[0], "{:?}", lines);
+        assert!(readyok[0] < bestmove[0] && bestmove[0] < readyok[1], "{:?}", lines);
+
+        // with a reply to ponder on next
+        let tokens: Vec<&str> = lines[bestmove[0]].split_whitespace().collect();
+        assert_eq!((tokens.len(), tokens[2]), (4, "ponder"), "{:?}", tokens);
+    }
+
+    #[test]
+    fn perft() {
+        let lines = run_script(include_str!("../../scripts/perft.uci"));
+
+        assert_eq!(
+            lines
+                .iter()
+                .filter(|line| line.starts_with("Nodes searched: "))
+                .count(),
+            2
+        );
+        assert!(
+            lines.contains(&"Nodes searched: 8902".to_string()),
+            "{:?}",
+            lines
+        );
+        assert!(
+            lines.contains(&"Nodes searched: 191".to_string()),
+            "{:?}",
+            lines
+        );
+    }
+}
@@ -1,83 +1,970 @@
+use std::cmp::Reverse;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
 use crate::engine::board::Board;
-use crate::engine::eval::Evaluator;
+use crate::engine::control::{ControlHandle, ProgressEvent};
+use crate::engine::eval::Evaluate;
 use crate::engine::movegen::{MoveGenerator, MoveList};
-use crate::engine::r#move::{Move, MoveUtils, UndoInfo};
+use crate::engine::movepick::{History, MovePicker};
+use crate::engine::r#move::{
+    Move, MoveUtils, UndoInfo, MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT, MOVE_TYPE_PROMOTION, NULL_MOVE,
+};
+use crate::engine::syzygy::{Tablebases, Wdl};
+use crate::engine::tt::{Bound, TranspositionTable};
+use crate::engine::tune::Weights;
 
 extern crate time;
 use time::{Duration, Instant};
 
-pub fn negamax(
+// how often (in nodes) the stop flag is checked during a search. With a
+// deadline the clock is checked as often, or more often when it is close.
+const STOP_CHECK_INTERVAL: u64 = 1024;
+const MIN_CHECK_INTERVAL: u64 = 16;
+
+// the search threads the UCI Threads option allows
+pub const DEFAULT_THREADS: usize = 1;
+pub const MAX_THREADS: usize = 64;
+
+// the root moves the UCI MultiPV option allows reporting lines for
+pub const DEFAULT_MULTI_PV: usize = 1;
+pub const MAX_MULTI_PV: usize = 256;
+
+// refutations are reported after an iteration at most this often, and for
+// the last iteration to complete
+const REFUTATION_INTERVAL_MILLIS: i64 = 1000;
+
+// the score of a position the tablebases say is won, less the ply it was
+// found at. Above any evaluation, but below a checkmate.
+pub const TB_WIN: i32 = 1_000_000;
+
+// the score of giving checkmate, less the ply it happens at, so that the
+// quickest mate scores best and the slowest way to be mated is preferred
+pub const MATE_SCORE: i32 = 2_000_000;
+
+// scores closer than this to MATE_SCORE are mates, and further from it
+// can't be reached with a search path this long
+pub const MAX_MATE_PLY: i32 = 1_000;
+
+pub fn is_mate_score(score: i32) -> bool {
+    score.abs() > MATE_SCORE - MAX_MATE_PLY
+}
+
+// The moves until mate, as the UCI "score mate" wants them: positive if
+// the side the score is for gives mate, negative if it is mated.
+pub fn mate_in(score: i32) -> Option<i32> {
+    if !is_mate_score(score) {
+        return None;
+    }
+
+    let moves = (MATE_SCORE - score.abs() + 1) / 2;
+    Some(if score > 0 { moves } else { -moves })
+}
+
+// a static score at least this good for either side is checked for the
+// side to move having no moves
+const DECISIVE_SCORE: i32 = 400;
+
+// Passing is searched this many plies shallower than a move would be, at
+// nodes with at least NULL_MOVE_MIN_DEPTH plies left. At
+// NULL_MOVE_VERIFY_DEPTH or more a cutoff is checked by searching the node
+// itself to the reduced depth, without passing. On the bench positions at
+// depth 7 passing cuts the nodes searched from 58.7M to 38.0M, and the
+// verification searches add 0.4% back (38,021,516 to 38,176,420); at depth
+// 6 they made no difference.
+const NULL_MOVE_REDUCTION: usize = 2;
+const NULL_MOVE_MIN_DEPTH: usize = 3;
+const NULL_MOVE_VERIFY_DEPTH: usize = 5;
+
+// a legal move at the root, with its score and the best line for the
+// opponent after it from the last iteration to search it
+#[derive(Clone)]
+struct RootMove {
+    root_move: Move,
+    score: i32,
+    replies: Vec<Move>,
+}
+
+// one of the best lines from the root, for MultiPV
+#[derive(Clone, Debug, PartialEq)]
+pub struct PvLine {
+    pub score: i32,
+    // starting with the root move
+    pub pv: Vec<Move>,
+}
+
+// What the score of an interior node says about its value, from where it
+// fell against the window it was searched with. The table stores each as
+// the matching bound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeType {
+    // inside the window, so exact
+    Pv,
+    // failed high: a move refuted the line, so the value is at least this
+    Cut,
+    // failed low: no move reached alpha, so the value is at most this
+    All,
+}
+
+impl NodeType {
+    pub fn classify(score: i32, alpha: i32, beta: i32) -> NodeType {
+        if score <= alpha {
+            NodeType::All
+        } else if score >= beta {
+            NodeType::Cut
+        } else {
+            NodeType::Pv
+        }
+    }
+
+    pub fn bound(self) -> Bound {
+        match self {
+            NodeType::Pv => Bound::Exact,
+            NodeType::Cut => Bound::Lower,
+            NodeType::All => Bound::Upper,
+        }
+    }
+}
+
+// Why an iterative deepening search ended. When several limits are given
+// the first one reached ends the search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    // every iteration up to the depth limit finished
+    Depth,
+    // the hard time limit ran out, or the soft one left too little time to
+    // start another iteration
+    Time,
+    Nodes,
+    // told to stop
+    Stopped,
+    // a mate search found one
+    Mate,
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            StopReason::Depth => "depth",
+            StopReason::Time => "time",
+            StopReason::Nodes => "nodes",
+            StopReason::Stopped => "stop",
+            StopReason::Mate => "mate",
+        };
+        write!(f, "{}", reason)
+    }
+}
+
+// How well the move ordering did in one iteration of iterative deepening.
+// Only plain counters are kept while searching, the rates are worked out
+// when asked for.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct IterationStats {
+    pub depth: usize,
+    // the nodes visited by this iteration, and by the one before it (0 if
+    // this is the first)
+    pub nodes: u64,
+    pub previous_nodes: u64,
+    // beta cutoffs, how many were caused by the first move searched, and the
+    // sum of the indices of the moves which caused them
+    pub cutoffs: u64,
+    pub first_move_cutoffs: u64,
+    pub cutoff_index_total: u64,
+    // the interior nodes searched to the end, by type
+    pub pv_nodes: u64,
+    pub cut_nodes: u64,
+    pub all_nodes: u64,
+}
+
+impl IterationStats {
+    // how many times bigger this iteration was than the last
+    pub fn branching_factor(&self) -> Option<f64> {
+        match self.previous_nodes {
+            0 => None,
+            previous => Some(self.nodes as f64 / previous as f64),
+        }
+    }
+
+    // the percentage of cutoffs caused by the first move searched
+    pub fn first_move_cutoff_rate(&self) -> f64 {
+        if self.cutoffs == 0 {
+            0.0
+        } else {
+            self.first_move_cutoffs as f64 * 100.0 / self.cutoffs as f64
+        }
+    }
+
+    // the average position of a move causing a cutoff, 0 meaning it is
+    // always the first move tried
+    pub fn average_cutoff_index(&self) -> f64 {
+        if self.cutoffs == 0 {
+            0.0
+        } else {
+            self.cutoff_index_total as f64 / self.cutoffs as f64
+        }
+    }
+}
+
+// How the search uses null move pruning, see null_move_cutoff
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NullMove {
+    Off,
+    // cutoffs are trusted without a verification search
+    Unverified,
+    #[default]
+    Verified,
+}
+
+// state carried down the search recursion
+#[derive(Default)]
+pub struct SearchContext {
+    // hashes of the positions on the current search path, one per ply
+    path: Vec<u64>,
+    // hashes of the positions the game reached before the root which can
+    // still repeat, see is_repetition
+    root_history: Vec<u64>,
+    // the moves from the root to the current node
+    line: Vec<Move>,
+    // pv[ply] is the best line found from the node at that ply of the
+    // current path, filled in as the search returns
+    pv: Vec<Vec<Move>>,
+    // how often the line being searched is reported, if at all
+    currline_interval: Option<Duration>,
+    next_currline: Option<Instant>,
+    // how many of the best lines are reported after each iteration, 0
+    // meaning only the best as for 1
+    multi_pv: usize,
+    nodes: u64,
+    // set from another thread to abandon the search part way through
+    stop: Option<Arc<AtomicBool>>,
+    // set while pondering, before the clock starts
+    pondering: Option<Arc<AtomicBool>>,
+    // when the clock started, and the time from then until the search is
+    // abandoned
+    clock_start: Option<Instant>,
+    hard_time: Option<Duration>,
+    deadline: Option<Instant>,
+    // stop after visiting this many nodes
+    node_limit: Option<u64>,
+    // the nodes between checks of the stop flag and the clock, 0 meaning
+    // STOP_CHECK_INTERVAL, kept between searches. It is halved when the
+    // nodes since the last check took more than half the time left, and
+    // doubled again when they took much less.
+    check_interval: u64,
+    next_check: u64,
+    last_check: Option<Instant>,
+    // the interval which ended at the last check of the clock
+    last_check_interval: u64,
+    aborted: bool,
+    stop_reason: Option<StopReason>,
+    // the legal moves at the root with their scores from the last completed
+    // iteration, best first
+    root_moves: Vec<RootMove>,
+    // if any, the only root moves searched, as for UCI searchmoves
+    search_moves: Vec<Move>,
+    // if set, the searches only look for a mate in this many moves, anything
+    // short of one failing low
+    mate_search: Option<usize>,
+    // the root move being searched
+    root_index: usize,
+    // beta cutoffs, and the sum of the indices of the moves which caused
+    // them, to measure how good the move ordering is
+    cutoffs: u64,
+    cutoff_index_total: u64,
+    // the counters for the iteration being searched, and those of every
+    // iteration completed since the search started
+    iteration: IterationStats,
+    stats: Vec<IterationStats>,
+    tablebases: Option<Arc<Tablebases>>,
+    tb_hits: u64,
+    // looks in the transposition table, and those which found the position
+    tt_probes: u64,
+    tt_hits: u64,
+    // kept between searches, so the next one starts with what this found,
+    // and shared with the helper threads
+    tt: Arc<TranspositionTable>,
+    // a helper thread's context, which leaves aging the table to the main
+    // thread
+    helper: bool,
+    // killers[ply] holds the last two quiet moves which caused a cutoff at
+    // that ply, newest first. A move refuting one line often refutes its
+    // siblings too.
+    killers: Vec<[Option<Move>; 2]>,
+    // the quiet moves which caused cutoffs, kept between searches
+    history: History,
+    // for looking for legal moves at the horizon
+    leaf_moves: MoveList,
+    // set when the score negamax just returned rests on a repetition draw,
+    // which depends on the path to the node and so can't go in the table
+    repetition_dependent: bool,
+    null_move: NullMove,
+    // leaves the child's table entry to load when it is probed, to measure
+    // what prefetching it gains
+    no_prefetch: bool,
+    // set while verifying a null move cutoff, when no side passes
+    verifying: bool,
+    // stores those scores anyway, to measure what goes wrong
+    store_repetition_scores: bool,
+    // tries passing in pawn endings too, to measure what goes wrong
+    null_move_without_pieces: bool,
+}
+
+impl SearchContext {
+    pub fn new() -> SearchContext {
+        SearchContext::default()
+    }
+
+    // the number of nodes visited since the current (or last) search started
+    pub fn nodes(&self) -> u64 {
+        self.nodes
+    }
+
+    // the average position in the move list of a move causing a beta cutoff,
+    // 0 meaning it is always the first move tried
+    pub fn average_cutoff_index(&self) -> f64 {
+        if self.cutoffs == 0 {
+            0.0
+        } else {
+            self.cutoff_index_total as f64 / self.cutoffs as f64
+        }
+    }
+
+    // the move ordering statistics of each completed iteration, shallowest
+    // first
+    pub fn stats(&self) -> &[IterationStats] {
+        &self.stats
+    }
+
+    // the root moves in the order the next iteration will search them
+    pub fn root_moves(&self) -> impl Iterator<Item = Move> + '_ {
+        self.root_moves.iter().map(|root| root.root_move)
+    }
+
+    // the best line from the last completed iteration
+    pub fn pv(&self) -> Vec<Move> {
+        match self.root_moves.first() {
+            Some(best) => std::iter::once(best.root_move).chain(best.replies.iter().copied()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // The best count root moves from the last completed iteration, best
+    // first, each with the line expected after it. Every root move is
+    // searched with a full window, so all of the scores are exact, except in
+    // a mate search where only the mates are and the rest are left out.
+    pub fn best_lines(&self, count: usize) -> Vec<PvLine> {
+        self.root_moves
+            .iter()
+            .filter(|root| self.is_exact(root.score))
+            .take(count)
+            .map(|root| PvLine {
+                score: root.score,
+                pv: std::iter::once(root.root_move).chain(root.replies.iter().copied()).collect(),
+            })
+            .collect()
+    }
+
+    // The root moves the last completed iteration found worse than the best
+    // one, each with the line which refutes it.
+    pub fn refutations(&self) -> impl Iterator<Item = (Move, &[Move])> + '_ {
+        let best = self.root_moves.first().map_or(i32::MIN, |best| best.score);
+        self.root_moves
+            .iter()
+            .filter(move |root| root.score < best && self.is_exact(root.score) && !root.replies.is_empty())
+            .map(|root| (root.root_move, root.replies.as_slice()))
+    }
+
+    // report the line being searched every interval, or never if None
+    pub fn set_currline_interval(&mut self, interval: Option<Duration>) {
+        self.currline_interval = interval;
+    }
+
+    // the number of lines reported after each iteration
+    pub fn set_multi_pv(&mut self, count: usize) {
+        self.multi_pv = count;
+    }
+
+    // positions found in the tablebases since the search started
+    pub fn tb_hits(&self) -> u64 {
+        self.tb_hits
+    }
+
+    // the number of positions the table has room for
+    pub fn tt_entries(&self) -> usize {
+        self.tt.len()
+    }
+
+    // looks in the transposition table since the search started, and how
+    // many found the position
+    pub fn tt_probes(&self) -> u64 {
+        self.tt_probes
+    }
+    pub fn tt_hits(&self) -> u64 {
+        self.tt_hits
+    }
+
+    // the table kept between searches, eg. to save or load it
+    pub fn tt(&self) -> &TranspositionTable {
+        &self.tt
+    }
+
+    // forget every position searched, as for a new game
+    pub fn clear_tt(&mut self) {
+        self.tt.clear();
+    }
+
+    // forget which quiet moves caused cutoffs, as for a new game
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    // replaces the table with an empty one of this many entries, 0 turning
+    // it off
+    pub fn resize_tt(&mut self, entries: usize) {
+        self.tt = Arc::new(TranspositionTable::new(entries));
+    }
+
+    // a context for a helper thread, searching the same position with the
+    // same table
+    fn helper(&self) -> SearchContext {
+        SearchContext {
+            tt: Arc::clone(&self.tt),
+            helper: true,
+            tablebases: self.tablebases.clone(),
+            search_moves: self.search_moves.clone(),
+            mate_search: self.mate_search,
+            ..SearchContext::default()
+        }
+    }
+
+    pub fn set_tablebases(&mut self, tablebases: Option<Arc<Tablebases>>) {
+        self.tablebases = tablebases;
+    }
+
+    pub fn tablebases(&self) -> Option<&Tablebases> {
+        self.tablebases.as_deref()
+    }
+
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = Some(stop);
+    }
+
+    // the searches from now on only look at these root moves, or at every
+    // one if there are none
+    pub fn set_search_moves(&mut self, moves: Vec<Move>) {
+        self.search_moves = moves;
+    }
+
+    // The searches from now on only look for a mate in this many moves, or
+    // for the best move if None. The root moves are searched with a window
+    // just wide enough for such mates, which is much quicker than a full
+    // one, and the search stops at the first iteration to find one.
+    pub fn set_mate_search(&mut self, moves: Option<usize>) {
+        self.mate_search = moves;
+    }
+
+    // the window the root moves are searched with
+    fn root_window(&self) -> (i32, i32) {
+        match self.mate_search {
+            // mate in n is delivered on ply 2n - 1
+            Some(moves) => (MATE_SCORE - 2 * (moves as i32).min(MAX_MATE_PLY), MATE_SCORE),
+            None => (-MATE_SCORE, MATE_SCORE),
+        }
+    }
+
+    // whether a root move's score is exact rather than a bound, which in a
+    // mate search means it is a mate
+    pub fn is_exact(&self, score: i32) -> bool {
+        score > self.root_window().0
+    }
+
+    // the searches from now on stop once they pass this many nodes, or
+    // only when told to if None
+    pub fn set_node_limit(&mut self, limit: Option<u64>) {
+        self.node_limit = limit;
+    }
+
+    // true if the last search was stopped before it finished, in which case
+    // its result should not be trusted
+    pub fn aborted(&self) -> bool {
+        self.aborted
+    }
+
+    // how the searches from now on use null move pruning
+    pub fn set_null_move(&mut self, null_move: NullMove) {
+        self.null_move = null_move;
+    }
+
+    // whether negamax starts loading each child's table entry before making
+    // the move, which it does unless turned off
+    pub fn set_tt_prefetch(&mut self, prefetch: bool) {
+        self.no_prefetch = !prefetch;
+    }
+
+    // whether scores resting on a repetition draw go in the table, which
+    // they don't unless turned on, as they are only true of the path
+    pub fn set_store_repetition_scores(&mut self, store: bool) {
+        self.store_repetition_scores = store;
+    }
+
+    // whether a side with only pawns left may pass for null move pruning,
+    // which it can't unless turned on, as zugzwang is common there
+    pub fn set_null_move_without_pieces(&mut self, allowed: bool) {
+        self.null_move_without_pieces = allowed;
+    }
+
+    // why the last iterative deepening search ended, once it has
+    pub fn stop_reason(&self) -> Option<StopReason> {
+        self.stop_reason
+    }
+
+    // the nodes searched between checks of the clock
+    pub fn check_interval(&self) -> u64 {
+        match self.check_interval {
+            0 => STOP_CHECK_INTERVAL,
+            interval => interval,
+        }
+    }
+
+    // the nodes searched before the last check of the clock, the longest
+    // the search could have run past its deadline unnoticed
+    pub fn last_check_interval(&self) -> u64 {
+        match self.last_check_interval {
+            0 => self.check_interval(),
+            interval => interval,
+        }
+    }
+
+    // the milliseconds since the clock started, if it has
+    pub fn clock_millis(&self) -> Option<usize> {
+        self.clock_start.map(|start| (Instant::now() - start).whole_milliseconds() as usize)
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.aborted {
+            return true;
+        }
+
+        // the node count is checked every time, as it costs nothing
+        if self.node_limit.is_some_and(|limit| self.nodes > limit) {
+            self.abort(StopReason::Nodes);
+        }
+        if self.nodes >= self.next_check {
+            let interval = self.check_interval();
+            self.next_check = self.nodes + interval;
+            if self.stop.as_ref().is_some_and(|stop| stop.load(Ordering::Relaxed)) {
+                self.abort(StopReason::Stopped);
+            }
+            self.start_clock();
+            if let Some(deadline) = self.deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    self.abort(StopReason::Time);
+                }
+                self.adapt_check_interval(now, deadline);
+            }
+        }
+
+        self.aborted
+    }
+
+    // Starts the clock, unless it is already running or the search is still
+    // pondering, in which case it starts once the ponder flag is cleared.
+    fn start_clock(&mut self) {
+        let pondering = self.pondering.as_ref().is_some_and(|ponder| ponder.load(Ordering::Relaxed));
+        if self.clock_start.is_some() || pondering {
+            return;
+        }
+
+        let now = Instant::now();
+        self.clock_start = Some(now);
+        self.deadline = self.hard_time.map(|time| now + time);
+    }
+
+    // the first limit reached is the reason the search ended
+    fn abort(&mut self, reason: StopReason) {
+        if !self.aborted {
+            self.aborted = true;
+            self.stop_reason = Some(reason);
+        }
+    }
+
+    // Checks the clock more often when the nodes since the last check took
+    // long enough that as many again would run past the deadline, and less
+    // often again once it is far off.
+    fn adapt_check_interval(&mut self, now: Instant, deadline: Instant) {
+        let interval = self.check_interval();
+        if let Some(last) = self.last_check {
+            let took = now - last;
+            let left = deadline - now;
+            if left < took * 2 {
+                self.check_interval = (interval / 2).max(MIN_CHECK_INTERVAL);
+            } else if left > took * 16 {
+                self.check_interval = (interval * 2).min(STOP_CHECK_INTERVAL);
+            }
+            self.next_check = self.nodes + self.check_interval();
+        }
+        self.last_check = Some(now);
+        self.last_check_interval = interval;
+    }
+
+    // true if the current line is due to be reported, checked as often as
+    // the stop flag
+    fn currline_due(&mut self) -> bool {
+        let interval = match self.currline_interval {
+            Some(interval) if self.nodes.is_multiple_of(STOP_CHECK_INTERVAL) => interval,
+            _ => return false,
+        };
+
+        let now = Instant::now();
+        match self.next_currline {
+            Some(next) if now < next => false,
+            _ => {
+                self.next_currline = Some(now + interval);
+                true
+            }
+        }
+    }
+
+    // the best line from the node at this ply is the move followed by the
+    // best line from its child
+    fn update_pv(&mut self, ply: usize, my_move: Move) {
+        let (lines, children) = self.pv.split_at_mut(ply + 1);
+        let line = &mut lines[ply];
+        line.clear();
+        line.push(my_move);
+        line.extend_from_slice(&children[0]);
+    }
+
+    // The move the last iteration found best here, if the line searched so
+    // far is the one it expected, which is likely to be best again. It is
+    // tried before any moves are generated.
+    fn previous_best(&self) -> Option<Move> {
+        let replies = &self.root_moves.get(self.root_index)?.replies;
+        let played = self.line.get(1..)?;
+
+        match replies.get(played.len()) {
+            Some(&my_move) if replies.starts_with(played) => Some(my_move),
+            _ => None,
+        }
+    }
+
+    fn killers(&self, ply: usize) -> [Option<Move>; 2] {
+        self.killers.get(ply).copied().unwrap_or_default()
+    }
+
+    fn store_killer(&mut self, ply: usize, my_move: Move) {
+        if self.killers.len() <= ply {
+            self.killers.resize(ply + 1, [None; 2]);
+        }
+
+        let killers = &mut self.killers[ply];
+        if killers[0] != Some(my_move) {
+            killers[1] = killers[0];
+            killers[0] = Some(my_move);
+        }
+    }
+
+    // Whether a position below the root is scored as a draw by repetition.
+    // One earlier occurrence is enough, but those on the search path and
+    // those in the game before the root are counted separately:
+    //
+    // - a position first reached in the search has to occur twice on the
+    //   path, and whoever went back to it could do so again
+    // - a position the game reached before the root is already on its
+    //   second occurrence the first time the search reaches it, so either
+    //   side can force the third
+    //
+    // The root itself is never scored this way, as the engine has to play a
+    // move from it whatever the history.
+    fn is_repetition(&self, board: &Board) -> bool {
+        self.is_path_repetition(board) || self.repeats_root_history(board)
+    }
+
+    // true if the position occurred earlier in the search path with the same
+    // side to move, and no irreversible move has been made since
+    fn is_path_repetition(&self, board: &Board) -> bool {
+        let hash = board.hash();
+
+        self.path
+            .iter()
+            .rev()
+            .take(board.fifty_move)
+            .skip(1)
+            .step_by(2)
+            .any(|&ancestor| ancestor == hash)
+    }
+
+    // true if the game reached the position before the root, and no
+    // irreversible move has been made since the root
+    fn repeats_root_history(&self, board: &Board) -> bool {
+        board.fifty_move > self.path.len() && self.root_history.contains(&board.hash())
+    }
+
+    // The score of the position from the tablebases, if it is in them.
+    // Only positions straight after a capture or pawn move are probed, as
+    // the tables don't know how many moves are left before a fifty move
+    // draw otherwise.
+    fn probe_tablebases(&mut self, board: &mut Board, move_generator: &MoveGenerator) -> Option<i32> {
+        let tablebases = self.tablebases.as_ref()?;
+        if board.fifty_move != 0 || !tablebases.can_probe(board) {
+            return None;
+        }
+        let wdl = tablebases.probe_wdl(board, move_generator)?;
+
+        self.tb_hits += 1;
+        let ply = self.path.len() as i32;
+        Some(match wdl {
+            Wdl::Win => TB_WIN - ply,
+            Wdl::Loss => ply - TB_WIN,
+            _ => 0,
+        })
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn negamax<E: Evaluate>(
     depth: usize,
     mut alpha: i32,
     beta: i32,
     board: &mut Board,
-    evaluator: &mut Evaluator,
+    evaluator: &mut E,
     move_generator: &MoveGenerator,
     move_lists: &mut Vec<MoveList>,
+    control: &mut ControlHandle,
+    context: &mut SearchContext,
 ) -> i32 {
+    context.nodes += 1;
+
+    // nothing is known about the line from here yet
+    let ply = context.line.len();
+    if context.pv.len() <= ply {
+        context.pv.resize_with(ply + 1, Vec::new);
+    }
+    context.pv[ply].clear();
+
+    if context.currline_due() {
+        control.report(ProgressEvent::CurrentLine {
+            line: context.line.clone(),
+        });
+    }
+
+    // the result is thrown away, so the score doesn't matter
+    if context.should_stop() {
+        return 0;
+    }
+
+    // a repeated position is a draw, whatever else is going on. Returning
+    // early stops lines like perpetual check from recursing to full depth.
+    if context.is_repetition(board) {
+        context.repetition_dependent = true;
+        return 0;
+    }
+
+    // lost to a variant's rule by the last move
+    if board.variant_state().is_some() {
+        return ply as i32 - MATE_SCORE;
+    }
+
+    if let Some(score) = context.probe_tablebases(board, move_generator) {
+        return score;
+    }
+
+    // Mate distance pruning: nothing here scores better than giving mate
+    // next move, or worse than being mated now, so once a quicker mate has
+    // been found elsewhere the node can't change the result.
+    alpha = alpha.max(ply as i32 - MATE_SCORE);
+    let beta = beta.min(MATE_SCORE - ply as i32 - 1);
+    if alpha >= beta {
+        return alpha;
+    }
+
     if depth == 0 {
-        evaluator.score(board.friendly_color())
+        // Far enough ahead to win, the stronger side is close to
+        // stalemating the other, which the evaluation can't see. So a
+        // decisive leaf is checked for having no moves, stalemate being a
+        // draw and checkmate a mate like anywhere else.
+        let score = evaluator.evaluate(board);
+        if score.abs() >= DECISIVE_SCORE {
+            let checkers = move_generator.gen_moves(board, &mut context.leaf_moves).checkers;
+            if context.leaf_moves.is_empty() {
+                return if checkers == 0 { 0 } else { ply as i32 - MATE_SCORE };
+            }
+        }
+        score
     } else {
-        let mut best = i32::MIN + 1;
+        let mut best = -MATE_SCORE;
+        let mut best_move = None;
+        let original_alpha = alpha;
+        // whether the best score, or any score, came from a line ending in
+        // a repetition
+        let mut best_dependent = false;
+        let mut any_dependent = false;
+
+        // The root's children are always searched, so that every root move
+        // has a line to report. The table's score is the same after a draw
+        // by the fifty move rule as before, so it isn't trusted near one.
+        let hash = board.hash();
+        let tt_entry = context.tt.probe(hash, ply);
+        context.tt_probes += 1;
+        if let Some(entry) = tt_entry {
+            context.tt_hits += 1;
+            if ply > 1 && board.fifty_move < 90 {
+                if let Some(score) = entry.cutoff(depth, alpha, beta) {
+                    // the line stops here, but its next move is known
+                    if let (Bound::Exact, Some(my_move)) = (entry.bound, entry.best_move) {
+                        if move_generator.is_pseudo_legal(board, my_move)
+                            && !board.leaves_king_in_check(my_move, move_generator)
+                        {
+                            context.pv[ply].push(my_move);
+                        }
+                    }
+                    return score;
+                }
+            }
+        }
 
-        // generate and order the moves
-        move_generator.gen_moves(board, &mut move_lists[depth - 1]);
-        move_lists[depth - 1].order_moves(board);
+        if let Some(score) = null_move_cutoff(depth, beta, board, evaluator, move_generator, move_lists, control, context) {
+            return score;
+        }
 
-        let num_moves = move_lists[depth - 1].len();
+        // Moves are only generated if the first one doesn't cut off. With no
+        // quiescence search the replies to captures at the horizon are never
+        // seen, so captures aren't split by whether they lose material:
+        // doing that made the search of the bench positions five times
+        // bigger. The best move from the last iteration is tried first if
+        // this is on its line, otherwise the table's. The killers come after
+        // the captures.
+        let tt_move = context.previous_best().or_else(|| tt_entry.and_then(|entry| entry.best_move));
+        let mut picker = MovePicker::new(tt_move)
+            .with_split_captures(false)
+            .with_refutations(context.killers(ply), None);
+        let mut next_move = picker.next(board, move_generator, &mut move_lists[depth - 1], &context.history);
 
-        // check for end of game
-        if num_moves == 0 {
-            // check for stalemate
-            if !move_generator.is_in_check(board) {
-                best = 0;
+        // Check for end of game. A draw is returned before the table is
+        // written: the same position reached with a different fifty move
+        // count or history isn't drawn, so an exact draw score stored here
+        // would cut off searches it isn't true for.
+        if next_move.is_none() {
+            // stalemate, or checkmate this many plies from the root
+            if picker.checkers() == Some(0) {
+                return 0;
             }
-
-            // otherwise loss
+            best = ply as i32 - MATE_SCORE;
         }
         // fifty move / low material / threefold repetition
         else if board.is_draw() {
-            best = 0;
+            context.repetition_dependent = board.is_threefold_repetition();
+            return 0;
         } else {
             // continue search
             let mut info = UndoInfo::default();
 
-            for i in 0..num_moves {
-                let my_move = move_lists[depth - 1].at(i);
+            context.path.push(board.hash());
+
+            let mut i = 0;
+            while let Some(my_move) = next_move {
+
+                // the child's table entry loads while the move is made and
+                // the evaluation updated. Leaves don't look in the table.
+                if depth > 1 && !context.no_prefetch {
+                    context.tt.prefetch(board.hash_after(my_move));
+                }
 
                 // do the move
                 board.make_move(my_move, &mut info);
 
                 // update evaluation
-                evaluator.update_score(info.evalutor_diff);
+                evaluator.on_make(board, my_move, &info);
 
                 // test the move
-                best = std::cmp::max(
-                    best,
-                    -negamax(
-                        depth - 1,
-                        -beta,
-                        -alpha,
-                        board,
-                        evaluator,
-                        move_generator,
-                        move_lists,
-                    ),
+                context.line.push(my_move);
+                context.repetition_dependent = false;
+                let score = -negamax(
+                    depth - 1,
+                    -beta,
+                    -alpha,
+                    board,
+                    evaluator,
+                    move_generator,
+                    move_lists,
+                    control,
+                    context,
                 );
+                context.line.pop();
+                any_dependent |= context.repetition_dependent;
+
+                if score > best {
+                    best = score;
+                    best_move = Some(my_move);
+                    best_dependent = context.repetition_dependent;
+                }
 
-                // update alpha
-                alpha = std::cmp::max(alpha, best);
+                // update alpha, and the best line if the move raised it
+                if score > alpha {
+                    alpha = score;
+                    context.update_pv(ply, my_move);
+                }
 
                 // undo changes
                 board.undo_move(my_move, &info);
 
                 // reset evaluation
-                evaluator.update_score(-info.evalutor_diff);
+                evaluator.on_undo(board, my_move, &info);
+
+                // the scores mean nothing now, so don't search the rest
+                if context.aborted {
+                    break;
+                }
 
                 // alpha/beta cut-off
                 if alpha >= beta {
+                    if is_quiet(board, my_move) {
+                        context.store_killer(ply, my_move);
+                        context.history.update(board.friendly_color(), my_move, depth);
+                    }
+                    context.cutoffs += 1;
+                    context.cutoff_index_total += i as u64;
+                    context.iteration.cutoffs += 1;
+                    context.iteration.first_move_cutoffs += (i == 0) as u64;
+                    context.iteration.cutoff_index_total += i as u64;
                     break;
                 }
+
+                i += 1;
+                next_move = picker.next(board, move_generator, &mut move_lists[depth - 1], &context.history);
+            }
+
+            context.path.pop();
+        }
+
+        // A fail high only rests on the move which caused it, but an exact
+        // score or fail low rests on every move: one scored as a draw by
+        // repetition might be better reached another way. A score resting
+        // on a repetition is true of this path to the node and no other, so
+        // it isn't stored, and it taints the nodes above it in turn.
+        let node_type = NodeType::classify(best, original_alpha, beta);
+        let dependent = match node_type {
+            NodeType::Cut => best_dependent,
+            NodeType::Pv | NodeType::All => any_dependent,
+        };
+        context.repetition_dependent = dependent;
+        let dependent = dependent && !context.store_repetition_scores;
+
+        // a stopped search leaves scores which mean nothing
+        if !context.aborted {
+            match node_type {
+                NodeType::Pv => context.iteration.pv_nodes += 1,
+                NodeType::Cut => context.iteration.cut_nodes += 1,
+                NodeType::All => context.iteration.all_nodes += 1,
+            }
+            if !dependent {
+                context.tt.store(hash, ply, depth, best, node_type.bound(), best_move);
+            } else if tt_entry.is_none() {
+                // the move is still worth trying first, so it is kept at a
+                // depth which never gives a cutoff
+                context.tt.store(hash, ply, 0, best, Bound::Upper, best_move);
             }
         }
 
@@ -85,108 +972,1577 @@ pub fn negamax(
     }
 }
 
-pub fn find_best_move(
-    max_depth: usize,
+// neither a capture nor a promotion, so ordered by the killers and history
+// rather than by what it wins
+fn is_quiet(board: &Board, my_move: Move) -> bool {
+    match my_move.get_move_type() {
+        MOVE_TYPE_EN_PASSANT | MOVE_TYPE_PROMOTION => false,
+        MOVE_TYPE_CASTLE => true,
+        _ => board.pieces[my_move.get_move_end() as usize].is_none(),
+    }
+}
+
+// Null move pruning: if the side to move could pass and a search to a
+// reduced depth still scores at least beta, a real move would almost
+// certainly do as well, so the node fails high without searching any.
+// That is wrong in zugzwang, where every move makes things worse, so:
+//
+// - the side to move has to have a piece, as zugzwang is common in pawn
+//   endings and rare otherwise
+// - deep in the tree, where a wrong cutoff throws away the most, the cutoff
+//   is only trusted once a search of the node to the reduced depth, with no
+//   passing anywhere below, agrees
+//
+// Passing is never tried at the root, twice in a row, in check, or when
+// beta is a mate or tablebase score. Returns beta on a cutoff.
+#[allow(clippy::too_many_arguments)]
+fn null_move_cutoff<E: Evaluate>(
+    depth: usize,
+    beta: i32,
     board: &mut Board,
-    evaluator: &mut Evaluator,
+    evaluator: &mut E,
     move_generator: &MoveGenerator,
     move_lists: &mut Vec<MoveList>,
-) -> Option<(Move, i32)> {
-    // setup evaluator
-    evaluator.init_score(board);
+    control: &mut ControlHandle,
+    context: &mut SearchContext,
+) -> Option<i32> {
+    let has_pieces =
+        board.material_key().has_non_pawn_material(board.friendly_color()) || context.null_move_without_pieces;
 
-    move_generator.gen_moves(board, &mut move_lists[max_depth - 1]);
-
-    let mut best_move = None;
-    let mut best_score = i32::MIN + 1;
+    if context.null_move == NullMove::Off
+        || context.verifying
+        || depth < NULL_MOVE_MIN_DEPTH
+        || context.line.last().is_none_or(|&last| last == NULL_MOVE)
+        || beta.abs() >= TB_WIN - MAX_MATE_PLY
+        || !has_pieces
+        || evaluator.evaluate(board) < beta
+        || move_generator.is_in_check(board)
+    {
+        return None;
+    }
 
     let mut info = UndoInfo::default();
+    context.path.push(board.hash());
+    board.make_null_move(&mut info);
+    context.line.push(NULL_MOVE);
+    let score = -negamax(
+        depth - 1 - NULL_MOVE_REDUCTION,
+        -beta,
+        1 - beta,
+        board,
+        evaluator,
+        move_generator,
+        move_lists,
+        control,
+        context,
+    );
+    context.line.pop();
+    board.undo_null_move(&info);
+    context.path.pop();
 
-    for i in 0..move_lists[max_depth - 1].len() {
-        let my_move = move_lists[max_depth - 1].at(i);
-
-        // test the move
-        board.make_move(my_move, &mut info);
-
-        // update evaluation
-        evaluator.update_score(info.evalutor_diff);
+    if context.aborted || score < beta {
+        return None;
+    }
 
-        let score = -negamax(
-            max_depth - 1,
-            i32::MIN + 1,
-            i32::MAX - 1,
+    if context.null_move == NullMove::Verified && depth >= NULL_MOVE_VERIFY_DEPTH {
+        context.verifying = true;
+        let verified = negamax(
+            depth - NULL_MOVE_REDUCTION,
+            beta - 1,
+            beta,
             board,
             evaluator,
             move_generator,
             move_lists,
+            control,
+            context,
         );
+        context.verifying = false;
 
-        // undo move
-        board.undo_move(my_move, &info);
-        
-        // update evaluation
-        evaluator.update_score(-info.evalutor_diff);
-
-        // store the best move
-        if score >= best_score {
-            best_score = score;
-            best_move = Some(my_move);
+        if context.aborted || verified < beta {
+            return None;
         }
     }
 
-    if best_move.is_some() {
-        Some((best_move.unwrap(), best_score))
-    } else {
-        None
-    }
+    Some(beta)
 }
 
-pub fn iterative_deepening(
-    max_depth: usize,
+// prepare a new search from the position: reset the context and generate
+// the root moves, ordered by the move generator's heuristics
+fn init_root<E: Evaluate>(
     board: &mut Board,
-    evaluator: &mut Evaluator,
+    evaluator: &mut E,
     move_generator: &MoveGenerator,
-    move_lists: &mut Vec<MoveList>,
-    max_time_millis: usize,
+    move_lists: &mut [MoveList],
+    context: &mut SearchContext,
 ) {
     // setup evaluator
-    evaluator.init_score(board);
+    evaluator.full(board);
 
-    move_generator.gen_moves(board, &mut move_lists[max_depth - 1]);
+    context.nodes = 0;
+    context.next_check = 0;
+    context.last_check = None;
+    context.cutoffs = 0;
+    context.cutoff_index_total = 0;
+    context.stats.clear();
+    context.tb_hits = 0;
+    context.tt_probes = 0;
+    context.tt_hits = 0;
+    context.aborted = false;
+    context.stop_reason = None;
+    context.path.clear();
+    context.path.push(board.hash());
+    context.root_history.clear();
+    context.root_history.extend(board.repeatable_history());
+    context.line.clear();
+    context.next_currline = None;
+    if !context.helper {
+        context.tt.new_search();
+    }
+    context.killers.clear();
+    context.history.age();
 
-    let mut best_move = 0;
+    let moves = &mut move_lists[0];
+    move_generator.gen_moves(board, moves);
+    moves.order_moves(board);
 
-    let start = Instant::now();
+    context.root_moves.clear();
+    context.root_moves.extend((0..moves.len()).map(|i| RootMove {
+        root_move: moves.at(i),
+        score: i32::MIN + 1,
+        replies: Vec::new(),
+    }));
+    if !context.search_moves.is_empty() {
+        let search_moves = &context.search_moves;
+        context.root_moves.retain(|root| search_moves.contains(&root.root_move));
+    }
 
-    for depth in 1..=max_depth {
-        // calculate score
-        let (my_move, score) = find_best_move(
-            max_depth,
-            board,
-            evaluator,
+    // moves which throw away a tablebase win (or draw) are not searched.
+    // The tables only give the result, so the search still has to find a
+    // way to make progress.
+    if let Some(tablebases) = &context.tablebases {
+        if tablebases.can_probe(board) {
+            let root_moves: Vec<Move> = context.root_moves().collect();
+            if let Some(kept) = tablebases.filter_root_moves(board, move_generator, &root_moves) {
+                context.root_moves.retain(|root| kept.contains(&root.root_move));
+            }
+        }
+    }
+}
+
+// Search every root move to the given depth, in the order left by the last
+// iteration, then sort them best first for the next one. Returns None if
+// there are no legal moves or the search was stopped part way through.
+//
+// Ties are broken by that order: of the moves with the best score, the one
+// searched first wins. As the first order comes from the move generator and
+// every sort is stable, the same search always returns the same move after
+// visiting the same number of nodes.
+fn search_root<E: Evaluate>(
+    depth: usize,
+    board: &mut Board,
+    evaluator: &mut E,
+    move_generator: &MoveGenerator,
+    move_lists: &mut Vec<MoveList>,
+    control: &mut ControlHandle,
+    context: &mut SearchContext,
+) -> Option<(Move, i32)> {
+    let mut info = UndoInfo::default();
+
+    let start_nodes = context.nodes;
+    context.iteration = IterationStats {
+        depth,
+        previous_nodes: context.stats.last().map_or(0, |last| last.nodes),
+        ..IterationStats::default()
+    };
+
+    for i in 0..context.root_moves.len() {
+        let my_move = context.root_moves[i].root_move;
+        context.root_index = i;
+
+        // test the move
+        board.make_move(my_move, &mut info);
+
+        // update evaluation
+        evaluator.on_make(board, my_move, &info);
+
+        context.line.push(my_move);
+        let (alpha, beta) = context.root_window();
+        let score = -negamax(
+            depth - 1,
+            -beta,
+            -alpha,
+            board,
+            evaluator,
             move_generator,
             move_lists,
-        ).unwrap();
+            control,
+            context,
+        );
+        context.line.pop();
+
+        // undo move
+        board.undo_move(my_move, &info);
 
-        best_move = my_move;
+        // update evaluation
+        evaluator.on_undo(board, my_move, &info);
+
+        // the scores of an unfinished iteration can't be compared
+        if context.aborted {
+            return None;
+        }
+
+        // every root move is searched with the same window, so the best line
+        // after it is exact unless it failed low
+        let root = &mut context.root_moves[i];
+        root.score = score;
+        root.replies.clone_from(&context.pv[1]);
+    }
+
+    context.iteration.nodes = context.nodes - start_nodes;
+    context.stats.push(context.iteration);
+
+    // must be a stable sort, see above
+    context.root_moves.sort_by_key(|root| Reverse(root.score));
+    context.root_moves.first().map(|best| (best.root_move, best.score))
+}
+
+pub fn find_best_move<E: Evaluate>(
+    max_depth: usize,
+    board: &mut Board,
+    evaluator: &mut E,
+    move_generator: &MoveGenerator,
+    move_lists: &mut Vec<MoveList>,
+    context: &mut SearchContext,
+) -> Option<(Move, i32)> {
+    init_root(board, evaluator, move_generator, move_lists, context);
+    search_root(
+        max_depth,
+        board,
+        evaluator,
+        move_generator,
+        move_lists,
+        &mut ControlHandle::new(),
+        context,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn iterative_deepening<E: Evaluate>(
+    max_depth: usize,
+    board: &mut Board,
+    evaluator: &mut E,
+    move_generator: &MoveGenerator,
+    move_lists: &mut Vec<MoveList>,
+    max_time_millis: usize,
+    hard_time_millis: usize,
+    control: &mut ControlHandle,
+    context: &mut SearchContext,
+) -> Option<(Move, i32)> {
+    let start = Instant::now();
+
+    // max_time_millis is only checked between iterations, but the search is
+    // abandoned as soon as hard_time_millis runs out. Both count from when
+    // the clock starts, which is after any pondering.
+    context.set_stop_flag(control.stop_flag());
+    context.pondering = control.ponder_flag();
+    context.hard_time = match hard_time_millis {
+        usize::MAX => None,
+        millis => Some(Duration::milliseconds(millis as i64)),
+    };
+    context.clock_start = None;
+    context.deadline = None;
+    context.start_clock();
+
+    // if the search is stopped before depth 1 completes there must still be
+    // a legal move to play, so start with the first root move
+    init_root(board, evaluator, move_generator, move_lists, context);
+    let mut best = (context.root_moves().next()?, evaluator.evaluate(board));
+
+    // the depth of the last iteration whose refutations were reported, and
+    // when
+    let mut refutations_reported: Option<(usize, Instant)> = None;
+    let mut completed = 0;
+    // unless a limit inside the search ends it first
+    let mut reason = StopReason::Depth;
+
+    // The first iteration is a one ply search of every root move, which
+    // costs next to nothing but sorts them by score, so the deeper
+    // iterations look at the most promising moves first and GUIs get a
+    // sensible depth 1 result straight away. Each later iteration searches
+    // in the order left by the one before.
+    for depth in 1..=max_depth {
+        if control.is_stopped() {
+            reason = StopReason::Stopped;
+            break;
+        }
+
+        // calculate score
+        let result = search_root(depth, board, evaluator, move_generator, move_lists, control, context);
+
+        // an unfinished iteration may not have seen the best move
+        let (best_move, score) = match result {
+            Some(result) if !context.aborted() => result,
+            _ => break,
+        };
 
         let end = Instant::now();
 
-        let millis: usize = (end - start).whole_milliseconds() as usize;
-        
-        // output pv line
-        println!(
-            "info score cp {} depth {} move {} time {}",
-            score,
-            depth,
-            best_move.move_to_string(),
-            millis,
-        );
+        // A mate search has nothing to report until an iteration finds a
+        // mate, and then it is done: the mate is within the moves asked for,
+        // and going deeper only looks for longer ones.
+        if context.is_exact(score) {
+            best = (best_move, score);
+            completed = depth;
+
+            let millis: usize = (end - start).whole_milliseconds() as usize;
+
+            control.report(ProgressEvent::SearchIteration {
+                depth,
+                best_move,
+                score,
+                millis,
+                nodes: context.nodes(),
+                pv: context.pv(),
+                lines: context.best_lines(context.multi_pv.max(1)),
+                stats: context.iteration,
+            });
+
+            let due = refutations_reported.is_none_or(|(_, reported)| {
+                end - reported >= Duration::milliseconds(REFUTATION_INTERVAL_MILLIS)
+            });
+            if due {
+                report_refutations(control, context);
+                refutations_reported = Some((depth, end));
+            }
+
+            if context.mate_search.is_some() {
+                reason = StopReason::Mate;
+                break;
+            }
+        }
 
         // check for out of time
-        if millis >= max_time_millis {
+        context.start_clock();
+        let clock_millis = context.clock_start.map(|clock_start| (end - clock_start).whole_milliseconds() as usize);
+        if depth < max_depth && clock_millis.is_some_and(|clock_millis| clock_millis >= max_time_millis) {
+            reason = StopReason::Time;
             break;
         }
     }
+    context.stop_reason.get_or_insert(reason);
+
+    // whatever the interval, GUIs see the refutations the final move is
+    // based on
+    if completed > 0 && refutations_reported.map(|(depth, _)| depth) != Some(completed) {
+        report_refutations(control, context);
+    }
+
+    Some(best)
+}
+
+// The depth of the last iteration the search completed, or 0 if it was
+// stopped before finishing any.
+fn completed_depth(context: &SearchContext) -> usize {
+    context.stats().last().map_or(0, |last| last.depth)
+}
+
+// Lazy SMP: helper threads run the same search as the main thread, sharing
+// only the transposition table. Each finds the entries the others store, so
+// they soon search different parts of the tree and the main thread finds
+// more of its positions in the table. Only the main thread reports progress
+// and watches the clock, and the helpers are stopped once it finishes.
+//
+// The move is taken from whichever thread completed the deepest iteration,
+// the main thread winning ties, and the context is left with the nodes
+// visited by every thread.
+#[allow(clippy::too_many_arguments)]
+pub fn parallel_search<E: Evaluate + Clone + Send>(
+    threads: usize,
+    max_depth: usize,
+    board: &mut Board,
+    evaluator: &mut E,
+    move_generator: &MoveGenerator,
+    move_lists: &mut Vec<MoveList>,
+    max_time_millis: usize,
+    hard_time_millis: usize,
+    control: &mut ControlHandle,
+    context: &mut SearchContext,
+) -> Option<(Move, i32)> {
+    if threads <= 1 {
+        return iterative_deepening(
+            max_depth,
+            board,
+            evaluator,
+            move_generator,
+            move_lists,
+            max_time_millis,
+            hard_time_millis,
+            control,
+            context,
+        );
+    }
+
+    // the table is aged before any helper stores in it
+    context.tt.new_search();
+    context.helper = true;
+
+    // the helpers evaluate with this thread's weights
+    let weights = Weights::current();
+    let helpers_stop = Arc::new(AtomicBool::new(false));
+    let (result, helpers) = std::thread::scope(|scope| {
+        let helpers: Vec<_> = (1..threads)
+            .map(|_| {
+                let mut board = board.clone();
+                let mut evaluator = evaluator.clone();
+                let mut move_lists: Vec<MoveList> = (0..move_lists.len()).map(|_| MoveList::new()).collect();
+                let mut control = ControlHandle::with_stop_flag(Arc::clone(&helpers_stop));
+                let mut context = context.helper();
+
+                scope.spawn(move || {
+                    weights.install();
+                    let result = iterative_deepening(
+                        max_depth,
+                        &mut board,
+                        &mut evaluator,
+                        move_generator,
+                        &mut move_lists,
+                        usize::MAX,
+                        usize::MAX,
+                        &mut control,
+                        &mut context,
+                    );
+                    (result, context)
+                })
+            })
+            .collect();
+
+        let result = iterative_deepening(
+            max_depth,
+            board,
+            evaluator,
+            move_generator,
+            move_lists,
+            max_time_millis,
+            hard_time_millis,
+            control,
+            context,
+        );
+        helpers_stop.store(true, Ordering::Relaxed);
+
+        let helpers: Vec<_> = helpers
+            .into_iter()
+            .map(|helper| helper.join().expect("search thread panicked"))
+            .collect();
+        (result, helpers)
+    });
+    context.helper = false;
+
+    let mut best = result.map(|result| (completed_depth(context), result));
+    for (result, helper) in helpers {
+        context.nodes += helper.nodes;
+        context.tt_probes += helper.tt_probes;
+        context.tt_hits += helper.tt_hits;
+        // in a mate search, a thread's mate beats a deeper search without one
+        if let (Some(result), Some((depth, best_result))) = (result, best) {
+            if (helper.is_exact(result.1), completed_depth(&helper)) > (context.is_exact(best_result.1), depth) {
+                best = Some((completed_depth(&helper), result));
+            }
+        }
+    }
+
+    best.map(|(_, result)| result)
+}
+
+fn report_refutations(control: &mut ControlHandle, context: &SearchContext) {
+    for (refuted, line) in context.refutations() {
+        control.report(ProgressEvent::Refutation {
+            refuted,
+            line: line.to_vec(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::eval::{Evaluator, MaterialOnly, SlowEvaluator};
+    use crate::engine::tt::DEFAULT_TT_ENTRIES;
+    use crate::engine::piece::Color;
+    use crate::engine::square::Square;
+
+    fn search(fen: &str, depth: usize, evaluator: &mut impl Evaluate) -> (String, i32) {
+        let mut board = Board::new(fen).unwrap();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        let (best_move, score) = find_best_move(
+            depth,
+            &mut board,
+            evaluator,
+            &move_generator,
+            &mut move_lists,
+            &mut context,
+        )
+        .unwrap();
+
+        (best_move.move_to_string(), score)
+    }
+
+    #[test]
+    fn material_only() {
+        // take the free queen
+        assert_eq!(
+            search("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", 2, &mut MaterialOnly),
+            ("d1d5".to_string(), 500)
+        );
+        // but not a defended pawn
+        assert_ne!(
+            search("4k3/8/4p3/3p4/8/8/8/3QK3 w - - 0 1", 2, &mut MaterialOnly).0,
+            "d1d5"
+        );
+        // back rank mate
+        assert_eq!(
+            search("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 2, &mut MaterialOnly).0,
+            "a1a8"
+        );
+    }
+
+    #[test]
+    fn side_to_move_sign() {
+        // the same free queen for either side, with the board flipped. A sign
+        // slip anywhere between the evaluation and the root makes one of them
+        // pick the worst move instead.
+        let white = search("4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1", 1, &mut Evaluator::default());
+        let black = search("3rk3/8/8/8/3Q4/8/8/4K3 b - - 0 1", 1, &mut Evaluator::default());
+
+        assert_eq!(white.0, "d1d5");
+        assert_eq!(black.0, "d8d4");
+        assert!(white.1 > 0);
+        assert_eq!(white.1, black.1);
+
+        // and the start position is level
+        assert_eq!(Evaluator::default().full(&Board::default()), 0);
+    }
+
+    // Positions a queen up where a natural looking move stalemates, with
+    // the move. The defending king is cornered, where the piece square
+    // tables want it, so only seeing that it has no moves saves the win.
+    const STALEMATE_TRAPS: [(&str, &str); 6] = [
+        ("k1K5/8/8/7Q/8/8/8/8 w - - 0 1", "h5c5"),
+        ("k1K5/Q7/8/8/8/8/8/8 w - - 0 1", "a7c5"),
+        ("k1K5/8/8/Q7/8/8/8/8 w - - 0 1", "a5c5"),
+        ("kRK5/8/8/8/8/8/8/8 w - - 0 1", "b8b7"),
+        ("k1K5/8/8/3R4/8/8/8/8 w - - 0 1", "d5d7"),
+        ("8/8/8/8/8/8/q7/5k1K b - - 0 1", "a2f2"),
+    ];
+
+    #[test]
+    fn stalemate_traps() {
+        for &(fen, stalemate) in STALEMATE_TRAPS.iter() {
+            for depth in 1..=8 {
+                let (best_move, score) = search(fen, depth, &mut Evaluator::default());
+                assert_ne!(best_move, stalemate, "{} at depth {}", fen, depth);
+                assert!(score > 500, "{} at depth {}: {}", fen, depth, score);
+            }
+        }
+
+        // the stalemate is a draw at the horizon too
+        let mut context = SearchContext::new();
+        let mut board = Board::new("k1K5/8/8/2Q5/8/8/8/8 b - - 0 1").unwrap();
+        let mut evaluator = Evaluator::default();
+        evaluator.full(&board);
+        let score = negamax(
+            0,
+            -MATE_SCORE,
+            MATE_SCORE,
+            &mut board,
+            &mut evaluator,
+            &MoveGenerator::new(),
+            &mut Vec::new(),
+            &mut ControlHandle::new(),
+            &mut context,
+        );
+        assert_eq!(score, 0);
+    }
+
+    #[test]
+    fn draws_not_stored() {
+        // Stalemate, a bare bishop and the fifty move rule. The last is
+        // only a draw with this move count, so an exact 0 in the table
+        // would be wrong for the same position reached sooner.
+        for fen in [
+            "k1K5/8/8/2Q5/8/8/8/8 b - - 0 1",
+            "4k3/8/8/8/8/8/8/4KB2 w - - 0 1",
+            "4k3/8/8/8/8/8/8/3QK3 w - - 100 80",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut evaluator = Evaluator::default();
+            evaluator.full(&board);
+            let mut context = SearchContext::new();
+            let score = negamax(
+                2,
+                -MATE_SCORE,
+                MATE_SCORE,
+                &mut board,
+                &mut evaluator,
+                &MoveGenerator::new(),
+                &mut (0..2).map(|_| MoveList::new()).collect(),
+                &mut ControlHandle::new(),
+                &mut context,
+            );
+            assert_eq!(score, 0, "{}", fen);
+            assert!(context.tt.probe(board.hash(), 0).is_none(), "{}", fen);
+        }
+    }
+
+    // the position after the moves from the fen, searched to the depth
+    fn search_game(fen: &str, moves: &[&str], depth: usize) -> (String, i32) {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::new(fen).unwrap();
+        let mut info = UndoInfo::default();
+        for uci_move in moves {
+            board.make_uci_move(uci_move, &move_generator, &mut info).unwrap();
+        }
+
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let (best_move, score) = find_best_move(
+            depth,
+            &mut board,
+            &mut Evaluator::default(),
+            &move_generator,
+            &mut move_lists,
+            &mut SearchContext::new(),
+        )
+        .unwrap();
+        (best_move.move_to_uci(false), score)
+    }
+
+    #[test]
+    fn root_history_repetitions() {
+        // black is a queen for a rook down, and the kings have shuffled
+        // back to the start
+        let fen = "6k1/5r2/8/8/8/8/8/3Q2K1 b - - 0 1";
+        let shuffle = ["g8h8", "g1h1", "h8g8", "h1g1"];
+
+        // Kh8 reaches the game's second position again, which white can't
+        // avoid repeating a third time, so black takes the draw
+        assert_eq!(search_game(fen, &shuffle, 2), ("g8h8".to_string(), 0));
+
+        // but with the kings on h8 and h1, going back to the first position
+        // needs white's Kg1 as well, which white doesn't play
+        let (_, score) = search_game(fen, &shuffle[..2], 2);
+        assert!(score < -300, "{}", score);
+    }
+
+    #[test]
+    fn threads() {
+        let move_generator = MoveGenerator::new();
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+
+        // Searches to depth 4, which the main thread always finishes. How
+        // many nodes the helpers add depends on the machine, so comparing
+        // speeds is left to `cargo bench --bench threads`.
+        let search = |threads| {
+            let mut board = Board::new(kiwipete).unwrap();
+            let mut move_lists = (0..4).map(|_| MoveList::new()).collect();
+            let mut context = SearchContext::new();
+            let result = parallel_search(
+                threads,
+                4,
+                &mut board,
+                &mut Evaluator::default(),
+                &move_generator,
+                &mut move_lists,
+                usize::MAX,
+                usize::MAX,
+                &mut ControlHandle::new(),
+                &mut context,
+            )
+            .unwrap();
+
+            assert!(board == Board::new(kiwipete).unwrap());
+            assert_eq!(context.stop_reason(), Some(StopReason::Depth));
+            assert_eq!(completed_depth(&context), 4);
+            (result, context.nodes())
+        };
+
+        let mut legal = MoveList::new();
+        move_generator.gen_moves(&mut Board::new(kiwipete).unwrap(), &mut legal);
+        let is_legal = |my_move| (0..legal.len()).any(|i| legal.at(i) == my_move);
+
+        // with no helpers it is the ordinary search
+        let one = search(1);
+        assert_eq!(one, search(1));
+        let mut board = Board::new(kiwipete).unwrap();
+        let expected = iterative_deepening(
+            4,
+            &mut board,
+            &mut Evaluator::default(),
+            &move_generator,
+            &mut (0..4).map(|_| MoveList::new()).collect(),
+            usize::MAX,
+            usize::MAX,
+            &mut ControlHandle::new(),
+            &mut SearchContext::new(),
+        );
+        assert_eq!(Some(one.0), expected);
+        assert!(is_legal(one.0 .0));
+
+        let (four, _) = search(4);
+        assert!(is_legal(four.0));
+    }
+
+    #[test]
+    fn multi_pv() {
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..3).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        let (best_move, score) =
+            find_best_move(3, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context)
+                .unwrap();
+        let lines = context.best_lines(3);
+        assert_eq!(lines.len(), 3);
+        assert_eq!((lines[0].pv[0], lines[0].score), (best_move, score));
+        assert_eq!(lines[0].pv, context.pv());
+
+        let mut legal = MoveList::new();
+        move_generator.gen_moves(&mut board, &mut legal);
+        for (i, line) in lines.iter().enumerate() {
+            assert!((0..legal.len()).any(|j| legal.at(j) == line.pv[0]));
+            assert!(lines[..i].iter().all(|better| better.pv[0] != line.pv[0] && better.score >= line.score));
+        }
+
+        // no more lines than moves
+        assert_eq!(context.best_lines(100).len(), 20);
+    }
+
+    #[test]
+    fn search_moves() {
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..3).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        let a2a3 = board.find_uci_move("a2a3", &move_generator).unwrap();
+        let e2e4 = board.find_uci_move("e2e4", &move_generator).unwrap();
+
+        // however bad the move is, it is the only one looked at
+        context.set_search_moves(vec![a2a3]);
+        let found = find_best_move(3, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert_eq!(found.map(|(found, _)| found), Some(a2a3));
+        assert_eq!(context.root_moves().collect::<Vec<_>>(), [a2a3]);
+
+        context.set_search_moves(vec![a2a3, e2e4]);
+        find_best_move(3, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert_eq!(context.root_moves().collect::<Vec<_>>(), [e2e4, a2a3]);
+
+        context.set_search_moves(Vec::new());
+        find_best_move(1, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert_eq!(context.root_moves().count(), 20);
+    }
+
+    #[test]
+    fn node_count() {
+        // every root move is a leaf at depth 1
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..2).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        find_best_move(1, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert_eq!(context.nodes(), 20);
+        assert_eq!(context.stats().last().map(|it| it.nodes), Some(20));
+
+        // counted from zero again by the next search
+        find_best_move(2, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert_eq!(context.nodes(), context.stats().last().unwrap().nodes);
+        assert!(context.nodes() > 20);
+    }
+
+    #[test]
+    fn mates() {
+        let evaluator = &mut Evaluator::default();
+
+        // Kc7 mates next move too, but Rh8 mates now
+        let (best_move, score) = search("k7/8/1K6/8/8/8/8/7R w - - 0 1", 4, evaluator);
+        assert_eq!((best_move.as_str(), mate_in(score)), ("h1h8", Some(1)));
+
+        // the rook cuts off the king, then the other mates on the back rank
+        let (_, score) = search("7k/8/8/8/8/8/1R6/R3K3 w - - 0 1", 4, evaluator);
+        assert_eq!((score, mate_in(score)), (MATE_SCORE - 3, Some(2)));
+
+        // a quiet first move: 1. Ng3 Kg8 2. Rf1 Kh8 3. Rf8#
+        let (best_move, score) = search("7k/7p/7P/8/8/8/8/K4NR1 w - - 0 1", 6, evaluator);
+        assert_eq!((best_move.as_str(), mate_in(score)), ("f1g3", Some(3)));
+
+        // and black puts it off as long as it can
+        let (_, score) = search("7k/1R6/8/8/8/8/8/R3K3 b - - 0 1", 3, evaluator);
+        assert_eq!(mate_in(score), Some(-1));
+        assert_eq!(mate_in(TB_WIN), None);
+    }
+
+    #[test]
+    fn draws() {
+        // a bishop can't mate, however far ahead the search looks
+        assert_eq!(search("4k3/8/8/8/8/8/8/4KB2 w - - 0 1", 3, &mut Evaluator::default()).1, 0);
+
+        // the knights going out and back twice repeats the start position
+        // for the third time
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        for (i, uci_move) in ["g1f3", "g8f6", "f3g1", "f6g8"].iter().cycle().take(8).enumerate() {
+            assert!(!board.is_draw(), "draw before move {}", i);
+            board.make_uci_move(uci_move, &move_generator, &mut info).unwrap();
+        }
+        assert!(board.is_draw());
+    }
+
+    #[test]
+    fn perpetual_check() {
+        // black threatens Rb1 mate and is a queen and rook up, so white's
+        // only way to survive is Qf6+ Kg8 Qg5+ Kh8 Qf6+ and so on
+        let mut board = Board::new("5r1k/5p1p/8/6Q1/8/8/qr4PP/7K w - - 0 1").unwrap();
+        let mut evaluator = Evaluator::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..5).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        let (best_move, score) = find_best_move(
+            5,
+            &mut board,
+            &mut evaluator,
+            &move_generator,
+            &mut move_lists,
+            &mut context,
+        )
+        .unwrap();
+
+        assert_eq!(best_move.move_to_string(), "g5f6");
+        assert_eq!(score, 0);
+        // the bound depends on move ordering, so is only a rough guard
+        // against the search blowing up
+        assert!(
+            context.nodes() < 800_000,
+            "searched {} nodes",
+            context.nodes()
+        );
+    }
+
+    #[test]
+    fn root_move_ordering() {
+        let move_generator = MoveGenerator::new();
+
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1",
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut evaluator = Evaluator::default();
+            let mut move_lists = (0..3).map(|_| MoveList::new()).collect();
+            let mut context = SearchContext::new();
+
+            // the first iteration agrees with a plain depth 1 search
+            let depth_1 = find_best_move(
+                1,
+                &mut board,
+                &mut evaluator,
+                &move_generator,
+                &mut move_lists,
+                &mut context,
+            );
+            let mut control = ControlHandle::new();
+            let ordered = iterative_deepening(
+                1,
+                &mut board,
+                &mut evaluator,
+                &move_generator,
+                &mut move_lists,
+                usize::MAX,
+                usize::MAX,
+                &mut control,
+                &mut context,
+            );
+            assert_eq!(ordered, depth_1, "{}", fen);
+
+            // and leaves the root moves sorted, best first
+            let scores: Vec<i32> = context.root_moves.iter().map(|root| root.score).collect();
+            assert!(scores.windows(2).all(|w| w[0] >= w[1]), "{}", fen);
+            assert_eq!(
+                context.root_moves().next(),
+                ordered.map(|(best_move, _)| best_move)
+            );
+
+            // the next iteration searches in that order, so of equal moves
+            // the one which was better at depth 1 is preferred
+            let order: Vec<Move> = context.root_moves().collect();
+            let (best_move, score) = search_root(
+                2,
+                &mut board,
+                &mut evaluator,
+                &move_generator,
+                &mut move_lists,
+                &mut control,
+                &mut context,
+            )
+            .unwrap();
+            let first_best = context
+                .root_moves
+                .iter()
+                .filter(|root| root.score == score)
+                .map(|root| order.iter().position(|&o| o == root.root_move).unwrap())
+                .min()
+                .unwrap();
+            assert_eq!(best_move, order[first_best], "{}", fen);
+        }
+    }
+
+    #[test]
+    fn stop_from_another_thread() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut board = Board::new(fen).unwrap();
+        let before = board.clone();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..=64).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        let completed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut control = ControlHandle::new().with_progress({
+            let completed = Arc::clone(&completed);
+            move |event| {
+                if let ProgressEvent::SearchIteration { best_move, .. } = event {
+                    completed.lock().unwrap().push(best_move);
+                }
+            }
+        });
+
+        // far too deep to finish, until the flag is set
+        let stop = control.stop_flag();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(200));
+            stop.store(true, Ordering::Relaxed);
+        });
+
+        let start = std::time::Instant::now();
+        let (best_move, _) = iterative_deepening(
+            64,
+            &mut board,
+            &mut Evaluator::default(),
+            &move_generator,
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut control,
+            &mut context,
+        )
+        .unwrap();
+        stopper.join().unwrap();
+
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert!(context.aborted());
+        assert!(board == before);
+
+        // the move is legal, and the one the last complete iteration found
+        let mut moves = MoveList::new();
+        move_generator.gen_moves(&mut board, &mut moves);
+        assert!((0..moves.len()).any(|i| moves.at(i) == best_move));
+        assert_eq!(completed.lock().unwrap().last(), Some(&best_move));
+    }
+
+    #[test]
+    fn pv_and_current_line() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let mut board = Board::new(fen).unwrap();
+        let mut evaluator = Evaluator::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..4).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        // report the current line every time the stop flag is checked
+        let reported = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut control = ControlHandle::new().with_progress({
+            let reported = Arc::clone(&reported);
+            move |event| {
+                if let ProgressEvent::CurrentLine { line } = event {
+                    reported.lock().unwrap().push(line);
+                }
+            }
+        });
+        context.set_currline_interval(Some(Duration::zero()));
+
+        let (best_move, _) = iterative_deepening(
+            4,
+            &mut board,
+            &mut evaluator,
+            &move_generator,
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut control,
+            &mut context,
+        )
+        .unwrap();
+
+        // every line is a sequence of legal moves from the root
+        let is_legal = |line: &[Move]| {
+            let mut board = Board::new(fen).unwrap();
+            let mut info = UndoInfo::default();
+            line.iter()
+                .all(|my_move| board.make_uci_move(&my_move.move_to_string(), &move_generator, &mut info).is_ok())
+        };
+
+        let pv = context.pv();
+        assert_eq!(pv.len(), 4);
+        assert_eq!(pv[0], best_move);
+        assert!(is_legal(&pv));
+
+        let reported = reported.lock().unwrap();
+        assert!(reported.len() > 10);
+        assert!(reported.iter().all(|line| line.len() <= 4 && is_legal(line)));
+
+        // refutations are of worse moves, by the best replies to them
+        let refutations: Vec<(Move, &[Move])> = context.refutations().collect();
+        assert!(!refutations.is_empty());
+        for (refuted, line) in refutations {
+            let full: Vec<Move> = std::iter::once(refuted).chain(line.iter().copied()).collect();
+            assert_ne!(refuted, best_move);
+            assert!(is_legal(&full));
+        }
+    }
+
+    #[test]
+    fn ordering_stats() {
+        let mut board = Board::default();
+        let mut evaluator = Evaluator::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..6).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+
+        iterative_deepening(
+            6,
+            &mut board,
+            &mut evaluator,
+            &move_generator,
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut ControlHandle::new(),
+            &mut context,
+        )
+        .unwrap();
+
+        let stats = context.stats();
+        assert_eq!(stats.iter().map(|it| it.depth).collect::<Vec<_>>(), vec![1, 2, 3, 4, 5, 6]);
+        assert_eq!(stats.iter().map(|it| it.nodes).sum::<u64>(), context.nodes());
+        assert_eq!(stats[0].branching_factor(), None);
+        for pair in stats.windows(2) {
+            assert_eq!(pair[1].previous_nodes, pair[0].nodes);
+        }
+
+        // a canary for the move ordering getting worse. With the killer
+        // moves about 93% of the cutoffs at depth 6 come from the first
+        // move, and without them 85%.
+        let last = stats.last().unwrap();
+        assert!(
+            last.first_move_cutoff_rate() > 80.0,
+            "{:.1}% of cutoffs on the first move",
+            last.first_move_cutoff_rate()
+        );
+    }
+
+    #[test]
+    fn killers() {
+        let mut context = SearchContext::new();
+        let sq = |notation: &str| Square::from_notation(notation).unwrap().sq() as u16;
+        let (e2e4, d2d4, g1f3) = (
+            Move::new_move(sq("e2"), sq("e4"), 0),
+            Move::new_move(sq("d2"), sq("d4"), 0),
+            Move::new_move(sq("g1"), sq("f3"), 0),
+        );
+        assert_eq!(context.killers(3), [None, None]);
+
+        // newest first, and the same move never fills both slots
+        context.store_killer(3, e2e4);
+        context.store_killer(3, e2e4);
+        assert_eq!(context.killers(3), [Some(e2e4), None]);
+        context.store_killer(3, d2d4);
+        context.store_killer(3, g1f3);
+        assert_eq!(context.killers(3), [Some(g1f3), Some(d2d4)]);
+        assert_eq!(context.killers(2), [None, None]);
+
+        // a new search starts without them
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..3).map(|_| MoveList::new()).collect();
+        find_best_move(3, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert!(context.killers.iter().flatten().any(Option::is_some));
+        assert!(!context.killers(3).contains(&Some(e2e4)));
+
+        // captures and promotions are ordered by what they win instead
+        let board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        assert!(!is_quiet(&board, Move::new_move(sq("e2"), sq("a6"), 0)));
+        assert!(is_quiet(&board, Move::new_move(sq("e1"), sq("g1"), MOVE_TYPE_CASTLE)));
+    }
+
+    #[test]
+    fn history() {
+        let move_generator = MoveGenerator::new();
 
+        // the result at depth 6, and the nodes it took with only the killers
+        // ordering quiet moves, which the history should improve on. The
+        // pruning depends on the move order, so from the start position the
+        // killers alone settle on e2e3 (-45) instead.
+        for (fen, best_move, score, nodes_without) in &[
+            (crate::engine::board::STARTING_FEN, "g1f3", -20, 405_307),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", "e2a6", -101, 1_060_793),
+        ] {
+            let mut board = Board::new(fen).unwrap();
+            let mut move_lists = (0..6).map(|_| MoveList::new()).collect();
+            let mut context = SearchContext::new();
+
+            let found = find_best_move(6, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context)
+                .map(|(found, score)| (found.move_to_string(), score));
+            assert_eq!(found, Some((best_move.to_string(), *score)), "{}", fen);
+            assert!(context.nodes() < *nodes_without, "{} nodes for {}", context.nodes(), fen);
+        }
+
+        // a new game starts from nothing
+        let mut context = SearchContext::new();
+        let e2e4 = Move::new_move(Square::E2.sq() as u16, Square::E4.sq() as u16, 0);
+        context.history.update(Color::White, e2e4, 4);
+        assert_eq!(context.history.score(Color::White, e2e4), 16);
+        context.clear_history();
+        assert_eq!(context.history.score(Color::White, e2e4), 0);
+    }
+
+    #[test]
+    fn transposition_table() {
+        let move_generator = MoveGenerator::new();
+        let search = |fen: &str, depth: usize, tt_entries: usize, context: &mut SearchContext| {
+            let mut board = Board::new(fen).unwrap();
+            let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+            context.resize_tt(tt_entries);
+
+            let (best_move, score) = find_best_move(
+                depth,
+                &mut board,
+                &mut Evaluator::default(),
+                &move_generator,
+                &mut move_lists,
+                context,
+            )
+            .unwrap();
+            (best_move.move_to_string(), score, context.nodes())
+        };
+
+        // the same moves and scores, from fewer nodes
+        let kiwipete = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let without = search(kiwipete, 5, 0, &mut SearchContext::new());
+        let with = search(kiwipete, 5, DEFAULT_TT_ENTRIES, &mut SearchContext::new());
+        assert_eq!((&with.0, with.1), (&without.0, without.1));
+        assert!(with.2 * 10 < without.2 * 9, "{} nodes with the table, {} without", with.2, without.2);
+
+        for fen in &[
+            "4k3/8/8/3q4/8/8/8/3RK3 w - - 0 1",
+            "6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/2B1P3/5Q2/PPPP1PPP/RNB1K1NR w KQkq - 4 4",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        ] {
+            let without = search(fen, 4, 0, &mut SearchContext::new());
+            let with = search(fen, 4, DEFAULT_TT_ENTRIES, &mut SearchContext::new());
+            assert_eq!((&with.0, with.1), (&without.0, without.1), "{}", fen);
+        }
+
+        // searching again starts with what the last search found
+        let mut context = SearchContext::new();
+        let first = search(kiwipete, 4, DEFAULT_TT_ENTRIES, &mut context);
+        let mut board = Board::new(kiwipete).unwrap();
+        let mut move_lists = (0..4).map(|_| MoveList::new()).collect();
+        let again = find_best_move(4, &mut board, &mut Evaluator::default(), &move_generator, &mut move_lists, &mut context);
+        assert_eq!(again.map(|(best_move, score)| (best_move.move_to_string(), score)), Some((first.0, first.1)));
+        assert!(context.nodes() < first.2 / 2);
+    }
+
+    #[test]
+    fn node_types() {
+        assert_eq!(NodeType::classify(10, 0, 50), NodeType::Pv);
+        assert_eq!(NodeType::classify(50, 0, 50), NodeType::Cut);
+        assert_eq!(NodeType::classify(0, 0, 50), NodeType::All);
+        assert_eq!(NodeType::classify(-10, 0, 50), NodeType::All);
+        assert_eq!(NodeType::Pv.bound(), Bound::Exact);
+        assert_eq!(NodeType::Cut.bound(), Bound::Lower);
+        assert_eq!(NodeType::All.bound(), Bound::Upper);
+
+        // every interior node searched to the end is one of the three
+        let mut board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut context = SearchContext::new();
+        let mut move_lists = (0..4).map(|_| MoveList::new()).collect();
+        iterative_deepening(
+            4,
+            &mut board,
+            &mut Evaluator::default(),
+            &MoveGenerator::new(),
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut ControlHandle::new(),
+            &mut context,
+        );
+        let last = context.stats().last().unwrap();
+        assert!(last.pv_nodes > 0 && last.cut_nodes > 0 && last.all_nodes > 0, "{:?}", last);
+        assert!(last.pv_nodes < last.cut_nodes + last.all_nodes, "{:?}", last);
+    }
+
+    // a fixed depth search with a table of this many entries, 0 for none
+    fn search_with_tt(board: &Board, depth: usize, entries: usize) -> (Move, i32) {
+        let mut board = board.clone();
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        context.resize_tt(entries);
+        find_best_move(
+            depth,
+            &mut board,
+            &mut Evaluator::default(),
+            &MoveGenerator::new(),
+            &mut move_lists,
+            &mut context,
+        )
+        .unwrap()
+    }
+
+    // Nothing in the search depends on the table but the move order and
+    // the cutoffs it gives, so at a fixed depth it can change the nodes
+    // searched but never the result.
+    #[test]
+    fn tt_keeps_results() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "6k1/5ppp/8/8/8/8/5PPP/3R2K1 w - - 0 1",
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3",
+        ] {
+            let board = Board::new(fen).unwrap();
+            for depth in 1..=4 {
+                assert_eq!(
+                    search_with_tt(&board, depth, 0),
+                    search_with_tt(&board, depth, DEFAULT_TT_ENTRIES),
+                    "{} at depth {}",
+                    fen,
+                    depth
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tt_keeps_results_randomized() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let move_generator = MoveGenerator::new();
+        let mut rng = StdRng::seed_from_u64(1476);
+        let mut moves = MoveList::new();
+        let mut info = UndoInfo::default();
+
+        for _ in 0..40 {
+            // a position some random moves into a game
+            let mut board = Board::default();
+            for _ in 0..rng.gen_range(4..30) {
+                move_generator.gen_moves(&mut board, &mut moves);
+                if moves.is_empty() {
+                    break;
+                }
+                board.make_move(moves.at(rng.gen_range(0..moves.len())), &mut info);
+            }
+            move_generator.gen_moves(&mut board, &mut moves);
+            if moves.is_empty() {
+                continue;
+            }
+
+            let depth = rng.gen_range(1..=4);
+            assert_eq!(
+                search_with_tt(&board, depth, 0),
+                search_with_tt(&board, depth, 1 << 16),
+                "{} at depth {}",
+                board.to_fen(),
+                depth
+            );
+        }
+    }
+
+    // deepens to the depth from the position after the moves, with scores
+    // resting on a repetition stored or not, and gives what the table holds
+    // for the position one move further on
+    fn tt_entry_after(fen: &str, moves: &[&str], next: &str, depth: usize, store: bool) -> Option<(i32, usize, Bound)> {
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::new(fen).unwrap();
+        let mut info = UndoInfo::default();
+        for token in moves {
+            board.make_uci_move(token, &move_generator, &mut info).unwrap();
+        }
+
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        context.set_store_repetition_scores(store);
+        iterative_deepening(
+            depth,
+            &mut board,
+            &mut Evaluator::default(),
+            &move_generator,
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut ControlHandle::new(),
+            &mut context,
+        )
+        .unwrap();
+
+        board.make_uci_move(next, &move_generator, &mut info).unwrap();
+        context.tt.probe(board.hash(), 1).map(|entry| (entry.score, entry.depth, entry.bound))
+    }
+
+    // A draw by repetition belongs to the path which reached the position,
+    // not the position, so a score which came from one can't be stored.
+    // After Kh1 Kh8 Kg1 black, a rook down, draws by stepping back to g8,
+    // which only repeats the game before the root. The draw is kept as a
+    // hint for the move at a depth which never cuts, where storing it would
+    // give an exact draw that any other path to the position could take.
+    #[test]
+    fn repetitions_kept_out_of_tt() {
+        let fen = "6k1/5r2/8/8/8/8/8/3Q2K1 w - - 0 1";
+        let moves = ["g1h1", "g8h8"];
+        for depth in 2..7 {
+            assert_eq!(
+                tt_entry_after(fen, &moves, "h1g1", depth, false),
+                Some((0, 0, Bound::Upper)),
+                "at depth {}",
+                depth
+            );
+            assert_eq!(
+                tt_entry_after(fen, &moves, "h1g1", depth, true),
+                Some((0, depth - 1, Bound::Exact)),
+                "at depth {}",
+                depth
+            );
+        }
+    }
+
+    // searches to the depth with null move pruning used as given, and tried
+    // in pawn endings too or not
+    fn search_null_move(fen: &str, depth: usize, null_move: NullMove, without_pieces: bool) -> (String, i32) {
+        let mut board = Board::new(fen).unwrap();
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        context.set_null_move(null_move);
+        context.set_null_move_without_pieces(without_pieces);
+        let (best_move, score) = iterative_deepening(
+            depth,
+            &mut board,
+            &mut Evaluator::default(),
+            &MoveGenerator::new(),
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut ControlHandle::new(),
+            &mut context,
+        )
+        .unwrap();
+        (best_move.move_to_string(), score)
+    }
+
+    // In these pawn endings the only winning move leaves the other side
+    // with nothing good to do, so when it may pass instead the win is
+    // never seen.
+    #[test]
+    fn null_move_pawn_endings() {
+        for &(fen, best_move) in [
+            ("8/8/3p4/8/3K2P1/8/5k2/8 w - - 0 1", "g4g5"),
+            ("8/8/6p1/8/3P2K1/8/8/1k6 w - - 0 1", "d4d5"),
+            ("8/8/8/3P1K1k/4p3/8/1P6/8 w - - 0 1", "f5e4"),
+        ]
+        .iter()
+        {
+            let guarded = search_null_move(fen, 7, NullMove::Verified, false);
+            assert_eq!(guarded, search_null_move(fen, 7, NullMove::Off, false), "{}", fen);
+            assert_eq!(guarded.0, best_move, "{}", fen);
+            assert!(guarded.1 > 500, "{} scored {}", fen, guarded.1);
+
+            let (_, unguarded) = search_null_move(fen, 7, NullMove::Unverified, true);
+            assert!(unguarded < 500, "{} scored {} with passing", fen, unguarded);
+        }
+    }
+
+    // Black's bishop is shut in by its own pawn, so this is a pawn ending in
+    // all but name, and passing is allowed. Kd6 wins the pawn, but a pass
+    // gives a false cutoff which makes Kf6 look best, and the verification
+    // search catches it.
+    #[test]
+    fn null_move_verification() {
+        let fen = "b3k3/1p6/1P2K3/4P3/8/8/8/8 w - - 0 1";
+        let verified = search_null_move(fen, 11, NullMove::Verified, false);
+        assert_eq!(verified, search_null_move(fen, 11, NullMove::Off, false));
+        assert_eq!(verified, ("e6d6".to_string(), 906));
+        assert_eq!(search_null_move(fen, 11, NullMove::Unverified, false).0, "e6f6");
+    }
+
+    // a prefetch is only a hint, so the search is the same without it
+    #[test]
+    fn tt_prefetch() {
+        for fen in crate::engine::bench::POSITIONS {
+            let searched = [true, false].map(|prefetch| {
+                let mut board = Board::new(fen).unwrap();
+                let mut context = SearchContext::new();
+                context.set_tt_prefetch(prefetch);
+                let result = find_best_move(
+                    4,
+                    &mut board,
+                    &mut Evaluator::default(),
+                    &MoveGenerator::new(),
+                    &mut (0..4).map(|_| MoveList::new()).collect(),
+                    &mut context,
+                );
+                (result, context.nodes())
+            });
+            assert_eq!(searched[0], searched[1], "{}", fen);
+        }
+    }
+
+    // Each limit given ends the search when it is reached, whatever the
+    // others. Returns why the search ended and the depth it completed.
+    fn limited_search(depth: usize, hard_time_millis: usize, nodes: Option<u64>) -> (Option<StopReason>, usize) {
+        let mut board = Board::new("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1").unwrap();
+        let mut move_lists = (0..depth).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        context.set_node_limit(nodes);
+        iterative_deepening(
+            depth,
+            &mut board,
+            &mut Evaluator::default(),
+            &MoveGenerator::new(),
+            &mut move_lists,
+            usize::MAX,
+            hard_time_millis,
+            &mut ControlHandle::new(),
+            &mut context,
+        )
+        .unwrap();
+        (context.stop_reason(), context.stats().last().map_or(0, |last| last.depth))
+    }
+
+    #[test]
+    fn combined_limits() {
+        // depth only
+        assert_eq!(limited_search(3, usize::MAX, None), (Some(StopReason::Depth), 3));
+        // time only, as deep as the search goes
+        assert_eq!(limited_search(30, 50, None).0, Some(StopReason::Time));
+        // both, with the depth reached first
+        assert_eq!(limited_search(2, 60_000, None), (Some(StopReason::Depth), 2));
+        // both, with the time up first
+        let (reason, depth) = limited_search(30, 50, Some(u64::MAX));
+        assert_eq!(reason, Some(StopReason::Time));
+        assert!(depth < 30);
+        // a tiny node limit ends the search before anything else
+        let (reason, depth) = limited_search(30, 60_000, Some(100));
+        assert_eq!(reason, Some(StopReason::Nodes));
+        assert!(depth <= 2, "{}", depth);
+        // nodes to spare
+        assert_eq!(limited_search(2, 60_000, Some(1_000_000)), (Some(StopReason::Depth), 2));
+
+        // a stopped search ends before the first iteration
+        let mut board = Board::default();
+        let mut move_lists = (0..4).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        let mut control = ControlHandle::new();
+        control.stop();
+        iterative_deepening(
+            4,
+            &mut board,
+            &mut Evaluator::default(),
+            &MoveGenerator::new(),
+            &mut move_lists,
+            usize::MAX,
+            usize::MAX,
+            &mut control,
+            &mut context,
+        );
+        assert_eq!(context.stop_reason(), Some(StopReason::Stopped));
+    }
+
+    #[test]
+    fn adaptive_check_interval() {
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..20).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        let mut deepen = |evaluator: &mut SlowEvaluator<Evaluator>, context: &mut SearchContext, depth, hard_time_millis| {
+            iterative_deepening(
+                depth,
+                &mut board,
+                evaluator,
+                &move_generator,
+                &mut move_lists,
+                usize::MAX,
+                hard_time_millis,
+                &mut ControlHandle::new(),
+                context,
+            )
+        };
+        assert_eq!(context.check_interval(), STOP_CHECK_INTERVAL);
+
+        // with a slow evaluation the nodes between checks take longer than
+        // there is left, so the clock is checked more often
+        let mut evaluator = SlowEvaluator::from(Evaluator::default());
+        evaluator.delay = Some(std::time::Duration::from_micros(100));
+        deepen(&mut evaluator, &mut context, 20, 150);
+        assert!(context.aborted());
+        assert!(context.check_interval() < STOP_CHECK_INTERVAL);
+        assert!(context.check_interval() >= MIN_CHECK_INTERVAL);
+
+        // and less often again while the deadline is far off
+        evaluator.delay = None;
+        deepen(&mut evaluator, &mut context, 5, 10_000);
+        assert!(!context.aborted());
+        assert_eq!(context.check_interval(), STOP_CHECK_INTERVAL);
+    }
+
+    #[test]
+    fn reproducible() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        let move_generator = MoveGenerator::new();
+        let mut first = None;
+
+        // the same search gives the same result, whatever the zobrist keys.
+        // They decide which positions share a slot in the transposition
+        // table, so the node count is only the same with the fixed keys.
+        for i in 0..20 {
+            let mut board = Board::new(fen).unwrap();
+            if i % 2 == 1 {
+                board.rand_zobrist_table(&mut rand::thread_rng());
+            }
+            let mut evaluator = Evaluator::default();
+            let mut move_lists = (0..3).map(|_| MoveList::new()).collect();
+            let mut context = SearchContext::new();
+
+            let result = find_best_move(
+                3,
+                &mut board,
+                &mut evaluator,
+                &move_generator,
+                &mut move_lists,
+                &mut context,
+            )
+            .map(|(best_move, score)| (best_move, score, context.nodes()));
+
+            assert!(result.is_some());
+            match first {
+                None => first = result,
+                Some(_) if i % 2 == 0 => assert_eq!(result, first),
+                Some((best_move, score, _)) => assert_eq!(result.map(|(m, s, _)| (m, s)), Some((best_move, score))),
+            }
+        }
+    }
 }
@@ -53,8 +53,11 @@ impl Square {
 
     pub fn from_notation(string: &str) -> Option<Square> {
         let letter = string.chars().next()?;
-        let file = "abcdefgh".chars().position(|x| x == letter).unwrap();
+        let file = "abcdefgh".chars().position(|x| x == letter)?;
         let rank = string.chars().nth(1)?.to_digit(10)?;
+        if !(1..=8).contains(&rank) {
+            return None;
+        }
         Some(Square::from_rf(8 - rank as usize, file))
     }
 
@@ -0,0 +1,1323 @@
+// Probing of Syzygy WDL (win/draw/loss) endgame tablebases.
+//
+// The tables (*.rtbw files) give the result of every position with their
+// material under perfect play, taking the fifty move rule into account. The
+// format is described in Ronald de Man's original probing code, and this
+// follows the layout of Stockfish's tbprobe.cpp: each file stores one or two
+// (depending on whether the material is symmetric) tables of positions per
+// side to move, split by the file of the leading pawn when there are pawns.
+// A position is turned into an index by mapping the pieces into a canonical
+// part of the board and encoding them group by group, and the result is
+// found by decompressing the block of the table holding that index.
+//
+// The tables only store positions without castling rights, and are free to
+// store any value for positions where a capture or pawn move wins, so the
+// captures are always searched before the table is believed.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+
+use crate::engine::bitboard::BitBoardUtils;
+use crate::engine::board::{Board, Variant};
+use crate::engine::error::SyzygyError;
+use crate::engine::movegen::{MoveGenerator, MoveList};
+use crate::engine::piece::{Color, Pieces};
+use crate::engine::r#move::{Move, MoveUtils, UndoInfo, MOVE_TYPE_CASTLE, MOVE_TYPE_EN_PASSANT};
+
+// the most pieces (including kings) a table can have
+const TB_PIECES: usize = 7;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+
+// flags at the start of a file
+const FLAG_SPLIT: u8 = 1;
+const FLAG_HAS_PAWNS: u8 = 2;
+// flag for a table which stores the same value for every position
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+// the piece order used in file names
+const PIECE_CHARS: [(char, usize); 6] =
+    [('K', 5), ('Q', 4), ('R', 3), ('B', 2), ('N', 1), ('P', 0)];
+
+// A result from the point of view of the side to move. Cursed wins and
+// blessed losses are wins and losses which the fifty move rule turns into
+// draws.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Wdl {
+        match value {
+            i32::MIN..=-2 => Wdl::Loss,
+            -1 => Wdl::BlessedLoss,
+            0 => Wdl::Draw,
+            1 => Wdl::CursedWin,
+            _ => Wdl::Win,
+        }
+    }
+
+    // the same result from the other side's point of view
+    pub fn flip(self) -> Wdl {
+        Wdl::from_value(-(self as i32))
+    }
+}
+
+impl Display for Wdl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed win",
+            Wdl::Win => "win",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                               Encoding tables                              */
+/* -------------------------------------------------------------------------- */
+
+// Squares in this section are numbered from a1 = 0 to h8 = 63, as in the
+// tablebase code, rather than from a8 as on the Board.
+fn rank_of(sq: usize) -> usize {
+    sq >> 3
+}
+fn file_of(sq: usize) -> usize {
+    sq & 7
+}
+// negative below the a1-h8 diagonal, 0 on it, positive above it
+fn off_a1h8(sq: usize) -> i32 {
+    rank_of(sq) as i32 - file_of(sq) as i32
+}
+
+struct Encoding {
+    // binomial[k][n] is the number of ways of choosing k of n things
+    binomial: [[u64; 64]; TB_PIECES],
+    // the squares of the b1-h1-h7 triangle to 0..27
+    map_b1h1h7: [u64; 64],
+    // the squares of the a1-d1-d4 triangle to 0..9, diagonal squares last
+    map_a1d1d4: [u64; 64],
+    // the 462 ways of placing two kings with the first in the a1-d1-d4
+    // triangle, indexed by that king's map_a1d1d4 and the other's square
+    map_kk: [[u64; 64]; 10],
+    // pawn squares a2-h7 to 0..47, higher for the pawns which lead
+    map_pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; TB_PIECES],
+    lead_pawns_size: [[u64; 4]; TB_PIECES],
+}
+
+impl Encoding {
+    fn new() -> Encoding {
+        let mut e = Encoding {
+            binomial: [[0; 64]; TB_PIECES],
+            map_b1h1h7: [0; 64],
+            map_a1d1d4: [0; 64],
+            map_kk: [[0; 64]; 10],
+            map_pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; TB_PIECES],
+            lead_pawns_size: [[0; 4]; TB_PIECES],
+        };
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_a1h8(sq) < 0 {
+                e.map_b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for &sq in &[0, 1, 2, 3, 8, 9, 10, 11, 16, 17, 18, 19, 24, 25, 26, 27] {
+            if off_a1h8(sq) < 0 {
+                e.map_a1d1d4[sq] = code;
+                code += 1;
+            } else if off_a1h8(sq) == 0 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            e.map_a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // if the first king is on the diagonal, the other must not be above it
+        let mut both_on_diagonal = Vec::new();
+        let mut code = 0;
+        for idx in 0..10 {
+            // b1 is the only square of the triangle mapped to 0
+            let firsts: Vec<usize> = (0..28)
+                .filter(|&s1| e.map_a1d1d4[s1] == idx as u64 && (idx != 0 || s1 == 1))
+                .collect();
+            for s1 in firsts {
+                for s2 in 0..64 {
+                    let adjacent = (rank_of(s1) as i32 - rank_of(s2) as i32).abs() <= 1
+                        && (file_of(s1) as i32 - file_of(s2) as i32).abs() <= 1;
+                    if adjacent || (off_a1h8(s1) == 0 && off_a1h8(s2) > 0) {
+                        continue;
+                    }
+                    if off_a1h8(s1) == 0 && off_a1h8(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        e.map_kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            e.map_kk[idx][s2] = code;
+            code += 1;
+        }
+
+        e.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..TB_PIECES.min(n + 1) {
+                e.binomial[k][n] = if k > 0 { e.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { e.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // the leading pawn is the one nearest the edge, and of those on the
+        // same file the one with the lowest rank
+        let mut available = 47;
+        for lead_pawns in 1..TB_PIECES - 1 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_pawns == 1 {
+                        e.map_pawns[sq] = available;
+                        e.map_pawns[sq ^ 7] = available - 1;
+                        available = available.saturating_sub(2);
+                    }
+                    e.lead_pawn_idx[lead_pawns][sq] = idx;
+                    idx += e.binomial[lead_pawns - 1][e.map_pawns[sq] as usize];
+                }
+                e.lead_pawns_size[lead_pawns][file] = idx;
+            }
+        }
+
+        e
+    }
+
+    fn get() -> &'static Encoding {
+        static ENCODING: OnceLock<Encoding> = OnceLock::new();
+        ENCODING.get_or_init(Encoding::new)
+    }
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                 Table files                                */
+/* -------------------------------------------------------------------------- */
+
+// The data for one side to move (and one leading pawn file), compressed
+// with a canonical Huffman code over symbols which each expand into a pair
+// of symbols, recursively
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    // piece codes in the order they are encoded: 1-6 for pawn to king of
+    // the side named first in the file name, 9-14 for the other side
+    pieces: [u8; TB_PIECES],
+    // the sizes of the groups of pieces encoded together, 0 terminated, and
+    // the multiplier for each group's part of the index
+    group_len: [usize; TB_PIECES + 1],
+    group_idx: [u64; TB_PIECES + 1],
+
+    block_size: usize,
+    span: u64,
+    sparse_index_size: usize,
+    num_blocks: usize,
+    block_length_size: usize,
+    min_sym_len: u8,
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    btree: usize,
+
+    // offsets into the file
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*bytes.get(pos)?, *bytes.get(pos + 1)?]))
+}
+fn read_u32(bytes: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(pos..pos + 4)?.try_into().ok()?,
+    ))
+}
+fn read_u32_be(bytes: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(pos..pos + 4)?.try_into().ok()?,
+    ))
+}
+
+// the children of a symbol, packed into 3 bytes
+fn btree_left(bytes: &[u8], btree: usize, sym: usize) -> Option<usize> {
+    let lr = bytes.get(btree + 3 * sym..btree + 3 * sym + 3)?;
+    Some((((lr[1] & 0xF) as usize) << 8) | lr[0] as usize)
+}
+fn btree_right(bytes: &[u8], btree: usize, sym: usize) -> Option<usize> {
+    let lr = bytes.get(btree + 3 * sym..btree + 3 * sym + 3)?;
+    Some(((lr[2] as usize) << 4) | (lr[1] >> 4) as usize)
+}
+
+struct WdlTable {
+    bytes: Vec<u8>,
+    // the file name without the extension, eg. KRvKN
+    name: String,
+    symmetric: bool,
+    has_pawns: bool,
+    // whether the pieces are encoded as a group of three, rather than just
+    // the kings
+    has_unique_pieces: bool,
+    // pawns on both sides, so the other side's pawns are encoded separately
+    pp: bool,
+    piece_count: usize,
+    // indexed by leading pawn file (a-d, or just 0 without pawns) and side
+    // to move (0 being the side named first)
+    pairs: Vec<Vec<PairsData>>,
+}
+
+impl WdlTable {
+    fn load(path: &Path, name: &str) -> Result<WdlTable, SyzygyError> {
+        let path_name = path.display().to_string();
+        let bytes = std::fs::read(path).map_err(|e| SyzygyError::Io(path_name.clone(), e.to_string()))?;
+        WdlTable::parse(bytes, name).ok_or(SyzygyError::BadFile(path_name))
+    }
+
+    fn parse(bytes: Vec<u8>, name: &str) -> Option<WdlTable> {
+        let (first, second) = name.split_once('v')?;
+        let count = |side: &str, c: char| side.chars().filter(|&x| x == c).count();
+        let pawn_counts = [count(first, 'P'), count(second, 'P')];
+        let symmetric = first == second;
+        let has_pawns = pawn_counts.iter().any(|&n| n > 0);
+
+        // a piece other than a king which is the only one of its kind
+        let has_unique_pieces = [first, second]
+            .iter()
+            .any(|side| "QRBNP".chars().any(|c| count(side, c) == 1));
+
+        // the pawns of the side with fewer (but some) pawns lead
+        let mut lead_counts = pawn_counts;
+        if lead_counts[1] > 0 && (lead_counts[0] == 0 || lead_counts[1] < lead_counts[0]) {
+            lead_counts.swap(0, 1);
+        }
+
+        // pawns on both sides
+        let pp = has_pawns && lead_counts[1] > 0;
+
+        let mut table = WdlTable {
+            bytes: Vec::new(),
+            name: name.to_string(),
+            symmetric,
+            has_pawns,
+            has_unique_pieces,
+            pp,
+            piece_count: first.len() + second.len(),
+            pairs: Vec::new(),
+        };
+        if table.piece_count > TB_PIECES || bytes.get(..4)? != WDL_MAGIC {
+            return None;
+        }
+
+        let flags = *bytes.get(4)?;
+        if (flags & FLAG_HAS_PAWNS != 0) != has_pawns || (flags & FLAG_SPLIT != 0) == symmetric {
+            return None;
+        }
+
+        let sides = if symmetric { 1 } else { 2 };
+        let files = if has_pawns { 4 } else { 1 };
+
+        let mut pos = 5;
+        table.pairs = vec![vec![PairsData::default(); sides]; files];
+        for file in 0..files {
+            let order_byte = |offset: usize| -> Option<u8> { bytes.get(pos + offset).copied() };
+            let order = [
+                [
+                    order_byte(0)? & 0xF,
+                    if pp { order_byte(1)? & 0xF } else { 0xF },
+                ],
+                [
+                    order_byte(0)? >> 4,
+                    if pp { order_byte(1)? >> 4 } else { 0xF },
+                ],
+            ];
+            pos += 1 + pp as usize;
+
+            for k in 0..table.piece_count {
+                let byte = *bytes.get(pos)?;
+                for side in 0..sides {
+                    table.pairs[file][side].pieces[k] =
+                        if side == 1 { byte >> 4 } else { byte & 0xF };
+                }
+                pos += 1;
+            }
+
+            for (d, &order) in table.pairs[file].iter_mut().zip(&order) {
+                set_groups(
+                    d,
+                    order,
+                    file,
+                    table.piece_count,
+                    has_pawns,
+                    has_unique_pieces,
+                    pp,
+                );
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..files {
+            for side in 0..sides {
+                pos = set_sizes(&mut table.pairs[file][side], &bytes, pos)?;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut table.pairs[file][side];
+                d.sparse_index = pos;
+                pos += d.sparse_index_size * 6;
+            }
+        }
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut table.pairs[file][side];
+                d.block_length = pos;
+                pos += d.block_length_size * 2;
+            }
+        }
+        // the padding before the data isn't there if no table has any, as
+        // when every position has the same value
+        let mut end = pos;
+        for file in 0..files {
+            for side in 0..sides {
+                let d = &mut table.pairs[file][side];
+                pos = (pos + 0x3F) & !0x3F;
+                d.data = pos;
+                pos += d.num_blocks * d.block_size;
+                if d.num_blocks > 0 {
+                    end = pos;
+                }
+            }
+        }
+
+        if end > bytes.len() {
+            return None;
+        }
+        table.bytes = bytes;
+        Some(table)
+    }
+
+    // the stored value for the position, from the point of view of the
+    // side to move
+    fn probe(&self, board: &Board) -> Option<Wdl> {
+        let e = Encoding::get();
+
+        // positions are stored with the side named first as white, and only
+        // with white to move if the material is symmetric
+        let black_to_move = !board.friendly_color().is_white();
+        let black_stronger =
+            material_name(board, Color::White) + "v" + &material_name(board, Color::Black)
+                != self.name;
+        let flip = (self.symmetric && black_to_move) || black_stronger;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = (flip ^ black_to_move) as usize;
+
+        let mut squares = Vec::with_capacity(TB_PIECES);
+        let mut pieces = Vec::with_capacity(TB_PIECES);
+        let mut lead_pawns = 0u64;
+        let mut file = 0;
+
+        // the board numbers squares from a8, the tables from a1
+        let board_sq = |sq: usize| sq ^ 56;
+
+        if self.has_pawns {
+            let code = self.pairs[0][0].pieces[0] ^ flip_color;
+            let color = if code & 8 == 0 {
+                Color::White
+            } else {
+                Color::Black
+            };
+
+            for sq in 0..64 {
+                if board.pieces[board_sq(sq)] == Some(Pieces::pawn(color)) {
+                    squares.push(sq ^ flip_squares);
+                    lead_pawns |= 1 << sq;
+                }
+            }
+            let lead = (0..squares.len()).fold(0, |best, i| {
+                if e.map_pawns[squares[i]] > e.map_pawns[squares[best]] {
+                    i
+                } else {
+                    best
+                }
+            });
+            squares.swap(0, lead);
+            file = file_of(squares[0]).min(7 - file_of(squares[0]));
+        }
+        let lead_count = squares.len();
+
+        for sq in 0..64 {
+            if let Some(piece) = board.pieces[board_sq(sq)] {
+                if lead_pawns & (1 << sq) == 0 {
+                    squares.push(sq ^ flip_squares);
+                    pieces.push(piece_code(piece) ^ flip_color);
+                }
+            }
+        }
+        if squares.len() != self.piece_count {
+            return None;
+        }
+        // the lead pawns' pieces aren't needed, but keep the indices lined up
+        let mut pieces: Vec<u8> = std::iter::repeat_n(0, lead_count).chain(pieces).collect();
+
+        let d = self.pairs.get(file)?.get(stm)?;
+
+        // put the pieces in the order the table encodes them
+        let size = squares.len();
+        for i in lead_count..size - 1 {
+            if let Some(j) = (i + 1..size).find(|&j| pieces[j] == d.pieces[i]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        // the leading piece goes on the a-d files
+        if file_of(squares[0]) > 3 {
+            squares.iter_mut().for_each(|sq| *sq ^= 7);
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = e.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|&sq| e.map_pawns[sq]);
+            for (i, &sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += e.binomial[i][e.map_pawns[sq] as usize];
+            }
+        } else {
+            // and below the 5th rank
+            if rank_of(squares[0]) > 3 {
+                squares.iter_mut().for_each(|sq| *sq ^= 56);
+            }
+
+            // the first piece of the leading group which is off the a1-h8
+            // diagonal goes below it
+            for i in 0..d.group_len[0] {
+                if off_a1h8(squares[i]) == 0 {
+                    continue;
+                }
+                if off_a1h8(squares[i]) > 0 {
+                    for sq in squares[i..].iter_mut() {
+                        *sq = ((*sq >> 3) | (*sq << 3)) & 63;
+                    }
+                }
+                break;
+            }
+
+            idx = if self.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as u64;
+                let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+                let rank = |sq: usize| rank_of(sq) as u64;
+
+                if off_a1h8(s0) != 0 {
+                    (e.map_a1d1d4[s0] * 63 + (s1 as u64 - adjust1)) * 62 + s2 as u64 - adjust2
+                } else if off_a1h8(s1) != 0 {
+                    (6 * 63 + rank(s0) * 28 + e.map_b1h1h7[s1]) * 62 + s2 as u64 - adjust2
+                } else if off_a1h8(s2) != 0 {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + rank(s0) * 7 * 28
+                        + (rank(s1) - adjust1) * 28
+                        + e.map_b1h1h7[s2]
+                } else {
+                    6 * 63 * 62
+                        + 4 * 28 * 62
+                        + 4 * 7 * 28
+                        + rank(s0) * 7 * 6
+                        + (rank(s1) - adjust1) * 6
+                        + (rank(s2) - adjust2)
+                }
+            } else {
+                e.map_kk[e.map_a1d1d4[squares[0]] as usize][squares[1]]
+            };
+        }
+
+        // the remaining groups, each in ascending order of square, skipping
+        // the squares taken by earlier groups
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.pp;
+
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let end = start + d.group_len[next];
+            squares[start..end].sort_unstable();
+
+            let mut n = 0;
+            for i in 0..d.group_len[next] {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                n += e.binomial[i + 1][sq - adjust - 8 * remaining_pawns as usize];
+            }
+
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start = end;
+            next += 1;
+        }
+
+        let value = decompress_pairs(&self.bytes, d, idx)?;
+        Some(Wdl::from_value(value as i32 - 2))
+    }
+}
+
+// split the pieces into groups and work out the size of each group's part
+// of the index, in the order given for the table
+fn set_groups(
+    d: &mut PairsData,
+    order: [u8; 2],
+    file: usize,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    pp: bool,
+) {
+    let e = Encoding::get();
+
+    let mut n = 0;
+    let mut first_len: i32 = if has_pawns {
+        0
+    } else if has_unique_pieces {
+        3
+    } else {
+        2
+    };
+    d.group_len[n] = 1;
+    for i in 1..piece_count {
+        first_len -= 1;
+        if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+            d.group_len[n] += 1;
+        } else {
+            n += 1;
+            d.group_len[n] = 1;
+        }
+    }
+    n += 1;
+    d.group_len[n] = 0;
+
+    let mut next = if pp { 2 } else { 1 };
+    let mut free_squares = 64 - d.group_len[0] - if pp { d.group_len[1] } else { 0 };
+    let mut idx: u64 = 1;
+    let mut k = 0;
+    while next < n || k == order[0] as usize || k == order[1] as usize {
+        if k == order[0] as usize {
+            d.group_idx[0] = idx;
+            idx *= if has_pawns {
+                e.lead_pawns_size[d.group_len[0]][file]
+            } else if has_unique_pieces {
+                31332
+            } else {
+                462
+            };
+        } else if k == order[1] as usize {
+            d.group_idx[1] = idx;
+            idx *= e.binomial[d.group_len[1]][48 - d.group_len[0]];
+        } else {
+            d.group_idx[next] = idx;
+            idx *= e.binomial[d.group_len[next]][free_squares];
+            free_squares -= d.group_len[next];
+            next += 1;
+        }
+        k += 1;
+    }
+    d.group_idx[n] = idx;
+}
+
+// read the header of a table's compressed data, returning the position
+// after it
+fn set_sizes(d: &mut PairsData, bytes: &[u8], mut pos: usize) -> Option<usize> {
+    d.flags = *bytes.get(pos)?;
+    pos += 1;
+
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        d.min_sym_len = *bytes.get(pos)?;
+        return Some(pos + 1);
+    }
+
+    let groups = d.group_len.iter().position(|&len| len == 0)?;
+    let table_size = d.group_idx[groups];
+
+    d.block_size = 1 << bytes.get(pos)?;
+    d.span = 1 << bytes.get(pos + 1)?;
+    d.sparse_index_size = table_size.div_ceil(d.span) as usize;
+    let padding = *bytes.get(pos + 2)? as usize;
+    d.num_blocks = read_u32(bytes, pos + 3)? as usize;
+    d.block_length_size = d.num_blocks + padding;
+    let max_sym_len = *bytes.get(pos + 7)?;
+    d.min_sym_len = *bytes.get(pos + 8)?;
+    pos += 9;
+    if max_sym_len < d.min_sym_len || max_sym_len - d.min_sym_len >= 64 {
+        return None;
+    }
+
+    // the canonical code gives longer symbols lower values, so the lowest
+    // symbol of each length can be turned into the lowest 64 bit code of
+    // that length, which is used to find the length of a code
+    d.lowest_sym = pos;
+    let lengths = (max_sym_len - d.min_sym_len) as usize + 1;
+    d.base64 = vec![0; lengths];
+    for i in (0..lengths - 1).rev() {
+        let lowest = read_u16(bytes, pos + 2 * i)? as u64;
+        let next_lowest = read_u16(bytes, pos + 2 * (i + 1))? as u64;
+        d.base64[i] = (d.base64[i + 1] + lowest).checked_sub(next_lowest)? / 2;
+    }
+    for (i, base) in d.base64.iter_mut().enumerate() {
+        *base = base
+            .checked_shl((64 - i - d.min_sym_len as usize) as u32)
+            .unwrap_or(0);
+    }
+    pos += 2 * lengths;
+
+    let symbols = read_u16(bytes, pos)? as usize;
+    pos += 2;
+    d.btree = pos;
+
+    // the number of symbols each symbol expands to, less one
+    d.symlen = vec![0; symbols];
+    let mut visited = vec![false; symbols];
+    for sym in 0..symbols {
+        if !visited[sym] {
+            d.symlen[sym] = set_symlen(d, bytes, sym, &mut visited)?;
+        }
+    }
+
+    Some(pos + 3 * symbols + (symbols & 1))
+}
+
+fn set_symlen(d: &mut PairsData, bytes: &[u8], sym: usize, visited: &mut [bool]) -> Option<u8> {
+    visited[sym] = true;
+
+    let right = btree_right(bytes, d.btree, sym)?;
+    if right == 0xFFF {
+        return Some(0);
+    }
+    let left = btree_left(bytes, d.btree, sym)?;
+
+    for child in [left, right] {
+        if !*visited.get(child)? {
+            d.symlen[child] = set_symlen(d, bytes, child, visited)?;
+        }
+    }
+
+    Some(d.symlen[left].wrapping_add(d.symlen[right]).wrapping_add(1))
+}
+
+// the value stored at the index
+fn decompress_pairs(bytes: &[u8], d: &PairsData, idx: u64) -> Option<u16> {
+    if d.flags & FLAG_SINGLE_VALUE != 0 {
+        return Some(d.min_sym_len as u16);
+    }
+
+    // the sparse index gives the block and offset of every span'th value,
+    // from which the block holding this one is found
+    let k = (idx / d.span) as usize;
+    let entry = d.sparse_index + 6 * k;
+    let mut block = read_u32(bytes, entry)? as usize;
+    let mut offset = read_u16(bytes, entry + 4)? as i64;
+    offset += (idx % d.span) as i64 - (d.span / 2) as i64;
+
+    let block_length = |block: usize| -> Option<i64> {
+        if block >= d.block_length_size {
+            return None;
+        }
+        Some(read_u16(bytes, d.block_length + 2 * block)? as i64)
+    };
+    while offset < 0 {
+        block = block.checked_sub(1)?;
+        offset += block_length(block)? + 1;
+    }
+    while offset > block_length(block)? {
+        offset -= block_length(block)? + 1;
+        block += 1;
+    }
+
+    // the block is a stream of big endian codes
+    let mut ptr = d.data + block * d.block_size;
+    let mut buf64 = ((read_u32_be(bytes, ptr)? as u64) << 32) | read_u32_be(bytes, ptr + 4)? as u64;
+    ptr += 8;
+    let mut buf64_size = 64;
+
+    let mut sym;
+    loop {
+        let mut len = 0;
+        while len + 1 < d.base64.len() && buf64 < d.base64[len] {
+            len += 1;
+        }
+
+        let shift = (64 - len - d.min_sym_len as usize) as u32;
+        sym = (buf64 - d.base64[len]).checked_shr(shift).unwrap_or(0) as usize;
+        sym += read_u16(bytes, d.lowest_sym + 2 * len)? as usize;
+
+        let expands_to = *d.symlen.get(sym)? as i64 + 1;
+        if offset < expands_to {
+            break;
+        }
+        offset -= expands_to;
+
+        let len = len + d.min_sym_len as usize;
+        buf64 <<= len;
+        buf64_size -= len;
+        if buf64_size <= 32 {
+            buf64_size += 32;
+            buf64 |= (read_u32_be(bytes, ptr)? as u64) << (64 - buf64_size);
+            ptr += 4;
+        }
+    }
+
+    // expand the symbol until reaching the value at the offset
+    while d.symlen[sym] != 0 {
+        let left = btree_left(bytes, d.btree, sym)?;
+        let left_len = *d.symlen.get(left)? as i64 + 1;
+        if offset < left_len {
+            sym = left;
+        } else {
+            offset -= left_len;
+            sym = btree_right(bytes, d.btree, sym)?;
+        }
+    }
+
+    btree_left(bytes, d.btree, sym).map(|value| value as u16)
+}
+
+// 1-6 for white pawn to king, 9-14 for black
+fn piece_code(piece: Pieces) -> u8 {
+    let kind = (piece.idx() % 6) as u8 + 1;
+    if piece.color().is_white() {
+        kind
+    } else {
+        kind + 8
+    }
+}
+
+// the pieces of one side as they appear in file names, eg. KRP
+fn material_name(board: &Board, color: Color) -> String {
+    let mut name = String::new();
+    for &(c, kind) in &PIECE_CHARS {
+        let piece = Pieces::from_idx(kind + if color.is_white() { 0 } else { 6 });
+        for _ in 0..board.get_bb(piece).count_1s() {
+            name.push(c);
+        }
+    }
+    name
+}
+
+/* -------------------------------------------------------------------------- */
+/*                                   Probing                                  */
+/* -------------------------------------------------------------------------- */
+
+pub struct Tablebases {
+    // the files found, by name
+    paths: HashMap<String, PathBuf>,
+    max_pieces: usize,
+    // tables are read when they are first needed, None if that failed
+    tables: Mutex<HashMap<String, Option<Arc<WdlTable>>>>,
+    // why tables couldn't be read, since they were last taken
+    errors: Mutex<Vec<SyzygyError>>,
+}
+
+impl Tablebases {
+    // the path is a list of directories separated by ':' (';' on windows),
+    // as for the SyzygyPath option
+    pub fn new(path: &str) -> Tablebases {
+        let separator = if cfg!(windows) { ';' } else { ':' };
+        let mut paths = HashMap::new();
+
+        for dir in path.split(separator).filter(|dir| !dir.is_empty()) {
+            let entries = match std::fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().and_then(|ext| ext.to_str()) != Some("rtbw") {
+                    continue;
+                }
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    if is_table_name(name) {
+                        paths.entry(name.to_string()).or_insert(path);
+                    }
+                }
+            }
+        }
+
+        let max_pieces = paths.keys().map(|name| name.len() - 1).max().unwrap_or(0);
+        Tablebases {
+            paths,
+            max_pieces,
+            tables: Mutex::new(HashMap::new()),
+            errors: Mutex::new(Vec::new()),
+        }
+    }
+
+    // The tables which failed to load since the last call, each once. A
+    // table is read in the middle of a search, which can't stop for it, so
+    // the failure is kept for whoever ran the search to report.
+    pub fn take_errors(&self) -> Vec<SyzygyError> {
+        std::mem::take(&mut self.errors.lock().unwrap())
+    }
+
+    // the number of tables found
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    // the most pieces, including kings, of any table found
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // true if the position has few enough pieces to be looked up, and no
+    // castling rights (which the tables don't include)
+    pub fn can_probe(&self, board: &Board) -> bool {
+        let white = Color::White;
+        let black = Color::Black;
+
+        // the tables are for standard chess only
+        board.variant() == Variant::Standard
+            && board.get_occupancy().count_1s() <= self.max_pieces
+            && !(board.can_castle_ks(white)
+                || board.can_castle_qs(white)
+                || board.can_castle_ks(black)
+                || board.can_castle_qs(black))
+    }
+
+    fn table(&self, board: &Board) -> Option<Arc<WdlTable>> {
+        let white = material_name(board, Color::White);
+        let black = material_name(board, Color::Black);
+
+        // files are named with the stronger side first
+        let name = [
+            format!("{}v{}", white, black),
+            format!("{}v{}", black, white),
+        ]
+        .iter()
+        .find(|name| self.paths.contains_key(name.as_str()))?
+        .clone();
+
+        let mut tables = self.tables.lock().unwrap();
+        tables
+            .entry(name.clone())
+            .or_insert_with(|| match WdlTable::load(&self.paths[&name], &name) {
+                Ok(table) => Some(Arc::new(table)),
+                Err(e) => {
+                    self.errors.lock().unwrap().push(e);
+                    None
+                }
+            })
+            .clone()
+    }
+
+    // the stored value, without checking the captures
+    fn probe_table(&self, board: &Board) -> Option<Wdl> {
+        // only kings, for which there is no table
+        if board.get_occupancy().count_1s() == 2 {
+            return Some(Wdl::Draw);
+        }
+
+        self.table(board)?.probe(board)
+    }
+
+    // The result of the position for the side to move, or None if it can't
+    // be found (eg. the table is missing). The position must have no
+    // castling rights.
+    pub fn probe_wdl(&self, board: &mut Board, move_generator: &MoveGenerator) -> Option<Wdl> {
+        let mut moves = MoveList::new();
+        move_generator.gen_moves(board, &mut moves);
+
+        let mut best = Wdl::Loss;
+        let mut captures = 0;
+        let mut info = UndoInfo::default();
+
+        for i in 0..moves.len() {
+            let my_move = moves.at(i);
+            if !is_capture(board, my_move) {
+                continue;
+            }
+            captures += 1;
+
+            board.make_move(my_move, &mut info);
+            let value = self.probe_wdl(board, move_generator).map(Wdl::flip);
+            board.undo_move(my_move, &info);
+
+            let value = value?;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some(value);
+                }
+            }
+        }
+
+        // with only captures to play the table isn't needed, and may not
+        // be right (eg. if the only moves are en passant captures)
+        if captures > 0 && captures == moves.len() {
+            return Some(best);
+        }
+
+        // the table can store anything when a capture is at least as good
+        let value = self.probe_table(board)?;
+        Some(value.max(best))
+    }
+
+    // The root moves which keep the best result the tables allow, or None
+    // if any of them can't be probed
+    pub fn filter_root_moves(
+        &self,
+        board: &mut Board,
+        move_generator: &MoveGenerator,
+        moves: &[Move],
+    ) -> Option<Vec<Move>> {
+        let mut info = UndoInfo::default();
+        let mut results = Vec::with_capacity(moves.len());
+
+        for &my_move in moves {
+            board.make_move(my_move, &mut info);
+            let value = self.probe_wdl(board, move_generator).map(Wdl::flip);
+            board.undo_move(my_move, &info);
+            results.push((my_move, value?));
+        }
+
+        let best = results.iter().map(|&(_, value)| value).max()?;
+        Some(
+            results
+                .into_iter()
+                .filter(|&(_, value)| value == best)
+                .map(|(my_move, _)| my_move)
+                .collect(),
+        )
+    }
+}
+
+fn is_capture(board: &Board, my_move: Move) -> bool {
+    match my_move.get_move_type() {
+        MOVE_TYPE_EN_PASSANT => true,
+        MOVE_TYPE_CASTLE => false,
+        _ => board.pieces[my_move.get_move_end() as usize].is_some(),
+    }
+}
+
+// eg. KRPvKR
+fn is_table_name(name: &str) -> bool {
+    match name.split_once('v') {
+        Some((white, black)) => [white, black].iter().all(|side| {
+            side.starts_with('K')
+                && side[1..].chars().all(|c| "QRBNP".contains(c))
+                && side.chars().filter(|&c| c == 'K').count() == 1
+        }),
+        None => false,
+    }
+}
+
+// Writes a table in which every position has the same value for each side
+// to move (and leading pawn file), for the tests. Values are stored as the
+// Wdl + 2.
+#[cfg(test)]
+pub(crate) fn write_single_value_table(dir: &Path, name: &str, pieces: &[u8], values: &[[u8; 2]]) {
+    let has_pawns = values.len() == 4;
+    let mut bytes = WDL_MAGIC.to_vec();
+    bytes.push(FLAG_SPLIT | if has_pawns { FLAG_HAS_PAWNS } else { 0 });
+
+    for _ in values {
+        bytes.push(0);
+        bytes.extend(pieces.iter().map(|&piece| piece | piece << 4));
+    }
+    if bytes.len() & 1 != 0 {
+        bytes.push(0);
+    }
+    for sides in values {
+        for &value in sides {
+            bytes.extend([FLAG_SINGLE_VALUE, value]);
+        }
+    }
+
+    std::fs::write(dir.join(format!("{}.rtbw", name)), bytes).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::board::BoardBuilder;
+    use crate::engine::castling::{CastleSide, CastlingRights};
+    use crate::engine::search::{self, SearchContext, TB_WIN};
+    use crate::engine::square::Square;
+
+    // a directory holding single value KQvK and KPvK tables, which is
+    // removed when dropped
+    struct TestTables {
+        dir: PathBuf,
+        tablebases: Tablebases,
+    }
+
+    impl Drop for TestTables {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn test_tables(name: &str) -> TestTables {
+        let dir = PathBuf::from(crate::engine::iolog::temp_path(name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+
+        // the side with the queen wins, whoever is to move
+        write_single_value_table(&dir, "KQvK", &[6, 5, 14], &[[4, 0]]);
+        // a rook's pawn is a draw, any other pawn wins
+        write_single_value_table(&dir, "KPvK", &[1, 6, 14], &[[2, 2], [4, 0], [4, 0], [4, 0]]);
+        // not a table
+        std::fs::write(dir.join("KQvK.txt"), "").unwrap();
+
+        let tablebases = Tablebases::new(&dir.to_string_lossy());
+        TestTables { dir, tablebases }
+    }
+
+    fn probe(tablebases: &Tablebases, fen: &str) -> Option<Wdl> {
+        let mut board = Board::new(fen).unwrap();
+        tablebases.probe_wdl(&mut board, &MoveGenerator::new())
+    }
+
+    #[test]
+    fn encoding() {
+        let e = Encoding::get();
+
+        assert_eq!(e.binomial[2][5], 10);
+        assert_eq!(e.binomial[3][62], 37820);
+
+        // every placement of the kings has its own index
+        let mut codes: Vec<u64> = e
+            .map_kk
+            .iter()
+            .flatten()
+            .copied()
+            .filter(|&code| code != 0)
+            .collect();
+        codes.sort_unstable();
+        codes.dedup();
+        assert_eq!(codes.len(), 461);
+        assert_eq!(codes.last(), Some(&461));
+
+        // pawns on a2-h7
+        let mut pawns: Vec<u64> = (8..56).map(|sq| e.map_pawns[sq]).collect();
+        pawns.sort_unstable();
+        assert_eq!(pawns, (0..48).collect::<Vec<u64>>());
+        assert_eq!(e.lead_pawns_size[1].iter().sum::<u64>(), 24);
+    }
+
+    #[test]
+    fn names() {
+        assert!(is_table_name("KRPvKR"));
+        assert!(is_table_name("KvK"));
+        assert!(!is_table_name("KRvKK"));
+        assert!(!is_table_name("KXvK"));
+        assert!(!is_table_name("KRK"));
+
+        let board = BoardBuilder::new()
+            .piece(Square::E8, Pieces::BlackKing)
+            .piece(Square::A7, Pieces::BlackPawn)
+            .piece(Square::B7, Pieces::BlackPawn)
+            .piece(Square::A1, Pieces::WhiteQueen)
+            .piece(Square::B1, Pieces::WhiteRook)
+            .piece(Square::C1, Pieces::WhiteBishop)
+            .piece(Square::D1, Pieces::WhiteKnight)
+            .piece(Square::E1, Pieces::WhiteKing)
+            .build()
+            .unwrap();
+        assert_eq!(material_name(&board, Color::White), "KQRBN");
+        assert_eq!(material_name(&board, Color::Black), "KPP");
+    }
+
+    #[test]
+    fn single_value_tables() {
+        let tables = test_tables("syzygy_single");
+        let tablebases = &tables.tablebases;
+        assert_eq!(tablebases.len(), 2);
+        assert_eq!(tablebases.max_pieces(), 3);
+
+        assert_eq!(
+            probe(tablebases, "4k3/8/8/8/8/8/8/3QK3 w - - 0 1"),
+            Some(Wdl::Win)
+        );
+        assert_eq!(
+            probe(tablebases, "4k3/8/8/8/8/8/8/3QK3 b - - 0 1"),
+            Some(Wdl::Loss)
+        );
+        // the same with the colours swapped
+        assert_eq!(
+            probe(tablebases, "3qk3/8/8/8/8/8/8/4K3 b - - 0 1"),
+            Some(Wdl::Win)
+        );
+        assert_eq!(
+            probe(tablebases, "3qk3/8/8/8/8/8/8/4K3 w - - 0 1"),
+            Some(Wdl::Loss)
+        );
+
+        // the queen can be taken, leaving a draw
+        assert_eq!(
+            probe(tablebases, "4k3/3Q4/8/8/8/8/8/4K3 b - - 0 1"),
+            Some(Wdl::Draw)
+        );
+
+        // the table for the leading pawn's file
+        assert_eq!(
+            probe(tablebases, "7k/8/8/8/8/8/P7/K7 w - - 0 1"),
+            Some(Wdl::Draw)
+        );
+        assert_eq!(
+            probe(tablebases, "7k/8/8/8/8/8/7P/K7 w - - 0 1"),
+            Some(Wdl::Draw)
+        );
+        assert_eq!(
+            probe(tablebases, "7k/8/8/8/8/8/4P3/K7 w - - 0 1"),
+            Some(Wdl::Win)
+        );
+        assert_eq!(
+            probe(tablebases, "7k/8/8/8/8/8/4P3/K7 b - - 0 1"),
+            Some(Wdl::Loss)
+        );
+        assert_eq!(
+            probe(tablebases, "7k/4p3/8/8/8/8/8/K7 b - - 0 1"),
+            Some(Wdl::Win)
+        );
+        assert_eq!(
+            probe(tablebases, "7k/p7/8/8/8/8/8/K7 w - - 0 1"),
+            Some(Wdl::Draw)
+        );
+
+        // missing tables, too many pieces and castling rights
+        assert_eq!(probe(tablebases, "4k3/8/8/8/8/8/8/3RK3 w - - 0 1"), None);
+        let board = BoardBuilder::new()
+            .piece(Square::E8, Pieces::BlackKing)
+            .piece(Square::E1, Pieces::WhiteKing)
+            .piece(Square::H1, Pieces::WhiteRook)
+            .castling(CastlingRights::only(Color::White, CastleSide::KingSide))
+            .build()
+            .unwrap();
+        assert!(!tablebases.can_probe(&board));
+        let board = board.into_builder().castling(CastlingRights::NONE).build().unwrap();
+        assert!(tablebases.can_probe(&board));
+    }
+
+    #[test]
+    fn root_moves() {
+        let tables = test_tables("syzygy_root");
+        let tablebases = &tables.tablebases;
+        let move_generator = MoveGenerator::new();
+        let mut board = Board::new("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+
+        let mut list = MoveList::new();
+        move_generator.gen_moves(&mut board, &mut list);
+        let moves: Vec<Move> = (0..list.len()).map(|i| list.at(i)).collect();
+
+        // only the moves which hang the queen are left out
+        let kept = tablebases
+            .filter_root_moves(&mut board, &move_generator, &moves)
+            .unwrap();
+        let mut dropped: Vec<String> = moves
+            .iter()
+            .filter(|my_move| !kept.contains(my_move))
+            .map(|my_move| my_move.move_to_string())
+            .collect();
+        dropped.sort();
+        assert_eq!(dropped, vec!["d1d7", "d1d8"]);
+    }
+
+    #[test]
+    fn search_scores() {
+        let tables = test_tables("syzygy_search");
+        let tablebases = Arc::new(Tablebases::new(&tables.dir.to_string_lossy()));
+        let mut board = Board::new("4k3/8/8/8/8/8/3r4/3QK3 w - - 0 1").unwrap();
+        let move_generator = MoveGenerator::new();
+        let mut move_lists = (0..2).map(|_| MoveList::new()).collect();
+        let mut context = SearchContext::new();
+        context.set_tablebases(Some(tablebases));
+
+        // taking the rook reaches a won table position
+        let (best_move, score) = search::find_best_move(
+            2,
+            &mut board,
+            &mut crate::engine::eval::MaterialOnly,
+            &move_generator,
+            &mut move_lists,
+            &mut context,
+        )
+        .unwrap();
+
+        assert!(["d1d2", "e1d2"].contains(&best_move.move_to_string().as_str()));
+        assert!(score > TB_WIN - 10);
+        assert!(context.tb_hits() > 0);
+    }
+
+    #[test]
+    fn unreadable_tables() {
+        let tables = test_tables("syzygy_unreadable");
+        let path = tables.dir.join("KRvK.rtbw");
+        std::fs::write(&path, [0u8; 16]).unwrap();
+        let tablebases = Tablebases::new(&tables.dir.to_string_lossy());
+
+        // the position isn't found, and why is kept until taken, once
+        assert_eq!(probe(&tablebases, "4k3/8/8/8/8/8/8/3RK3 w - - 0 1"), None);
+        assert_eq!(probe(&tablebases, "4k3/8/8/8/8/8/8/3RK3 b - - 0 1"), None);
+        assert_eq!(tablebases.take_errors(), vec![SyzygyError::BadFile(path.display().to_string())]);
+        assert_eq!(tablebases.take_errors(), vec![]);
+
+        // the others still load
+        assert_eq!(probe(&tablebases, "4k3/8/8/8/8/8/8/3QK3 w - - 0 1"), Some(Wdl::Win));
+        assert_eq!(tablebases.take_errors(), vec![]);
+    }
+
+    // Checks against real tables, which SYZYGY_PATH has to name a directory
+    // of, holding at least the 3 and 4 piece tables:
+    //
+    //     SYZYGY_PATH=<dir> cargo test real_tables -- --ignored
+    #[test]
+    #[ignore]
+    fn real_tables() {
+        let path = std::env::var("SYZYGY_PATH").expect("SYZYGY_PATH should name the tables' directory");
+        let tablebases = Tablebases::new(&path);
+
+        for (fen, wdl) in &[
+            ("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", Wdl::Win),
+            ("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1", Wdl::Win.flip()),
+            ("4k3/8/8/8/8/4K3/4P3/8 b - - 0 1", Wdl::Loss),
+            ("k7/8/K7/P7/8/8/8/8 w - - 0 1", Wdl::Draw),
+            ("4k3/4P3/4K3/8/8/8/8/8 b - - 0 1", Wdl::Draw),
+            ("8/8/8/8/8/8/8/KR5k w - - 0 1", Wdl::Win),
+            ("8/8/8/8/8/8/2k5/KR6 w - - 0 1", Wdl::Win),
+            ("8/8/8/8/8/2k5/8/KN6 w - - 0 1", Wdl::Draw),
+            ("8/8/8/3k4/8/8/8/KQ5r w - - 0 1", Wdl::Win),
+        ] {
+            assert_eq!(probe(&tablebases, fen), Some(*wdl), "{}", fen);
+        }
+    }
+}
@@ -0,0 +1,304 @@
+// Decides how long to think for a move given the clock. Two limits are
+// produced: the soft limit is checked between iterations of iterative
+// deepening, and the hard limit aborts the search part way through, which
+// gives a search that needs longer than usual room to finish an iteration.
+//
+// With movestogo the time is shared between the moves left before the next
+// time control, otherwise (sudden death) as if there were always the same
+// number of moves left.
+
+pub const DEFAULT_MOVE_OVERHEAD: usize = 30;
+pub const MAX_MOVE_OVERHEAD: usize = 5000;
+pub const DEFAULT_TIME_FLOOR: usize = 50;
+pub const DEFAULT_OVERSHOOT_MARGIN: usize = 10;
+pub const MAX_OVERSHOOT_MARGIN: usize = 1000;
+
+// in sudden death, the clock is split as if this many moves were left to play
+const MOVES_TO_GO: usize = 30;
+// with movestogo, the clock is split as if there were this many more moves
+// to play, so that there is always some left over at the time control
+const MOVES_TO_GO_BUFFER: usize = 2;
+// the hard limit as a multiple of the soft limit
+const HARD_LIMIT_FACTOR: usize = 3;
+// below this much remaining time, at most half of what is left is used
+const EMERGENCY_MILLIS: usize = 2000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeLimits {
+    pub soft: usize,
+    pub hard: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TimeManager {
+    // expected delay between the engine sending a move and the clock
+    // stopping, eg. network or GUI latency
+    pub move_overhead: usize,
+    // time which is never used, whatever the other limits say
+    pub floor: usize,
+    // how far past the hard limit a search can run before it is reported
+    pub overshoot_margin: usize,
+}
+
+impl Default for TimeManager {
+    fn default() -> TimeManager {
+        TimeManager {
+            move_overhead: DEFAULT_MOVE_OVERHEAD,
+            floor: DEFAULT_TIME_FLOOR,
+            overshoot_margin: DEFAULT_OVERSHOOT_MARGIN,
+        }
+    }
+}
+
+impl TimeManager {
+    // the milliseconds a search which took elapsed ran past its hard limit,
+    // if more than the margin
+    pub fn overshoot(&self, limits: TimeLimits, elapsed: usize) -> Option<usize> {
+        elapsed
+            .checked_sub(limits.hard)
+            .filter(|&overshoot| overshoot > self.overshoot_margin)
+    }
+
+    // limits in milliseconds for the side to move, moves_to_go being the
+    // number of moves (including this one) until the next time control
+    pub fn limits(
+        &self,
+        remaining: usize,
+        increment: usize,
+        moves_to_go: Option<usize>,
+    ) -> TimeLimits {
+        // movestogo 0 is taken to mean this is the last move before the
+        // control, the same as 1
+        let moves_to_go = moves_to_go.map(|moves| moves.max(1));
+        let share = match moves_to_go {
+            Some(moves) => remaining / (moves + MOVES_TO_GO_BUFFER),
+            None => remaining / MOVES_TO_GO,
+        };
+        let soft = share + increment * 3 / 4;
+
+        // the time that can be spent without running into the floor, once
+        // the overhead has been taken off
+        let usable = remaining
+            .saturating_sub(self.floor)
+            .saturating_sub(self.move_overhead);
+
+        // never plan on spending all of it, and when short of time no more
+        // than half of what is left
+        let mut max_hard = if remaining < EMERGENCY_MILLIS {
+            usable / 2
+        } else {
+            usable * 3 / 4
+        };
+
+        // and leave the moves after this one before the control at least
+        // half of an even share each
+        if let Some(moves) = moves_to_go {
+            max_hard = max_hard.min(usable * 2 / moves);
+        }
+
+        let hard = (soft * HARD_LIMIT_FACTOR)
+            .saturating_sub(self.move_overhead)
+            .min(max_hard);
+
+        TimeLimits {
+            soft: soft.min(hard),
+            hard,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hard_limit_leaves_floor() {
+        for &remaining in &[
+            0, 1, 10, 49, 50, 51, 80, 100, 500, 1999, 2000, 2001, 10_000, 60_000, 3_600_000,
+        ] {
+            for &increment in &[0, 1, 100, 1000, 2000, 10_000, 100_000] {
+                for &move_overhead in &[0, 1, 30, 100, 1000, MAX_MOVE_OVERHEAD] {
+                    for &floor in &[0, 1, DEFAULT_TIME_FLOOR, 500] {
+                        let manager = TimeManager {
+                            move_overhead,
+                            floor,
+                            ..TimeManager::default()
+                        };
+                        for &moves_to_go in &[None, Some(0), Some(1), Some(2), Some(10), Some(40)] {
+                            let limits = manager.limits(remaining, increment, moves_to_go);
+                            let case = (
+                                remaining,
+                                increment,
+                                move_overhead,
+                                floor,
+                                moves_to_go,
+                                limits,
+                            );
+
+                            assert!(limits.soft <= limits.hard, "{:?}", case);
+                            if remaining > floor {
+                                assert!(limits.hard < remaining - floor, "{:?}", case);
+                            } else {
+                                assert_eq!(limits.hard, 0, "{:?}", case);
+                            }
+                            if remaining < EMERGENCY_MILLIS {
+                                assert!(limits.hard <= remaining / 2, "{:?}", case);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn overshoot() {
+        let manager = TimeManager::default();
+        let limits = TimeLimits { soft: 100, hard: 300 };
+        assert_eq!(manager.overshoot(limits, 200), None);
+        assert_eq!(manager.overshoot(limits, 300 + DEFAULT_OVERSHOOT_MARGIN), None);
+        assert_eq!(manager.overshoot(limits, 301 + DEFAULT_OVERSHOOT_MARGIN), Some(1 + DEFAULT_OVERSHOOT_MARGIN));
+
+        let strict = TimeManager {
+            overshoot_margin: 0,
+            ..manager
+        };
+        assert_eq!(strict.overshoot(limits, 301), Some(1));
+    }
+
+    #[test]
+    fn overhead() {
+        let limits = TimeManager {
+            move_overhead: 0,
+            floor: 0,
+            ..TimeManager::default()
+        }
+        .limits(60_000, 0, None);
+        assert_eq!(
+            limits,
+            TimeLimits {
+                soft: 2000,
+                hard: 6000
+            }
+        );
+
+        let limits = TimeManager {
+            move_overhead: 30,
+            floor: 0,
+            ..TimeManager::default()
+        }
+        .limits(60_000, 0, None);
+        assert_eq!(
+            limits,
+            TimeLimits {
+                soft: 2000,
+                hard: 5970
+            }
+        );
+    }
+
+    struct Control {
+        name: &'static str,
+        // the time at the start of each control, added to what is left
+        time: usize,
+        moves: usize,
+        increment: usize,
+    }
+
+    // Plays the controls one after another, spending the soft limit (or
+    // the hard limit every move) plus the overhead on each move, and
+    // returns the least time left before any move and at the end of each
+    // control
+    fn play(control: &Control, controls: usize, use_hard: bool) -> (usize, Vec<usize>) {
+        let manager = TimeManager::default();
+        let mut remaining = 0;
+        let mut least = usize::MAX;
+        let mut left_over = Vec::new();
+
+        for _ in 0..controls {
+            remaining += control.time;
+            for moves_to_go in (1..=control.moves).rev() {
+                least = least.min(remaining);
+                let limits = manager.limits(remaining, control.increment, Some(moves_to_go));
+                assert!(limits.soft <= limits.hard, "{}: {:?}", control.name, limits);
+
+                let spent = if use_hard { limits.hard } else { limits.soft };
+                remaining = remaining - spent - manager.move_overhead + control.increment;
+            }
+            left_over.push(remaining);
+        }
+
+        (least, left_over)
+    }
+
+    #[test]
+    fn moves_to_go() {
+        for control in &[
+            Control {
+                name: "40 moves in 5 minutes, repeating",
+                time: 300_000,
+                moves: 40,
+                increment: 0,
+            },
+            Control {
+                name: "40 moves in 90 minutes + 30 seconds",
+                time: 5_400_000,
+                moves: 40,
+                increment: 30_000,
+            },
+            Control {
+                name: "10 moves in 10 seconds",
+                time: 10_000,
+                moves: 10,
+                increment: 0,
+            },
+        ] {
+            // the clock never runs below the floor, even if the hard limit
+            // is used every move
+            let (least, _) = play(control, 3, true);
+            assert!(least > DEFAULT_TIME_FLOOR, "{}: {}", control.name, least);
+
+            // and most of the time is used, with some left at each control
+            let (least, left_over) = play(control, 3, false);
+            assert!(least > DEFAULT_TIME_FLOOR, "{}: {}", control.name, least);
+            for left in left_over {
+                let added = control.time + control.moves * control.increment;
+                assert!(left > 0, "{}: {}", control.name, left);
+                assert!(
+                    left < added / 2,
+                    "{}: {} of {} left",
+                    control.name,
+                    left,
+                    added
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn last_move_before_control() {
+        let manager = TimeManager::default();
+
+        for &remaining in &[1000, 10_000, 60_000] {
+            let limits = manager.limits(remaining, 0, Some(1));
+            let sudden_death = manager.limits(remaining, 0, None);
+
+            // more than usual, as the time is about to be added to, but
+            // still leaving some
+            assert!(limits.soft > sudden_death.soft, "{:?}", limits);
+            assert!(limits.soft <= remaining / 3, "{:?}", limits);
+            assert!(limits.hard <= remaining * 3 / 4, "{:?}", limits);
+            assert_eq!(manager.limits(remaining, 0, Some(0)), limits);
+        }
+
+        // with more moves to go each gets less
+        let limits: Vec<TimeLimits> = [1, 2, 5, 20, 40]
+            .iter()
+            .map(|&moves| manager.limits(60_000, 0, Some(moves)))
+            .collect();
+        for pair in limits.windows(2) {
+            assert!(pair[0].soft > pair[1].soft, "{:?}", pair);
+            assert!(pair[0].hard >= pair[1].hard, "{:?}", pair);
+        }
+    }
+}
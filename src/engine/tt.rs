@@ -0,0 +1,590 @@
+// The transposition table: scores of positions already searched, found by
+// their zobrist hash, so that a position reached again by another move
+// order (or in the next search) isn't searched from scratch.
+//
+// Each hash has one slot. A new entry replaces the one there if it was
+// searched at least as deeply, or if the old entry is from an earlier
+// search, so the results of deep searches aren't pushed out by the many
+// shallow ones below them.
+//
+// The table is shared by every search thread without a lock. An entry is
+// packed into one word, and stored next to its hash xored with that word,
+// so an entry half written by one thread while another reads it doesn't
+// match the hash and is ignored.
+
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::engine::error::TtError;
+use crate::engine::r#move::Move;
+use crate::engine::search::{is_mate_score, TB_WIN};
+
+pub const DEFAULT_TT_ENTRIES: usize = 1 << 16;
+
+// the sizes the UCI Hash option allows, in megabytes
+pub const DEFAULT_HASH_MB: usize = 64;
+pub const MAX_HASH_MB: usize = 4096;
+
+// A saved table starts with this, then the version, the number of slots in
+// the table saved, its generation and the zobrist checksum of the boards it
+// was filled from. Then the count of entries, and each entry's hash and
+// packed data, all little endian. Empty slots aren't saved.
+const FILE_MAGIC: &[u8; 4] = b"CRTT";
+const FILE_VERSION: u32 = 1;
+
+// scores this close to TB_WIN depend on the ply they were found at
+const TB_SCORE_MARGIN: i32 = 1_000;
+
+// what the stored score says about the real one
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Bound {
+    Exact,
+    // the search failed high, so the score is at least this
+    Lower,
+    // no move reached alpha, so the score is at most this
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TtEntry {
+    pub hash: u64,
+    pub depth: usize,
+    pub score: i32,
+    pub bound: Bound,
+    pub best_move: Option<Move>,
+    // the search which stored it
+    generation: u8,
+}
+
+impl TtEntry {
+    // the score if it settles the node, searched to depth with the window
+    // alpha to beta
+    pub fn cutoff(&self, depth: usize, alpha: i32, beta: i32) -> Option<i32> {
+        if self.depth < depth {
+            return None;
+        }
+
+        match self.bound {
+            Bound::Exact => Some(self.score),
+            Bound::Lower if self.score >= beta => Some(self.score),
+            Bound::Upper if self.score <= alpha => Some(self.score),
+            _ => None,
+        }
+    }
+}
+
+// the generation is kept in this many bits of an entry, which is enough
+// to tell this search from the last few
+const GENERATION_BITS: u32 = 6;
+const GENERATION_MASK: u8 = (1 << GENERATION_BITS) - 1;
+
+impl TtEntry {
+    // from the low bits up: the move (0 for none, which is never a real
+    // move), score, depth, bound and generation
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 1,
+            Bound::Lower => 2,
+            Bound::Upper => 3,
+        };
+
+        self.best_move.unwrap_or(0) as u64
+            | (self.score as u32 as u64) << 16
+            | (self.depth.min(u8::MAX as usize) as u64) << 48
+            | bound << 56
+            | ((self.generation & GENERATION_MASK) as u64) << 58
+    }
+
+    // None for an empty slot, as no entry has a bound of 0
+    fn unpack(hash: u64, data: u64) -> Option<TtEntry> {
+        let bound = match (data >> 56) & 3 {
+            1 => Bound::Exact,
+            2 => Bound::Lower,
+            3 => Bound::Upper,
+            _ => return None,
+        };
+
+        Some(TtEntry {
+            hash,
+            depth: ((data >> 48) & 0xff) as usize,
+            score: (data >> 16) as u32 as i32,
+            bound,
+            best_move: match data as u16 {
+                0 => None,
+                my_move => Some(my_move),
+            },
+            generation: (data >> 58) as u8,
+        })
+    }
+}
+
+#[derive(Default)]
+struct Slot {
+    // the hash xored with the data
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    fn load(&self) -> Option<TtEntry> {
+        let data = self.data.load(Ordering::Relaxed);
+        TtEntry::unpack(self.key.load(Ordering::Relaxed) ^ data, data)
+    }
+}
+
+pub struct TranspositionTable {
+    entries: Vec<Slot>,
+    generation: AtomicU8,
+}
+
+impl Default for TranspositionTable {
+    fn default() -> TranspositionTable {
+        TranspositionTable::new(DEFAULT_TT_ENTRIES)
+    }
+}
+
+impl TranspositionTable {
+    // a table of 0 entries stores nothing
+    pub fn new(entries: usize) -> TranspositionTable {
+        TranspositionTable {
+            entries: (0..entries).map(|_| Slot::default()).collect(),
+            generation: AtomicU8::new(0),
+        }
+    }
+
+    // as many entries as fit in the memory
+    pub fn entries_in_megabytes(megabytes: usize) -> usize {
+        megabytes * 1024 * 1024 / std::mem::size_of::<Slot>()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // forget everything, as for a new game
+    pub fn clear(&self) {
+        for slot in &self.entries {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+        self.generation.store(0, Ordering::Relaxed);
+    }
+
+    // entries from before now can be replaced by shallower ones
+    pub fn new_search(&self) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        self.generation
+            .store(generation.wrapping_add(1) & GENERATION_MASK, Ordering::Relaxed);
+    }
+
+    // Starts loading the position's entry into the cache, so a probe soon
+    // after doesn't wait on memory. Only x86_64 has the hint, elsewhere
+    // this does nothing.
+    pub fn prefetch(&self, hash: u64) {
+        #[cfg(target_arch = "x86_64")]
+        if let Some(idx) = self.index(hash) {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            // every x86_64 cpu has sse, and a prefetch reads nothing
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(&self.entries[idx] as *const Slot as *const i8) };
+        }
+        #[cfg(not(target_arch = "x86_64"))]
+        let _ = hash;
+    }
+
+    // The entry for the position, if there is one. The score is relative to
+    // the node at ply.
+    pub fn probe(&self, hash: u64, ply: usize) -> Option<TtEntry> {
+        let entry = self.entries.get(self.index(hash)?)?.load()?;
+        if entry.hash != hash {
+            return None;
+        }
+
+        Some(TtEntry {
+            score: score_from_tt(entry.score, ply),
+            ..entry
+        })
+    }
+
+    pub fn store(
+        &self,
+        hash: u64,
+        ply: usize,
+        depth: usize,
+        score: i32,
+        bound: Bound,
+        best_move: Option<Move>,
+    ) {
+        let generation = self.generation.load(Ordering::Relaxed);
+        let slot = match self.index(hash) {
+            Some(idx) => &self.entries[idx],
+            None => return,
+        };
+
+        // another thread may store here first, in which case one of the two
+        // entries is lost
+        let old = slot.load();
+        let replace = match old {
+            Some(old) => old.hash == hash || old.generation != generation || depth >= old.depth,
+            None => true,
+        };
+
+        if replace {
+            // keep the old move if this search didn't find one
+            let best_move = match old {
+                Some(old) if old.hash == hash => best_move.or(old.best_move),
+                _ => best_move,
+            };
+
+            let data = TtEntry {
+                hash,
+                depth,
+                score: score_to_tt(score, ply),
+                bound,
+                best_move,
+                generation,
+            }
+            .pack();
+            slot.key.store(hash ^ data, Ordering::Relaxed);
+            slot.data.store(data, Ordering::Relaxed);
+        }
+    }
+
+    // Writes every entry, with the checksum of the zobrist keys their hashes
+    // were made with. Returns the number of entries written.
+    pub fn save_to(&self, writer: &mut impl Write, zobrist_checksum: u64) -> std::io::Result<usize> {
+        let entries: Vec<(u64, u64)> = self
+            .entries
+            .iter()
+            .filter_map(|slot| {
+                let data = slot.data.load(Ordering::Relaxed);
+                slot.load().map(|entry| (entry.hash, data))
+            })
+            .collect();
+
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_all(&FILE_VERSION.to_le_bytes())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        writer.write_all(&[self.generation.load(Ordering::Relaxed)])?;
+        writer.write_all(&zobrist_checksum.to_le_bytes())?;
+        writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (hash, data) in &entries {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&data.to_le_bytes())?;
+        }
+        writer.flush()?;
+
+        Ok(entries.len())
+    }
+
+    // Replaces the table's contents with those saved by save_to, refusing
+    // anything saved with other zobrist keys. The table keeps its size: if
+    // two entries land in the same slot the deeper one is kept. Returns the
+    // number of entries loaded. name is used in the errors.
+    pub fn load_from(&self, reader: &mut impl Read, zobrist_checksum: u64, name: &str) -> Result<usize, TtError> {
+        let bad_file = |_| TtError::BadFile(name.to_string());
+        let read_u64 = |reader: &mut dyn Read| -> Result<u64, TtError> {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes).map_err(bad_file)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+
+        let mut header = [0; 8];
+        reader.read_exact(&mut header).map_err(bad_file)?;
+        if &header[..4] != FILE_MAGIC || header[4..8] != FILE_VERSION.to_le_bytes() {
+            return Err(TtError::BadFile(name.to_string()));
+        }
+        // the entries are placed by their hashes, so the saved size isn't
+        // needed
+        read_u64(reader)?;
+        let mut generation = [0];
+        reader.read_exact(&mut generation).map_err(bad_file)?;
+        if read_u64(reader)? != zobrist_checksum {
+            return Err(TtError::ZobristMismatch(name.to_string()));
+        }
+
+        let count = read_u64(reader)?;
+        let mut saved = Vec::new();
+        for _ in 0..count {
+            let hash = read_u64(reader)?;
+            let data = read_u64(reader)?;
+            match TtEntry::unpack(hash, data) {
+                Some(entry) => saved.push((entry, data)),
+                None => return Err(TtError::BadFile(name.to_string())),
+            }
+        }
+
+        self.clear();
+        self.generation.store(generation[0] & GENERATION_MASK, Ordering::Relaxed);
+
+        // shallowest first, so that deeper entries replace them
+        saved.sort_by_key(|(entry, _)| entry.depth);
+        let mut loaded = 0;
+        for (entry, data) in saved {
+            if let Some(idx) = self.index(entry.hash) {
+                let slot = &self.entries[idx];
+                if slot.load().is_none() {
+                    loaded += 1;
+                }
+                slot.key.store(entry.hash ^ data, Ordering::Relaxed);
+                slot.data.store(data, Ordering::Relaxed);
+            }
+        }
+
+        Ok(loaded)
+    }
+
+    pub fn save(&self, path: &str, zobrist_checksum: u64) -> Result<usize, TtError> {
+        let io_error = |e: std::io::Error| TtError::Io(path.to_string(), e.to_string());
+        let file = std::fs::File::create(path).map_err(io_error)?;
+        self.save_to(&mut std::io::BufWriter::new(file), zobrist_checksum)
+            .map_err(io_error)
+    }
+
+    pub fn load(&self, path: &str, zobrist_checksum: u64) -> Result<usize, TtError> {
+        let file = std::fs::File::open(path).map_err(|e| TtError::Io(path.to_string(), e.to_string()))?;
+        self.load_from(&mut std::io::BufReader::new(file), zobrist_checksum, path)
+    }
+
+    fn index(&self, hash: u64) -> Option<usize> {
+        match self.entries.len() {
+            0 => None,
+            len => Some((hash % len as u64) as usize),
+        }
+    }
+}
+
+// Tablebase and mate scores count down with the ply they are found at,
+// which differs between the nodes reaching a position, so they are stored
+// counted from the position itself.
+fn is_ply_score(score: i32) -> bool {
+    (TB_WIN - TB_SCORE_MARGIN..=TB_WIN).contains(&score.abs()) || is_mate_score(score)
+}
+
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    match score {
+        s if is_ply_score(s) && s > 0 => s + ply as i32,
+        s if is_ply_score(s) => s - ply as i32,
+        s => s,
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    match score {
+        s if is_ply_score(s) && s > 0 => s - ply as i32,
+        s if is_ply_score(s) => s + ply as i32,
+        s => s,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::board::Board;
+    use crate::engine::control::ControlHandle;
+    use crate::engine::eval::Evaluator;
+    use crate::engine::movegen::{MoveGenerator, MoveList};
+    use crate::engine::search::{iterative_deepening, NullMove, SearchContext, MATE_SCORE};
+
+    #[test]
+    fn replace_by_depth() {
+        let tt = TranspositionTable::new(16);
+        tt.store(3, 1, 4, 50, Bound::Exact, Some(7));
+
+        // a shallower search of another position sharing the slot
+        tt.store(19, 1, 2, -10, Bound::Lower, Some(8));
+        assert_eq!(tt.probe(3, 1).map(|entry| entry.score), Some(50));
+        assert_eq!(tt.probe(19, 1), None);
+
+        // the same position is always updated, keeping its move if need be
+        tt.store(3, 1, 1, 20, Bound::Upper, None);
+        let entry = tt.probe(3, 1).unwrap();
+        assert_eq!(
+            (entry.depth, entry.bound, entry.best_move),
+            (1, Bound::Upper, Some(7))
+        );
+
+        // and anything is replaced by the next search
+        tt.store(3, 1, 4, 50, Bound::Exact, Some(7));
+        tt.new_search();
+        tt.store(19, 1, 2, -10, Bound::Lower, Some(8));
+        assert_eq!(tt.probe(19, 1).map(|entry| entry.score), Some(-10));
+
+        tt.clear();
+        assert_eq!(tt.probe(19, 1), None);
+        assert_eq!(TranspositionTable::new(0).probe(19, 1), None);
+    }
+
+    #[test]
+    fn megabytes() {
+        let entries = TranspositionTable::entries_in_megabytes(1);
+        assert_eq!(entries, (1 << 20) / std::mem::size_of::<Slot>());
+        assert_eq!(TranspositionTable::entries_in_megabytes(64), entries * 64);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        let tt = TranspositionTable::new(64);
+        let entry = TtEntry {
+            hash: 5,
+            depth: 3,
+            score: -250,
+            bound: Bound::Lower,
+            best_move: Some(0x1234),
+            generation: 0,
+        };
+        assert_eq!(TtEntry::unpack(5, entry.pack()), Some(entry));
+
+        // a slot holding one entry's hash and another's data is empty
+        tt.store(5, 0, 3, -250, Bound::Lower, Some(0x1234));
+        assert_eq!(tt.probe(5, 0), Some(entry));
+        tt.entries[5].data.store(TtEntry { depth: 9, ..entry }.pack(), Ordering::Relaxed);
+        assert_eq!(tt.probe(5, 0), None);
+
+        // every thread reads back either nothing or a whole entry
+        std::thread::scope(|scope| {
+            for thread in 0..4u64 {
+                let tt = &tt;
+                scope.spawn(move || {
+                    for i in 0..10_000u64 {
+                        let hash = i % 128;
+                        tt.store(hash, 0, thread as usize, hash as i32, Bound::Exact, Some(hash as Move + 1));
+                        if let Some(found) = tt.probe(hash, 0) {
+                            assert_eq!((found.score, found.best_move), (hash as i32, Some(hash as Move + 1)));
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn cutoffs() {
+        let entry = |bound| TtEntry {
+            hash: 0,
+            depth: 3,
+            score: 100,
+            bound,
+            best_move: None,
+            generation: 0,
+        };
+
+        assert_eq!(entry(Bound::Exact).cutoff(3, 0, 50), Some(100));
+        assert_eq!(entry(Bound::Exact).cutoff(4, 0, 50), None);
+        assert_eq!(entry(Bound::Lower).cutoff(2, 0, 50), Some(100));
+        assert_eq!(entry(Bound::Lower).cutoff(2, 0, 200), None);
+        assert_eq!(entry(Bound::Upper).cutoff(2, 150, 200), Some(100));
+        assert_eq!(entry(Bound::Upper).cutoff(2, 0, 200), None);
+
+        // a bound on the edge of the window settles the node, as it fails the
+        // same way the search would
+        assert_eq!(entry(Bound::Lower).cutoff(3, 0, 100), Some(100));
+        assert_eq!(entry(Bound::Lower).cutoff(3, 0, 101), None);
+        assert_eq!(entry(Bound::Upper).cutoff(3, 100, 200), Some(100));
+        assert_eq!(entry(Bound::Upper).cutoff(3, 99, 200), None);
+        // and nothing settles a deeper search
+        assert_eq!(entry(Bound::Lower).cutoff(4, 0, 50), None);
+        assert_eq!(entry(Bound::Upper).cutoff(4, 150, 200), None);
+    }
+
+    #[test]
+    fn tablebase_scores() {
+        // a win found 5 plies from the root, 3 of them after this position
+        let tt = TranspositionTable::new(16);
+        tt.store(1, 2, 4, TB_WIN - 5, Bound::Exact, None);
+        // is a win 4 plies from the root if the position is reached sooner
+        assert_eq!(tt.probe(1, 1).unwrap().score, TB_WIN - 4);
+
+        tt.store(2, 2, 4, 5 - TB_WIN, Bound::Exact, None);
+        assert_eq!(tt.probe(2, 4).unwrap().score, 7 - TB_WIN);
+
+        // as are mates
+        tt.store(4, 3, 4, MATE_SCORE - 5, Bound::Lower, None);
+        assert_eq!(tt.probe(4, 5).unwrap().score, MATE_SCORE - 7);
+
+        tt.store(3, 2, 4, 250, Bound::Exact, None);
+        assert_eq!(tt.probe(3, 9).unwrap().score, 250);
+    }
+
+    #[test]
+    fn save_and_load() {
+        const DEPTH: usize = 6;
+        let move_generator = MoveGenerator::new();
+        let board = Board::new("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let zobrist = board.zobrist_checksum();
+
+        let search = |context: &mut SearchContext| {
+            let mut move_lists = (0..DEPTH).map(|_| MoveList::new()).collect();
+            let (best_move, _) = iterative_deepening(
+                DEPTH,
+                &mut board.clone(),
+                &mut Evaluator::default(),
+                &move_generator,
+                &mut move_lists,
+                usize::MAX,
+                usize::MAX,
+                &mut ControlHandle::new(),
+                context,
+            )
+            .unwrap();
+            (best_move, context.nodes())
+        };
+
+        // Null move pruning makes a search depend on what the table already
+        // holds, as the passes are searched shallower than the moves, so
+        // the move found could change.
+        let mut context = SearchContext::new();
+        context.set_null_move(NullMove::Off);
+        let (best_move, nodes) = search(&mut context);
+        let mut saved = Vec::new();
+        let count = context.tt().save_to(&mut saved, zobrist).unwrap();
+        assert!(count > 0);
+        assert_eq!(saved.len(), 33 + 16 * count);
+
+        // the search after loading the table finds the same move straight
+        // away
+        context.clear_tt();
+        assert_eq!(context.tt().load_from(&mut saved.as_slice(), zobrist, "saved"), Ok(count));
+        let (reloaded_move, reloaded_nodes) = search(&mut context);
+        assert_eq!(reloaded_move, best_move);
+        assert!(reloaded_nodes * 10 < nodes, "{} nodes, {} after loading", nodes, reloaded_nodes);
+
+        // a smaller table keeps the deepest of the entries sharing a slot
+        let small = TranspositionTable::new(64);
+        let loaded = small.load_from(&mut saved.as_slice(), zobrist, "saved").unwrap();
+        assert!(loaded <= 64);
+        let deepest = |tt: &TranspositionTable| tt.entries.iter().filter_map(Slot::load).map(|entry| entry.depth).max();
+        assert_eq!(deepest(&small), deepest(context.tt()));
+
+        // nothing is loaded from another scheme's table, or a damaged one
+        assert_eq!(
+            small.load_from(&mut saved.as_slice(), zobrist ^ 1, "saved"),
+            Err(TtError::ZobristMismatch("saved".to_string()))
+        );
+        assert_eq!(
+            small.load_from(&mut &saved[..saved.len() - 1], zobrist, "saved"),
+            Err(TtError::BadFile("saved".to_string()))
+        );
+        assert_eq!(small.load_from(&mut &b"not a table"[..], zobrist, "saved"), Err(TtError::BadFile("saved".to_string())));
+    }
+
+    #[test]
+    fn save_and_load_files() {
+        let tt = TranspositionTable::new(16);
+        tt.store(3, 0, 4, 50, Bound::Exact, Some(7));
+
+        let path = std::env::temp_dir().join(format!("chess_rs_tt_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        assert_eq!(tt.save(path, 99), Ok(1));
+        tt.clear();
+        assert_eq!(tt.load(path, 99), Ok(1));
+        assert_eq!(tt.probe(3, 0).map(|entry| entry.score), Some(50));
+        std::fs::remove_file(path).unwrap();
+
+        assert!(matches!(tt.load(path, 99), Err(TtError::Io(..))));
+        assert!(matches!(tt.save("/nonexistent/dir/table.bin", 99), Err(TtError::Io(..))));
+    }
+}
@@ -0,0 +1,168 @@
+// Evaluation parameters which can be changed without rebuilding, for tuning
+// them with a tool that plays games between settings. Built with the `tune`
+// feature they are uci spin options read at runtime; otherwise they are
+// never advertised and every read is the default, known at compile time.
+//
+// The weights belong to a thread, so that the engines in one process (the
+// tests, or a tuner running several) don't see each other's settings. A
+// search installs the engine's weights on each of its threads.
+
+#[cfg(feature = "tune")]
+use std::cell::Cell;
+
+#[cfg(any(test, feature = "tune"))]
+use crate::engine::piece::{PieceType, Pieces};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Param {
+    PawnValue,
+    KnightValue,
+    BishopValue,
+    RookValue,
+    QueenValue,
+    SimplificationDivisor,
+}
+
+// only the uci options read all of it
+#[cfg_attr(not(feature = "tune"), allow(dead_code))]
+pub struct ParamInfo {
+    pub param: Param,
+    pub name: &'static str,
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+}
+
+impl ParamInfo {
+    // the value given to setoption, if it is a number in range
+    #[cfg(any(test, feature = "tune"))]
+    pub fn parse(&self, value: &str) -> Option<i32> {
+        value.parse::<i32>().ok().filter(|value| (self.min..=self.max).contains(value))
+    }
+}
+
+pub const PARAM_COUNT: usize = 6;
+
+// in the order of Param
+pub const PARAMS: [ParamInfo; PARAM_COUNT] = [
+    ParamInfo { param: Param::PawnValue, name: "PawnValue", default: 100, min: 0, max: 2000 },
+    ParamInfo { param: Param::KnightValue, name: "KnightValue", default: 315, min: 0, max: 2000 },
+    ParamInfo { param: Param::BishopValue, name: "BishopValue", default: 325, min: 0, max: 2000 },
+    ParamInfo { param: Param::RookValue, name: "RookValue", default: 500, min: 0, max: 3000 },
+    ParamInfo { param: Param::QueenValue, name: "QueenValue", default: 900, min: 0, max: 5000 },
+    ParamInfo { param: Param::SimplificationDivisor, name: "SimplificationDivisor", default: 8, min: 1, max: 64 },
+];
+
+#[cfg(any(test, feature = "tune"))]
+pub fn find(name: &str) -> Option<&'static ParamInfo> {
+    PARAMS.iter().find(|info| info.name == name)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Weights {
+    values: [i32; PARAM_COUNT],
+}
+
+impl Weights {
+    pub const DEFAULT: Weights = Weights::defaults();
+
+    const fn defaults() -> Weights {
+        let mut values = [0; PARAM_COUNT];
+        let mut i = 0;
+        while i < PARAM_COUNT {
+            values[i] = PARAMS[i].default;
+            i += 1;
+        }
+        Weights { values }
+    }
+
+    pub fn get(&self, param: Param) -> i32 {
+        self.values[param as usize]
+    }
+    #[cfg(feature = "tune")]
+    pub fn set(&mut self, param: Param, value: i32) {
+        self.values[param as usize] = value;
+    }
+
+    // the weights of this thread
+    #[cfg(feature = "tune")]
+    pub fn current() -> Weights {
+        WEIGHTS.with(|weights| weights.get())
+    }
+    #[cfg(not(feature = "tune"))]
+    pub fn current() -> Weights {
+        Weights::DEFAULT
+    }
+
+    // makes these the weights of this thread
+    #[cfg(feature = "tune")]
+    pub fn install(self) {
+        WEIGHTS.with(|weights| weights.set(self));
+    }
+    #[cfg(not(feature = "tune"))]
+    pub fn install(self) {}
+}
+
+impl Default for Weights {
+    fn default() -> Weights {
+        Weights::DEFAULT
+    }
+}
+
+#[cfg(feature = "tune")]
+thread_local! {
+    static WEIGHTS: Cell<Weights> = const { Cell::new(Weights::DEFAULT) };
+}
+
+// a parameter of this thread's weights
+#[cfg(feature = "tune")]
+#[inline(always)]
+pub fn value(param: Param) -> i32 {
+    WEIGHTS.with(|weights| weights.get().get(param))
+}
+#[cfg(not(feature = "tune"))]
+#[inline(always)]
+pub fn value(param: Param) -> i32 {
+    Weights::DEFAULT.get(param)
+}
+
+// the value of a piece, negative for black
+#[cfg(any(test, feature = "tune"))]
+pub fn piece_value(piece: Pieces) -> i32 {
+    let param = match piece.piece_type() {
+        PieceType::Pawn => Param::PawnValue,
+        PieceType::Knight => Param::KnightValue,
+        PieceType::Bishop => Param::BishopValue,
+        PieceType::Rook => Param::RookValue,
+        PieceType::Queen => Param::QueenValue,
+        PieceType::King => return 0,
+    };
+    value(param) * if piece.color().is_white() { 1 } else { -1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::eval::Evaluator;
+
+    #[test]
+    fn params_in_order() {
+        for (i, info) in PARAMS.iter().enumerate() {
+            assert_eq!(info.param as usize, i, "{}", info.name);
+            assert!((info.min..=info.max).contains(&info.default), "{}", info.name);
+            assert!(find(info.name).is_some());
+        }
+        assert!(find("KingValue").is_none());
+        assert_eq!(PARAMS[0].parse("200"), Some(200));
+        assert_eq!(PARAMS[0].parse("-1"), None);
+        assert_eq!(PARAMS[0].parse("lots"), None);
+    }
+
+    #[test]
+    fn defaults_match_evaluator() {
+        for idx in 0..12 {
+            let piece = Pieces::from_idx(idx);
+            assert_eq!(piece_value(piece), Evaluator::piece_value(piece), "{}", piece.notation());
+        }
+    }
+}
@@ -1,151 +1,2903 @@
+use std::fmt::Display;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::engine::bench;
+use crate::engine::config::Config;
+use crate::engine::control::{ControlHandle, ProgressEvent};
+use crate::engine::error::{OptionError, ScriptError, SearchError};
+use crate::engine::gamereport::{GameReport, SearchRecord};
+use crate::engine::iolog::IoLog;
+use crate::engine::movetable;
 use crate::engine::perft;
-use crate::engine::r#move::{MoveUtils, UndoInfo};
-use crate::engine::search;
+use crate::engine::playout;
+use crate::engine::script;
+use crate::engine::r#move::{Move, MoveUtils, UndoInfo};
+use crate::engine::search::{self, SearchContext, StopReason};
+use crate::engine::syzygy::Tablebases;
+use crate::engine::timeman;
+use crate::engine::tt::TranspositionTable;
+#[cfg(feature = "tune")]
+use crate::engine::tune;
+use crate::engine::tune::Weights;
+use crate::engine::ucioptions::UciOptions;
+use crate::Error;
 use crate::engine::{
-    board::Board,
-    eval::Evaluator,
+    board::{Board, GameState},
+    castling::{CastleSide, CastlingRights},
+    eval::{self, Evaluate},
     movegen::{MoveGenerator, MoveList},
+    piece::Color,
 };
 
+// the depth of a search with nothing else to end it
 const MAX_DEPTH: usize = 6;
 
-fn parse_moves(
-    board: &mut Board,
-    tokens: &[&str],
-    move_generator: &MoveGenerator,
-    start_idx: usize,
-) {
-    let mut move_list = MoveList::new();
-    let mut info = UndoInfo::default();
+// The deepest any search goes, which the move lists are sized for. A search
+// with a clock or node limit, or an infinite one, deepens until that ends
+// it rather than stopping at MAX_DEPTH.
+const MAX_SEARCH_DEPTH: usize = 64;
 
-    // skip (start_idx + 1) to skip previous tokens and "moves" token
-    'outer: for &token in tokens.iter().skip(start_idx + 1) {
-        move_generator.gen_moves(board, &mut move_list);
-        for i in 0..move_list.len() {
-            if move_list.at(i).move_to_string().eq(token) {
-                board.make_move(move_list.at(i), &mut info);
-                continue 'outer;
-            }
+// perft depth for the bench command when none is given
+const BENCH_DEPTH: usize = 5;
+
+// how long quitting waits for a search which doesn't stop (bench can't be
+// stopped) before going without it
+const QUIT_TIMEOUT_MILLIS: u64 = 2000;
+
+// a position command for the start position with at most one move, after a
+// game at least this long, is taken to be a new game
+const LONG_GAME_PLIES: usize = 10;
+
+// how often the line being searched is sent, for UCI_ShowCurrLine
+const CURRLINE_INTERVAL_MILLIS: i64 = 1000;
+
+// the words starting each go parameter, which end the list of searchmoves
+const GO_PARAMETERS: &[&str] = &[
+    "searchmoves", "ponder", "wtime", "btime", "winc", "binc", "movestogo", "depth", "nodes", "mate",
+    "movetime", "infinite", "perft", "playouts",
+];
+
+#[cfg(feature = "nnue")]
+type BaseEvaluator = crate::engine::nnue::Backend;
+#[cfg(not(feature = "nnue"))]
+type BaseEvaluator = crate::engine::eval::Evaluator;
+
+// tests can slow the evaluation down, to run searches late
+#[cfg(not(test))]
+type EngineEvaluator = BaseEvaluator;
+#[cfg(test)]
+type EngineEvaluator = crate::engine::eval::SlowEvaluator<BaseEvaluator>;
+
+// line based output shared between the engine and any progress callbacks
+// also owns the optional log of everything sent and received
+#[derive(Clone)]
+pub struct UciOutput {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+    log: Arc<Mutex<Option<IoLog>>>,
+    // set by "debug on"
+    debug: Arc<AtomicBool>,
+}
+
+impl UciOutput {
+    pub fn new(writer: impl Write + Send + 'static) -> UciOutput {
+        UciOutput {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+            log: Arc::new(Mutex::new(None)),
+            debug: Arc::new(AtomicBool::new(false)),
         }
+    }
 
-        // if move wasn't found then stop making moves
-        break;
+    pub fn stdout() -> UciOutput {
+        UciOutput::new(std::io::stdout())
     }
-}
 
-pub fn uci() {
-    // setup
-    let move_generator = MoveGenerator::new();
-    let mut evaluator = Evaluator::default();
-    let mut move_lists = Vec::new();
-    for _ in 0..MAX_DEPTH {
-        move_lists.push(MoveList::new());
+    // start logging to a file, or stop if the path is empty
+    pub fn set_log_file(&self, path: &str) {
+        let log = if path.is_empty() { None } else { Some(IoLog::new(path)) };
+        *self.log.lock().unwrap() = log;
     }
-    let mut board = Board::default();
 
-    loop {
-        let mut line_str = String::new();
-        std::io::stdin().read_line(&mut line_str).unwrap();
+    // sends why the log file couldn't be opened, once its thread has tried
+    pub fn report_log_errors(&self) {
+        let errors = self.log.lock().unwrap().as_ref().map_or_else(Vec::new, IoLog::take_errors);
+        for e in errors {
+            self.error(&Error::from(e));
+        }
+    }
 
-        let tokens = line_str.split_whitespace().collect::<Vec<&str>>();
+    // stop logging, once everything logged so far is in the file
+    pub fn close_log(&self) {
+        let log = self.log.lock().unwrap().take();
+        drop(log);
+    }
 
-        if tokens.is_empty() {
-            continue;
+    pub fn log_received(&self, line: &str) {
+        if let Some(log) = &*self.log.lock().unwrap() {
+            log.received(line);
         }
+    }
 
-        match tokens[0] {
-            "isready" => {
-                println!("readyok");
+    pub fn write_line(&self, line: impl Display) {
+        let line = line.to_string();
+        let mut writer = self.writer.lock().unwrap();
+
+        // a closed output (eg. the gui exited) is not worth crashing over
+        let _ = writeln!(writer, "{}", line);
+        let _ = writer.flush();
+
+        // logged while the writer is locked so the log has the same order
+        if let Some(log) = &*self.log.lock().unwrap() {
+            log.sent(&line);
+        }
+    }
+
+    pub fn error(&self, e: &Error) {
+        self.write_line(format_args!("info string {}", e));
+    }
+
+    pub fn set_debug(&self, debug: bool) {
+        self.debug.store(debug, Ordering::Relaxed);
+    }
+
+    pub fn is_debug(&self) -> bool {
+        self.debug.load(Ordering::Relaxed)
+    }
+
+    // diagnostics for "debug on", which are dropped otherwise. Every
+    // diagnostic goes through here so the setting is always respected.
+    pub fn debug_info(&self, line: impl Display) {
+        if self.is_debug() {
+            self.write_line(format_args!("info string {}", line));
+        }
+    }
+}
+
+// "mate N" for a forced mate in N moves (negative if the engine is mated),
+// otherwise "cp X"
+fn score_to_string(score: i32) -> String {
+    match search::mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+// the score sent for a position with no move to search: mated, lost to a
+// variant's rule, or a draw
+fn game_over_score(state: GameState) -> Option<&'static str> {
+    match state {
+        GameState::Checkmate | GameState::ThirdCheck | GameState::KingOfTheHill => Some("mate 0"),
+        GameState::Stalemate => Some("cp 0"),
+        _ => None,
+    }
+}
+
+// the sides each color may still castle on, for `d`
+fn castling_line(rights: CastlingRights) -> String {
+    let sides = |color| {
+        let sides: Vec<&str> = [(CastleSide::KingSide, "O-O"), (CastleSide::QueenSide, "O-O-O")]
+            .iter()
+            .filter(|&&(side, _)| rights.allows(color, side))
+            .map(|&(_, castle)| castle)
+            .collect();
+        match sides.is_empty() {
+            true => "-".to_string(),
+            false => sides.join(" "),
+        }
+    };
+    format!("white {}, black {}", sides(Color::White), sides(Color::Black))
+}
+
+// moves separated by spaces, as in a pv
+fn line_to_string(line: &[Move], chess960: bool) -> String {
+    line.iter().map(|my_move| my_move.move_to_uci(chess960)).collect::<Vec<String>>().join(" ")
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct GoParams {
+    pub depth: Option<usize>,
+    pub perft: Option<usize>,
+    pub infinite: bool,
+    // search the position after the move the engine expects the opponent
+    // to play, keeping the clock stopped until ponderhit
+    pub ponder: bool,
+    // remaining time and increment in milliseconds, indexed by Color::idx
+    pub time: [Option<usize>; 2],
+    pub increment: [Option<usize>; 2],
+    // moves until the next time control, if it isn't sudden death
+    pub moves_to_go: Option<usize>,
+    // search for exactly this many milliseconds
+    pub move_time: Option<usize>,
+    // stop once this many nodes have been searched
+    pub nodes: Option<u64>,
+    // the only root moves to search, in coordinate notation
+    pub search_moves: Vec<String>,
+    // look for a mate in this many moves
+    pub mate: Option<usize>,
+    // play this many games out instead of searching, see playout.rs
+    pub playouts: Option<u64>,
+}
+
+// clock times may be negative if the engine has lost on time, which is
+// treated as having no time left
+fn parse_millis(token: Option<&&str>) -> Result<usize, Error> {
+    match token.map(|t| t.parse::<i64>()) {
+        Some(Ok(millis)) => Ok(millis.max(0) as usize),
+        _ => Err(Error::from(SearchError::InvalidTime(
+            token.map_or(String::new(), |t| t.to_string()),
+        ))),
+    }
+}
+
+// The name and value from "setoption name <name> [value <value>]", both of
+// which may contain spaces. A button has no value, so gets an empty one.
+pub fn parse_setoption(tokens: &[&str]) -> Option<(String, String)> {
+    if tokens.get(1) != Some(&"name") {
+        return None;
+    }
+
+    let value_idx = tokens.iter().position(|&t| t == "value").unwrap_or(tokens.len());
+    let name = tokens[2..value_idx.max(2)].join(" ");
+    let value = tokens.get(value_idx + 1..).unwrap_or_default().join(" ");
+
+    if name.is_empty() {
+        None
+    } else {
+        Some((name, value))
+    }
+}
+
+pub fn parse_go(tokens: &[&str]) -> Result<GoParams, Error> {
+    let mut params = GoParams::default();
+
+    // skip the "go" token
+    let mut i = 1;
+    while i < tokens.len() {
+        match tokens[i] {
+            "depth" | "perft" => {
+                let depth = match tokens.get(i + 1).map(|t| t.parse::<usize>()) {
+                    Some(Ok(d)) => d,
+                    _ => return Err(Error::from(SearchError::InvalidDepth(0))),
+                };
+
+                if tokens[i] == "perft" {
+                    if depth == 0 {
+                        return Err(Error::from(SearchError::InvalidDepth(depth)));
+                    }
+                    params.perft = Some(depth);
+                } else {
+                    if !(1..=MAX_SEARCH_DEPTH).contains(&depth) {
+                        return Err(Error::from(SearchError::InvalidDepth(depth)));
+                    }
+                    params.depth = Some(depth);
+                }
+
+                i += 1;
             }
-            "ucinewgame" => {
-                board.reset();
+            "infinite" => {
+                params.infinite = true;
             }
-            "uci" => {
-                println!("id name Avocado");
-                println!("id author upsidedown8");
-                println!("uciok")
+            "ponder" => {
+                params.ponder = true;
             }
-            "quit" => {
-                break;
+            "searchmoves" => {
+                // every token up to the next parameter is a move
+                while let Some(token) = tokens.get(i + 1).filter(|token| !GO_PARAMETERS.contains(token)) {
+                    params.search_moves.push(token.to_string());
+                    i += 1;
+                }
             }
-            "d" => {
-                println!("{}", board.to_string());
-                println!("fen: {}", board.to_fen());
-            }
-            "position" => {
-                if tokens.len() >= 2 {
-                    match tokens[1] {
-                        "fen" => {
-                            // fen: rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1
-                            // tokens:                   1                      2   3  4 5 6
-
-                            if tokens.len() >= 8 {
-                                let fen = tokens
-                                    .iter()
-                                    .skip(2)
-                                    .take(6)
-                                    .fold(String::new(), |acc, &s| acc + s + " ");
-                                let tmp_board = board;
-
-                                if let Err(..) = board.load_fen(&fen) {
-                                    // fix any changes
-                                    board = tmp_board;
-                                    continue;
-                                };
-
-                                if tokens.len() >= 9 {
-                                    parse_moves(&mut board, &tokens, &move_generator, 8);
-                                }
-                            }
-                        }
-                        "startpos" => {
-                            board.reset();
+            "movestogo" => {
+                let token = tokens.get(i + 1);
+                match token.map(|t| t.parse::<usize>()) {
+                    Some(Ok(moves)) => params.moves_to_go = Some(moves),
+                    _ => {
+                        return Err(Error::from(SearchError::InvalidMovesToGo(
+                            token.map_or(String::new(), |t| t.to_string()),
+                        )))
+                    }
+                }
 
-                            // ie. contains moves ...
-                            if tokens.len() >= 3 {
-                                parse_moves(&mut board, &tokens, &move_generator, 2);
-                            }
-                        }
-                        _ => {}
+                i += 1;
+            }
+            "mate" => {
+                let token = tokens.get(i + 1);
+                match token.map(|t| t.parse::<usize>()) {
+                    Some(Ok(moves)) if moves > 0 => params.mate = Some(moves),
+                    _ => {
+                        return Err(Error::from(SearchError::InvalidMate(
+                            token.map_or(String::new(), |t| t.to_string()),
+                        )))
                     }
                 }
+
+                i += 1;
             }
-            "go" => {
-                if tokens.len() >= 3 && tokens[1].eq("perft") {
-                    let depth = match str::parse::<usize>(tokens[2]) {
-                        Ok(d) => d,
-                        _ => continue,
-                    };
+            "movetime" => {
+                params.move_time = Some(parse_millis(tokens.get(i + 1))?);
+                i += 1;
+            }
+            "nodes" => {
+                let token = tokens.get(i + 1);
+                match token.map(|t| t.parse::<u64>()) {
+                    Some(Ok(nodes)) => params.nodes = Some(nodes),
+                    _ => {
+                        return Err(Error::from(SearchError::InvalidNodes(
+                            token.map_or(String::new(), |t| t.to_string()),
+                        )))
+                    }
+                }
+
+                i += 1;
+            }
+            "playouts" => {
+                let token = tokens.get(i + 1);
+                match token.map(|t| t.parse::<u64>()) {
+                    Some(Ok(count)) if count > 0 => params.playouts = Some(count),
+                    _ => {
+                        return Err(Error::from(SearchError::InvalidPlayouts(
+                            token.map_or(String::new(), |t| t.to_string()),
+                        )))
+                    }
+                }
+
+                i += 1;
+            }
+            "wtime" | "btime" | "winc" | "binc" => {
+                let millis = parse_millis(tokens.get(i + 1))?;
+                let color = match tokens[i] {
+                    "wtime" | "winc" => Color::White,
+                    _ => Color::Black,
+                };
 
-                    perft::perft_divide(depth, &mut board);
+                if tokens[i].ends_with("time") {
+                    params.time[color.idx()] = Some(millis);
                 } else {
-                    let mut depth = 6;
+                    params.increment[color.idx()] = Some(millis);
+                }
+
+                i += 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    Ok(params)
+}
+
+// the state a search needs, which is handed to the search thread while it
+// runs and handed back when it finishes
+struct Searcher {
+    evaluator: EngineEvaluator,
+    move_lists: Vec<MoveList>,
+    context: SearchContext,
+    // the searches which ran past their hard limit by more than the margin
+    overshoots: usize,
+    // what the last go search did, until it is added to the game report
+    last_search: Option<SearchRecord>,
+}
+
+// the last position command, and which of its moves were made
+#[derive(Default)]
+struct PositionCommand {
+    // "startpos" or "fen" followed by the fen fields
+    start: Vec<String>,
+    moves: Vec<String>,
+    // Board::opening_key of the start position and after each move made
+    opening_keys: Vec<u64>,
+}
+
+pub struct UciEngine {
+    move_generator: Arc<MoveGenerator>,
+    // None while a search thread is running
+    searcher: Option<Searcher>,
+    search_thread: Option<JoinHandle<Searcher>>,
+    infinite: bool,
+    options: UciOptions,
+    board: Board,
+    position: PositionCommand,
+    stop: Arc<AtomicBool>,
+    // set by go ponder, and cleared by ponderhit
+    pondering: Arc<AtomicBool>,
+    // new games found from the position commands, without a ucinewgame
+    detected_new_games: usize,
+    // the searches of this game, sent on quit or with the stats command
+    game_report: GameReport,
+    // the evaluation parameters, set with the tune options
+    weights: Weights,
+    // set while an analysis queue runs on the search thread, when stop
+    // ends its current search without waiting for the rest
+    queue_running: bool,
+    // ends the analysis queue after its current search
+    abort_queue: Arc<AtomicBool>,
+    // the side to move at the last go, taken to be the engine's
+    engine_color: Option<Color>,
+    // set while the search thread is priming the table, when any command
+    // stops it
+    priming: bool,
+    out: UciOutput,
+}
+
+impl UciEngine {
+    pub fn new(out: UciOutput) -> UciEngine {
+        let mut move_lists = Vec::new();
+        for _ in 0..MAX_SEARCH_DEPTH {
+            move_lists.push(MoveList::new());
+        }
+
+        // the line is always tracked, and dropped if no one wants it
+        let options = UciOptions::default();
+        let mut context = SearchContext::new();
+        context.set_currline_interval(Some(time::Duration::milliseconds(CURRLINE_INTERVAL_MILLIS)));
+        context.resize_tt(TranspositionTable::entries_in_megabytes(options.hash_mb));
+
+        UciEngine {
+            move_generator: Arc::new(MoveGenerator::new()),
+            searcher: Some(Searcher {
+                evaluator: EngineEvaluator::default(),
+                move_lists,
+                context,
+                overshoots: 0,
+                last_search: None,
+            }),
+            search_thread: None,
+            infinite: false,
+            options,
+            board: Board::default(),
+            position: PositionCommand::default(),
+            stop: Arc::new(AtomicBool::new(false)),
+            pondering: Arc::new(AtomicBool::new(false)),
+            detected_new_games: 0,
+            game_report: GameReport::default(),
+            weights: Weights::default(),
+            queue_running: false,
+            abort_queue: Arc::new(AtomicBool::new(false)),
+            engine_color: None,
+            priming: false,
+            out,
+        }
+    }
+
+    // block until the current search (if any) has printed its bestmove
+    pub fn wait_for_search(&mut self) {
+        if let Some(handle) = self.search_thread.take() {
+            self.searcher_returned(handle.join().expect("search thread panicked"));
+        }
+        self.queue_running = false;
+    }
+
+    // The searcher back from the search thread. The searches of an analysis
+    // queue aren't moves of the game, so aren't added to its report.
+    fn searcher_returned(&mut self, mut searcher: Searcher) {
+        if let Some(search) = searcher.last_search.take() {
+            if !self.queue_running {
+                self.game_report.record(&search);
+            }
+        }
+        self.searcher = Some(searcher);
+    }
+
+    fn send_game_report(&self) {
+        for line in self.game_report.lines() {
+            self.out.write_line(format_args!("info string {}", line));
+        }
+    }
+
+    fn stop_search(&mut self) {
+        self.abort_queue.store(true, Ordering::Relaxed);
+        self.stop.store(true, Ordering::Relaxed);
+        self.wait_for_search();
+    }
+
+    // Stops any search and closes the log, so nothing is lost when the
+    // process exits. A search which hasn't stopped within the timeout is
+    // left behind rather than keeping the engine running.
+    pub fn quit(&mut self) {
+        self.abort_queue.store(true, Ordering::Relaxed);
+        self.stop.store(true, Ordering::Relaxed);
+
+        if let Some(handle) = self.search_thread.take() {
+            let deadline = Instant::now() + Duration::from_millis(QUIT_TIMEOUT_MILLIS);
+            while !handle.is_finished() && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            if handle.is_finished() {
+                self.searcher_returned(handle.join().expect("search thread panicked"));
+                self.queue_running = false;
+            } else {
+                self.out.debug_info("search did not stop, quitting without it");
+            }
+        }
+
+        if self.game_report.searches > 0 {
+            self.send_game_report();
+        }
+        self.out.close_log();
+    }
+
+    #[cfg(test)]
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    fn new_game(&mut self) {
+        self.game_report = GameReport::default();
+        self.engine_color = None;
+        self.board.reset();
+        if let Some(searcher) = self.searcher.as_mut() {
+            searcher.context.clear_tt();
+            searcher.context.clear_history();
+        }
+        self.position = PositionCommand::default();
+    }
+
+    // Some GUIs never send ucinewgame, so a new game is assumed when the
+    // moves are neither the last game with moves added or taken back, nor
+    // just the start position again after a long game
+    fn is_new_game(&self, start: &[&str], moves: &[&str]) -> bool {
+        let old = &self.position;
+
+        // nothing has been played since the last new game
+        if old.start.is_empty() {
+            return false;
+        }
+        if start[0] == "startpos" && moves.len() <= 1 && old.moves.len() >= LONG_GAME_PLIES {
+            return true;
+        }
+
+        old.start != start || !moves.iter().zip(&old.moves).all(|(a, b)| a == b)
+    }
+
+    // make moves until one fails, recording those which were made
+    fn parse_moves(&mut self, moves: &[&str]) {
+        let mut info = UndoInfo::default();
+
+        for &token in moves {
+            // if move wasn't found then stop making moves
+            if let Err(e) = self.board.make_uci_move(token, &self.move_generator, &mut info) {
+                self.out.error(&Error::from(e));
+                break;
+            }
+            self.position.moves.push(token.to_string());
+            self.position.opening_keys.push(self.board.opening_key());
+        }
+    }
+
+    // false if the position couldn't be set up, when the board is left as
+    // it was
+    fn position(&mut self, tokens: &[&str]) -> bool {
+        // the moves (if any) come after a "moves" token, and the fen is
+        // everything before it, as the counters may be left off
+        let fen_end = tokens.iter().position(|&t| t == "moves").unwrap_or(tokens.len());
+        let (start, moves) = match tokens[1] {
+            "fen" => (&tokens[1..fen_end], tokens.get(fen_end + 1..)),
+            "startpos" => (&tokens[1..2], tokens.get(3..)),
+            _ => return false,
+        };
+        let moves = moves.unwrap_or_default();
+
+        // GUIs usually send the whole game again with another move on the
+        // end, in which case only the new moves need to be made
+        let made = self.position.moves.len();
+        let is_extension = self.position.start == start
+            && moves.len() >= made
+            && moves.iter().zip(&self.position.moves).all(|(a, b)| a == b);
+
+        if is_extension {
+            self.parse_moves(&moves[made..]);
+            return true;
+        }
+
+        if self.is_new_game(start, moves) {
+            self.new_game();
+            self.detected_new_games += 1;
+            self.out.debug_info(format_args!(
+                "new game without ucinewgame ({} so far)",
+                self.detected_new_games
+            ));
+        }
+
+        if start[0] == "fen" {
+            let fen = start[1..].join(" ");
+            // load_fen leaves the board unchanged if the fen is invalid
+            if let Err(e) = self.board.load_fen(&fen) {
+                self.out.error(&Error::from(e));
+                return false;
+            };
+        } else {
+            self.board.reset();
+        }
+
+        self.position = PositionCommand {
+            start: start.iter().map(|s| s.to_string()).collect(),
+            moves: Vec::new(),
+            opening_keys: vec![self.board.opening_key()],
+        };
+        self.parse_moves(moves);
+        true
+    }
+
+    fn go(&mut self, tokens: &[&str]) {
+        let params = match parse_go(tokens) {
+            Ok(params) => params,
+            Err(e) => {
+                self.out.error(&e);
+                return;
+            }
+        };
+
+        self.stop.store(false, Ordering::Relaxed);
+        self.pondering.store(params.ponder, Ordering::Relaxed);
+        self.infinite = params.infinite;
+        self.engine_color = Some(self.board.friendly_color());
 
-                    // parse command
-                    let mut i = 1;
+        if let Some(depth) = params.perft {
+            self.go_perft(depth);
+        } else if let Some(score) = game_over_score(self.board.game_state(&self.move_generator)) {
+            self.go_game_over(score);
+        } else if let Some(count) = params.playouts {
+            self.go_playouts(count);
+        } else {
+            // Every limit given applies, and the search ends at whichever
+            // is reached first: depth (and mate) between iterations, nodes
+            // and time inside them. Infinite overrides them all, searching
+            // until told to stop.
+            let infinite = params.infinite;
 
-                    while i < tokens.len() {
-                        match tokens[i] {
-                            "depth" => {
-                                depth = tokens[i + 1].parse().unwrap();
-                            },
-                            _ => {},
+            // a fixed move time is used up, rather than starting no new
+            // iteration after a soft limit
+            let side = self.board.friendly_color().idx();
+            let limits = match (params.move_time, params.time[side]) {
+                (Some(move_time), _) if !infinite => Some(timeman::TimeLimits {
+                    soft: usize::MAX,
+                    hard: move_time,
+                }),
+                (None, Some(remaining)) if !infinite => {
+                    let increment = params.increment[side].unwrap_or(0);
+                    let limits = self.options.time_manager().limits(remaining, increment, params.moves_to_go);
+                    self.out.debug_info(format_args!(
+                        "time {} increment {} movestogo {} overhead {}: soft limit {} hard limit {}",
+                        remaining,
+                        increment,
+                        params.moves_to_go.map_or("-".to_string(), |moves| moves.to_string()),
+                        self.options.move_overhead,
+                        limits.soft,
+                        limits.hard
+                    ));
+                    Some(limits)
+                }
+                _ => None,
+            };
+
+            // only a search nothing else will end stops at the default depth
+            let limited = infinite || limits.is_some() || params.nodes.is_some();
+            let depth = match (infinite, params.depth) {
+                (false, Some(depth)) => depth,
+                _ if limited => MAX_SEARCH_DEPTH,
+                _ => MAX_DEPTH,
+            };
+
+            // Mate in n is found by a search of 2n plies, as checkmate is
+            // only seen at a node with moves left to search, whatever the
+            // default depth. Only mates are looked for, and if there is none
+            // an ordinary search finds the best try.
+            let mate = params.mate.filter(|_| !infinite);
+            let mate_depth = mate.map(|moves| {
+                if moves.saturating_mul(2) > MAX_SEARCH_DEPTH {
+                    self.out.write_line(format_args!(
+                        "info string mate {} is beyond the maximum depth, searching {} plies",
+                        moves, MAX_SEARCH_DEPTH
+                    ));
+                }
+                params.depth.unwrap_or(MAX_SEARCH_DEPTH).min(moves.saturating_mul(2))
+            });
+
+            let mut searcher = self.searcher.take().expect("search already running");
+            searcher.context.set_node_limit(params.nodes.filter(|_| !infinite));
+            searcher.context.set_multi_pv(self.options.multi_pv);
+            searcher.context.set_mate_search(mate);
+
+            // moves which aren't legal here are left out, rather than
+            // refusing to search
+            let mut search_moves = Vec::new();
+            for token in &params.search_moves {
+                match self.board.find_uci_move(token, &self.move_generator) {
+                    Ok(my_move) => search_moves.push(my_move),
+                    Err(e) => self.out.write_line(format_args!("info string searchmoves: {} ignored", e)),
+                }
+            }
+            searcher.context.set_search_moves(search_moves);
+            let mut board = self.board.clone();
+            let move_generator = Arc::clone(&self.move_generator);
+            let stop = Arc::clone(&self.stop);
+            let pondering = Arc::clone(&self.pondering);
+
+            let out = self.out.clone();
+            let show_wdl = self.options.show_wdl;
+            let multi_pv = self.options.multi_pv;
+            let (show_refutations, show_currline) = (self.options.show_refutations, self.options.show_currline);
+            let chess960 = self.options.chess960;
+            let mut control = ControlHandle::with_stop_flag(self.stop.clone())
+                .with_ponder_flag(Arc::clone(&self.pondering))
+                .with_progress(move |event| match event {
+                    ProgressEvent::SearchIteration {
+                        depth,
+                        millis,
+                        nodes,
+                        lines,
+                        stats,
+                        ..
+                    } => {
+                        // the lines are only numbered if more than one was
+                        // asked for
+                        for (i, line) in lines.iter().enumerate() {
+                            let number = if multi_pv > 1 {
+                                format!(" multipv {}", i + 1)
+                            } else {
+                                String::new()
+                            };
+                            let wdl = if show_wdl {
+                                let (win, draw, loss) = eval::wdl(line.score);
+                                format!(" wdl {} {} {}", win, draw, loss)
+                            } else {
+                                String::new()
+                            };
+
+                            out.write_line(format_args!(
+                                "info{} score {}{} depth {} time {} nodes {} nps {} pv {}",
+                                number,
+                                score_to_string(line.score),
+                                wdl,
+                                depth,
+                                millis,
+                                nodes,
+                                nodes * 1000 / millis.max(1) as u64,
+                                line_to_string(&line.pv, chess960),
+                            ));
                         }
-                        i += 1;
+
+                        out.debug_info(format_args!(
+                            "depth {} nodes {} branching factor {} first move cutoffs {:.1}% average cutoff index {:.2} pv/cut/all nodes {}/{}/{}",
+                            depth,
+                            stats.nodes,
+                            stats
+                                .branching_factor()
+                                .map_or("-".to_string(), |factor| format!("{:.2}", factor)),
+                            stats.first_move_cutoff_rate(),
+                            stats.average_cutoff_index(),
+                            stats.pv_nodes,
+                            stats.cut_nodes,
+                            stats.all_nodes
+                        ));
                     }
+                    ProgressEvent::Refutation { refuted, line } if show_refutations || out.is_debug() => {
+                        out.write_line(format_args!(
+                            "info refutation {} {}",
+                            refuted.move_to_uci(chess960),
+                            line_to_string(&line, chess960)
+                        ));
+                    }
+                    ProgressEvent::CurrentLine { line } if show_currline || out.is_debug() => {
+                        out.write_line(format_args!("info currline {}", line_to_string(&line, chess960)));
+                    }
+                    _ => {}
+                });
 
-                    if let Some((best_move, _)) = search::find_best_move(
+            let out = self.out.clone();
+            let threads = self.options.threads;
+            let weights = self.weights;
+            let time_manager = self.options.time_manager();
+            self.search_thread = Some(std::thread::spawn(move || {
+                weights.install();
+                let start = std::time::Instant::now();
+                let mut result = search::parallel_search(
+                    threads,
+                    mate_depth.unwrap_or(depth),
+                    &mut board,
+                    &mut searcher.evaluator,
+                    &move_generator,
+                    &mut searcher.move_lists,
+                    limits.map_or(usize::MAX, |limits| limits.soft),
+                    limits.map_or(usize::MAX, |limits| limits.hard),
+                    &mut control,
+                    &mut searcher.context,
+                );
+
+                // a mate search which ran to its depth without finding one
+                // leaves the rest of the time for the best try
+                let no_mate = result.is_some_and(|(_, score)| !searcher.context.is_exact(score));
+                if let Some(moves) = mate.filter(|_| no_mate && searcher.context.stop_reason() == Some(StopReason::Depth)) {
+                    out.write_line(format_args!("info string no mate in {} found", moves));
+                    searcher.context.set_mate_search(None);
+                    let elapsed = start.elapsed().as_millis() as usize;
+                    result = search::parallel_search(
+                        threads,
                         depth,
                         &mut board,
-                        &mut evaluator,
+                        &mut searcher.evaluator,
                         &move_generator,
-                        &mut move_lists,
-                    ) {
-                        println!("bestmove {}", best_move.move_to_string());
+                        &mut searcher.move_lists,
+                        limits.map_or(usize::MAX, |limits| limits.soft.saturating_sub(elapsed)),
+                        limits.map_or(usize::MAX, |limits| limits.hard.saturating_sub(elapsed)),
+                        &mut control,
+                        &mut searcher.context,
+                    );
+                }
+                searcher.last_search = Some(SearchRecord {
+                    depth: searcher.context.stats().last().map_or(0, |last| last.depth),
+                    nodes: searcher.context.nodes(),
+                    millis: start.elapsed().as_millis() as u64,
+                    allocated_millis: limits.map(|limits| limits.hard as u64),
+                    tt_probes: searcher.context.tt_probes(),
+                    tt_hits: searcher.context.tt_hits(),
+                });
+
+                // a table which couldn't be read was left out of the search
+                if let Some(tablebases) = searcher.context.tablebases() {
+                    for e in tablebases.take_errors() {
+                        out.error(&Error::from(e));
+                    }
+                }
+
+                // the clock is only checked every so many nodes, so the
+                // search can run on past the hard limit
+                let elapsed = searcher.context.clock_millis();
+                if let (Some(limits), Some(elapsed)) = (limits, elapsed) {
+                    if let Some(overshoot) = time_manager.overshoot(limits, elapsed) {
+                        searcher.overshoots += 1;
+                        out.write_line(format_args!(
+                            "info string time overshoot {}ms (limit {}, nodes-between-checks {})",
+                            overshoot,
+                            limits.hard,
+                            searcher.context.last_check_interval()
+                        ));
+                    }
+                }
+
+                // every move made in the search has been undone, so the
+                // score should be back to what it was at the root
+                if out.is_debug() {
+                    if let Some(drift) = eval::eval_drift(&mut searcher.evaluator, &board) {
+                        out.debug_info(format_args!("eval drift {}", drift));
+                    }
+                }
+
+                // an infinite search only reports its move once told to stop,
+                // and a ponder search once told to stop or that the opponent
+                // played the expected move
+                while (infinite || pondering.load(Ordering::Relaxed)) && !stop.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+
+                match result {
+                    Some((best_move, _)) => {
+                        // the reply to ponder on is the next move of the
+                        // line, if the move came from it
+                        let pv = searcher.context.pv();
+                        let ponder = match pv.as_slice() {
+                            [first, reply, ..] if *first == best_move => {
+                                format!(" ponder {}", reply.move_to_uci(chess960))
+                            }
+                            _ => String::new(),
+                        };
+                        out.write_line(format_args!("bestmove {}{}", best_move.move_to_uci(chess960), ponder));
+                    }
+                    None => {
+                        out.error(&Error::from(SearchError::NoLegalMoves));
+                        out.write_line("bestmove (none)");
                     }
                 }
+
+                let millis = start.elapsed().as_millis().max(1) as u64;
+                let nodes = searcher.context.nodes();
+                out.debug_info(format_args!(
+                    "search finished: nodes {} time {} nps {} average cutoff index {:.2} time overshoots {} ended by {}",
+                    nodes,
+                    millis,
+                    nodes * 1000 / millis,
+                    searcher.context.average_cutoff_index(),
+                    searcher.overshoots,
+                    searcher.context.stop_reason().map_or("-".to_string(), |reason| reason.to_string())
+                ));
+
+                searcher
+            }));
+        }
+    }
+
+    // With Ponder During Opponent Time, a position command which leaves the
+    // opponent to move (say once they are out of the book) starts a search
+    // of the position, while the engine would otherwise wait. It sends
+    // nothing, and the next command stops it before doing anything else, so
+    // all it leaves behind is a hash table holding the likely replies.
+    fn prime_tt(&mut self) {
+        let opponent_to_move = self.engine_color.is_some_and(|color| color != self.board.friendly_color());
+        if !self.options.prime_in_opponent_time || !opponent_to_move || self.searcher.is_none() {
+            return;
+        }
+        if self.board.game_state(&self.move_generator) != GameState::Ongoing {
+            return;
+        }
+
+        let mut searcher = self.searcher.take().unwrap();
+        searcher.context.set_node_limit(None);
+        searcher.context.set_multi_pv(1);
+        searcher.context.set_search_moves(Vec::new());
+        searcher.context.set_mate_search(None);
+
+        self.stop.store(false, Ordering::Relaxed);
+        self.priming = true;
+        self.out.debug_info("priming the hash table in the opponent's time");
+
+        let mut board = self.board.clone();
+        let move_generator = Arc::clone(&self.move_generator);
+        let mut control = ControlHandle::with_stop_flag(Arc::clone(&self.stop));
+        let weights = self.weights;
+        self.search_thread = Some(std::thread::spawn(move || {
+            weights.install();
+            search::iterative_deepening(
+                MAX_SEARCH_DEPTH,
+                &mut board,
+                &mut searcher.evaluator,
+                &move_generator,
+                &mut searcher.move_lists,
+                usize::MAX,
+                usize::MAX,
+                &mut control,
+                &mut searcher.context,
+            );
+            searcher
+        }));
+    }
+
+    // ends the priming search straight away, keeping what it found
+    fn stop_priming(&mut self) {
+        self.priming = false;
+        self.stop.store(true, Ordering::Relaxed);
+        self.wait_for_search();
+
+        let context = &self.searcher.as_ref().unwrap().context;
+        self.out.debug_info(format_args!(
+            "priming stopped: depth {} nodes {}",
+            context.stats().last().map_or(0, |last| last.depth),
+            context.nodes()
+        ));
+    }
+
+    // A go in a checkmate or stalemate, which GUIs do send (pondering after
+    // a claim or resignation the engine didn't expect), has nothing to
+    // search. It still ends like any other go, straight away or, when
+    // pondering or infinite, after ponderhit or stop.
+    fn go_game_over(&mut self, score: &'static str) {
+        let searcher = self.searcher.take().expect("search already running");
+        let infinite = self.infinite;
+        let stop = Arc::clone(&self.stop);
+        let pondering = Arc::clone(&self.pondering);
+
+        let out = self.out.clone();
+        self.search_thread = Some(std::thread::spawn(move || {
+            while (infinite || pondering.load(Ordering::Relaxed)) && !stop.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            out.write_line(format_args!("info depth 0 score {}", score));
+            out.write_line("bestmove (none)");
+
+            searcher
+        }));
+    }
+
+    // The playouts run on the search thread, with a worker for each of the
+    // Threads, and can be stopped like a search. Each root move's results
+    // are sent best first, then the best move.
+    fn go_playouts(&mut self, count: u64) {
+        let searcher = self.searcher.take().expect("search already running");
+        let board = self.board.clone();
+        let move_generator = Arc::clone(&self.move_generator);
+        let (threads, seed, max_moves, chess960) = (self.options.threads, self.options.playout_seed, self.options.playout_moves, self.options.chess960);
+        let stop = Arc::clone(&self.stop);
+
+        let out = self.out.clone();
+        self.search_thread = Some(std::thread::spawn(move || {
+            let mut stats = playout::playouts(&board, &move_generator, count, max_moves, threads, seed, &stop);
+            stats.sort_by(|a, b| b.win_rate().total_cmp(&a.win_rate()));
+
+            for stat in &stats {
+                out.write_line(format_args!(
+                    "info string playouts {} games {} wins {} draws {} losses {} winrate {:.3}",
+                    stat.root_move.move_to_uci(chess960),
+                    stat.playouts(),
+                    stat.wins,
+                    stat.draws,
+                    stat.losses,
+                    stat.win_rate()
+                ));
+            }
+            match playout::best_move(&stats) {
+                Some(best_move) => out.write_line(format_args!("bestmove {}", best_move.move_to_uci(chess960))),
+                None => out.write_line("bestmove (none)"),
+            }
+
+            searcher
+        }));
+    }
+
+    // analysequeue <file>: the positions of the queue (see
+    // script::parse_queue) are analysed one after another on the search
+    // thread, each between `begin position N` and `end position N` lines
+    // with the info and bestmove of a go. A stop ends the current search,
+    // which still sends its bestmove, and the queue goes on to the next
+    // position; quit ends the queue. A position which can't be set up gets
+    // an error in its block instead.
+    fn analyse_queue(&mut self, tokens: &[&str]) {
+        let path = tokens.get(1..).unwrap_or_default().join(" ");
+        if path.is_empty() {
+            return self.out.write_line("info string usage: analysequeue <file>");
+        }
+        let entries = std::fs::read_to_string(&path)
+            .map_err(|e| ScriptError::Io(path.clone(), e.to_string()))
+            .and_then(|queue| script::parse_queue(&queue));
+        let entries = match entries {
+            Ok(entries) => entries,
+            Err(e) => return self.out.error(&Error::from(e)),
+        };
+
+        let mut engine = self.queue_engine();
+        let abort = Arc::clone(&self.abort_queue);
+        abort.store(false, Ordering::Relaxed);
+        self.queue_running = true;
+
+        let out = self.out.clone();
+        self.search_thread = Some(std::thread::spawn(move || {
+            for (i, entry) in entries.iter().enumerate() {
+                if abort.load(Ordering::Relaxed) {
+                    break;
+                }
+
+                out.write_line(format_args!("begin position {}", i + 1));
+                let position: Vec<&str> = entry.position.split_whitespace().collect();
+                if engine.position(&position) {
+                    let go: Vec<&str> = entry.go.split_whitespace().collect();
+                    engine.go(&go);
+                    engine.wait_for_search();
+                }
+                out.write_line(format_args!("end position {}", i + 1));
             }
-            _ => {}
+
+            engine.searcher.take().expect("queue search still running")
+        }));
+    }
+
+    // An engine for the analysis queue's thread, with this one's options
+    // and searcher, which searches until this one's stop flag is set
+    fn queue_engine(&mut self) -> UciEngine {
+        UciEngine {
+            move_generator: Arc::clone(&self.move_generator),
+            searcher: self.searcher.take(),
+            search_thread: None,
+            infinite: false,
+            // the queue's positions are searched one after another anyway
+            options: UciOptions {
+                prime_in_opponent_time: false,
+                ..self.options.clone()
+            },
+            board: self.board.clone(),
+            position: PositionCommand::default(),
+            stop: Arc::clone(&self.stop),
+            pondering: Arc::new(AtomicBool::new(false)),
+            detected_new_games: 0,
+            game_report: GameReport::default(),
+            weights: self.weights,
+            queue_running: false,
+            abort_queue: Arc::new(AtomicBool::new(false)),
+            engine_color: None,
+            priming: false,
+            out: self.out.clone(),
+        }
+    }
+
+    // perft runs on the search thread too, so that it can be stopped and
+    // isready is still answered. Root moves are printed as they are counted.
+    fn go_perft(&mut self, depth: usize) {
+        let searcher = self.searcher.take().expect("search already running");
+        let mut board = self.board.clone();
+        let move_generator = Arc::clone(&self.move_generator);
+
+        let out = self.out.clone();
+        let mut control = ControlHandle::with_stop_flag(self.stop.clone())
+            .with_progress(move |event| {
+                if let ProgressEvent::PerftRootMove { root_move, nodes } = event {
+                    out.write_line(format_args!("{}: {}", root_move.move_to_string(), nodes));
+                }
+            });
+
+        let out = self.out.clone();
+        self.search_thread = Some(std::thread::spawn(move || {
+            let result = perft::perft_divide_with_control(depth, &mut board, &move_generator, &mut control);
+
+            out.write_line("");
+            if result.complete {
+                out.write_line(format_args!("Nodes searched: {}", result.nodes));
+            } else {
+                out.write_line(format_args!(
+                    "Nodes searched: {} (stopped after {} of {} moves)",
+                    result.nodes,
+                    result.divide.len(),
+                    result.root_moves
+                ));
+            }
+
+            searcher
+        }));
+    }
+
+    // a perft of the start position, to compare builds and machines. It
+    // runs on the search thread like go perft, and stop ends it with the
+    // nodes of the root moves counted so far.
+    fn bench(&mut self, tokens: &[&str]) {
+        let depth = match tokens.get(1).map(|t| t.parse::<usize>()) {
+            None => BENCH_DEPTH,
+            Some(Ok(depth)) if depth >= 1 => depth,
+            Some(Ok(depth)) => return self.out.error(&Error::from(SearchError::InvalidDepth(depth))),
+            Some(Err(_)) => return self.out.error(&Error::from(SearchError::InvalidDepth(0))),
+        };
+
+        self.stop.store(false, Ordering::Relaxed);
+        let control = ControlHandle::with_stop_flag(self.stop.clone());
+
+        let searcher = self.searcher.take().expect("search already running");
+        let move_generator = Arc::clone(&self.move_generator);
+        let out = self.out.clone();
+        self.search_thread = Some(std::thread::spawn(move || {
+            let result = bench::perft(&move_generator, depth, &control);
+
+            if result.complete {
+                out.write_line(format_args!("Nodes searched: {}", result.ops));
+            } else {
+                out.write_line(format_args!("Nodes searched: {} (stopped)", result.ops));
+            }
+            out.write_line(format_args!("Nodes/second: {}", result.ops_per_second()));
+
+            searcher
+        }));
+    }
+
+    // "tt save <path>" and "tt load <path>" keep the hash table between
+    // sessions, for long analysis
+    fn tt_command(&mut self, tokens: &[&str]) {
+        let path = tokens.get(2..).unwrap_or_default().join(" ");
+        let tt = self.searcher.as_ref().unwrap().context.tt();
+        let zobrist = self.board.zobrist_checksum();
+
+        let result = match tokens.get(1) {
+            Some(&"save") if !path.is_empty() => tt.save(&path, zobrist).map(|count| ("saved", "to", count)),
+            Some(&"load") if !path.is_empty() => tt.load(&path, zobrist).map(|count| ("loaded", "from", count)),
+            _ => return self.out.write_line("info string usage: tt save|load <path>"),
+        };
+        match result {
+            Ok((done, to, count)) => {
+                self.out.write_line(format_args!("info string {} {} entries {} {}", done, count, to, path))
+            }
+            Err(e) => self.out.error(&Error::from(e)),
         }
     }
+
+    // Recomputes everything the board and evaluator keep incrementally, for
+    // the position and after making and undoing each legal move from it,
+    // and reports anything which disagrees.
+    fn verify(&mut self) {
+        let searcher = self.searcher.as_mut().unwrap();
+        let evaluator = &mut searcher.evaluator;
+        let moves = &mut searcher.move_lists[0];
+        let mut board = self.board.clone();
+        let mut info = UndoInfo::default();
+
+        let mut problems = board.check_consistency();
+        evaluator.full(&board);
+        self.move_generator.gen_moves(&mut board, moves);
+
+        for i in 0..moves.len() {
+            let my_move = moves.at(i);
+            let name = my_move.move_to_string();
+
+            board.make_move(my_move, &mut info);
+            evaluator.on_make(&board, my_move, &info);
+            let made = board.check_consistency().into_iter();
+            problems.extend(made.map(|problem| format!("after {}: {}", name, problem)));
+            if let Some(drift) = eval::eval_drift(evaluator, &board) {
+                problems.push(format!("after {}: eval drift {}", name, drift));
+            }
+
+            board.undo_move(my_move, &info);
+            evaluator.on_undo(&board, my_move, &info);
+            let undone = board.check_consistency().into_iter();
+            problems.extend(undone.map(|problem| format!("undoing {}: {}", name, problem)));
+            if let Some(drift) = eval::eval_drift(evaluator, &board) {
+                problems.push(format!("undoing {}: eval drift {}", name, drift));
+            }
+            if board.to_fen() != self.board.to_fen() || board.hash() != self.board.hash() {
+                problems.push(format!("undoing {} doesn't restore the position", name));
+                board.clone_from(&self.board);
+                evaluator.full(&board);
+            }
+        }
+
+        for problem in &problems {
+            self.out.write_line(format_args!("info string verify: {}", problem));
+        }
+        self.out.write_line(format_args!(
+            "info string verify: checked the position and {} moves, {} problems",
+            moves.len(),
+            problems.len()
+        ));
+    }
+
+    fn setoption(&mut self, tokens: &[&str]) {
+        match parse_setoption(tokens) {
+            Some((name, value)) => self.set_option(&name, &value),
+            None => self.out.write_line(format_args!("info string invalid setoption '{}'", tokens.join(" "))),
+        }
+    }
+
+    // the options from a config file, before the gui sets any
+    pub fn apply_config(&mut self, config: &Config) {
+        for (name, value) in config.options() {
+            self.set_option(name, value);
+        }
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) {
+        // used from the next go, and by anything this thread evaluates
+        #[cfg(feature = "tune")]
+        if let Some(info) = tune::find(name) {
+            match info.parse(value) {
+                Some(value) => {
+                    self.weights.set(info.param, value);
+                    self.weights.install();
+                }
+                None => self.out.write_line(format_args!("info string invalid {} '{}'", name, value)),
+            }
+            return;
+        }
+
+        match self.options.set(name, value) {
+            Ok(()) => {}
+            // options the engine doesn't have are ignored
+            Err(OptionError::Unknown(_)) => return,
+            Err(e) => {
+                self.out.write_line(format_args!("info string {}", e));
+                return;
+            }
+        }
+
+        match name {
+            // the position is kept, with the new rules from here on
+            "UCI_Variant" => self.board.set_variant(self.options.variant),
+            "Debug Log File" => {
+                self.out.set_log_file(self.options.log_file.as_deref().unwrap_or(""));
+            }
+            // the old table is dropped, so nothing is kept
+            "Hash" => {
+                let entries = TranspositionTable::entries_in_megabytes(self.options.hash_mb);
+                self.searcher.as_mut().unwrap().context.resize_tt(entries);
+            }
+            "Clear Hash" => {
+                self.searcher.as_mut().unwrap().context.clear_tt();
+            }
+            "SyzygyPath" => {
+                let tablebases = self.options.syzygy_path.as_deref().map(|path| {
+                    let tablebases = Tablebases::new(path);
+                    self.out.write_line(format_args!(
+                        "info string found {} tablebases with up to {} pieces",
+                        tablebases.len(),
+                        tablebases.max_pieces()
+                    ));
+                    Arc::new(tablebases)
+                });
+                self.searcher.as_mut().unwrap().context.set_tablebases(tablebases);
+            }
+            #[cfg(feature = "nnue")]
+            "EvalFile" => match crate::engine::nnue::Backend::load(value) {
+                Ok(evaluator) => self.searcher.as_mut().unwrap().evaluator = EngineEvaluator::from(evaluator),
+                Err(e) => self.out.write_line(format_args!("info string {}", e)),
+            },
+            // the rest are read from the options when they're needed
+            _ => {}
+        }
+    }
+
+    // returns false once the engine should exit
+    pub fn handle_command(&mut self, line: &str) -> bool {
+        self.out.log_received(line);
+        self.out.report_log_errors();
+
+        let tokens = line.split_whitespace().collect::<Vec<&str>>();
+
+        if tokens.is_empty() {
+            return true;
+        }
+
+        if self.priming {
+            self.stop_priming();
+        }
+
+        // isready is answered straight away, but anything else which uses
+        // the engine's state has to wait for a running search to finish
+        match tokens[0] {
+            "isready" | "uci" | "stop" | "quit" | "debug" | "ponderhit" => {}
+            _ => self.wait_for_search(),
+        }
+
+        match tokens[0] {
+            "isready" => {
+                self.out.write_line("readyok");
+            }
+            "debug" => match tokens.get(1) {
+                Some(&"on") => self.out.set_debug(true),
+                Some(&"off") => self.out.set_debug(false),
+                _ => {}
+            },
+            "ucinewgame" => {
+                self.new_game();
+            }
+            "uci" => {
+                self.out.write_line("id name Avocado");
+                self.out.write_line("id author upsidedown8");
+                for line in UciOptions::advertise() {
+                    self.out.write_line(line);
+                }
+                #[cfg(feature = "tune")]
+                for info in tune::PARAMS.iter() {
+                    self.out.write_line(format_args!(
+                        "option name {} type spin default {} min {} max {}",
+                        info.name, info.default, info.min, info.max
+                    ));
+                }
+                self.out.write_line("uciok");
+            }
+            // a queue goes on to its next position
+            "stop" if self.queue_running => {
+                self.stop.store(true, Ordering::Relaxed);
+            }
+            "stop" => {
+                self.stop_search();
+            }
+            // the search goes on, but now with its clock running
+            "ponderhit" => {
+                self.pondering.store(false, Ordering::Relaxed);
+            }
+            "quit" => {
+                self.quit();
+                return false;
+            }
+            "d" => {
+                self.out.write_line(&self.board);
+                self.out.write_line(format_args!("fen: {}", self.board.to_fen()));
+                self.out.write_line(format_args!("castling: {}", castling_line(self.board.castling_rights())));
+            }
+            "position" if tokens.len() >= 2 => {
+                let set_up = self.position(&tokens);
+                if self.out.is_debug() {
+                    if let Some((eco, name)) = Board::identify_opening(&self.position.opening_keys) {
+                        self.out.debug_info(format_args!("opening {} {}", eco, name));
+                    }
+                }
+                if set_up {
+                    self.prime_tt();
+                }
+            }
+            "go" => {
+                self.go(&tokens);
+            }
+            "setoption" => {
+                self.setoption(&tokens);
+            }
+            "bench" => {
+                self.bench(&tokens);
+            }
+            "verify" => {
+                self.verify();
+            }
+            "moves" => {
+                let evaluator = &mut self.searcher.as_mut().unwrap().evaluator;
+                let rows = movetable::move_rows(&mut self.board, &self.move_generator, evaluator);
+                self.out.write_line(movetable::move_table(&rows, self.options.chess960).trim_end());
+            }
+            "tt" => {
+                self.tt_command(&tokens);
+            }
+            "stats" => {
+                self.send_game_report();
+            }
+            "analysequeue" => {
+                self.analyse_queue(&tokens);
+            }
+            _ => {}
+        }
+
+        true
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        self.stop_search();
+    }
+}
+
+// what the engine is sent, read on another thread so that ctrl-c can be
+// seen while waiting for input
+pub enum Input {
+    Line(String),
+    // the end of the input, or a read error
+    Eof,
+    // ctrl-c
+    Interrupt,
+}
+
+// Handles input until quit, ctrl-c or the end of the input. Every way out
+// stops the search and flushes the log, so the process can then exit.
+pub fn uci_loop(engine: &mut UciEngine, input: &Receiver<Input>) {
+    loop {
+        match input.recv() {
+            Ok(Input::Line(line)) => {
+                if !engine.handle_command(&line) {
+                    return;
+                }
+            }
+            // let a search given a fixed limit finish first (eg. when
+            // commands are piped in)
+            Ok(Input::Eof) | Err(_) => {
+                if engine.infinite || engine.priming {
+                    engine.stop_search();
+                }
+                engine.wait_for_search();
+                engine.quit();
+                return;
+            }
+            Ok(Input::Interrupt) => {
+                engine.quit();
+                return;
+            }
+        }
+    }
+}
+
+// the config file's options are set first, so --log and setoption win
+pub fn uci(log_file: Option<&str>, config: &Config) {
+    let out = UciOutput::stdout();
+    let mut engine = UciEngine::new(out.clone());
+    engine.apply_config(config);
+
+    if let Some(path) = log_file {
+        out.set_log_file(path);
+    }
+
+    let (sender, receiver) = mpsc::channel();
+
+    // The first ctrl-c quits as the quit command would. If the engine is
+    // busy and doesn't get to it, a second one exits straight away.
+    let interrupted = AtomicBool::new(false);
+    let interrupt = sender.clone();
+    let _ = ctrlc::set_handler(move || {
+        if interrupted.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        let _ = interrupt.send(Input::Interrupt);
+    });
+
+    // never joined, as it may be blocked reading when the engine quits
+    std::thread::spawn(move || loop {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) | Err(_) => {
+                let _ = sender.send(Input::Eof);
+                return;
+            }
+            Ok(_) => {
+                if sender.send(Input::Line(line)).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    uci_loop(&mut engine, &receiver);
+}
+
+// collects everything written to a UciOutput so tests can inspect it
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct CapturedOutput {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+#[cfg(test)]
+impl CapturedOutput {
+    pub(crate) fn lines(&self) -> Vec<String> {
+        let buffer = self.buffer.lock().unwrap();
+        String::from_utf8_lossy(&buffer).lines().map(String::from).collect()
+    }
+}
+
+#[cfg(test)]
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(commands: &[&str]) -> Vec<String> {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+
+        for command in commands {
+            engine.handle_command(command);
+        }
+        engine.wait_for_search();
+
+        captured.lines()
+    }
+
+    // the move from a bestmove line, without any move to ponder on
+    fn best_move(line: &str) -> Option<&str> {
+        line.strip_prefix("bestmove ")?.split_whitespace().next()
+    }
+
+    #[test]
+    fn mate_scores() {
+        assert_eq!(score_to_string(35), "cp 35");
+        assert_eq!(score_to_string(search::MATE_SCORE - 1), "mate 1");
+        assert_eq!(score_to_string(search::MATE_SCORE - 3), "mate 2");
+        assert_eq!(score_to_string(2 - search::MATE_SCORE), "mate -1");
+        assert_eq!(score_to_string(search::TB_WIN - 5), format!("cp {}", search::TB_WIN - 5));
+
+        // the rook cuts off the king, then the other mates on the back rank
+        let lines = run(&["position fen 7k/8/8/8/8/8/1R6/R3K3 w - - 0 1", "go depth 4"]);
+        let last = lines.iter().rev().find(|line| line.starts_with("info score ")).unwrap();
+        assert!(last.starts_with("info score mate 2 depth 4 "), "{}", last);
+
+        // and being mated is counted from the engine's side
+        let lines = run(&["position fen 7k/8/8/8/8/8/1R6/R3K3 w - - 0 1 moves b2b7 h8g8", "go depth 2"]);
+        assert!(lines.iter().any(|line| line.starts_with("info score mate 1 ")), "{:?}", lines);
+        let lines = run(&["position fen 7k/8/8/8/8/8/1R6/R3K3 w - - 0 1 moves b2b7", "go depth 3"]);
+        assert!(lines.iter().any(|line| line.starts_with("info score mate -1 ")), "{:?}", lines);
+    }
+
+    #[test]
+    fn nodes_and_nps() {
+        let lines = run(&["position startpos", "go depth 1"]);
+        let tokens: Vec<&str> = lines[0].split_whitespace().collect();
+        let field = |name: &str| tokens[tokens.iter().position(|&t| t == name).unwrap() + 1];
+
+        assert_eq!(field("nodes"), "20");
+        let (nodes, millis, nps): (u64, u64, u64) =
+            (field("nodes").parse().unwrap(), field("time").parse().unwrap(), field("nps").parse().unwrap());
+        assert_eq!(nps, nodes * 1000 / millis.max(1));
+    }
+
+    #[test]
+    fn show_wdl() {
+        let lines = run(&["position startpos", "go depth 1"]);
+        assert!(lines[0].starts_with("info score cp "));
+        assert!(!lines[0].contains("wdl"));
+
+        let lines = run(&["setoption name UCI_ShowWDL value true", "position startpos", "go depth 1"]);
+        let tokens: Vec<&str> = lines[0].split_whitespace().collect();
+        assert_eq!(tokens[4], "wdl");
+
+        let permille: u32 = tokens[5..8].iter().map(|t| t.parse::<u32>().unwrap()).sum();
+        assert_eq!(permille, 1000);
+    }
+
+    #[test]
+    fn moves_to_go() {
+        let params = parse_go(&["go", "wtime", "60000", "btime", "50000", "movestogo", "12"]).unwrap();
+        assert_eq!(params.moves_to_go, Some(12));
+        assert_eq!(params.time, [Some(50000), Some(60000)]);
+
+        assert_eq!(parse_go(&["go", "wtime", "60000"]).unwrap().moves_to_go, None);
+        assert_eq!(
+            parse_go(&["go", "movestogo", "-1"]),
+            Err(Error::from(SearchError::InvalidMovesToGo("-1".to_string())))
+        );
+        assert!(parse_go(&["go", "movestogo"]).is_err());
+    }
+
+    #[test]
+    fn setoption_parsing() {
+        let parse = |line: &str| parse_setoption(&line.split_whitespace().collect::<Vec<_>>());
+        let option = |name: &str, value: &str| Some((name.to_string(), value.to_string()));
+
+        assert_eq!(parse("setoption name Hash value 128"), option("Hash", "128"));
+        assert_eq!(parse("setoption name Move Overhead value 50"), option("Move Overhead", "50"));
+        assert_eq!(parse("setoption name SyzygyPath value /a b/c"), option("SyzygyPath", "/a b/c"));
+        assert_eq!(parse("setoption name Clear Hash"), option("Clear Hash", ""));
+        assert_eq!(parse("setoption name Hash value"), option("Hash", ""));
+
+        assert_eq!(parse("setoption"), None);
+        assert_eq!(parse("setoption Hash value 128"), None);
+        assert_eq!(parse("setoption name"), None);
+        assert_eq!(parse("setoption name value 128"), None);
+    }
+
+    #[test]
+    fn hash_options() {
+        let lines = run(&["uci"]);
+        assert!(lines.contains(&"option name Hash type spin default 64 min 1 max 4096".to_string()));
+        assert!(lines.contains(&"option name Clear Hash type button".to_string()));
+
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let entries = |engine: &UciEngine| engine.searcher.as_ref().unwrap().context.tt_entries();
+        assert_eq!(entries(&engine), TranspositionTable::entries_in_megabytes(64));
+
+        engine.handle_command("setoption name Hash value 2");
+        assert_eq!(entries(&engine), TranspositionTable::entries_in_megabytes(2));
+
+        // bad values and lines are reported and change nothing
+        for line in ["setoption name Hash value 0", "setoption name Hash value lots", "setoption Hash value 8"] {
+            engine.handle_command(line);
+            assert_eq!(entries(&engine), TranspositionTable::entries_in_megabytes(2));
+        }
+        assert_eq!(
+            captured.lines(),
+            [
+                "info string invalid Hash '0'",
+                "info string invalid Hash 'lots'",
+                "info string invalid setoption 'setoption Hash value 8'"
+            ]
+        );
+
+        // a search repeated with the table is quick, and without it isn't
+        let nodes = |engine: &mut UciEngine| {
+            engine.handle_command("go depth 4");
+            engine.wait_for_search();
+            engine.searcher.as_ref().unwrap().context.nodes()
+        };
+        let first = nodes(&mut engine);
+        let again = nodes(&mut engine);
+        engine.handle_command("setoption name Clear Hash");
+        let cleared = nodes(&mut engine);
+        assert!(again * 2 < first && again * 2 < cleared, "{} {} {}", first, again, cleared);
+    }
+
+    #[test]
+    fn move_time_and_nodes() {
+        let params = parse_go(&["go", "movetime", "100", "nodes", "5000"]).unwrap();
+        assert_eq!((params.move_time, params.nodes), (Some(100), Some(5000)));
+        assert_eq!(
+            parse_go(&["go", "nodes", "-5"]),
+            Err(Error::from(SearchError::InvalidNodes("-5".to_string())))
+        );
+        assert!(parse_go(&["go", "movetime"]).is_err());
+
+        // the search stops as soon as it has passed the node limit
+        let lines = run(&["debug on", "position startpos", "go nodes 5000"]);
+        let finished = lines.iter().find(|line| line.contains("search finished")).unwrap();
+        let nodes: u64 = finished.split_whitespace().nth(5).unwrap().parse().unwrap();
+        assert!((5000..=5001).contains(&nodes), "{}", finished);
+        assert!(lines.iter().any(|line| line.starts_with("bestmove ")));
+
+        // and a move time is used up however deep that gets
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let start = Instant::now();
+        engine.handle_command("go movetime 100");
+        engine.wait_for_search();
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100) && elapsed < Duration::from_millis(500), "{:?}", elapsed);
+        assert!(captured.lines().last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn timed_search_depth() {
+        // with a clock to end it the search goes past the default depth
+        let depth = |lines: &[String]| -> usize {
+            let last_info = lines.iter().rev().find(|line| line.starts_with("info score")).unwrap();
+            let tokens: Vec<_> = last_info.split_whitespace().collect();
+            let i = tokens.iter().position(|&token| token == "depth").unwrap();
+            tokens[i + 1].parse().unwrap()
+        };
+        let position = "position fen 8/8/8/4k3/8/8/8/R3K3 w - - 0 1";
+        assert_eq!(depth(&run(&[position, "go"])), MAX_DEPTH);
+        assert!(depth(&run(&[position, "go movetime 500"])) > MAX_DEPTH);
+        assert!(depth(&run(&[position, "go wtime 20000 btime 20000"])) > MAX_DEPTH);
+        assert_eq!(depth(&run(&[position, "go depth 8"])), 8);
+        assert!(parse_go(&["go", "depth", &(MAX_SEARCH_DEPTH + 1).to_string()]).is_err());
+    }
+
+    #[test]
+    fn threads_option() {
+        let lines = run(&["uci"]);
+        assert!(lines.contains(&"option name Threads type spin default 1 min 1 max 64".to_string()));
+
+        let lines = run(&[
+            "setoption name Threads value 0",
+            "setoption name Threads value 4",
+            "position startpos moves e2e4",
+            "go depth 3",
+        ]);
+        assert_eq!(lines[0], "info string invalid Threads '0'");
+
+        let best_move = best_move(lines.last().unwrap()).unwrap();
+        let mut board = Board::default();
+        let move_generator = MoveGenerator::new();
+        let mut info = UndoInfo::default();
+        board.make_uci_move("e2e4", &move_generator, &mut info).unwrap();
+        assert!(board.make_uci_move(best_move, &move_generator, &mut info).is_ok());
+    }
+
+    #[test]
+    fn multi_pv_option() {
+        let lines = run(&["uci"]);
+        assert!(lines.contains(&"option name MultiPV type spin default 1 min 1 max 256".to_string()));
+
+        let lines = run(&["setoption name MultiPV value 0", "setoption name MultiPV value 3", "go depth 2"]);
+        assert_eq!(lines[0], "info string invalid MultiPV '0'");
+
+        // three numbered lines for each depth, best first
+        for depth in 1..=2 {
+            let infos: Vec<Vec<&str>> = lines
+                .iter()
+                .map(|line| line.split_whitespace().collect::<Vec<_>>())
+                .filter(|tokens| tokens[1] == "multipv" && tokens[7] == depth.to_string())
+                .collect();
+            assert_eq!(infos.len(), 3, "{:?}", lines);
+
+            for (i, tokens) in infos.iter().enumerate() {
+                assert_eq!(tokens[..5], ["info", "multipv", &(i + 1).to_string(), "score", "cp"]);
+                let pv = tokens.iter().position(|&token| token == "pv").unwrap() + 1;
+                assert!(infos[..i].iter().all(|better| {
+                    let better_pv = better.iter().position(|&token| token == "pv").unwrap() + 1;
+                    better[better_pv] != tokens[pv] && better[5].parse::<i32>().unwrap() >= tokens[5].parse().unwrap()
+                }));
+            }
+        }
+
+        // and one unnumbered line with the default
+        let lines = run(&["go depth 2"]);
+        assert_eq!(lines.iter().filter(|line| line.starts_with("info score")).count(), 2);
+    }
+
+    #[test]
+    fn search_moves() {
+        let params = parse_go(&["go", "searchmoves", "e2e4", "d2d4", "depth", "2"]).unwrap();
+        assert_eq!((params.search_moves, params.depth), (vec!["e2e4".to_string(), "d2d4".to_string()], Some(2)));
+
+        // the move is played whatever its score
+        let lines = run(&["position startpos", "go depth 3 searchmoves a2a3"]);
+        assert_eq!(best_move(lines.last().unwrap()), Some("a2a3"));
+
+        // and moves which can't be played are left out
+        let lines = run(&["position startpos", "go searchmoves a2a3 e2e5 a2 depth 2"]);
+        assert_eq!(lines[0], "info string searchmoves: illegal move 'e2e5' ignored");
+        assert_eq!(lines[1], "info string searchmoves: invalid move notation 'a2' ignored");
+        assert_eq!(best_move(lines.last().unwrap()), Some("a2a3"));
+    }
+
+    #[test]
+    fn go_mate() {
+        assert_eq!(parse_go(&["go", "mate", "3"]).unwrap().mate, Some(3));
+        assert_eq!(
+            parse_go(&["go", "mate", "0"]),
+            Err(Error::from(SearchError::InvalidMate("0".to_string())))
+        );
+
+        // Every defence loses as quickly, so whichever the search picks, the
+        // line is played out to check it ends in mate. A line cut short by
+        // the hash table has to leave a mate in the moves still to go.
+        fn mating_line(fen: &str, moves: usize, lines: &[String]) -> String {
+            let last_info = lines.iter().rfind(|line| line.starts_with("info score ")).unwrap().clone();
+            let (_, pv) = last_info.split_once(" pv ").unwrap();
+            let move_generator = MoveGenerator::new();
+            let mut board = Board::new(fen).unwrap();
+            let mut info = UndoInfo::default();
+            for uci_move in pv.split(' ') {
+                board.make_uci_move(uci_move, &move_generator, &mut info).unwrap();
+            }
+            if board.game_state(&move_generator) != GameState::Checkmate {
+                let left = moves - pv.split(' ').count() / 2;
+                let lines = run(&[&format!("position fen {}", board.to_fen()), &format!("go mate {}", left)]);
+                mating_line(&board.to_fen(), left, &lines);
+            }
+            last_info
+        }
+
+        // the mating move and its score
+        for (fen, moves, mating_move) in &[
+            // 1. Ra6 Bd6 2. Rxa7#, or 1... bxa6 2. b7#
+            ("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1", 2, "a1a6"),
+            // Legal's mate: 1. Bxf7+ Ke7 2. Nd5#
+            ("rn1qkbnr/ppp2p1p/3p2p1/4N3/2B1P3/2N5/PPPP1PPP/R1BbK2R w KQkq - 0 6", 2, "c4f7"),
+            // the knight clears the way: 1. Ng3 Kg8 2. Rf1 Kh8 3. Rf8#
+            ("7k/7p/7P/8/8/8/8/K4NR1 w - - 0 1", 3, "f1g3"),
+        ] {
+            let lines = run(&[&format!("position fen {}", fen), &format!("go mate {}", moves)]);
+            let last_info = mating_line(fen, *moves, &lines);
+            assert!(last_info.starts_with(&format!("info score mate {} ", moves)), "{}", last_info);
+            assert_eq!(best_move(lines.last().unwrap()), Some(*mating_move));
+        }
+
+        // with no mate the best try is played
+        let lines = run(&["position startpos", "go mate 1"]);
+        assert!(lines.contains(&"info string no mate in 1 found".to_string()), "{:?}", lines);
+        assert!(lines.iter().all(|line| !line.contains("score mate")), "{:?}", lines);
+        assert!(best_move(lines.last().unwrap()).is_some());
+
+        // deeper than the default depth: the rook mates in five, and not in
+        // four
+        let fen = "8/8/8/8/8/8/1k6/3K2R1 w - - 0 1";
+        let lines = run(&["debug on", &format!("position fen {}", fen), "go mate 5"]);
+        let last_info = mating_line(fen, 5, &lines);
+        assert!(last_info.starts_with("info score mate 5 depth "), "{}", last_info);
+        assert!(lines.iter().any(|line| line.ends_with("ended by mate")), "{:?}", lines);
+
+        let lines = run(&[&format!("position fen {}", fen), "go mate 4"]);
+        assert!(lines.contains(&"info string no mate in 4 found".to_string()), "{:?}", lines);
+        assert!(lines.iter().all(|line| !line.contains("score mate")), "{:?}", lines);
+
+        // the search stops at the mate, however many moves it was given
+        let lines = run(&["debug on", "position fen 7k/8/5K2/8/8/8/8/6Q1 w - - 0 1", "go mate 40"]);
+        assert_eq!(lines[0], "info string mate 40 is beyond the maximum depth, searching 64 plies");
+        let finished = lines.iter().find(|line| line.contains("search finished")).unwrap();
+        assert!(finished.ends_with("ended by mate"), "{}", finished);
+        assert!(lines.contains(&"bestmove g1g7".to_string()), "{:?}", lines);
+    }
+
+    #[test]
+    fn syzygy_path() {
+        let dir = std::path::PathBuf::from(crate::engine::iolog::temp_path("uci_syzygy"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        crate::engine::syzygy::write_single_value_table(&dir, "KQvK", &[6, 5, 14], &[[4, 0]]);
+
+        // the move which hangs the queen is never chosen
+        let lines = run(&[
+            &format!("setoption name SyzygyPath value {}", dir.display()),
+            "position fen 4k3/8/8/8/8/8/8/3QK3 w - - 0 1",
+            "go depth 1",
+            "setoption name SyzygyPath value <empty>",
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(lines[0], "info string found 1 tablebases with up to 3 pieces");
+        let best_move = lines.last().unwrap();
+        assert!(best_move.starts_with("bestmove "));
+        assert!(best_move != "bestmove d1d7" && best_move != "bestmove d1d8");
+
+        // a table which can't be read is reported once, after the search
+        // which needed it
+        let dir = std::path::PathBuf::from(crate::engine::iolog::temp_path("uci_syzygy_bad"));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        std::fs::write(dir.join("KRvK.rtbw"), [0u8; 16]).unwrap();
+        let lines = run(&[
+            &format!("setoption name SyzygyPath value {}", dir.display()),
+            "position fen 4k3/8/8/8/8/8/8/3RK3 w - - 0 1",
+            "go depth 2",
+            "go depth 2",
+        ]);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let error = format!(
+            "info string tablebase error: '{}' is not a valid tablebase file",
+            dir.join("KRvK.rtbw").display()
+        );
+        assert_eq!(lines.iter().filter(|&line| *line == error).count(), 1, "{:?}", lines);
+        let reported = lines.iter().position(|line| *line == error).unwrap();
+        assert!(lines[reported + 1].starts_with("bestmove "), "{:?}", lines);
+    }
+
+    #[test]
+    fn debug_log_file() {
+        let path = crate::engine::iolog::temp_path("session.log");
+        let set_log = format!("setoption name Debug Log File value {}", path);
+
+        run(&[&set_log, "isready", "position startpos moves e2e4", "go depth 1"]);
+
+        // the engine has been dropped, so everything has been written
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let logged: Vec<&str> = contents.lines().map(|line| &line[13..]).collect();
+
+        assert_eq!(logged[0], ">> isready");
+        assert_eq!(logged[1], "<< readyok");
+        assert_eq!(logged[2], ">> position startpos moves e2e4");
+        assert_eq!(logged[3], ">> go depth 1");
+        assert!(logged[4].starts_with("<< info score cp "));
+        assert!(logged[5].starts_with("<< bestmove "));
+        assert_eq!(logged.len(), 6);
+        std::fs::remove_file(&path).unwrap();
+
+        // an empty value turns logging off again
+        run(&[&set_log, "isready", "setoption name Debug Log File value", "isready"]);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let logged: Vec<&str> = contents.lines().map(|line| &line[13..]).collect();
+        assert_eq!(
+            logged,
+            vec![">> isready", "<< readyok", ">> setoption name Debug Log File value"]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    // runs uci_loop on the input, which must end it
+    fn run_loop(engine: &mut UciEngine, input: Vec<Input>) {
+        let (sender, receiver) = mpsc::channel();
+        for input in input {
+            sender.send(input).unwrap();
+        }
+
+        let start = Instant::now();
+        uci_loop(engine, &receiver);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn log_file_error() {
+        let path = crate::engine::iolog::temp_path("missing/uci.log");
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        engine.handle_command(&format!("setoption name Debug Log File value {}", path));
+
+        // the file is opened by the log's thread, so the error is sent with
+        // whatever the engine answers once it has tried
+        let prefix = format!("info string log file error: can't open '{}': ", path);
+        for _ in 0..100 {
+            if captured.lines().iter().any(|line| line.starts_with(&prefix)) {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+            engine.handle_command("isready");
+        }
+        let lines = captured.lines();
+        assert_eq!(lines.iter().filter(|line| line.starts_with(&prefix)).count(), 1, "{:?}", lines);
+    }
+
+    #[test]
+    fn quit() {
+        let line = |line: &str| Input::Line(line.to_string());
+        // the game report follows the move
+        let ends_with_move = |lines: Vec<String>| {
+            let report = lines.iter().rev().take_while(|line| line.starts_with("info string ")).count();
+            report == 5 && lines[lines.len() - report - 1].starts_with("bestmove ")
+        };
+
+        let path = crate::engine::iolog::temp_path("quit.log");
+        let captured = CapturedOutput::default();
+        let out = UciOutput::new(captured.clone());
+        out.set_log_file(&path);
+        let mut engine = UciEngine::new(out);
+
+        // the search is stopped, and nothing after quit is handled
+        run_loop(
+            &mut engine,
+            vec![line("position startpos"), line("go infinite"), line("quit"), line("isready")],
+        );
+        let lines = captured.lines();
+        assert!(ends_with_move(lines.clone()), "{:?}", lines);
+
+        // the log is complete while the engine is still alive
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let logged: Vec<&str> = contents.lines().map(|line| &line[13..]).collect();
+        // an iteration may finish after quit is read, before the search sees
+        // the stop flag
+        let quit = logged.iter().position(|&line| line == ">> quit").unwrap();
+        let (after_quit, report) = logged[quit + 1..].split_at(logged.len() - quit - 1 - 5);
+        let (last, after_quit) = after_quit.split_last().unwrap();
+        assert!(last.starts_with("<< bestmove "), "{:?}", logged);
+        assert!(after_quit.iter().all(|line| line.starts_with("<< info ")), "{:?}", logged);
+        assert!(report.iter().all(|line| line.starts_with("<< info string ")), "{:?}", logged);
+        std::fs::remove_file(&path).unwrap();
+
+        // ctrl-c takes the same way out
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        run_loop(&mut engine, vec![line("go infinite"), Input::Interrupt, line("isready")]);
+        assert!(ends_with_move(captured.lines()));
+
+        // as does the end of the input, once a search with a limit is done
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        run_loop(&mut engine, vec![line("go depth 3"), Input::Eof]);
+        assert!(ends_with_move(captured.lines()));
+    }
+
+    #[test]
+    fn game_report() {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let report = |captured: &CapturedOutput| {
+            let lines = captured.lines();
+            let start = lines.iter().rposition(|line| line.starts_with("info string game summary")).unwrap();
+            lines[start..].to_vec()
+        };
+
+        // white's first five moves of a game
+        let game = ["e2e4", "e7e5", "g1f3", "b8c6", "f1b5", "a7a6", "b5a4", "g8f6"];
+        engine.handle_command("ucinewgame");
+        for i in 0..5 {
+            engine.handle_command(&format!("position startpos moves {}", game[..i * 2].join(" ")));
+            engine.handle_command(match i {
+                0 => "go depth 2",
+                _ => "go wtime 100000 btime 100000 depth 3",
+            });
+            engine.wait_for_search();
+            assert!(captured.lines().last().unwrap().starts_with("bestmove "));
+        }
+        engine.handle_command("stats");
+        let lines = report(&captured);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], "info string game summary: 5 searches");
+        assert_eq!(lines[1], "info string depth: average 2.80 max 3");
+
+        let numbers = |line: &str| -> Vec<u64> {
+            line.split(|c: char| !c.is_ascii_digit()).filter_map(|word| word.parse().ok()).collect()
+        };
+        let nodes = numbers(&lines[2]);
+        assert!(nodes[0] > 5 * 20, "{}", lines[2]);
+        // four searches were given time, but needed little of it
+        let time = numbers(&lines[3]);
+        assert!(time[1] <= time[0] && time[1] < time[2], "{}", lines[3]);
+        assert!(time[2] > 0, "{}", lines[3]);
+        let tt = numbers(&lines[4]);
+        assert!(tt[0] > 0 && tt[0] < tt[1], "{}", lines[4]);
+
+        // a new game starts again, and quit sends the report too
+        engine.handle_command("ucinewgame");
+        engine.handle_command("stats");
+        assert_eq!(report(&captured)[0], "info string game summary: 0 searches");
+        engine.handle_command("go depth 1");
+        engine.quit();
+        assert_eq!(report(&captured)[0], "info string game summary: 1 searches");
+    }
+
+    #[test]
+    fn responsive_while_searching() {
+        let (sender, receiver) = mpsc::channel();
+        let line = |line: &str| Input::Line(line.to_string());
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+
+        // the commands arrive one by one while the search runs
+        let gui = std::thread::spawn(move || {
+            for command in ["position startpos moves e2e4", "go infinite", "isready", "stop", "isready", "quit"] {
+                sender.send(line(command)).unwrap();
+                std::thread::sleep(Duration::from_millis(50));
+            }
+        });
+        let start = Instant::now();
+        uci_loop(&mut engine, &receiver);
+        gui.join().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // the first isready is answered mid-search, and the search only ends
+        // when told to stop
+        let lines = captured.lines();
+        let readyok: Vec<usize> = (0..lines.len()).filter(|&i| lines[i] == "readyok").collect();
+        let bestmove: Vec<usize> = (0..lines.len()).filter(|&i| lines[i].starts_with("bestmove ")).collect();
+        assert_eq!((readyok.len(), bestmove.len()), (2, 1), "{:?}", lines);
+        assert!(readyok[0] < bestmove[0] && bestmove[0] < readyok[1], "{:?}", lines);
+        assert!(lines[..bestmove[0]].iter().any(|line| line.starts_with("info score ")));
+
+        let mut board = Board::new(&play(crate::engine::board::STARTING_FEN, &["e2e4"])).unwrap();
+        let mut info = UndoInfo::default();
+        let best_move = best_move(&lines[bestmove[0]]).unwrap();
+        assert!(board.make_uci_move(best_move, &MoveGenerator::new(), &mut info).is_ok());
+    }
+
+    #[test]
+    fn infinite_until_stop() {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let bestmoves = || captured.lines().into_iter().filter(|line| line.starts_with("bestmove ")).collect::<Vec<_>>();
+
+        // deep enough iterations finish to be reported, but the move isn't
+        engine.handle_command("position startpos");
+        engine.handle_command("go infinite");
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(bestmoves().is_empty());
+
+        engine.handle_command("stop");
+        engine.wait_for_search();
+        let lines = captured.lines();
+        let bestmoves = bestmoves();
+        assert_eq!(bestmoves.len(), 1, "{:?}", lines);
+        let best_move = best_move(&bestmoves[0]).unwrap();
+
+        // it is legal, and the one the deepest complete iteration found
+        let mut board = Board::default();
+        let mut info = UndoInfo::default();
+        assert!(board.make_uci_move(best_move, &MoveGenerator::new(), &mut info).is_ok());
+        let last_info = lines.iter().rev().find(|line| line.starts_with("info score ")).unwrap();
+        let pv = last_info.split(" pv ").nth(1).unwrap();
+        assert_eq!(pv.split_whitespace().next(), Some(best_move));
+    }
+
+    #[test]
+    fn ponder() {
+        assert!(parse_go(&["go", "wtime", "100", "btime", "100", "ponder"]).unwrap().ponder);
+        assert!(run(&["uci"]).contains(&"option name Ponder type check default false".to_string()));
+
+        // with 100ms left the search would be over at once, but the clock
+        // doesn't start while pondering
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        engine.handle_command("position startpos moves e2e4 e7e5");
+        engine.handle_command("go wtime 100 btime 100 ponder");
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(captured.lines().iter().all(|line| !line.starts_with("bestmove ")));
+
+        // the opponent played something else
+        engine.handle_command("stop");
+        engine.wait_for_search();
+        assert!(best_move(captured.lines().last().unwrap()).is_some());
+
+        // and a ponderhit runs the clock from then on
+        let start = Instant::now();
+        engine.handle_command("go wtime 100 btime 100 ponder");
+        engine.handle_command("ponderhit");
+        engine.wait_for_search();
+        assert!(start.elapsed() < Duration::from_millis(250), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn stop_before_depth_1() {
+        for fen in &[
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "5r1k/5p1p/8/6Q1/8/8/qr4PP/7K w - - 0 1",
+            "4k3/8/8/8/8/8/4q3/4K3 w - - 0 1",
+            "7k/8/8/8/8/8/8/K7 b - - 0 1",
+        ] {
+            let lines = run(&[&format!("position fen {}", fen), "go infinite", "stop"]);
+            let best_move = lines
+                .last()
+                .and_then(|line| best_move(line))
+                .unwrap_or_else(|| panic!("no bestmove for {}: {:?}", fen, lines));
+
+            let mut board = Board::new(fen).unwrap();
+            let mut info = UndoInfo::default();
+            assert!(
+                board.make_uci_move(best_move, &MoveGenerator::new(), &mut info).is_ok(),
+                "{} is not legal in {}",
+                best_move,
+                fen
+            );
+        }
+    }
+
+    #[test]
+    fn go_after_variant_loss() {
+        // the white king already stands in the centre, so black has lost
+        // whatever moves it still has
+        let lines = run(&[
+            "setoption name UCI_Variant value kingofthehill",
+            "position fen 8/8/8/3K4/8/8/8/7k b - - 0 1",
+            "go depth 2",
+        ]);
+        assert_eq!(lines, vec!["info depth 0 score mate 0", "bestmove (none)"]);
+    }
+
+    #[test]
+    fn go_without_legal_moves() {
+        let checkmate = "position fen 7k/6Q1/6K1/8/8/8/8/8 b - - 0 1";
+        let stalemate = "position fen 7k/5Q2/6K1/8/8/8/8/8 b - - 0 1";
+
+        for (position, score) in [(checkmate, "mate 0"), (stalemate, "cp 0")] {
+            let expected = vec![format!("info depth 0 score {}", score), "bestmove (none)".to_string()];
+            assert_eq!(run(&[position, "go depth 3"]), expected);
+            assert_eq!(run(&[position, "go infinite", "stop"]), expected);
+
+            // pondering waits for ponderhit or stop, either way round
+            for ending in [["ponderhit", "stop"], ["stop", "ponderhit"]] {
+                let captured = CapturedOutput::default();
+                let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+                engine.handle_command(position);
+                engine.handle_command("go wtime 1000 btime 1000 ponder");
+                std::thread::sleep(Duration::from_millis(50));
+                assert!(captured.lines().is_empty(), "{:?}", captured.lines());
+
+                engine.handle_command(ending[0]);
+                engine.wait_for_search();
+                assert_eq!(captured.lines(), expected);
+                engine.handle_command(ending[1]);
+                engine.handle_command("isready");
+                assert_eq!(captured.lines()[2..], ["readyok"]);
+            }
+
+            // and the engine goes on to search the next position
+            let lines = run(&[position, "go ponder", "stop", "position startpos", "go depth 1"]);
+            assert_eq!(lines[..2], expected[..]);
+            assert!(best_move(lines.last().unwrap()).is_some(), "{:?}", lines);
+        }
+    }
+
+    #[test]
+    fn go_playouts() {
+        assert_eq!(parse_go(&["go", "playouts", "50"]).unwrap().playouts, Some(50));
+        assert!(parse_go(&["go", "playouts", "0"]).is_err());
+
+        let position = "position fen 7k/8/6K1/8/8/8/8/R7 w - - 0 1";
+        let lines = run(&["setoption name Playout Seed value 3", position, "go playouts 500"]);
+        assert!(lines[0].starts_with("info string playouts a1a8 "), "{:?}", lines);
+        assert!(lines[0].ends_with(" losses 0 winrate 1.000"), "{:?}", lines);
+        assert_eq!(lines.last().unwrap(), "bestmove a1a8");
+        assert_eq!(run(&["setoption name Playout Seed value 3", position, "go playouts 500"]), lines);
+
+        // with a limit of one move each only the mate isn't a draw
+        let lines = run(&["uci", "setoption name Playout Move Limit value 0", "setoption name Playout Move Limit value 1", position, "go playouts 500"]);
+        assert!(lines.contains(&"option name Playout Move Limit type spin default 100 min 1 max 1024".to_string()));
+        assert!(lines.contains(&"info string invalid Playout Move Limit '0'".to_string()), "{:?}", lines);
+        let playouts: Vec<_> = lines.iter().filter(|line| line.starts_with("info string playouts ")).collect();
+        assert!(playouts[0].starts_with("info string playouts a1a8 ") && playouts[0].ends_with(" winrate 1.000"), "{:?}", playouts);
+        assert!(playouts[1..].iter().all(|line| line.ends_with(" winrate 0.500")), "{:?}", playouts);
+
+        // stopped long before the games are played
+        let start = Instant::now();
+        let lines = run(&["position startpos", "go playouts 1000000000", "stop"]);
+        assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+        assert_eq!(lines.len(), 21, "{:?}", lines);
+        assert!(lines.last().unwrap().starts_with("bestmove "), "{:?}", lines);
+    }
+
+    #[test]
+    fn display_castling() {
+        let lines = run(&["position fen r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1", "d"]);
+        assert!(lines.contains(&"fen: r3k2r/8/8/8/8/8/8/R3K2R w qK - 0 1".to_string()), "{:?}", lines);
+        assert_eq!(lines.last().unwrap(), "castling: white O-O, black O-O-O");
+
+        let lines = run(&["position startpos moves e2e4 e7e5 e1e2", "d"]);
+        assert_eq!(lines.last().unwrap(), "castling: white -, black O-O O-O-O");
+    }
+
+    #[test]
+    fn opening_with_debug() {
+        let lines = run(&["position startpos moves e2e4 c7c5 g1f3"]);
+        assert!(lines.is_empty(), "{:?}", lines);
+
+        let lines = run(&[
+            "debug on",
+            "position startpos moves e2e4 c7c5 g1f3",
+            "position startpos moves e2e4 c7c5 g1f3 d7d6 d2d4 c5d4 f3d4 g8f6 b1c3 a7a6 c1e3",
+            "ucinewgame",
+            "position fen 8/5k2/8/3p4/8/2K5/8/8 w - - 0 1",
+        ]);
+        assert_eq!(
+            lines,
+            [
+                "info string opening B27 Sicilian Defence",
+                "info string opening B90 Sicilian Defence, Najdorf Variation",
+            ]
+        );
+    }
+
+    #[test]
+    fn time_overshoot() {
+        let lines = run(&["uci", "setoption name Overshoot Margin value 1001"]);
+        assert!(lines.contains(&"option name Overshoot Margin type spin default 10 min 0 max 1000".to_string()));
+        assert_eq!(lines.last().unwrap(), "info string invalid Overshoot Margin '1001'");
+
+        // a slow evaluation runs a thousand nodes well past the limit before
+        // the clock is first looked at, and the next search looks sooner
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let searcher = engine.searcher.as_mut().unwrap();
+        searcher.evaluator.delay = Some(Duration::from_micros(200));
+        let mut intervals = Vec::new();
+        for _ in 0..2 {
+            engine.handle_command("debug on");
+            engine.handle_command("go movetime 20");
+            engine.wait_for_search();
+            let lines = captured.lines();
+            let warning = lines.iter().rev().find(|line| line.starts_with("info string time overshoot ")).unwrap();
+            assert!(warning.contains("(limit 20, nodes-between-checks "), "{}", warning);
+            let interval: u64 = warning.trim_end_matches(')').rsplit(' ').next().unwrap().parse().unwrap();
+            intervals.push(interval);
+        }
+        assert_eq!(intervals[0], 1024);
+        assert!(intervals[1] < intervals[0], "{:?}", intervals);
+
+        let lines = captured.lines();
+        let finished = lines.iter().rev().find(|line| line.contains("search finished")).unwrap();
+        assert!(finished.contains("time overshoots 2"), "{}", finished);
+
+        // within the margin nothing is reported
+        let lines = run(&["setoption name Overshoot Margin value 1000", "go movetime 20"]);
+        assert!(!lines.iter().any(|line| line.starts_with("info string time overshoot ")));
+    }
+
+    #[test]
+    fn combined_limits() {
+        let ended_by = |commands: &[&str]| {
+            let lines = run(commands);
+            let finished = lines.iter().find(|line| line.contains("search finished")).unwrap().clone();
+            finished.rsplit(' ').next().unwrap().to_string()
+        };
+
+        assert_eq!(ended_by(&["debug on", "go depth 2 movetime 60000 nodes 1000000"]), "depth");
+        assert_eq!(ended_by(&["debug on", "go depth 6 movetime 60000 nodes 50"]), "nodes");
+        assert_eq!(ended_by(&["debug on", "go depth 6 movetime 1 nodes 100000000"]), "time");
+
+        // infinite searches past all of them until stopped
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        engine.handle_command("go infinite depth 1 nodes 10 movetime 1");
+        std::thread::sleep(Duration::from_millis(300));
+        engine.handle_command("stop");
+        engine.wait_for_search();
+        let lines = captured.lines();
+        assert!(lines.iter().any(|line| line.starts_with("info score") && line.contains(" depth 2 ")), "{:?}", lines);
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+    }
+
+    // Plays 1.e4 as White, waits while the opponent thinks, and searches the
+    // reply to 1...e5 to depth 3. Returns what was sent, how long the
+    // commands took once the opponent had been thinking, and the nodes the
+    // last search took.
+    fn opponent_time(prime: bool) -> (Vec<String>, Duration, u64) {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        engine.handle_command(&format!("setoption name Ponder During Opponent Time value {}", prime));
+        engine.handle_command("debug on");
+        engine.handle_command("position startpos");
+        engine.handle_command("go depth 1");
+        engine.wait_for_search();
+
+        engine.handle_command("position startpos moves e2e4");
+        std::thread::sleep(Duration::from_millis(300));
+        let start = Instant::now();
+        engine.handle_command("isready");
+        engine.handle_command("position startpos moves e2e4 e7e5");
+        let elapsed = start.elapsed();
+        assert!(!engine.priming);
+
+        engine.handle_command("go depth 3");
+        engine.wait_for_search();
+        let lines = captured.lines();
+        let last_info = lines.iter().rev().find(|line| line.starts_with("info score ")).unwrap();
+        let nodes = last_info.split(" nodes ").nth(1).unwrap().split_whitespace().next().unwrap().parse().unwrap();
+        (lines, elapsed, nodes)
+    }
+
+    #[test]
+    fn ponder_during_opponent_time() {
+        assert!(run(&["uci"]).contains(&"option name Ponder During Opponent Time type check default false".to_string()));
+
+        let (lines, elapsed, primed_nodes) = opponent_time(true);
+        let primed = lines.iter().position(|line| line.ends_with("priming the hash table in the opponent's time"));
+        let stopped = lines.iter().position(|line| line.starts_with("info string priming stopped: depth "));
+        let (primed, stopped) = (primed.unwrap(), stopped.unwrap());
+
+        // only once, with the opponent to move, and silently until stopped
+        // by isready, which is still answered at once
+        assert_eq!(lines.iter().filter(|line| line.contains("priming the hash table")).count(), 1);
+        assert!(lines[primed + 1..stopped].is_empty(), "{:?}", lines);
+        assert_eq!(lines[stopped + 1], "readyok");
+        assert!(elapsed < Duration::from_millis(200), "took {:?}", elapsed);
+        assert_eq!(lines.iter().filter(|line| line.starts_with("bestmove ")).count(), 2);
+
+        // the search of the reply finds the table already holding some of it
+        let (lines, _, nodes) = opponent_time(false);
+        assert!(!lines.iter().any(|line| line.contains("priming")));
+        assert!(primed_nodes < nodes, "{} nodes primed, {} without", primed_nodes, nodes);
+    }
+
+    // the fen after playing the moves from the position
+    fn play(fen: &str, moves: &[&str]) -> String {
+        let mut board = Board::new(fen).unwrap();
+        let mut info = UndoInfo::default();
+        for uci_move in moves {
+            board.make_uci_move(uci_move, &MoveGenerator::new(), &mut info).unwrap();
+        }
+        board.to_fen()
+    }
+
+    #[test]
+    fn incremental_position() {
+        let startpos = crate::engine::board::STARTING_FEN;
+        let mut engine = UciEngine::new(UciOutput::new(CapturedOutput::default()));
+
+        // once the board no longer matches the last command it is easy to
+        // tell whether the next one rebuilt the position or extended it
+        let tampered = "rnbqkbnr/pppppppp/8/8/4P3/P7/1PPP1PPP/RNBQKBNR b KQkq - 0 1";
+        let tamper = |engine: &mut UciEngine, fen: &str| engine.board.load_fen(fen).unwrap();
+
+        // the same game with more moves only makes the new moves
+        engine.handle_command("position startpos moves e2e4");
+        assert_eq!(engine.board().to_fen(), play(startpos, &["e2e4"]));
+        tamper(&mut engine, tampered);
+        engine.handle_command("position startpos moves e2e4 e7e5 g1f3");
+        assert_eq!(engine.board().to_fen(), play(tampered, &["e7e5", "g1f3"]));
+
+        // a different game is set up from scratch
+        tamper(&mut engine, tampered);
+        engine.handle_command("position startpos moves d2d4");
+        assert_eq!(engine.board().to_fen(), play(startpos, &["d2d4"]));
+
+        // as is the same game from a different start
+        let fen = "4k3/8/8/8/8/8/3P4/4K3 w - - 0 1";
+        engine.handle_command(&format!("position fen {} moves d2d4", fen));
+        assert_eq!(engine.board().to_fen(), play(fen, &["d2d4"]));
+        let tampered_fen = "4k3/8/8/8/3P4/8/8/3K4 b - - 0 1";
+        tamper(&mut engine, tampered_fen);
+        engine.handle_command(&format!("position fen {} moves d2d4 e8e7", fen));
+        assert_eq!(engine.board().to_fen(), play(tampered_fen, &["e8e7"]));
+
+        // and anything after ucinewgame
+        engine.handle_command("position startpos moves e2e4");
+        engine.handle_command("ucinewgame");
+        tamper(&mut engine, tampered);
+        engine.handle_command("position startpos moves e2e4 e7e5");
+        assert_eq!(engine.board().to_fen(), play(startpos, &["e2e4", "e7e5"]));
+    }
+
+    #[test]
+    fn fen_without_counters() {
+        let mut engine = UciEngine::new(UciOutput::new(CapturedOutput::default()));
+
+        engine.handle_command("position fen 4k3/8/8/8/8/8/3P4/4K3 w - - moves d2d4 e8e7");
+        assert_eq!(engine.board().to_fen(), play("4k3/8/8/8/8/8/3P4/4K3 w - - 0 1", &["d2d4", "e8e7"]));
+    }
+
+    #[test]
+    fn castling_notation() {
+        let mut engine = UciEngine::new(UciOutput::new(CapturedOutput::default()));
+        let italian = ["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6", "e1g1", "f8c5"];
+        let expected = play(crate::engine::board::STARTING_FEN, &italian);
+
+        // the king taking its own rook is castling, whether or not the
+        // engine was told it is playing Chess960
+        for chess960 in &["false", "true"] {
+            engine.handle_command(&format!("setoption name UCI_Chess960 value {}", chess960));
+            engine.handle_command("ucinewgame");
+            engine.handle_command(&format!("position startpos moves {}", italian.join(" ")));
+            assert_eq!(engine.board().to_fen(), expected);
+
+            engine.handle_command("ucinewgame");
+            let king_takes_rook = italian.join(" ").replace("e1g1", "e1h1");
+            engine.handle_command(&format!("position startpos moves {}", king_takes_rook));
+            assert_eq!(engine.board().to_fen(), expected);
+            assert_eq!(engine.position.moves.len(), italian.len());
+        }
+
+        // both sides, both ways round
+        let fen = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+        engine.handle_command(&format!("position fen {} moves e1a1 e8h8", fen));
+        assert_eq!(engine.board().to_fen(), play(fen, &["e1c1", "e8g8"]));
+
+        // castling is mate, and is written the way the gui asked for
+        let mate = "position fen 8/8/8/4Q3/8/5k1P/8/4K2R w K - 0 1";
+        let lines = run(&[mate, "go depth 2"]);
+        assert_eq!(lines.last().unwrap(), "bestmove e1g1");
+        let lines = run(&["setoption name UCI_Chess960 value true", mate, "go depth 2"]);
+        assert!(lines[0].ends_with("pv e1h1"));
+        assert_eq!(lines.last().unwrap(), "bestmove e1h1");
+    }
+
+    #[test]
+    fn refutations() {
+        let fen = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3";
+        let position = format!("position fen {}", fen);
+        let generator = MoveGenerator::new();
+
+        // each line is a sequence of legal moves from the position
+        let assert_legal = |line: &[&str]| {
+            let mut board = Board::new(fen).unwrap();
+            let mut info = UndoInfo::default();
+            for uci_move in line {
+                assert!(board.make_uci_move(uci_move, &generator, &mut info).is_ok(), "{:?}", line);
+            }
+        };
+
+        let lines = run(&["setoption name UCI_ShowRefutations value true", &position, "go depth 3"]);
+        let best_move = best_move(lines.last().unwrap()).unwrap();
+
+        let refutations: Vec<Vec<&str>> = lines
+            .iter()
+            .filter_map(|line| line.strip_prefix("info refutation "))
+            .map(|line| line.split_whitespace().collect())
+            .collect();
+        assert!(!refutations.is_empty(), "{:?}", lines);
+        for line in &refutations {
+            // a move other than the best one, and at least one reply
+            assert_ne!(line[0], best_move);
+            assert!(line.len() >= 2, "{:?}", line);
+            assert_legal(line);
+        }
+
+        // the pv is legal too, and starts with the move played
+        let pv: Vec<&str> = lines
+            .iter()
+            .rfind(|line| line.starts_with("info score "))
+            .and_then(|line| line.split(" pv ").nth(1))
+            .unwrap()
+            .split_whitespace()
+            .collect();
+        assert_eq!(pv[0], best_move);
+        assert_eq!(pv.len(), 3);
+        assert_legal(&pv);
+
+        // none without the option
+        let lines = run(&[&position, "go depth 3"]);
+        assert!(!lines.iter().any(|line| line.starts_with("info refutation ")));
+    }
+
+    #[test]
+    fn config_then_setoption() {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+
+        let config = Config::parse("move_overhead = 250\nshow_wdl = true\n").unwrap();
+        engine.apply_config(&config);
+        assert_eq!(engine.options.move_overhead, 250);
+        assert!(engine.options.show_wdl);
+
+        // the gui has the last word
+        engine.handle_command("setoption name Move Overhead value 20");
+        assert_eq!(engine.options.move_overhead, 20);
+        assert!(engine.options.show_wdl);
+
+        // and without a file the defaults are kept
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        engine.apply_config(&Config::default());
+        assert_eq!(engine.options.move_overhead, timeman::DEFAULT_MOVE_OVERHEAD);
+        assert!(!engine.options.show_wdl);
+        assert!(captured.lines().is_empty());
+    }
+
+    #[test]
+    fn detect_new_game() {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let detected = || {
+            captured
+                .lines()
+                .iter()
+                .filter(|line| line.starts_with("info string new game without ucinewgame"))
+                .count()
+        };
+
+        let knights = "g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8 e2e4 e7e5";
+        engine.handle_command("debug on");
+        engine.handle_command("position startpos moves g1f3");
+        engine.handle_command(&format!("position startpos moves {}", knights));
+        // moves taken back are the same game
+        engine.handle_command("position startpos moves g1f3 g8f6 f3g1 f6g8 g1f3 g8f6 f3g1 f6g8");
+        assert_eq!(engine.board().ply(), 8);
+        assert_eq!(detected(), 0);
+
+        // the next game starts without a ucinewgame
+        engine.handle_command(&format!("position startpos moves {}", knights));
+        engine.handle_command("position startpos moves e2e4");
+        assert_eq!(detected(), 1);
+        assert_eq!(engine.detected_new_games, 1);
+        assert!(engine.position.moves == ["e2e4"]);
+        // with none of the last game's positions in the history
+        assert_eq!(engine.board().ply(), 1);
+
+        // a different game altogether
+        engine.handle_command("position startpos moves d2d4 d7d5");
+        engine.handle_command("position fen 4k3/8/8/8/8/8/3P4/4K3 w - - 0 1");
+        assert_eq!(detected(), 3);
+
+        // but not after a ucinewgame
+        engine.handle_command("ucinewgame");
+        engine.handle_command("position startpos moves e2e4");
+        assert_eq!(detected(), 3);
+    }
+
+    #[test]
+    fn reproducible() {
+        let commands = [
+            "position fen r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "go depth 3",
+            "go depth 3",
+        ];
+
+        // everything but the fields named
+        let without = |lines: &[String], fields: &[&str]| -> Vec<String> {
+            lines
+                .iter()
+                .map(|line| {
+                    let mut tokens: Vec<&str> = line.split_whitespace().collect();
+                    for field in fields {
+                        if let Some(idx) = tokens.iter().position(|t| t == field) {
+                            tokens.drain(idx..idx + 2);
+                        }
+                    }
+                    tokens.join(" ")
+                })
+                .collect()
+        };
+        let nodes = |line: &str| -> u64 {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let idx = tokens.iter().position(|&t| t == "nodes").unwrap();
+            tokens[idx + 1].parse().unwrap()
+        };
+
+        let first = run(&commands);
+        assert_eq!(first.len(), 8);
+        assert_eq!(without(&first, &["time", "nps"]), without(&run(&commands), &["time", "nps"]));
+
+        // the second search finds the same, quicker as the transposition
+        // table is kept
+        assert_eq!(without(&first[..4], &["time", "nodes", "nps"]), without(&first[4..], &["time", "nodes", "nps"]));
+        assert!(nodes(&first[6]) < nodes(&first[2]));
+
+        // unless there is a new game in between
+        let new_game = run(&[commands[0], commands[1], "ucinewgame", commands[0], commands[2]]);
+        assert_eq!(without(&new_game, &["time", "nps"]), without(&[&first[..4], &first[..4]].concat(), &["time", "nps"]));
+    }
+
+    #[test]
+    fn debug_diagnostics() {
+        let session = |debug: &str| {
+            run(&[
+                debug,
+                "position startpos",
+                "go depth 3",
+                "isready",
+                "go wtime 10000 btime 10000 depth 2",
+            ])
+        };
+
+        // only protocol lines, each exactly as usual
+        let is_protocol = |line: &String| {
+            line.starts_with("info score cp ") || line.starts_with("bestmove ") || line == "readyok"
+        };
+
+        let lines = session("debug off");
+        assert!(lines.iter().all(is_protocol), "{:?}", lines);
+
+        let lines = session("debug on");
+        let (diagnostics, protocol): (Vec<String>, Vec<String>) = lines.into_iter().partition(|line| {
+            ["info string ", "info refutation ", "info currline "]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+        });
+        let without_debug = session("debug off");
+        assert_eq!(protocol.len(), without_debug.len());
+        // isready is answered while the first search runs, so readyok can
+        // come anywhere in its output
+        let searches = |lines: &[String]| lines.iter().filter(|line| *line != "readyok").cloned().collect::<Vec<_>>();
+        assert_eq!(protocol.iter().filter(|line| *line == "readyok").count(), 1);
+        for (line, expected) in searches(&protocol).iter().zip(&searches(&without_debug)) {
+            // only the times can differ
+            assert_eq!(line.split(" time ").next(), expected.split(" time ").next());
+        }
+        assert!(protocol.iter().all(is_protocol), "{:?}", protocol);
+
+        assert!(diagnostics.iter().any(|line| line.contains("branching factor")));
+        assert!(diagnostics.iter().any(|line| line.starts_with("info refutation ")));
+        assert!(diagnostics.iter().any(|line| line.contains("soft limit")));
+        assert_eq!(diagnostics.iter().filter(|line| line.contains("search finished")).count(), 2);
+    }
+
+    #[test]
+    fn verify() {
+        let lines = run(&[
+            "position fen r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "verify",
+            "position fen 8/2P5/8/8/1pP5/8/8/k6K b - c3 0 1",
+            "verify",
+        ]);
+        assert_eq!(
+            lines,
+            vec![
+                "info string verify: checked the position and 48 moves, 0 problems",
+                "info string verify: checked the position and 5 moves, 0 problems",
+            ]
+        );
+    }
+
+    #[test]
+    fn perft() {
+        let lines = run(&["position startpos", "go perft 2"]);
+        assert_eq!(lines.len(), 22);
+        assert_eq!(lines[0], "a2a3: 20");
+        assert_eq!(lines[21], "Nodes searched: 400");
+    }
+
+    #[test]
+    fn analyse_queue() {
+        let path = std::env::temp_dir().join(format!("chess_rs_uci_queue_{}.txt", std::process::id()));
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "startpos moves e2e4 ; depth 2\n\
+             r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1 ; movetime 60000\n\
+             7k/8/6K1/8/8/8/8/R7 w - - 0 1 ; depth 3\n",
+        )
+        .unwrap();
+
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        let start = Instant::now();
+        engine.handle_command(&format!("analysequeue {}", path));
+
+        // isready is still answered, and a stop during the long second
+        // search moves on to the third position
+        while !captured.lines().contains(&"begin position 2".to_string()) {
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        std::thread::sleep(Duration::from_millis(100));
+        engine.handle_command("isready");
+        assert!(captured.lines().contains(&"readyok".to_string()));
+        engine.handle_command("stop");
+        engine.wait_for_search();
+        assert!(start.elapsed() < Duration::from_secs(5), "{:?}", start.elapsed());
+        std::fs::remove_file(path).unwrap();
+
+        let lines: Vec<String> = captured.lines().into_iter().filter(|line| line != "readyok").collect();
+        let blocks: Vec<&[String]> = lines.split(|line| line.starts_with("end position ")).collect();
+        assert_eq!(blocks.len(), 4, "{:?}", lines);
+        for (i, block) in blocks[..3].iter().enumerate() {
+            assert_eq!(block[0], format!("begin position {}", i + 1));
+            assert!(block[1..].iter().any(|line| line.starts_with("info score ")), "{:?}", block);
+            assert!(best_move(block.last().unwrap()).is_some(), "{:?}", block);
+        }
+        assert_eq!(best_move(blocks[0].last().unwrap()).map(|m| m.len()), Some(4));
+        assert_eq!(best_move(blocks[2].last().unwrap()), Some("a1a8"));
+        assert!(blocks[3].is_empty());
+
+        // quit ends the whole queue
+        std::fs::write(path, "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1\nstartpos\n").unwrap();
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+        engine.handle_command(&format!("analysequeue {}", path));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!engine.handle_command("quit"));
+        std::fs::remove_file(path).unwrap();
+        let lines = captured.lines();
+        assert_eq!(lines.first().unwrap(), "begin position 1");
+        assert_eq!(lines.last().unwrap(), "end position 1");
+
+        let lines = run(&["analysequeue", "analysequeue /no/such/queue"]);
+        assert_eq!(lines[0], "info string usage: analysequeue <file>");
+        assert!(lines[1].starts_with("info string script error: can't read"), "{:?}", lines);
+    }
+
+    #[test]
+    fn tt_save_load() {
+        let path = std::env::temp_dir().join(format!("chess_rs_uci_tt_{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let lines = run(&["go depth 3", &format!("tt save {}", path), "ucinewgame", &format!("tt load {}", path)]);
+        let saved = lines.iter().find(|line| line.starts_with("info string saved ")).unwrap();
+        let count = saved.split_whitespace().nth(3).unwrap();
+        assert_eq!(saved, &format!("info string saved {} entries to {}", count, path));
+        assert_eq!(lines.last().unwrap(), &format!("info string loaded {} entries from {}", count, path));
+        std::fs::remove_file(path).unwrap();
+
+        let lines = run(&["tt save", &format!("tt load {}", path)]);
+        assert_eq!(lines[0], "info string usage: tt save|load <path>");
+        assert!(lines[1].starts_with("info string hash table error: can't access"), "{:?}", lines);
+    }
+
+    #[test]
+    fn bench() {
+        let lines = run(&["bench 3"]);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "Nodes searched: 8902");
+        assert!(lines[1].starts_with("Nodes/second: "), "{:?}", lines);
+
+        // a bad depth is an error, and runs nothing
+        let lines = run(&["bench x"]);
+        assert_eq!(lines, ["info string search error: invalid search depth 0"]);
+    }
+
+    #[test]
+    fn stop_bench() {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+
+        // like go perft 7, bench 7 is still running when stopped
+        let start = std::time::Instant::now();
+        engine.handle_command("bench 7");
+        engine.handle_command("isready");
+        assert_eq!(captured.lines().last().map(String::as_str), Some("readyok"));
+        engine.handle_command("stop");
+        engine.handle_command("isready");
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        let lines = captured.lines();
+        assert_eq!(lines.len(), 4, "{:?}", lines);
+        assert!(lines[1].starts_with("Nodes searched: "), "{:?}", lines);
+        assert!(lines[1].ends_with(" (stopped)"), "{:?}", lines);
+        assert!(lines[2].starts_with("Nodes/second: "), "{:?}", lines);
+        assert_eq!(lines[3], "readyok");
+    }
+
+    #[test]
+    fn stop_perft() {
+        let captured = CapturedOutput::default();
+        let mut engine = UciEngine::new(UciOutput::new(captured.clone()));
+
+        // perft 7 takes minutes, so it is still running while isready is
+        // answered and when it is stopped
+        let start = std::time::Instant::now();
+        engine.handle_command("position startpos");
+        engine.handle_command("go perft 7");
+        engine.handle_command("isready");
+        assert_eq!(captured.lines().last().map(String::as_str), Some("readyok"));
+        engine.handle_command("stop");
+        assert!(start.elapsed() < Duration::from_secs(30));
+
+        let lines = captured.lines();
+        let summary = lines.last().unwrap();
+        assert!(summary.starts_with("Nodes searched: "), "{:?}", lines);
+        assert!(summary.ends_with(" of 20 moves)"), "{:?}", lines);
+
+        // and the engine is idle and usable again
+        engine.handle_command("isready");
+        engine.handle_command("go depth 1");
+        engine.wait_for_search();
+        let lines = captured.lines();
+        assert_eq!(lines[lines.len() - 3], "readyok");
+        assert!(lines.last().unwrap().starts_with("bestmove "));
+    }
+
+    #[test]
+    fn variant_option() {
+        let lines = run(&["uci"]);
+        assert!(lines.contains(
+            &"option name UCI_Variant type combo default standard var standard var 3check var kingofthehill".to_string()
+        ));
+
+        // the king walks into the centre rather than anywhere else
+        let lines = run(&[
+            "setoption name UCI_Variant value atomic",
+            "setoption name UCI_Variant value kingofthehill",
+            "position fen 8/8/8/8/8/3K4/8/7k w - - 0 1",
+            "go depth 2",
+        ]);
+        assert_eq!(lines[0], "info string invalid UCI_Variant 'atomic'");
+        assert!(matches!(lines.last().unwrap().as_str(), "bestmove d3d4" | "bestmove d3e4"), "{:?}", lines);
+    }
+
+    #[cfg(not(feature = "tune"))]
+    #[test]
+    fn tune_options_hidden() {
+        assert!(!run(&["uci"]).iter().any(|line| line.starts_with("option name PawnValue")));
+    }
+
+    #[cfg(feature = "tune")]
+    #[test]
+    fn tune_options() {
+        let lines = run(&["uci"]);
+        assert!(lines.contains(&"option name PawnValue type spin default 100 min 0 max 2000".to_string()));
+
+        // three pawns up with nothing else on the board, where the quiet
+        // moves barely change the score
+        let position = "position fen 4k3/8/8/8/8/8/PPP5/4K3 w - - 0 1";
+        let score = |lines: &[String]| -> i32 {
+            let line = lines.iter().find(|line| line.starts_with("info score cp ")).unwrap();
+            line.split_whitespace().nth(3).unwrap().parse().unwrap()
+        };
+
+        let default = score(&run(&[position, "go depth 1"]));
+        let lines = run(&["setoption name PawnValue value lots", "setoption name PawnValue value 200", position, "go depth 1"]);
+        assert_eq!(lines[0], "info string invalid PawnValue 'lots'");
+
+        // another 100 for each pawn, and an eighth more for the pieces
+        // being traded off
+        let shift = score(&lines) - default;
+        assert!((330..=345).contains(&shift), "{} {}", default, shift);
+    }
 }
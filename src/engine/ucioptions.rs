@@ -0,0 +1,390 @@
+// The engine's uci options. Each one is described once in OPTIONS, with its
+// type, default and limits, which is what the uci command advertises and
+// what setoption checks values against, so the two can't disagree.
+//
+// UciOptions only holds the values. Anything else an option does, like
+// resizing the hash table or loading tablebases, is up to the engine once
+// the value has been accepted.
+
+use crate::engine::board::Variant;
+use crate::engine::error::OptionError;
+use crate::engine::playout;
+use crate::engine::search;
+use crate::engine::timeman::{self, TimeManager};
+use crate::engine::tt;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OptionKind {
+    Check { default: bool },
+    Spin { default: usize, min: usize, max: usize },
+    Combo { default: &'static str, vars: &'static [&'static str] },
+    // empty by default
+    String,
+    Button,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UciOption {
+    pub name: &'static str,
+    pub kind: OptionKind,
+}
+
+// in the order they are sent in reply to uci
+pub const OPTIONS: &[UciOption] = &[
+    UciOption {
+        name: "UCI_ShowWDL",
+        kind: OptionKind::Check { default: false },
+    },
+    UciOption {
+        name: "UCI_ShowRefutations",
+        kind: OptionKind::Check { default: false },
+    },
+    UciOption {
+        name: "UCI_ShowCurrLine",
+        kind: OptionKind::Check { default: false },
+    },
+    UciOption {
+        name: "UCI_Chess960",
+        kind: OptionKind::Check { default: false },
+    },
+    UciOption {
+        name: "UCI_Variant",
+        kind: OptionKind::Combo {
+            default: "standard",
+            vars: &["standard", "3check", "kingofthehill"],
+        },
+    },
+    UciOption {
+        name: "Debug Log File",
+        kind: OptionKind::String,
+    },
+    UciOption {
+        name: "Move Overhead",
+        kind: OptionKind::Spin {
+            default: timeman::DEFAULT_MOVE_OVERHEAD,
+            min: 0,
+            max: timeman::MAX_MOVE_OVERHEAD,
+        },
+    },
+    UciOption {
+        name: "Overshoot Margin",
+        kind: OptionKind::Spin {
+            default: timeman::DEFAULT_OVERSHOOT_MARGIN,
+            min: 0,
+            max: timeman::MAX_OVERSHOOT_MARGIN,
+        },
+    },
+    UciOption {
+        name: "Hash",
+        kind: OptionKind::Spin {
+            default: tt::DEFAULT_HASH_MB,
+            min: 1,
+            max: tt::MAX_HASH_MB,
+        },
+    },
+    UciOption {
+        name: "Clear Hash",
+        kind: OptionKind::Button,
+    },
+    UciOption {
+        name: "Ponder",
+        kind: OptionKind::Check { default: false },
+    },
+    UciOption {
+        name: "Threads",
+        kind: OptionKind::Spin {
+            default: search::DEFAULT_THREADS,
+            min: 1,
+            max: search::MAX_THREADS,
+        },
+    },
+    UciOption {
+        name: "MultiPV",
+        kind: OptionKind::Spin {
+            default: search::DEFAULT_MULTI_PV,
+            min: 1,
+            max: search::MAX_MULTI_PV,
+        },
+    },
+    UciOption {
+        name: "SyzygyPath",
+        kind: OptionKind::String,
+    },
+    UciOption {
+        name: "Playout Seed",
+        kind: OptionKind::String,
+    },
+    UciOption {
+        name: "Playout Move Limit",
+        kind: OptionKind::Spin {
+            default: playout::DEFAULT_PLAYOUT_MOVES,
+            min: 1,
+            max: playout::MAX_PLAYOUT_MOVES,
+        },
+    },
+    UciOption {
+        name: "Ponder During Opponent Time",
+        kind: OptionKind::Check { default: false },
+    },
+    #[cfg(feature = "nnue")]
+    UciOption {
+        name: "EvalFile",
+        kind: OptionKind::String,
+    },
+];
+
+// a value setoption gave, once it has been checked against the option
+#[derive(Clone, Debug, PartialEq)]
+enum OptionValue {
+    Check(bool),
+    Spin(usize),
+    Combo(String),
+    // None if the value was empty
+    String(Option<String>),
+    Button,
+}
+
+impl UciOption {
+    pub fn find(name: &str) -> Option<&'static UciOption> {
+        OPTIONS.iter().find(|option| option.name == name)
+    }
+
+    // the line describing the option, sent in reply to uci
+    pub fn advertise(&self) -> String {
+        match self.kind {
+            OptionKind::Check { default } => format!("option name {} type check default {}", self.name, default),
+            OptionKind::Spin { default, min, max } => format!(
+                "option name {} type spin default {} min {} max {}",
+                self.name, default, min, max
+            ),
+            OptionKind::Combo { default, vars } => {
+                let mut line = format!("option name {} type combo default {}", self.name, default);
+                for var in vars {
+                    line.push_str(" var ");
+                    line.push_str(var);
+                }
+                line
+            }
+            OptionKind::String => format!("option name {} type string default <empty>", self.name),
+            OptionKind::Button => format!("option name {} type button", self.name),
+        }
+    }
+
+    // None if the value isn't one the option can take
+    fn parse(&self, value: &str) -> Option<OptionValue> {
+        match self.kind {
+            OptionKind::Check { .. } => match value {
+                "true" => Some(OptionValue::Check(true)),
+                "false" => Some(OptionValue::Check(false)),
+                _ => None,
+            },
+            OptionKind::Spin { min, max, .. } => match value.parse::<usize>() {
+                Ok(n) if (min..=max).contains(&n) => Some(OptionValue::Spin(n)),
+                _ => None,
+            },
+            OptionKind::Combo { vars, .. } => {
+                vars.contains(&value).then(|| OptionValue::Combo(value.to_string()))
+            }
+            OptionKind::String => match value {
+                "" | "<empty>" => Some(OptionValue::String(None)),
+                value => Some(OptionValue::String(Some(value.to_string()))),
+            },
+            OptionKind::Button => Some(OptionValue::Button),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct UciOptions {
+    pub show_wdl: bool,
+    // refutations and the current line are also sent with "debug on"
+    pub show_refutations: bool,
+    pub show_currline: bool,
+    // castling is sent as the king taking its rook, though either way is
+    // understood in position commands
+    pub chess960: bool,
+    pub variant: Variant,
+    pub log_file: Option<String>,
+    pub move_overhead: usize,
+    pub overshoot_margin: usize,
+    pub hash_mb: usize,
+    pub ponder: bool,
+    // the main search thread and its helpers
+    pub threads: usize,
+    // the number of best lines sent after each iteration
+    pub multi_pv: usize,
+    pub syzygy_path: Option<String>,
+    // makes go playouts repeatable, if set
+    pub playout_seed: Option<u64>,
+    // a playout still going after this many moves each is a draw
+    pub playout_moves: usize,
+    // a position with the opponent to move is searched until the next
+    // command, to fill the hash table
+    pub prime_in_opponent_time: bool,
+    #[cfg(feature = "nnue")]
+    pub eval_file: Option<String>,
+}
+
+impl Default for UciOptions {
+    fn default() -> UciOptions {
+        UciOptions {
+            show_wdl: false,
+            show_refutations: false,
+            show_currline: false,
+            chess960: false,
+            variant: Variant::Standard,
+            log_file: None,
+            move_overhead: timeman::DEFAULT_MOVE_OVERHEAD,
+            overshoot_margin: timeman::DEFAULT_OVERSHOOT_MARGIN,
+            hash_mb: tt::DEFAULT_HASH_MB,
+            ponder: false,
+            threads: search::DEFAULT_THREADS,
+            multi_pv: search::DEFAULT_MULTI_PV,
+            syzygy_path: None,
+            playout_seed: None,
+            playout_moves: playout::DEFAULT_PLAYOUT_MOVES,
+            prime_in_opponent_time: false,
+            #[cfg(feature = "nnue")]
+            eval_file: None,
+        }
+    }
+}
+
+impl UciOptions {
+    // the lines describing every option, sent in reply to uci
+    pub fn advertise() -> impl Iterator<Item = String> {
+        OPTIONS.iter().map(UciOption::advertise)
+    }
+
+    // Sets the option from a setoption command. Nothing changes if the name
+    // or the value is wrong.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), OptionError> {
+        let option = UciOption::find(name).ok_or_else(|| OptionError::Unknown(name.to_string()))?;
+        let invalid = || OptionError::Invalid(name.to_string(), value.to_string());
+
+        match (option.name, option.parse(value).ok_or_else(invalid)?) {
+            ("UCI_ShowWDL", OptionValue::Check(on)) => self.show_wdl = on,
+            ("UCI_ShowRefutations", OptionValue::Check(on)) => self.show_refutations = on,
+            ("UCI_ShowCurrLine", OptionValue::Check(on)) => self.show_currline = on,
+            ("UCI_Chess960", OptionValue::Check(on)) => self.chess960 = on,
+            ("UCI_Variant", OptionValue::Combo(var)) => {
+                self.variant = Variant::from_name(&var).ok_or_else(invalid)?;
+            }
+            ("Debug Log File", OptionValue::String(path)) => self.log_file = path,
+            ("Move Overhead", OptionValue::Spin(millis)) => self.move_overhead = millis,
+            ("Overshoot Margin", OptionValue::Spin(millis)) => self.overshoot_margin = millis,
+            ("Hash", OptionValue::Spin(megabytes)) => self.hash_mb = megabytes,
+            ("Ponder", OptionValue::Check(on)) => self.ponder = on,
+            ("Threads", OptionValue::Spin(threads)) => self.threads = threads,
+            ("MultiPV", OptionValue::Spin(count)) => self.multi_pv = count,
+            ("SyzygyPath", OptionValue::String(path)) => self.syzygy_path = path,
+            ("Playout Seed", OptionValue::String(seed)) => {
+                self.playout_seed = match seed {
+                    Some(seed) => Some(seed.parse::<u64>().map_err(|_| invalid())?),
+                    None => None,
+                };
+            }
+            ("Playout Move Limit", OptionValue::Spin(moves)) => self.playout_moves = moves,
+            ("Ponder During Opponent Time", OptionValue::Check(on)) => self.prime_in_opponent_time = on,
+            #[cfg(feature = "nnue")]
+            ("EvalFile", OptionValue::String(path)) => self.eval_file = path,
+            // a button has no value to keep
+            (_, OptionValue::Button) => {}
+            (name, value) => unreachable!("option {} can't take {:?}", name, value),
+        }
+
+        Ok(())
+    }
+
+    pub fn time_manager(&self) -> TimeManager {
+        TimeManager {
+            move_overhead: self.move_overhead,
+            overshoot_margin: self.overshoot_margin,
+            ..TimeManager::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_match_table() {
+        // setting every option to the default it advertises changes nothing
+        let mut options = UciOptions::default();
+        for option in OPTIONS {
+            let default = match option.kind {
+                OptionKind::Check { default } => default.to_string(),
+                OptionKind::Spin { default, .. } => default.to_string(),
+                OptionKind::Combo { default, .. } => default.to_string(),
+                OptionKind::String => "<empty>".to_string(),
+                OptionKind::Button => continue,
+            };
+            options.set(option.name, &default).unwrap();
+        }
+        assert_eq!(options, UciOptions::default());
+    }
+
+    #[test]
+    fn advertise() {
+        let lines: Vec<String> = UciOptions::advertise().collect();
+        assert_eq!(lines.len(), OPTIONS.len());
+        assert_eq!(lines[0], "option name UCI_ShowWDL type check default false");
+        assert!(lines.contains(
+            &"option name UCI_Variant type combo default standard var standard var 3check var kingofthehill"
+                .to_string()
+        ));
+        assert!(lines.contains(&"option name Clear Hash type button".to_string()));
+        assert!(lines.contains(&"option name SyzygyPath type string default <empty>".to_string()));
+        assert!(lines.contains(&format!(
+            "option name Hash type spin default {} min 1 max {}",
+            tt::DEFAULT_HASH_MB,
+            tt::MAX_HASH_MB
+        )));
+    }
+
+    #[test]
+    fn set() {
+        let mut options = UciOptions::default();
+
+        options.set("Threads", "4").unwrap();
+        options.set("UCI_ShowWDL", "true").unwrap();
+        options.set("UCI_Variant", "3check").unwrap();
+        options.set("Playout Seed", "42").unwrap();
+        options.set("SyzygyPath", "/tb").unwrap();
+        options.set("Move Overhead", "250").unwrap();
+        assert_eq!(options.threads, 4);
+        assert!(options.show_wdl);
+        assert_eq!(options.variant, Variant::ThreeCheck);
+        assert_eq!(options.playout_seed, Some(42));
+        assert_eq!(options.syzygy_path.as_deref(), Some("/tb"));
+        assert_eq!(options.time_manager().move_overhead, 250);
+
+        options.set("Playout Seed", "<empty>").unwrap();
+        options.set("SyzygyPath", "").unwrap();
+        assert_eq!(options.playout_seed, None);
+        assert_eq!(options.syzygy_path, None);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let mut options = UciOptions::default();
+
+        for (name, value) in [
+            ("Threads", "0"),
+            ("Threads", "lots"),
+            ("Hash", &(tt::MAX_HASH_MB + 1).to_string()),
+            ("UCI_ShowWDL", "yes"),
+            ("UCI_Variant", "atomic"),
+            ("Playout Seed", "-1"),
+        ] {
+            assert_eq!(
+                options.set(name, value),
+                Err(OptionError::Invalid(name.to_string(), value.to_string()))
+            );
+        }
+        assert_eq!(options.set("Contempt", "10"), Err(OptionError::Unknown("Contempt".to_string())));
+        assert_eq!(options, UciOptions::default());
+    }
+}
@@ -0,0 +1,7 @@
+pub mod cli;
+pub mod engine;
+pub mod prelude;
+
+pub use engine::error::Error;
+// `r#move` is awkward to write outside the crate
+pub use engine::r#move as moves;
@@ -1,12 +1,13 @@
-mod engine;
-
-use engine::{board::Board, eval::Evaluator, movegen::{MoveGenerator, MoveList}};
-use engine::search;
-use engine::r#move::{MoveUtils, UndoInfo};
-use engine::perft;
-use engine::uci;
+use chess_rs::engine::{board::Board, eval::Evaluator, movegen::{MoveGenerator, MoveList}};
+use chess_rs::engine::search::{self, SearchContext};
+use chess_rs::engine::r#move::{MoveUtils, UndoInfo};
+use chess_rs::engine::perft;
+use chess_rs::cli;
+use chess_rs::engine::config::{self, Config};
+use chess_rs::engine::error::EXIT_USAGE;
 
 
+#[allow(dead_code)]
 fn two_player_console() {
     const MAX_DEPTH: usize = 6;
 
@@ -17,48 +18,44 @@ fn two_player_console() {
     for _ in 0..MAX_DEPTH {
         move_lists.push(MoveList::new());
     }
+    let mut context = SearchContext::new();
 
     println!("Enter fen: ");
     let mut fen = String::new();
     std::io::stdin().read_line(&mut fen).expect("Failed to read fen");
 
-    let mut board = Board::new(&fen).expect("Invalid fen");
+    let mut board = match Board::new(&fen) {
+        Ok(board) => board,
+        Err(e) => {
+            eprintln!("{}", chess_rs::Error::from(e));
+            return;
+        }
+    };
 
     perft::perft_divide(MAX_DEPTH, &mut board);
 
     let mut info = UndoInfo::default();
     
     loop {
-        println!("{}\n{}", board.to_string(), board.to_fen());
+        println!("{}\n{}", board, board.to_fen());
         
         let mut possible_moves = MoveList::new();
-        move_generator.gen_moves(&mut board, &mut possible_moves);
-
-        if possible_moves.len() != 0 {
-            let mut my_move = 0;
+        let check_info = move_generator.gen_moves(&mut board, &mut possible_moves);
 
+        if !possible_moves.is_empty() {
             if board.friendly_color().is_white() {
                 let mut msg = String::new();
                 std::io::stdin().read_line(&mut msg).unwrap();
 
-                for i in 0..possible_moves.len() {
-                    if possible_moves.at(i).move_to_string() == msg.trim() {
-                        my_move = possible_moves.at(i);
-                        break;
-                    }
-                }
-
-                if my_move == 0 {
-                    panic!("Illegal move entered");
+                if let Err(e) = board.make_uci_move(msg.trim(), &move_generator, &mut info) {
+                    eprintln!("{}", chess_rs::Error::from(e));
                 }
-            } else if let Some((best_move, _)) = search::find_best_move(MAX_DEPTH, &mut board, &mut evaluator, &move_generator, &mut move_lists) {
-                my_move = best_move;
+            } else if let Some((best_move, _)) = search::find_best_move(MAX_DEPTH, &mut board, &mut evaluator, &move_generator, &mut move_lists, &mut context) {
+                println!("{}", best_move.move_to_string());
+                board.make_move(best_move, &mut info);
             }
-            
-            println!("{}", my_move.move_to_string());
-            board.make_move(my_move, &mut info);
         } else {
-            if move_generator.is_in_check(&mut board) {
+            if check_info.checkers != 0 {
                 println!("Winner: {}", board.enemy_color());
             } else {
                 println!("Stalemate");
@@ -69,6 +66,83 @@ fn two_player_console() {
     }
 }
 
+// the file given with --config, or chess_rs.toml next to the executable if
+// there is one
+fn load_config(args: &[String]) -> Option<Result<Config, chess_rs::Error>> {
+    let path = match args.iter().position(|arg| arg == "--config") {
+        Some(idx) => args.get(idx + 1)?.clone(),
+        None => match std::env::current_exe() {
+            Ok(exe) if exe.with_file_name(config::DEFAULT_FILE_NAME).is_file() => {
+                exe.with_file_name(config::DEFAULT_FILE_NAME).to_string_lossy().into_owned()
+            }
+            _ => return Some(Ok(Config::default())),
+        },
+    };
+
+    Some(Config::load(&path).map_err(chess_rs::Error::from))
+}
+
 fn main() {
-    uci::uci();
+    let args: Vec<String> = std::env::args().collect();
+
+    if args.get(1).map(String::as_str) == Some("fen-check") {
+        let stdin = std::io::stdin();
+        let code = cli::fen_check(&args[2..], stdin.lock(), &mut std::io::stdout());
+        std::process::exit(code);
+    }
+
+    if args.get(1).map(String::as_str) == Some("perft") {
+        let code = cli::perft_command(&args[2..], &mut std::io::stdout());
+        std::process::exit(code);
+    }
+
+    if args.get(1).map(String::as_str) == Some("dump-tables") {
+        let code = cli::dump_tables_command(&args[2..], &mut std::io::stdout());
+        std::process::exit(code);
+    }
+
+    if args.get(1).map(String::as_str) == Some("annotate") {
+        std::process::exit(cli::annotate_command(&args[2..]));
+    }
+
+    if args.get(1).map(String::as_str) == Some("config") {
+        if args.get(2).map(String::as_str) == Some("--write-default") {
+            print!("{}", config::default_template());
+            return;
+        }
+        eprintln!("usage: chess_rs config --write-default");
+        std::process::exit(EXIT_USAGE);
+    }
+
+    let config = match load_config(&args) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        None => {
+            eprintln!("usage: chess_rs [--config <file>] [--log <file>]");
+            std::process::exit(EXIT_USAGE);
+        }
+    };
+    for warning in config.warnings() {
+        eprintln!("config warning: {}", warning);
+    }
+
+    if args.get(1).map(String::as_str) == Some("script") {
+        let script_args: Vec<String> = args[2..]
+            .iter()
+            .take_while(|arg| *arg != "--config")
+            .cloned()
+            .collect();
+        std::process::exit(cli::script_command(&script_args, &config));
+    }
+
+    // --log <file> writes every line sent and received to the file
+    let log_file = args
+        .iter()
+        .position(|arg| arg == "--log")
+        .and_then(|idx| args.get(idx + 1));
+
+    cli::uci(log_file.map(String::as_str), &config);
 }
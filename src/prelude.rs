@@ -0,0 +1,15 @@
+// The types and functions most users of the library need, so that
+// `use chess_rs::prelude::*;` is enough to load a position, search it and
+// show the result. Everything else is still reachable through
+// chess_rs::engine.
+
+pub use crate::engine::board::{Board, GameState};
+pub use crate::engine::control::ControlHandle;
+pub use crate::engine::eval::{Evaluate, Evaluator};
+pub use crate::engine::movegen::{MoveGenerator, MoveList};
+pub use crate::engine::piece::{Color, PieceType, Pieces};
+pub use crate::engine::r#move::{Move, MoveUtils, UndoInfo};
+pub use crate::engine::san::move_to_san;
+pub use crate::engine::search::{find_best_move, iterative_deepening, parallel_search, PvLine, SearchContext};
+pub use crate::engine::square::Square;
+pub use crate::Error;